@@ -1,11 +1,166 @@
-use super::{ base_types::*, committee::Committee, message::*, error::* };
-use std::collections::{ HashMap, HashSet };
-use tokio::sync::mpsc;
+use crate::fp_ensure;
+
+use super::{ base_types::*, committee::*, downloader::*, message::*, error::*, sharding::*, storage::* };
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use tokio::sync::{mpsc, oneshot, Mutex, MutexGuard};
 
 /// Trait for verifying escrow on source chain
-pub trait EscrowVerifier {
-    /// Verify the escrow exists and has the correct amount locked
-    fn verify_escrow(&self, transfer: &CrossChainTransfer) -> Result<bool, FastPayError>;
+pub trait EscrowVerifier: Send + Sync + 'static {
+    /// Verify the escrow exists and has the correct amount locked. An escrow the source
+    /// chain does not hold yet is `EscrowNotFound`, so that the order can be sent again
+    /// once this verifier catches up; `false` means the escrow does not match the transfer.
+    fn verify_escrow<'a>(
+        &'a self,
+        transfer: &'a CrossChainTransfer
+    ) -> BoxFuture<'a, Result<bool, FastPayError>>;
+}
+
+/// Evidence that the escrow of a transfer was found on the source chain. Only
+/// `EscrowCache::verify` creates it.
+pub struct VerifiedEscrow {
+    transfer_digest: ContentDigest,
+}
+
+/// Transfers whose escrow is being verified, by id, with the number of orders waiting for each
+type InFlightTransfers = Arc<RwLock<HashMap<InteropTxId, (CrossChainTransfer, usize)>>>;
+
+/// What the source chain answered about the escrow of the transfer in flight under an id
+#[derive(Debug, Clone)]
+struct EscrowAnswer {
+    transfer: Option<CrossChainTransfer>,
+    found: Result<bool, FastPayError>,
+}
+
+/// Lets the downloader query an escrow verifier about the transfers in flight.
+struct EscrowRequester<V> {
+    verifier: Arc<V>,
+    in_flight: InFlightTransfers,
+}
+
+impl<V> Clone for EscrowRequester<V> {
+    fn clone(&self) -> Self {
+        Self {
+            verifier: self.verifier.clone(),
+            in_flight: self.in_flight.clone(),
+        }
+    }
+}
+
+impl<V: EscrowVerifier> Requester for EscrowRequester<V> {
+    type Key = InteropTxId;
+    type Value = EscrowAnswer;
+
+    fn query(&mut self, key: Self::Key) -> BoxFuture<Self::Value> {
+        let verifier = self.verifier.clone();
+        let transfer = self.in_flight.read().unwrap().get(&key).map(|(transfer, _)| transfer.clone());
+        Box::pin(async move {
+            let found = match &transfer {
+                Some(transfer) => verifier.verify_escrow(transfer).await,
+                None => Err(FastPayError::EscrowVerificationFailed {
+                    error: "The transfer is no longer in flight".to_string(),
+                }),
+            };
+            EscrowAnswer { transfer, found }
+        })
+    }
+}
+
+/// Verifies escrows outside of the authority state. Concurrent orders for the same transfer
+/// id wait for a single query to the source chain, and only take its answer if they carry
+/// the very transfer that was verified. Answers are dropped once delivered, so the cache only
+/// holds the queries in flight: an authority that voted answers re-submitted orders from its
+/// own state without verifying again.
+#[derive(Clone)]
+pub struct EscrowCache {
+    handle: DownloadHandle<InteropTxId, EscrowAnswer>,
+    in_flight: InFlightTransfers,
+}
+
+impl EscrowCache {
+    /// Start the downloader task in charge of the given verifier.
+    pub fn start<V: EscrowVerifier>(verifier: V) -> (tokio::task::JoinHandle<()>, Self) {
+        let in_flight = InFlightTransfers::default();
+        let requester = EscrowRequester {
+            verifier: Arc::new(verifier),
+            in_flight: in_flight.clone(),
+        };
+        let (task, handle) = Downloader::start(requester, Vec::new());
+        let task = tokio::spawn(async move {
+            let _ = task.await;
+        });
+        (task, Self { handle, in_flight })
+    }
+
+    /// Check the escrow of a transfer.
+    pub async fn verify(&self, transfer: &CrossChainTransfer) -> Result<VerifiedEscrow, FastPayError> {
+        let id = transfer.interop_tx_id;
+        {
+            let mut in_flight = self.in_flight.write().unwrap();
+            let (current, waiting) = in_flight.entry(id).or_insert_with(|| (transfer.clone(), 0));
+            if current == transfer {
+                *waiting += 1;
+            }
+        }
+        let mut handle = self.handle.clone();
+        let answer = handle.query(id).await;
+        handle.forget(id).await.unwrap_or(());
+        {
+            let mut in_flight = self.in_flight.write().unwrap();
+            if let Some((current, waiting)) = in_flight.get_mut(&id)
+                && current == transfer
+            {
+                *waiting -= 1;
+                if *waiting == 0 {
+                    in_flight.remove(&id);
+                }
+            }
+        }
+
+        let answer = answer.map_err(|e| FastPayError::EscrowVerificationFailed { error: e.to_string() })?;
+        // Another transfer under the same id was being verified, ask again later
+        fp_ensure!(
+            answer.transfer.as_ref() == Some(transfer),
+            FastPayError::EscrowVerificationFailed {
+                error: "Another transfer with the same id is being verified".to_string(),
+            }
+        );
+        if !answer.found? {
+            return Err(FastPayError::InvalidTransferAmount {
+                error: transfer.amount.to_string(),
+            });
+        }
+        Ok(VerifiedEscrow {
+            transfer_digest: ContentDigest::new(transfer),
+        })
+    }
+}
+
+/// Certificate in the inbox of a shard, with where to report once the shard has stored it
+pub struct CrossShardDelivery {
+    pub update: CrossShardCrossChainUpdate,
+    pub done: oneshot::Sender<Result<(), FastPayError>>,
+}
+
+/// State of a sender's account, like a FastPay account
+#[derive(Default, Debug, Clone)]
+pub struct AccountState {
+    /// Nonce expected for the next order of the sender
+    pub next_nonce: u64,
+
+    /// Order this authority voted for, waiting for its certificate
+    pub pending_order: Option<CrossChainTransferOrder>,
+
+    /// Order this authority voted to refund, waiting for the refund certificate. It holds
+    /// the nonce like a pending order, but is never voted for delivery.
+    pub pending_refund: Option<CrossChainRefundOrder>,
+
+    /// Nonce expected for the next message of the sender
+    pub next_message_nonce: u64,
+
+    /// Message order this authority voted for, waiting for its certificate
+    pub pending_message: Option<CrossChainMessageOrder>,
 }
 
 /// State for a single shard of a bridge authority
@@ -13,84 +168,426 @@ pub struct BridgeShardState {
     /// Shard identifier
     pub shard_id: ShardId,
 
-    /// Processed cross-chain transfers (to prevent replay)
-    pub processed_transfers: HashSet<InteropTxId>,
+    /// How senders are mapped to shards
+    pub sharding: ShardingStrategy,
+
+    /// Accounts of the senders handled by this shard
+    pub accounts: HashMap<Pubkey, AccountState>,
+
+    /// Certificates of processed cross-chain transfers (to prevent replay)
+    pub processed_transfers: HashMap<InteropTxId, CertifiedCrossChainTransferOrder>,
 
     /// Pending cross-chain transfers waiting for certification
     pub pending_transfers: HashMap<InteropTxId, CrossChainTransferOrder>,
+
+    /// Certificates of processed cross-chain messages (to prevent replay)
+    pub processed_messages: HashMap<InteropTxId, CertifiedCrossChainMessageOrder>,
+
+    /// Transfers this authority voted to refund; it no longer votes for their delivery
+    pub refund_votes: HashMap<InteropTxId, CrossChainRefundOrder>,
+
+    /// Certificates of refunded transfers
+    pub refunded_transfers: HashMap<InteropTxId, CertifiedCrossChainRefundOrder>,
+
+    /// Records in the log of the shard, and in the snapshot it last replaced it with
+    log_length: usize,
+    snapshot_length: usize,
 }
 
+/// Records appended to the log of a shard before it is replaced by a snapshot of the shard.
+/// At least as many records as in the snapshot are appended first, so that taking snapshots
+/// costs a constant time per record.
+const MIN_RECORDS_BEFORE_SNAPSHOT: usize = 10_000;
+
 impl BridgeShardState {
     /// Create a new shard state
-    pub fn new(shard_id: ShardId) -> Self {
+    pub fn new(shard_id: ShardId, sharding: ShardingStrategy) -> Self {
         Self {
             shard_id,
-            processed_transfers: HashSet::new(),
+            sharding,
+            accounts: HashMap::new(),
+            processed_transfers: HashMap::new(),
             pending_transfers: HashMap::new(),
+            processed_messages: HashMap::new(),
+            refund_votes: HashMap::new(),
+            refunded_transfers: HashMap::new(),
+            log_length: 0,
+            snapshot_length: 0,
+        }
+    }
+
+    /// Records leading to the current state of the shard: certificates, then the votes still
+    /// waiting for theirs. Votes of certified orders are left out.
+    pub fn snapshot(&self) -> Vec<ShardRecord> {
+        let mut records = Vec::new();
+        records.extend(self.processed_transfers.values().cloned().map(ShardRecord::Certificate));
+        records.extend(self.processed_messages.values().cloned().map(ShardRecord::MessageCertificate));
+        records.extend(self.refunded_transfers.values().cloned().map(ShardRecord::RefundCertificate));
+        records.extend(self.pending_transfers.values().cloned().map(ShardRecord::Vote));
+        records.extend(
+            self.accounts
+                .values()
+                .filter_map(|account| account.pending_message.clone())
+                .map(ShardRecord::MessageVote),
+        );
+        records.extend(self.refund_votes.values().cloned().map(ShardRecord::RefundVote));
+        records
+    }
+
+    /// Whether the log grew enough since the last snapshot to be replaced by a new one
+    fn needs_snapshot(&self) -> bool {
+        self.log_length - self.snapshot_length >= self.snapshot_length.max(MIN_RECORDS_BEFORE_SNAPSHOT)
+    }
+
+    /// Check that the authority may vote for an order. Returns the order it already voted
+    /// for if this is a re-submission.
+    pub fn check_order(
+        &self,
+        order: &CrossChainTransferOrder
+    ) -> Result<Option<&CrossChainTransferOrder>, FastPayError> {
+        let transfer = &order.transfer;
+        fp_ensure!(
+            !self.processed_transfers.contains_key(&transfer.interop_tx_id),
+            FastPayError::CertificateAlreadyExists
+        );
+        let account = match self.accounts.get(&transfer.sender) {
+            Some(account) => account,
+            None => {
+                fp_ensure!(transfer.nonce == 0, FastPayError::UnexpectedTransactionIndex);
+                return Ok(None);
+            }
+        };
+        if let Some(refund) = &account.pending_refund {
+            // The nonce is taken until the refund is certified.
+            return Err(FastPayError::PreviousTransferMustBeConfirmedFirst {
+                pending_confirmation: refund.transfer().clone(),
+            });
+        }
+        if let Some(pending) = &account.pending_order {
+            // Never vote for two different orders with the same nonce.
+            fp_ensure!(
+                pending == order,
+                FastPayError::PreviousTransferMustBeConfirmedFirst {
+                    pending_confirmation: pending.transfer.clone(),
+                }
+            );
+            return Ok(Some(pending));
+        }
+        fp_ensure!(transfer.nonce == account.next_nonce, FastPayError::UnexpectedTransactionIndex);
+        fp_ensure!(transfer.nonce < u64::MAX, FastPayError::SequenceOverflow);
+        Ok(None)
+    }
+
+    /// Record that the authority voted for an order.
+    pub fn record_vote(&mut self, order: CrossChainTransferOrder) {
+        let account = self.accounts.entry(order.transfer.sender).or_default();
+        account.pending_order = Some(order.clone());
+        self.pending_transfers.insert(order.transfer.interop_tx_id, order);
+    }
+
+    /// Record that a certificate was processed.
+    pub fn record_certificate(
+        &mut self,
+        certificate: &CertifiedCrossChainTransferOrder
+    ) -> Result<(), FastPayError> {
+        let transfer = &certificate.value.transfer;
+        self.processed_transfers.insert(transfer.interop_tx_id, certificate.clone());
+        self.pending_transfers.remove(&transfer.interop_tx_id);
+
+        // Only the shard of the sender keeps its account.
+        if self.sharding.shard_of(&transfer.sender) != self.shard_id {
+            return Ok(());
+        }
+        let account = self.accounts.entry(transfer.sender).or_default();
+        if transfer.nonce >= account.next_nonce {
+            account.next_nonce = transfer.nonce
+                .checked_add(1)
+                .ok_or(FastPayError::SequenceOverflow)?;
+            account.pending_order = None;
+            account.pending_refund = None;
+        }
+        Ok(())
+    }
+
+    /// Check that the authority may vote to refund a transfer: either it never voted for its
+    /// delivery, or the deadline of the transfer has passed. A certified transfer is never
+    /// refunded, since its certificate may already have been delivered. Returns true if it
+    /// already voted.
+    pub fn check_refund_order(
+        &self,
+        order: &CrossChainRefundOrder,
+        now: Timestamp
+    ) -> Result<bool, FastPayError> {
+        let transfer = order.transfer();
+        // Even if it voted to refund before the certificate arrived
+        fp_ensure!(
+            !self.processed_transfers.contains_key(&transfer.interop_tx_id),
+            FastPayError::CertificateAlreadyExists
+        );
+        if self.refund_votes.contains_key(&transfer.interop_tx_id) {
+            return Ok(true);
+        }
+        // Refunding takes the nonce of the transfer, so it must be usable like for delivery.
+        let voted_delivery = self.check_order(&order.transfer_order)?.is_some();
+        fp_ensure!(!voted_delivery || transfer.is_expired(now), FastPayError::TransferNotExpired);
+        Ok(false)
+    }
+
+    /// Record that the authority voted to refund a transfer. The nonce stays taken, and the
+    /// vote for its delivery, if any, is withdrawn so that it is never handed out again.
+    pub fn record_refund_vote(&mut self, order: CrossChainRefundOrder) {
+        let interop_tx_id = order.transfer().interop_tx_id;
+        let account = self.accounts.entry(order.transfer().sender).or_default();
+        if account.pending_order.as_ref() == Some(&order.transfer_order) {
+            account.pending_order = None;
+        }
+        // The nonce of a certified transfer is already released
+        if order.transfer().nonce >= account.next_nonce {
+            account.pending_refund = Some(order.clone());
+        }
+        self.pending_transfers.remove(&interop_tx_id);
+        self.refund_votes.insert(interop_tx_id, order);
+    }
+
+    /// Record that a refund certificate was processed, releasing the nonce of the transfer.
+    pub fn record_refund_certificate(
+        &mut self,
+        certificate: &CertifiedCrossChainRefundOrder
+    ) -> Result<(), FastPayError> {
+        let transfer = certificate.value.transfer();
+        self.refunded_transfers.insert(transfer.interop_tx_id, certificate.clone());
+        self.refund_votes.remove(&transfer.interop_tx_id);
+        self.pending_transfers.remove(&transfer.interop_tx_id);
+        let account = self.accounts.entry(transfer.sender).or_default();
+        if transfer.nonce >= account.next_nonce {
+            account.next_nonce = transfer.nonce
+                .checked_add(1)
+                .ok_or(FastPayError::SequenceOverflow)?;
+            account.pending_order = None;
+            account.pending_refund = None;
+        }
+        Ok(())
+    }
+
+    /// Check that the authority may vote for a message order. Returns the order it already
+    /// voted for if this is a re-submission.
+    pub fn check_message_order(
+        &self,
+        order: &CrossChainMessageOrder
+    ) -> Result<Option<&CrossChainMessageOrder>, FastPayError> {
+        let message = &order.message;
+        fp_ensure!(
+            !self.processed_messages.contains_key(&message.interop_tx_id),
+            FastPayError::CertificateAlreadyExists
+        );
+        let account = match self.accounts.get(&message.sender) {
+            Some(account) => account,
+            None => {
+                fp_ensure!(message.nonce == 0, FastPayError::UnexpectedTransactionIndex);
+                return Ok(None);
+            }
+        };
+        if let Some(pending) = &account.pending_message {
+            // Never vote for two different messages with the same nonce.
+            fp_ensure!(
+                pending == order,
+                FastPayError::PreviousMessageMustBeConfirmedFirst {
+                    pending_confirmation: pending.message.clone(),
+                }
+            );
+            return Ok(Some(pending));
+        }
+        fp_ensure!(
+            message.nonce == account.next_message_nonce,
+            FastPayError::UnexpectedTransactionIndex
+        );
+        fp_ensure!(message.nonce < u64::MAX, FastPayError::SequenceOverflow);
+        Ok(None)
+    }
+
+    /// Record that the authority voted for a message order.
+    pub fn record_message_vote(&mut self, order: CrossChainMessageOrder) {
+        let account = self.accounts.entry(order.message.sender).or_default();
+        account.pending_message = Some(order);
+    }
+
+    /// Record that a message certificate was processed.
+    pub fn record_message_certificate(
+        &mut self,
+        certificate: &CertifiedCrossChainMessageOrder
+    ) -> Result<(), FastPayError> {
+        let message = &certificate.value.message;
+        self.processed_messages.insert(message.interop_tx_id, certificate.clone());
+        let account = self.accounts.entry(message.sender).or_default();
+        if message.nonce >= account.next_message_nonce {
+            account.next_message_nonce = message.nonce
+                .checked_add(1)
+                .ok_or(FastPayError::SequenceOverflow)?;
+            account.pending_message = None;
+        }
+        Ok(())
+    }
+
+    /// Replay a record read back from storage.
+    pub fn apply(&mut self, record: ShardRecord) -> Result<(), FastPayError> {
+        match record {
+            ShardRecord::Vote(order) => {
+                self.record_vote(order);
+                Ok(())
+            }
+            ShardRecord::Certificate(certificate) => self.record_certificate(&certificate),
+            ShardRecord::MessageVote(order) => {
+                self.record_message_vote(order);
+                Ok(())
+            }
+            ShardRecord::MessageCertificate(certificate) => {
+                self.record_message_certificate(&certificate)
+            }
+            ShardRecord::RefundVote(order) => {
+                self.record_refund_vote(order);
+                Ok(())
+            }
+            ShardRecord::RefundCertificate(certificate) => {
+                self.record_refund_certificate(&certificate)
+            }
         }
     }
 }
 
-/// The bridge authority implementation
-pub struct BridgeAuthorityState<V: EscrowVerifier> {
+/// The bridge authority implementation. Each shard has its own lock, so that shards process
+/// requests in parallel, and signatures are checked before taking it. Records are synced to
+/// disk on blocking threads, holding only the lock of their shard meanwhile.
+pub struct BridgeAuthorityState<S: AuthorityStore> {
     /// The authority's identity
     pub name: AuthorityName,
 
     /// The authority's keypair
     pub secret: KeyPair,
 
-    /// The committee configuration
-    pub committee: Committee,
+    /// The committees of the current and past epochs, only written on reconfiguration
+    pub committees: RwLock<CommitteeHistory>,
+
+    /// Held while a committee change is persisted, so that changes are applied one at a time
+    reconfiguration: Mutex<()>,
 
-    /// Total number of shards
-    pub number_of_shards: u32,
+    /// How senders are mapped to shards
+    pub sharding: ShardingStrategy,
 
     /// States for all shards managed by this authority
-    pub shard_states: HashMap<ShardId, BridgeShardState>,
+    pub shard_states: HashMap<ShardId, Mutex<BridgeShardState>>,
 
-    /// Channel for cross-shard communication
-    pub cross_shard_sender: mpsc::UnboundedSender<CrossShardCrossChainUpdate>,
+    /// Inboxes of the shards, for the certificates processed by the shard of their sender
+    pub cross_shard_senders: HashMap<ShardId, mpsc::UnboundedSender<CrossShardDelivery>>,
 
-    /// Escrow verifier
-    pub escrow_verifier: V,
+    /// Durable storage for the state of the shards
+    pub store: Arc<S>,
 }
 
-impl<V: EscrowVerifier> BridgeAuthorityState<V> {
-    /// Create a new bridge authority state with multiple shards
+impl<S: AuthorityStore + 'static> BridgeAuthorityState<S> {
+    /// Create a new bridge authority state with multiple shards, recovering the state of
+    /// each shard from the given store. Returns the inbox of each shard along with it.
     pub fn new(
         name: AuthorityName,
         secret: KeyPair,
         committee: Committee,
-        number_of_shards: u32,
-        escrow_verifier: V
-    ) -> (Self, mpsc::UnboundedReceiver<CrossShardCrossChainUpdate>) {
-        // Create channel for cross-shard communication
-        let (cross_shard_sender, cross_shard_receiver) = mpsc::unbounded_channel();
+        sharding: ShardingStrategy,
+        store: S
+    ) -> Result<
+        (Self, HashMap<ShardId, mpsc::UnboundedReceiver<CrossShardDelivery>>),
+        FastPayError
+    > {
+        // Replay the reconfigurations that happened since the given committee
+        let mut committees = CommitteeHistory::new(committee);
+        for change in store.load_committee_changes()? {
+            committees.advance(change)?;
+        }
 
-        // Create states for all shards
+        // Create states for all shards and replay their logs
         let mut shard_states = HashMap::new();
-        for i in 0..number_of_shards {
-            let shard_id = i as ShardId;
-            shard_states.insert(shard_id, BridgeShardState::new(shard_id));
+        let mut cross_shard_senders = HashMap::new();
+        let mut cross_shard_receivers = HashMap::new();
+        for shard_id in sharding.shard_ids() {
+            let mut shard_state = BridgeShardState::new(shard_id, sharding);
+            for record in store.load(shard_id)? {
+                shard_state.apply(record)?;
+                shard_state.log_length += 1;
+            }
+            // Drop the records that no longer matter
+            let snapshot = shard_state.snapshot();
+            if snapshot.len() < shard_state.log_length {
+                store.replace(shard_id, &snapshot)?;
+                shard_state.log_length = snapshot.len();
+            }
+            shard_state.snapshot_length = shard_state.log_length;
+            shard_states.insert(shard_id, Mutex::new(shard_state));
+
+            let (sender, receiver) = mpsc::unbounded_channel();
+            cross_shard_senders.insert(shard_id, sender);
+            cross_shard_receivers.insert(shard_id, receiver);
         }
 
         let state = Self {
             name,
             secret,
-            committee,
-            number_of_shards,
+            committees: RwLock::new(committees),
+            reconfiguration: Mutex::new(()),
+            sharding,
             shard_states,
-            cross_shard_sender,
-            escrow_verifier,
+            cross_shard_senders,
+            store: Arc::new(store),
         };
 
-        (state, cross_shard_receiver)
+        Ok((state, cross_shard_receivers))
+    }
+
+    /// Lock the state of a shard
+    async fn shard(&self, shard_id: ShardId) -> Result<MutexGuard<'_, BridgeShardState>, FastPayError> {
+        let shard_state = self.shard_states
+            .get(&shard_id)
+            .ok_or(FastPayError::ShardStateNotFound { shard_id })?;
+        Ok(shard_state.lock().await)
+    }
+
+    /// Write to the store on a blocking thread, so that syncing to disk does not hold up the
+    /// other requests of the runtime.
+    async fn write<F>(&self, write: F) -> Result<(), FastPayError>
+    where
+        F: FnOnce(&S) -> Result<(), FastPayError> + Send + 'static,
+    {
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || write(&store))
+            .await
+            .map_err(|e| FastPayError::StorageError { error: e.to_string() })?
+    }
+
+    /// Append a record to the log of a shard, and apply it once it is durable. The log is
+    /// replaced by a snapshot of the shard from time to time.
+    async fn persist(&self, shard_state: &mut BridgeShardState, record: ShardRecord) -> Result<(), FastPayError> {
+        let shard_id = shard_state.shard_id;
+        let entry = record.clone();
+        self.write(move |store| store.append(shard_id, &entry)).await?;
+        shard_state.apply(record)?;
+        shard_state.log_length += 1;
+
+        if shard_state.needs_snapshot() {
+            let snapshot = shard_state.snapshot();
+            let length = snapshot.len();
+            self.write(move |store| store.replace(shard_id, &snapshot)).await?;
+            shard_state.log_length = length;
+            shard_state.snapshot_length = length;
+        }
+        Ok(())
+    }
+
+    /// The committees of the current and past epochs. It may be read while holding the lock of a
+    /// shard, but the guard is never held across an await, so no shard is locked under it.
+    pub fn committees(&self) -> RwLockReadGuard<'_, CommitteeHistory> {
+        self.committees.read().unwrap()
     }
 
     /// Get the shard ID for a transfer based on sender address
     pub fn get_shard_id(&self, transfer: &CrossChainTransfer) -> ShardId {
-        let first_byte = transfer.sender.0[0];
-        (first_byte as u32) % 16
+        transfer.shard_id(&self.sharding)
     }
 
     /// Check if a transfer belongs to a specific shard
@@ -98,106 +595,367 @@ impl<V: EscrowVerifier> BridgeAuthorityState<V> {
         self.get_shard_id(transfer) == shard_id
     }
 
-    /// Handle a cross-chain transfer order for a specific shard
-    pub fn handle_cross_chain_transfer_order(
-        &mut self,
-        order: CrossChainTransferOrder,
-        shard_id: ShardId
-    ) -> Result<SignedCrossChainTransferOrder, FastPayError> {
-        // Verify transfer is in this shard
-        if !self.in_shard(&order.transfer, shard_id) {
+    /// Check that a sender belongs to a shard, i.e. that the relayer routed its request like we would
+    fn check_shard(&self, sender: &Pubkey, shard_id: ShardId) -> Result<(), FastPayError> {
+        let expected = self.sharding.shard_of(sender);
+        if expected != shard_id {
             return Err(FastPayError::WrongShard {
-                err: format!(
-                    "Transfer sender {} is not in shard {}",
-                    order.transfer.sender.0[0],
-                    shard_id
-                ),
+                expected,
+                num_shards: self.sharding.number_of_shards(),
             });
         }
+        Ok(())
+    }
 
-        // Get the shard state
-        let shard_state = self.shard_states
-            .get_mut(&shard_id)
-            .ok_or(FastPayError::ShardStateNotFound { shard_id: shard_id })?;
+    /// Answer a transfer order that needs no new vote: return the certificate of a processed
+    /// transfer, or our vote again for a re-submitted order. Returns None if the order is
+    /// acceptable but its escrow must be verified before voting.
+    pub async fn check_cross_chain_transfer_order(
+        &self,
+        order: &CrossChainTransferOrder,
+        shard_id: ShardId
+    ) -> Result<Option<TransferOrderResponse>, FastPayError> {
+        // Verify transfer is in this shard
+        self.check_shard(&order.transfer.sender, shard_id)?;
 
         // Verify the transfer order signature
         order.check_signature()?;
 
-        // Check if already processed
+        let shard_state = self.shard(shard_id).await?;
+        self.check_order_in_shard(&shard_state, order)
+    }
+
+    /// Checks of a transfer order against the state of its shard
+    fn check_order_in_shard(
+        &self,
+        shard_state: &BridgeShardState,
+        order: &CrossChainTransferOrder
+    ) -> Result<Option<TransferOrderResponse>, FastPayError> {
+        // Return the certificate if the transfer was already processed
+        if let Some(certificate) = shard_state.processed_transfers.get(&order.transfer.interop_tx_id) {
+            return Ok(Some(TransferOrderResponse::Certificate(certificate.clone())));
+        }
+
+        // Never vote for delivering a transfer that may be refunded
         let interop_tx_id = order.transfer.interop_tx_id;
-        if shard_state.processed_transfers.contains(&interop_tx_id) {
-            return Err(FastPayError::CertificateAlreadyExists);
+        fp_ensure!(
+            !order.transfer.is_expired(current_timestamp()) &&
+                !shard_state.refund_votes.contains_key(&interop_tx_id) &&
+                !shard_state.refunded_transfers.contains_key(&interop_tx_id),
+            FastPayError::TransferExpired
+        );
+
+        // Check nonce and conflicts with a pending order of the sender. If we already voted
+        // for this order, simply vote again.
+        match shard_state.check_order(order)? {
+            Some(pending) => Ok(Some(TransferOrderResponse::Vote(self.sign(pending.clone())))),
+            None => Ok(None),
         }
+    }
 
-        // Verify escrow on source chain
-        if !self.escrow_verifier.verify_escrow(&order.transfer)? {
-            return Err(FastPayError::InvalidTransferAmount {
-                error: order.transfer.amount.to_string(),
-            });
+    /// Handle a cross-chain transfer order for a specific shard, once its escrow was verified
+    /// outside of the authority state
+    pub async fn handle_cross_chain_transfer_order(
+        &self,
+        order: CrossChainTransferOrder,
+        shard_id: ShardId,
+        escrow: &VerifiedEscrow
+    ) -> Result<TransferOrderResponse, FastPayError> {
+        fp_ensure!(
+            escrow.transfer_digest == ContentDigest::new(&order.transfer),
+            FastPayError::EscrowVerificationFailed {
+                error: "Escrow was verified for another transfer".to_string(),
+            }
+        );
+        self.check_shard(&order.transfer.sender, shard_id)?;
+        order.check_signature()?;
+
+        // The state may have changed while the escrow was being verified
+        let mut shard_state = self.shard(shard_id).await?;
+        if let Some(response) = self.check_order_in_shard(&shard_state, &order)? {
+            return Ok(response);
         }
 
-        // Store the order
-        shard_state.pending_transfers.insert(interop_tx_id, order.clone());
+        // Persist the vote before it leaves the authority
+        self.persist(&mut shard_state, ShardRecord::Vote(order.clone())).await?;
+        drop(shard_state);
 
         // Sign the order
-        let signed_order = SignedCrossChainTransferOrder::new(order, self.name, &self.secret);
+        Ok(TransferOrderResponse::Vote(self.sign(order)))
+    }
+
+    /// Handle a refund order for a specific shard
+    pub async fn handle_refund_order(
+        &self,
+        order: CrossChainRefundOrder,
+        shard_id: ShardId
+    ) -> Result<RefundOrderResponse, FastPayError> {
+        self.check_shard(&order.transfer().sender, shard_id)?;
+        order.check_signature()?;
+
+        let mut shard_state = self.shard(shard_id).await?;
+
+        // Return the certificate if the transfer was already refunded
+        let interop_tx_id = order.transfer().interop_tx_id;
+        if let Some(certificate) = shard_state.refunded_transfers.get(&interop_tx_id) {
+            return Ok(RefundOrderResponse::Certificate(certificate.clone()));
+        }
+
+        if !shard_state.check_refund_order(&order, current_timestamp())? {
+            // Persist the vote before it leaves the authority
+            self.persist(&mut shard_state, ShardRecord::RefundVote(order.clone())).await?;
+        }
+        drop(shard_state);
+
+        let vote = self.sign(order);
+        Ok(RefundOrderResponse::Vote(vote))
+    }
+
+    /// Process a refund certificate on the shard of the sender of the transfer
+    pub async fn handle_refund_certificate(
+        &self,
+        certificate: CertifiedCrossChainRefundOrder,
+        shard_id: ShardId
+    ) -> Result<(), FastPayError> {
+        self.check_shard(&certificate.value.transfer().sender, shard_id)?;
+
+        // Nothing to do if the certificate was already processed
+        let interop_tx_id = certificate.value.transfer().interop_tx_id;
+        if self.shard(shard_id).await?.refunded_transfers.contains_key(&interop_tx_id) {
+            return Ok(());
+        }
+
+        certificate.check(self.committees().get(certificate.epoch)?)?;
+        fp_ensure!(certificate.value.transfer().nonce < u64::MAX, FastPayError::SequenceOverflow);
+
+        // Another request may have processed it in the meantime
+        let mut shard_state = self.shard(shard_id).await?;
+        if shard_state.refunded_transfers.contains_key(&interop_tx_id) {
+            return Ok(());
+        }
+        self.persist(&mut shard_state, ShardRecord::RefundCertificate(certificate)).await
+    }
+
+    /// Handle a cross-chain message order for a specific shard. Messages go through the same
+    /// shards as transfers, but have their own nonces.
+    pub async fn handle_cross_chain_message_order(
+        &self,
+        order: CrossChainMessageOrder,
+        shard_id: ShardId
+    ) -> Result<MessageOrderResponse, FastPayError> {
+        self.check_shard(&order.message.sender, shard_id)?;
+        order.message.check_payload()?;
+        order.check_signature()?;
+
+        let mut shard_state = self.shard(shard_id).await?;
+
+        // Return the certificate if the message was already processed
+        if let Some(certificate) = shard_state.processed_messages.get(&order.message.interop_tx_id) {
+            return Ok(MessageOrderResponse::Certificate(certificate.clone()));
+        }
+
+        if shard_state.check_message_order(&order)?.is_none() {
+            // Persist the vote before it leaves the authority
+            self.persist(&mut shard_state, ShardRecord::MessageVote(order.clone())).await?;
+        }
+        drop(shard_state);
+
+        let vote = self.sign(order);
+        Ok(MessageOrderResponse::Vote(vote))
+    }
+
+    /// Process the certificate of a message on the shard of its sender
+    pub async fn handle_message_certificate(
+        &self,
+        certificate: CertifiedCrossChainMessageOrder,
+        shard_id: ShardId
+    ) -> Result<(), FastPayError> {
+        self.check_shard(&certificate.value.message.sender, shard_id)?;
+
+        // Nothing to do if the certificate was already processed
+        let interop_tx_id = certificate.value.message.interop_tx_id;
+        if self.shard(shard_id).await?.processed_messages.contains_key(&interop_tx_id) {
+            return Ok(());
+        }
+
+        certificate.check(self.committees().get(certificate.epoch)?)?;
+        fp_ensure!(certificate.value.message.nonce < u64::MAX, FastPayError::SequenceOverflow);
+
+        // Another request may have processed it in the meantime
+        let mut shard_state = self.shard(shard_id).await?;
+        if shard_state.processed_messages.contains_key(&interop_tx_id) {
+            return Ok(());
+        }
+        self.persist(&mut shard_state, ShardRecord::MessageCertificate(certificate)).await
+    }
+
+    /// Report the pending order, our vote and the certificate known for a transfer
+    pub async fn handle_transfer_info_request(
+        &self,
+        request: TransferInfoRequest,
+        shard_id: ShardId
+    ) -> Result<TransferInfoResponse, FastPayError> {
+        self.check_shard(&request.sender, shard_id)?;
+
+        let interop_tx_id = request.interop_tx_id;
+        let (pending_order, certificate) = {
+            let shard_state = self.shard(shard_id).await?;
+            (
+                shard_state.pending_transfers.get(&interop_tx_id).cloned(),
+                shard_state.processed_transfers.get(&interop_tx_id).cloned(),
+            )
+        };
+        fp_ensure!(
+            pending_order.is_some() || certificate.is_some(),
+            FastPayError::CertificateNotfound
+        );
+        let signed_order = pending_order.clone().map(|order| self.sign(order));
+        Ok(TransferInfoResponse {
+            interop_tx_id,
+            pending_order,
+            signed_order,
+            certificate,
+        })
+    }
+
+    /// Report the next nonce and the pending order of a sender
+    pub async fn handle_account_info_request(
+        &self,
+        request: AccountInfoRequest,
+        shard_id: ShardId
+    ) -> Result<AccountInfoResponse, FastPayError> {
+        self.check_shard(&request.sender, shard_id)?;
+
+        let account = self.shard(shard_id).await?.accounts.get(&request.sender).cloned().unwrap_or_default();
+        Ok(AccountInfoResponse {
+            sender: request.sender,
+            next_nonce: account.next_nonce,
+            pending_order: account.pending_order.map(|order| self.sign(order)),
+            next_message_nonce: account.next_message_nonce,
+        })
+    }
 
-        Ok(signed_order)
+    /// Move to the committee of the next epoch, once certified by the current committee
+    pub async fn handle_committee_change(
+        &self,
+        change: CertifiedCommitteeChange
+    ) -> Result<CommitteeInfoResponse, FastPayError> {
+        {
+            let _reconfiguration = self.reconfiguration.lock().await;
+            let is_new = {
+                let committees = self.committees();
+                let current = committees.current();
+                if change.value.epoch > current.epoch {
+                    change.check(current)?;
+                }
+                change.value.epoch > current.epoch
+            };
+            if is_new {
+                let record = change.clone();
+                self.write(move |store| store.append_committee_change(&record)).await?;
+                self.committees.write().unwrap().advance(change)?;
+            }
+        }
+        let since_epoch = self.committees().current().epoch;
+        Ok(self.handle_committee_info_request(CommitteeInfoRequest { since_epoch }))
+    }
+
+    /// Report the current epoch and the certified changes that lead to it
+    pub fn handle_committee_info_request(
+        &self,
+        request: CommitteeInfoRequest
+    ) -> CommitteeInfoResponse {
+        let committees = self.committees();
+        CommitteeInfoResponse {
+            epoch: committees.current().epoch,
+            changes: committees.changes_since(request.since_epoch),
+        }
+    }
+
+    /// Vote for a value in the current committee, from the shard of its sender. Signatures
+    /// are deterministic, so voting twice yields the same vote.
+    fn sign<T: Votable>(&self, value: T) -> SignedOrder<T> {
+        let shard = self.sharding.shard_of(&value.key().0);
+        SignedOrder::new(value, self.name, self.committees().current(), shard, &self.secret)
     }
 
     /// Handle a cross-shard update
-    pub fn handle_cross_shard_update(
-        &mut self,
+    pub async fn handle_cross_shard_update(
+        &self,
         update: CrossShardCrossChainUpdate
     ) -> Result<(), FastPayError> {
-        // Get the shard state
-        let shard_state = self.shard_states
-            .get_mut(&update.shard_id)
-            .ok_or(FastPayError::ShardStateNotFound { shard_id: update.shard_id })?;
-
-        // Verify the certificate
-        update.transfer_certificate.check(&self.committee)?;
+        let shard_id = update.shard_id;
 
-        // Mark as processed
+        // Nothing to do if the certificate was already processed
         let interop_tx_id = update.transfer_certificate.value.transfer.interop_tx_id;
-        shard_state.processed_transfers.insert(interop_tx_id);
+        if self.shard(shard_id).await?.processed_transfers.contains_key(&interop_tx_id) {
+            return Ok(());
+        }
 
-        // Remove from pending if present
-        shard_state.pending_transfers.remove(&interop_tx_id);
+        // Verify the certificate against the committee of its epoch
+        let certificate = &update.transfer_certificate;
+        certificate.check(self.committees().get(certificate.epoch)?)?;
+        let nonce = update.transfer_certificate.value.transfer.nonce;
+        fp_ensure!(nonce < u64::MAX, FastPayError::SequenceOverflow);
 
-        Ok(())
+        // Persist, then mark as processed, unless another update did in the meantime
+        let mut shard_state = self.shard(shard_id).await?;
+        if shard_state.processed_transfers.contains_key(&interop_tx_id) {
+            return Ok(());
+        }
+        self.persist(&mut shard_state, ShardRecord::Certificate(update.transfer_certificate)).await
     }
 
-    /// Propagate a certified transfer to all shards
-    pub fn propagate_certified_transfer(
+    /// Propagate a certified transfer to all shards. Returns once every shard has stored it.
+    pub async fn propagate_certified_transfer(
         &self,
         certificate: CertifiedCrossChainTransferOrder
     ) -> Result<(), FastPayError> {
-        // Verify the certificate
-        certificate.check(&self.committee)?;
-
-        // Broadcast to all shards
-        for shard_id in self.shard_states.keys() {
+        // Verify the certificate against the committee of its epoch
+        certificate.check(self.committees().get(certificate.epoch)?)?;
+
+        // Broadcast to all shards. A shard that is not running yet may take the update later.
+        let inbox_error = |shard_id| FastPayError::CrossShardDeliveryFailed { shard_id };
+        let mut deliveries = Vec::new();
+        for (shard_id, sender) in &self.cross_shard_senders {
+            let (done, delivered) = oneshot::channel();
             let update = CrossShardCrossChainUpdate {
                 shard_id: *shard_id,
                 transfer_certificate: certificate.clone(),
             };
-
-            self.cross_shard_sender.send(update).map_err(|_| FastPayError::ConfigurationError {
-                error: "Failed to send cross-shard update".to_string(),
-            })?;
+            sender.send(CrossShardDelivery { update, done }).map_err(|_| inbox_error(*shard_id))?;
+            deliveries.push(async move { (*shard_id, delivered.await) });
+        }
+        for (shard_id, result) in futures::future::join_all(deliveries).await {
+            result.map_err(|_| inbox_error(shard_id))??;
         }
 
         Ok(())
     }
+
+    /// Same as `propagate_certified_transfer` for a certificate in compact form.
+    pub async fn propagate_compact_certificate(
+        &self,
+        certificate: CompactCertificate
+    ) -> Result<(), FastPayError> {
+        let certificate = certificate.expand(self.committees().get(certificate.epoch)?)?;
+        self.propagate_certified_transfer(certificate).await
+    }
 }
 
 pub struct DummyEscrowVerifier;
 
 impl EscrowVerifier for DummyEscrowVerifier {
-    fn verify_escrow(&self, _transfer: &CrossChainTransfer) -> Result<bool, FastPayError> {
+    fn verify_escrow<'a>(
+        &'a self,
+        _transfer: &'a CrossChainTransfer
+    ) -> BoxFuture<'a, Result<bool, FastPayError>> {
         // Dummy implementation always returns true
         // For a real implementation, this would check the source chain or some mechanism that verifies the escrow
-        Ok(true)
+        Box::pin(async { Ok(true) })
     }
 }
+
+#[cfg(test)]
+#[path = "unit_tests/authority_tests.rs"]
+mod authority_tests;
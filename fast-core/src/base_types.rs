@@ -1,10 +1,11 @@
 use ed25519_dalek as dalek;
 use ed25519_dalek::{Signer, Verifier};
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha512};
+use sha2::{Digest, Sha256, Sha512};
 use std::hash::Hash;
 
 use crate::error::FastPayError;
+use crate::sharding::ShardingStrategy;
 
 /// Chain identifier for source and destination chains
 #[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize)]
@@ -23,6 +24,28 @@ impl Pubkey {
     pub fn base58(&self) -> String {
         bs58::encode(self.0).into_string()
     }
+
+    /// Whether the key is a point of the ed25519 curve, i.e. may have a private key
+    pub fn is_on_curve(&self) -> bool {
+        dalek::VerifyingKey::from_bytes(&self.0).is_ok()
+    }
+
+    /// Program derived address of an SVM program for the given seeds, with its bump seed:
+    /// the first hash of the seeds and a decreasing bump that is off the curve, so that only
+    /// the program can sign for it.
+    pub fn find_program_address(seeds: &[&[u8]], program: &Pubkey) -> Option<(Pubkey, u8)> {
+        (0..=u8::MAX).rev().find_map(|bump| {
+            let mut hasher = Sha256::new();
+            for seed in seeds {
+                hasher.update(seed);
+            }
+            hasher.update([bump]);
+            hasher.update(program.0);
+            hasher.update(b"ProgramDerivedAddress");
+            let address = Pubkey(hasher.finalize().into());
+            (!address.is_on_curve()).then_some((address, bump))
+        })
+    }
 }
 
 #[derive(Eq, PartialEq, Copy, Clone, Serialize, Deserialize, Debug)]
@@ -46,6 +69,11 @@ impl InteropTxId {
 }
 
 pub type ShardId = u32;
+pub type Epoch = u64;
+/// Identifier of a bridge deployment, carried by every message between its nodes
+pub type NetworkId = u32;
+/// Seconds since the Unix epoch
+pub type Timestamp = u64;
 pub type AuthorityName = Pubkey;
 pub struct KeyPair(dalek::SigningKey);
 
@@ -58,6 +86,17 @@ impl KeyPair {
     pub fn public(&self) -> Pubkey {
         Pubkey(self.0.verifying_key().to_bytes())
     }
+
+    /// Sign raw bytes, e.g. a transaction of another chain. Bridge values are signed with
+    /// `Signature::new` instead.
+    pub fn sign_bytes(&self, message: &[u8]) -> [u8; 64] {
+        self.0.sign(message).to_bytes()
+    }
+
+    /// Secret key followed by public key, as stored by SVM wallets.
+    pub fn to_keypair_bytes(&self) -> [u8; 64] {
+        self.0.to_keypair_bytes()
+    }
 }
 
 /// Cross-chain transfer information
@@ -72,6 +111,7 @@ pub struct CrossChainTransfer {
     pub interop_tx_id: InteropTxId,
     pub escrow_account: Pubkey, // Escrow account on source chain
     pub nonce: u64,             // Nonce to prevent replay attacks
+    pub deadline: Timestamp,    // Past this time, the transfer may only be refunded
 }
 
 impl CrossChainTransfer {
@@ -80,16 +120,105 @@ impl CrossChainTransfer {
     }
 
     /// Determine which shard should process this transfer
-    pub fn shard_id(&self) -> ShardId {
-        // Shard based on the first byte of sender address, like in FastPay
-        let bytes = self.sender.0.as_ref();
-        (bytes[0] as u32) % 16 // 16 shards like in the original
+    pub fn shard_id(&self, sharding: &ShardingStrategy) -> ShardId {
+        sharding.shard_of(&self.sender)
+    }
+
+    pub fn is_expired(&self, now: Timestamp) -> bool {
+        now >= self.deadline
+    }
+
+    /// Whether the id of the transfer is the one derived from its content, so that it cannot
+    /// be reused by another transfer.
+    pub fn has_derived_id(&self) -> bool {
+        self.interop_tx_id
+            == InteropTxId::generate(
+                self.source_chain,
+                self.destination_chain,
+                self.sender,
+                self.recipient,
+                self.amount,
+                self.token_mint,
+                self.nonce,
+            )
     }
 }
 
 /// Implementation of BcsSignable trait for CrossChainTransfer
 impl BcsSignable for CrossChainTransfer {}
 
+/// Largest payload a cross-chain message may carry, so that it fits in a destination transaction
+pub const MAX_MESSAGE_PAYLOAD_SIZE: usize = 512;
+
+/// Cross-chain message: a call of a program on the destination chain
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+pub struct CrossChainMessage {
+    pub source_chain: ChainId,
+    pub destination_chain: ChainId,
+    pub sender: Pubkey,
+    pub target_program: Pubkey, // Program called on destination chain
+    pub payload: Vec<u8>,       // Opaque to the bridge, at most MAX_MESSAGE_PAYLOAD_SIZE bytes
+    pub interop_tx_id: InteropTxId,
+    pub nonce: u64, // Nonce to prevent replay attacks, separate from transfer nonces
+}
+
+impl CrossChainMessage {
+    pub fn key(&self) -> (Pubkey, InteropTxId) {
+        (self.sender, self.interop_tx_id)
+    }
+
+    /// Determine which shard should process this message
+    pub fn shard_id(&self, sharding: &ShardingStrategy) -> ShardId {
+        sharding.shard_of(&self.sender)
+    }
+
+    pub fn check_payload(&self) -> Result<(), FastPayError> {
+        if self.payload.len() > MAX_MESSAGE_PAYLOAD_SIZE {
+            return Err(FastPayError::MessagePayloadTooLarge {
+                size: self.payload.len(),
+                max: MAX_MESSAGE_PAYLOAD_SIZE,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl BcsSignable for CrossChainMessage {}
+
+/// Current time, in seconds since the Unix epoch
+pub fn current_timestamp() -> Timestamp {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Hash of the signable content of a value
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize, PartialOrd, Ord)]
+pub struct ContentDigest(pub [u8; 32]);
+
+impl ContentDigest {
+    pub fn new<T>(value: &T) -> Self
+    where
+        T: Signable<Vec<u8>>,
+    {
+        let mut message = Vec::new();
+        value.write(&mut message);
+        let result = Sha512::digest(&message);
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(&result[0..32]);
+        ContentDigest(bytes)
+    }
+}
+
+/// Capacity in which a key signs. Part of the signed content so that signatures made in one
+/// role cannot be passed off as signatures made in another.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize)]
+pub enum SignerRole {
+    User,
+    Authority,
+}
+
 /// Generation of InteropTxId
 impl InteropTxId {
     /// Generate a deterministic InteropTxId from transfer details
@@ -0,0 +1,253 @@
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use super::{base_types::*, error::*};
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// First message of a handshake, from the party opening the channel.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hello {
+    pub initiator: Pubkey,
+    /// Identity expected at the other end
+    pub responder: Pubkey,
+    /// Ephemeral x25519 key of the initiator
+    pub ephemeral: [u8; 32],
+    pub signature: Signature,
+}
+
+/// Answer to a `Hello`, completing the handshake.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Accept {
+    /// Ephemeral x25519 key of the responder
+    pub ephemeral: [u8; 32],
+    pub signature: Signature,
+}
+
+/// What the initiator signs: its ephemeral key, for a given responder.
+#[derive(Serialize, Deserialize)]
+struct HelloBinding {
+    initiator: Pubkey,
+    responder: Pubkey,
+    ephemeral: [u8; 32],
+}
+
+impl BcsSignable for HelloBinding {}
+
+/// What the responder signs: both ephemeral keys, so that its answer is fresh.
+#[derive(Serialize, Deserialize)]
+struct AcceptBinding {
+    initiator: Pubkey,
+    responder: Pubkey,
+    initiator_ephemeral: [u8; 32],
+    responder_ephemeral: [u8; 32],
+}
+
+impl BcsSignable for AcceptBinding {}
+
+fn handshake_error(error: &str) -> FastPayError {
+    FastPayError::HandshakeFailed {
+        error: error.to_string(),
+    }
+}
+
+fn ephemeral_secret() -> StaticSecret {
+    StaticSecret::from(rand::random::<[u8; 32]>())
+}
+
+/// Initiator side of a handshake, waiting for the `Accept` of the responder.
+pub struct Initiator {
+    secret: StaticSecret,
+    hello: Hello,
+}
+
+impl Initiator {
+    pub fn new(identity: &KeyPair, responder: Pubkey) -> Self {
+        let secret = ephemeral_secret();
+        let binding = HelloBinding {
+            initiator: identity.public(),
+            responder,
+            ephemeral: PublicKey::from(&secret).to_bytes(),
+        };
+        let hello = Hello {
+            initiator: binding.initiator,
+            responder,
+            ephemeral: binding.ephemeral,
+            signature: Signature::new(&binding, identity),
+        };
+        Self { secret, hello }
+    }
+
+    pub fn hello(&self) -> &Hello {
+        &self.hello
+    }
+
+    /// Check the answer of the responder and derive the keys of the channel.
+    pub fn finish(self, accept: &Accept) -> Result<Session, FastPayError> {
+        let binding = AcceptBinding {
+            initiator: self.hello.initiator,
+            responder: self.hello.responder,
+            initiator_ephemeral: self.hello.ephemeral,
+            responder_ephemeral: accept.ephemeral,
+        };
+        accept.signature.check(&binding, self.hello.responder)?;
+        let shared = self.secret.diffie_hellman(&PublicKey::from(accept.ephemeral));
+        if !shared.was_contributory() {
+            return Err(handshake_error("Invalid ephemeral key"));
+        }
+        Ok(Session::derive(&binding, shared.as_bytes(), true))
+    }
+}
+
+/// Answer a `Hello` sent to `identity`, returning the channel with the initiator.
+pub fn accept(identity: &KeyPair, hello: &Hello) -> Result<(Session, Accept), FastPayError> {
+    if hello.responder != identity.public() {
+        return Err(handshake_error("Hello is meant for another identity"));
+    }
+    let hello_binding = HelloBinding {
+        initiator: hello.initiator,
+        responder: hello.responder,
+        ephemeral: hello.ephemeral,
+    };
+    hello.signature.check(&hello_binding, hello.initiator)?;
+
+    let secret = ephemeral_secret();
+    let shared = secret.diffie_hellman(&PublicKey::from(hello.ephemeral));
+    if !shared.was_contributory() {
+        return Err(handshake_error("Invalid ephemeral key"));
+    }
+    let binding = AcceptBinding {
+        initiator: hello.initiator,
+        responder: hello.responder,
+        initiator_ephemeral: hello.ephemeral,
+        responder_ephemeral: PublicKey::from(&secret).to_bytes(),
+    };
+    let accept = Accept {
+        ephemeral: binding.responder_ephemeral,
+        signature: Signature::new(&binding, identity),
+    };
+    Ok((Session::derive(&binding, shared.as_bytes(), false), accept))
+}
+
+/// Counters of the last 64 messages received, to reject replays.
+#[derive(Default)]
+struct ReplayWindow {
+    /// Highest counter seen so far, plus one
+    next: u64,
+    /// Bit `i` is set if counter `next - 1 - i` was seen
+    seen: u64,
+}
+
+impl ReplayWindow {
+    fn check_and_update(&mut self, counter: u64) -> bool {
+        if counter >= self.next {
+            let shift = counter - self.next + 1;
+            self.seen = if shift >= 64 { 0 } else { self.seen << shift };
+            self.seen |= 1;
+            self.next = counter + 1;
+            return true;
+        }
+        let age = self.next - 1 - counter;
+        if age >= 64 || self.seen & (1 << age) != 0 {
+            return false;
+        }
+        self.seen |= 1 << age;
+        true
+    }
+}
+
+const COUNTER_SIZE: usize = 8;
+
+/// Encrypted channel with an authenticated peer. Each message carries its counter, so that
+/// messages may arrive out of order, but at most once.
+pub struct Session {
+    /// Identity proven by the other end
+    pub peer: Pubkey,
+    sealer: ChaCha20Poly1305,
+    opener: ChaCha20Poly1305,
+    next_counter: AtomicU64,
+    window: Mutex<ReplayWindow>,
+    closed: AtomicBool,
+}
+
+impl Session {
+    /// Derive one key per direction from the shared secret and the whole handshake
+    fn derive(binding: &AcceptBinding, shared: &[u8; 32], initiator: bool) -> Self {
+        let transcript = Sha256::new()
+            .chain_update(binding.initiator.0)
+            .chain_update(binding.responder.0)
+            .chain_update(binding.initiator_ephemeral)
+            .chain_update(binding.responder_ephemeral)
+            .finalize();
+        let mut keys = [0u8; 64];
+        Hkdf::<Sha256>::new(Some(&transcript), shared)
+            .expand(b"fastpay channel keys", &mut keys)
+            .expect("64 bytes is a valid HKDF output length");
+        let (to_responder, to_initiator) = keys.split_at(32);
+        let (seal_key, open_key, peer) = if initiator {
+            (to_responder, to_initiator, binding.responder)
+        } else {
+            (to_initiator, to_responder, binding.initiator)
+        };
+        Self {
+            peer,
+            sealer: ChaCha20Poly1305::new(Key::from_slice(seal_key)),
+            opener: ChaCha20Poly1305::new(Key::from_slice(open_key)),
+            next_counter: AtomicU64::new(0),
+            window: Mutex::new(ReplayWindow::default()),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    fn nonce(counter: u64) -> Nonce {
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&counter.to_le_bytes());
+        Nonce::from(nonce)
+    }
+
+    /// Encrypt a message, bound to the associated data `aad`
+    pub fn seal(&self, aad: &[u8], message: &[u8]) -> Vec<u8> {
+        let counter = self.next_counter.fetch_add(1, Ordering::Relaxed);
+        let ciphertext = self
+            .sealer
+            .encrypt(&Self::nonce(counter), Payload { msg: message, aad })
+            .expect("Encryption does not fail on in-memory buffers");
+        let mut sealed = Vec::with_capacity(COUNTER_SIZE + ciphertext.len());
+        sealed.extend_from_slice(&counter.to_le_bytes());
+        sealed.extend_from_slice(&ciphertext);
+        sealed
+    }
+
+    /// Decrypt a message, unless it was forged, altered or already received
+    pub fn open(&self, aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < COUNTER_SIZE {
+            return None;
+        }
+        let (counter, ciphertext) = sealed.split_at(COUNTER_SIZE);
+        let counter = u64::from_le_bytes(counter.try_into().ok()?);
+        let message = self
+            .opener
+            .decrypt(&Self::nonce(counter), Payload { msg: ciphertext, aad })
+            .ok()?;
+        if !self.window.lock().unwrap().check_and_update(counter) {
+            return None;
+        }
+        Some(message)
+    }
+
+    /// Stop using the channel, e.g. because the peer lost it
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+#[path = "unit_tests/channel_tests.rs"]
+mod channel_tests;
@@ -1,19 +1,37 @@
 // filepath: /home/dhruv/dev/fastpay/fastpay_core/src/bridge_committee.rs
 
-use crate::base_types::AuthorityName;
-use std::collections::{BTreeMap};
+use crate::fp_ensure;
+
+use crate::base_types::*;
+use crate::error::FastPayError;
+use crate::sharding::ShardingStrategy;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
 
 #[derive(Eq, PartialEq, Clone, Hash, Debug)]
 pub struct Committee {
+    /// Bridge deployment the committee serves.
+    pub network: NetworkId,
+    pub epoch: Epoch,
     pub voting_rights: BTreeMap<AuthorityName, usize>,
+    /// Number of shards of each authority, which decides the shard named in its votes.
+    pub num_shards: BTreeMap<AuthorityName, u32>,
     pub total_votes: usize,
 }
 
 impl Committee {
-    pub fn new(voting_rights: BTreeMap<AuthorityName, usize>) -> Self {
+    pub fn new(
+        network: NetworkId,
+        epoch: Epoch,
+        voting_rights: BTreeMap<AuthorityName, usize>,
+        num_shards: BTreeMap<AuthorityName, u32>,
+    ) -> Self {
         let total_votes = voting_rights.iter().fold(0, |sum, (_, votes)| sum + *votes);
         Committee {
+            network,
+            epoch,
             voting_rights,
+            num_shards,
             total_votes,
         }
     }
@@ -22,6 +40,22 @@ impl Committee {
         *self.voting_rights.get(author).unwrap_or(&0)
     }
 
+    /// Shard of an authority that processes the orders of a sender.
+    pub fn shard_of(&self, author: &AuthorityName, sender: &Pubkey) -> Result<ShardId, FastPayError> {
+        let num_shards = *self.num_shards.get(author).ok_or(FastPayError::UnknownSigner)?;
+        Ok(ShardingStrategy::new(num_shards)?.shard_of(sender))
+    }
+
+    /// Position of an authority in the committee, in the order of the voting rights.
+    pub fn position(&self, author: &AuthorityName) -> Option<usize> {
+        self.voting_rights.keys().position(|name| name == author)
+    }
+
+    /// Authority at a given position of the committee.
+    pub fn authority_at(&self, index: usize) -> Option<&AuthorityName> {
+        self.voting_rights.keys().nth(index)
+    }
+
     pub fn quorum_threshold(&self) -> usize {
         // If N = 3f + 1 + k (0 <= k < 3)
         // then (2 N + 3) / 3 = 2f + 1 + (2k + 2)/3 = 2f + 1 + k = N - f
@@ -50,4 +84,149 @@ impl Committee {
         }
         V::default()
     }
-}
\ No newline at end of file
+}
+
+/// Hand-over from the committee of the previous epoch to a new committee. Signing the
+/// network keeps a change certified for one deployment from being replayed on another.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct CommitteeChange {
+    pub network: NetworkId,
+    /// Epoch of the new committee.
+    pub epoch: Epoch,
+    pub voting_rights: BTreeMap<AuthorityName, usize>,
+    pub num_shards: BTreeMap<AuthorityName, u32>,
+}
+
+impl BcsSignable for CommitteeChange {}
+
+/// Vote of a member of the outgoing committee for a change.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct SignedCommitteeChange {
+    pub value: CommitteeChange,
+    pub authority: AuthorityName,
+    pub signature: Signature,
+}
+
+/// Change certified by a quorum of the outgoing committee.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct CertifiedCommitteeChange {
+    pub value: CommitteeChange,
+    pub signatures: Vec<(AuthorityName, Signature)>,
+}
+
+impl CommitteeChange {
+    pub fn committee(&self) -> Committee {
+        Committee::new(self.network, self.epoch, self.voting_rights.clone(), self.num_shards.clone())
+    }
+}
+
+impl SignedCommitteeChange {
+    pub fn new(value: CommitteeChange, authority: AuthorityName, secret: &KeyPair) -> Self {
+        let signature = Signature::new(&value, secret);
+        Self {
+            value,
+            authority,
+            signature,
+        }
+    }
+}
+
+impl CertifiedCommitteeChange {
+    /// Verify the change against the committee it replaces.
+    pub fn check(&self, previous: &Committee) -> Result<(), FastPayError> {
+        fp_ensure!(
+            self.value.network == previous.network,
+            FastPayError::WrongNetwork {
+                expected: previous.network,
+                found: self.value.network,
+            }
+        );
+        fp_ensure!(
+            Some(self.value.epoch) == previous.epoch.checked_add(1),
+            FastPayError::EpochMismatch {
+                expected: previous.epoch.saturating_add(1),
+                found: self.value.epoch,
+            }
+        );
+        fp_ensure!(self.value.committee().total_votes > 0, FastPayError::InvalidCommitteeChange);
+        // Every member must say how it shards, so that its votes can be checked
+        fp_ensure!(
+            self.value
+                .voting_rights
+                .keys()
+                .all(|authority| self.value.num_shards.get(authority).is_some_and(|n| *n > 0)),
+            FastPayError::InvalidCommitteeChange
+        );
+        let mut weight = 0;
+        let mut used_authorities = HashSet::new();
+        for (authority, _) in self.signatures.iter() {
+            fp_ensure!(
+                used_authorities.insert(*authority),
+                FastPayError::CertificateAuthorityReuse
+            );
+            let voting_rights = previous.weight(authority);
+            fp_ensure!(voting_rights > 0, FastPayError::UnknownSigner);
+            weight += voting_rights;
+        }
+        fp_ensure!(weight >= previous.quorum_threshold(), FastPayError::CertificateRequiresQuorum);
+        Signature::verify_batch(&self.value, &self.signatures)
+    }
+}
+
+/// The committees of all known epochs, so that certificates remain verifiable against their
+/// own epoch after a reconfiguration.
+#[derive(Clone, Debug)]
+pub struct CommitteeHistory {
+    committees: BTreeMap<Epoch, Committee>,
+    changes: Vec<CertifiedCommitteeChange>,
+}
+
+impl CommitteeHistory {
+    pub fn new(committee: Committee) -> Self {
+        let mut committees = BTreeMap::new();
+        committees.insert(committee.epoch, committee);
+        Self {
+            committees,
+            changes: Vec::new(),
+        }
+    }
+
+    /// The committee of the latest epoch.
+    pub fn current(&self) -> &Committee {
+        self.committees
+            .values()
+            .next_back()
+            .expect("History starts with one committee")
+    }
+
+    /// The committee of a given epoch.
+    pub fn get(&self, epoch: Epoch) -> Result<&Committee, FastPayError> {
+        self.committees
+            .get(&epoch)
+            .ok_or(FastPayError::UnknownEpoch { epoch })
+    }
+
+    /// Certified changes that lead past the given epoch, oldest first.
+    pub fn changes_since(&self, epoch: Epoch) -> Vec<CertifiedCommitteeChange> {
+        self.changes
+            .iter()
+            .filter(|change| change.value.epoch > epoch)
+            .cloned()
+            .collect()
+    }
+
+    /// Move to the next epoch. Returns false if the change was already known.
+    pub fn advance(&mut self, change: CertifiedCommitteeChange) -> Result<bool, FastPayError> {
+        if change.value.epoch <= self.current().epoch {
+            return Ok(false);
+        }
+        change.check(self.current())?;
+        self.committees.insert(change.value.epoch, change.value.committee());
+        self.changes.push(change);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+#[path = "unit_tests/committee_tests.rs"]
+mod committee_tests;
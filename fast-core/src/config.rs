@@ -0,0 +1,333 @@
+use super::{base_types::*, committee::Committee, error::*, escrow::*, sharding::ShardingStrategy};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::path::Path;
+
+/// Configuration of one authority, as written in `authority_*.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthorityConfig {
+    /// Authority name (hex-encoded public key)
+    pub name: String,
+
+    /// Hex-encoded secret key, or path to a JSON file holding it
+    pub secret_key: String,
+
+    /// Path to the committee configuration file
+    pub committee: String,
+
+    /// How to check escrows on the source chain. Required, so that a missing section does
+    /// not silently turn verification off.
+    pub escrow: EscrowConfig,
+}
+
+/// How an authority checks escrows on the source chain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EscrowConfig {
+    /// Accept every transfer. Only meant for local testing, and only if written explicitly.
+    Disabled,
+    /// Read escrow accounts from the JSON-RPC endpoint of the source SVM chain.
+    SvmRpc {
+        url: String,
+        /// Base58 address of the portal program owning the escrow accounts
+        portal_program: String,
+        #[serde(default)]
+        commitment: Commitment,
+    },
+}
+
+/// Configuration of the committee, as written in `committee.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommitteeConfig {
+    #[serde(default)]
+    pub epoch: Epoch,
+    /// Bridge deployment of the committee. Nodes only talk to nodes of the same network.
+    #[serde(default)]
+    pub network: NetworkId,
+    pub authorities: Vec<AuthorityEntry>,
+}
+
+/// A member of the committee and where to reach its shards.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthorityEntry {
+    /// Authority name (hex-encoded public key)
+    pub name: String,
+    pub host: String,
+    /// Port of shard 0. Shard `i` listens on `port + i`.
+    pub port: u16,
+    pub weight: u64,
+    pub num_shards: u32,
+    /// How to reach the shards
+    #[serde(default)]
+    pub transport: Transport,
+    #[serde(default)]
+    pub security: Security,
+}
+
+/// Network protocol spoken by the shards of an authority.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    /// One datagram per message, limited to 64 KiB
+    #[default]
+    Udp,
+    /// Length-prefixed frames over reused connections
+    Tcp,
+}
+
+/// Protection of the traffic with the shards of an authority.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Security {
+    /// Encrypted channels, authenticated with the keys of both ends
+    #[default]
+    Encrypted,
+    /// Cleartext from anyone, for local tests
+    Plaintext,
+}
+
+fn config_error(path: &Path, error: impl std::fmt::Display) -> FastPayError {
+    FastPayError::ConfigurationError {
+        error: format!("{}: {}", path.display(), error),
+    }
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T, FastPayError> {
+    let file = File::open(path).map_err(|e| config_error(path, e))?;
+    serde_json::from_reader(BufReader::new(file)).map_err(|e| config_error(path, e))
+}
+
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), FastPayError> {
+    let file = File::create(path).map_err(|e| config_error(path, e))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), value).map_err(|e| config_error(path, e))
+}
+
+fn decode_hex<const N: usize>(value: &str, what: &str) -> Result<[u8; N], FastPayError> {
+    let bytes = hex::decode(value.trim()).map_err(|e| FastPayError::ConfigurationError {
+        error: format!("Invalid {} {:?}: {}", what, value, e),
+    })?;
+    bytes.try_into().map_err(|bytes: Vec<u8>| FastPayError::ConfigurationError {
+        error: format!("Invalid {} {:?}: expected {} bytes, got {}", what, value, N, bytes.len()),
+    })
+}
+
+/// Decode a hex-encoded authority name.
+pub fn decode_authority_name(name: &str) -> Result<AuthorityName, FastPayError> {
+    Ok(Pubkey(decode_hex(name, "public key")?))
+}
+
+/// Decode a base58 address, as used by SVM chains.
+pub fn decode_address(address: &str) -> Result<Pubkey, FastPayError> {
+    let bytes = bs58::decode(address.trim()).into_vec().map_err(|e| {
+        FastPayError::ConfigurationError {
+            error: format!("Invalid address {:?}: {}", address, e),
+        }
+    })?;
+    let bytes: [u8; 32] = bytes.try_into().map_err(|_| FastPayError::ConfigurationError {
+        error: format!("Invalid address {:?}: expected 32 bytes", address),
+    })?;
+    Ok(Pubkey(bytes))
+}
+
+/// Read a key pair stored as a JSON array of 64 bytes, as written by `solana-keygen`.
+pub fn read_key_pair_file<P: AsRef<Path>>(path: P) -> Result<KeyPair, FastPayError> {
+    let path = path.as_ref();
+    let bytes: Vec<u8> = read_json(path)?;
+    if bytes.len() != 64 {
+        return Err(config_error(path, "expected a key pair of 64 bytes"));
+    }
+    let mut secret = [0u8; 32];
+    secret.copy_from_slice(&bytes[..32]);
+    let key_pair = KeyPair::from(secret);
+    if key_pair.public().0[..] != bytes[32..] {
+        return Err(config_error(path, "inconsistent key pair"));
+    }
+    Ok(key_pair)
+}
+
+/// Store a key pair like `solana-keygen` does.
+pub fn write_key_pair_file<P: AsRef<Path>>(path: P, key_pair: &KeyPair) -> Result<(), FastPayError> {
+    write_json(path.as_ref(), &key_pair.to_keypair_bytes().to_vec())
+}
+
+/// Encode an authority name as hex.
+pub fn encode_authority_name(name: &AuthorityName) -> String {
+    hex::encode(name.0)
+}
+
+impl AuthorityConfig {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, FastPayError> {
+        let path = path.as_ref();
+        let config: Self = read_json(path)?;
+        config.authority_name().map_err(|e| config_error(path, e))?;
+        if let EscrowConfig::SvmRpc { portal_program, .. } = &config.escrow {
+            decode_address(portal_program).map_err(|e| config_error(path, e))?;
+        }
+        Ok(config)
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), FastPayError> {
+        write_json(path.as_ref(), self)
+    }
+
+    pub fn authority_name(&self) -> Result<AuthorityName, FastPayError> {
+        decode_authority_name(&self.name)
+    }
+
+    /// Load the secret key, given inline or as a path to a JSON file, and check that it
+    /// matches the name of the authority.
+    pub fn key_pair(&self) -> Result<KeyPair, FastPayError> {
+        let path = Path::new(&self.secret_key);
+        let secret: String = if self.secret_key.contains('/') && path.exists() {
+            read_json(path)?
+        } else {
+            self.secret_key.clone()
+        };
+        let key_pair = KeyPair::from(decode_hex(&secret, "secret key")?);
+        if key_pair.public() != self.authority_name()? {
+            return Err(FastPayError::ConfigurationError {
+                error: format!("Secret key does not match authority {}", self.name),
+            });
+        }
+        Ok(key_pair)
+    }
+}
+
+impl CommitteeConfig {
+    /// Read and validate a committee configuration.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, FastPayError> {
+        let path = path.as_ref();
+        let config: Self = read_json(path)?;
+        config.validate().map_err(|e| config_error(path, e))?;
+        Ok(config)
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), FastPayError> {
+        write_json(path.as_ref(), self)
+    }
+
+    /// Reject empty committees, duplicate or malformed keys, zero weights, invalid shard
+    /// counts and overlapping shard ports.
+    pub fn validate(&self) -> Result<(), FastPayError> {
+        let error = |error: String| FastPayError::ConfigurationError { error };
+        if self.authorities.is_empty() {
+            return Err(error("Committee has no authorities".to_string()));
+        }
+        let mut names = HashMap::new();
+        let mut ports: Vec<(&str, u32, u32, Vec<IpAddr>)> = Vec::new();
+        for (index, entry) in self.authorities.iter().enumerate() {
+            let name = entry.authority_name()?;
+            if let Some(previous) = names.insert(name, index) {
+                return Err(error(format!(
+                    "Authority {} is listed twice (entries {} and {})",
+                    entry.name, previous, index
+                )));
+            }
+            if entry.weight == 0 {
+                return Err(error(format!("Authority {} has zero weight", entry.name)));
+            }
+            entry.sharding()?;
+            let start = entry.port as u32;
+            let end = start + entry.num_shards;
+            if end > u16::MAX as u32 + 1 {
+                return Err(error(format!(
+                    "Shards of authority {} do not fit below port {}",
+                    entry.name,
+                    u16::MAX
+                )));
+            }
+            // Hosts are compared by address, so that `localhost` and `127.0.0.1` collide
+            let addresses = entry.resolve_host()?;
+            for (other, other_start, other_end, other_addresses) in &ports {
+                let same_host = addresses.iter().any(|address| {
+                    other_addresses
+                        .iter()
+                        .any(|other| other == address || other.is_unspecified() || address.is_unspecified())
+                });
+                if same_host && start < *other_end && *other_start < end {
+                    return Err(error(format!(
+                        "Ports {}..{} of authority {} collide with ports {}..{} of authority {}",
+                        start, end, entry.name, other_start, other_end, other
+                    )));
+                }
+            }
+            ports.push((&entry.name, start, end, addresses));
+        }
+        Ok(())
+    }
+
+    /// Voting rights and shard counts of the committee.
+    pub fn committee(&self) -> Result<Committee, FastPayError> {
+        let mut voting_rights = BTreeMap::new();
+        let mut num_shards = BTreeMap::new();
+        for entry in &self.authorities {
+            voting_rights.insert(entry.authority_name()?, entry.weight as usize);
+            num_shards.insert(entry.authority_name()?, entry.num_shards);
+        }
+        Ok(Committee::new(self.network, self.epoch, voting_rights, num_shards))
+    }
+
+    /// Find the entry of an authority.
+    pub fn entry(&self, name: &AuthorityName) -> Option<&AuthorityEntry> {
+        self.authorities
+            .iter()
+            .find(|entry| entry.authority_name().ok().as_ref() == Some(name))
+    }
+}
+
+impl AuthorityEntry {
+    pub fn authority_name(&self) -> Result<AuthorityName, FastPayError> {
+        decode_authority_name(&self.name)
+    }
+
+    pub fn sharding(&self) -> Result<ShardingStrategy, FastPayError> {
+        ShardingStrategy::new(self.num_shards).map_err(|_| FastPayError::ConfigurationError {
+            error: format!("Authority {} must have at least one shard", self.name),
+        })
+    }
+
+    /// Check that a server listens where relayers expect the shards of this authority.
+    pub fn check_listener(&self, port: u16, num_shards: u32) -> Result<(), FastPayError> {
+        if self.port != port || self.num_shards != num_shards {
+            return Err(FastPayError::ConfigurationError {
+                error: format!(
+                    "Committee expects authority {} on port {} with {} shards, not port {} with {} shards",
+                    self.name, self.port, self.num_shards, port, num_shards
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Addresses the host of the authority resolves to.
+    pub fn resolve_host(&self) -> Result<Vec<IpAddr>, FastPayError> {
+        let addresses: Vec<_> = (self.host.as_str(), 0)
+            .to_socket_addrs()
+            .map_err(|e| FastPayError::ConfigurationError {
+                error: format!("Invalid host of authority {}: {}", self.name, e),
+            })?
+            .map(|address| address.ip())
+            .collect();
+        if addresses.is_empty() {
+            return Err(FastPayError::ConfigurationError {
+                error: format!("Host {} of authority {} has no address", self.host, self.name),
+            });
+        }
+        Ok(addresses)
+    }
+
+    /// Network address of a shard.
+    pub fn shard_address(&self, shard_id: ShardId) -> Result<SocketAddr, FastPayError> {
+        let port = u16::try_from(self.port as u32 + shard_id).map_err(|_| FastPayError::ConfigurationError {
+            error: format!("Shard {} of authority {} has no port", shard_id, self.name),
+        })?;
+        Ok(SocketAddr::new(self.resolve_host()?[0], port))
+    }
+}
+
+#[cfg(test)]
+#[path = "unit_tests/config_tests.rs"]
+mod config_tests;
@@ -1,4 +1,3 @@
-// Insn't required now becuase we are not yet verifying with the escrow
 use futures::{
     channel::{mpsc, oneshot},
     future, SinkExt, StreamExt,
@@ -35,6 +34,8 @@ enum DownloadCommand<K, V> {
     Request(K, oneshot::Sender<V>),
     /// A value has been downloaded.
     Publish(K, V),
+    /// Drop a downloaded value, so that the next request fetches it again.
+    Forget(K),
     /// Shut down the main handler.
     Quit,
 }
@@ -56,6 +57,12 @@ impl<K, V> DownloadHandle<K, V> {
         Ok(value)
     }
 
+    /// Drop the value of a key from the cache, e.g. after a transient failure.
+    pub async fn forget(&mut self, key: K) -> Result<(), failure::Error> {
+        self.0.send(DownloadCommand::Forget(key)).await?;
+        Ok(())
+    }
+
     /// Shut down the main handler.
     pub async fn stop(&mut self) -> Result<(), failure::Error> {
         self.0.send(DownloadCommand::Quit).await?;
@@ -154,6 +161,12 @@ where
                         }
                     }
                 }
+                Some(DownloadCommand::Forget(key)) => {
+                    // Downloads in progress still deliver to their subscribers.
+                    if let Some(DownloadStatus::Ready(_)) = self.downloads.get(&key) {
+                        self.downloads.remove(&key);
+                    }
+                }
                 _ => return,
             }
         }
@@ -1,7 +1,7 @@
 use failure::Fail;
 use serde::{ Deserialize, Serialize };
 
-use crate::base_types::CrossChainTransfer;
+use crate::base_types::{CrossChainMessage, CrossChainTransfer};
 
 #[macro_export]
 macro_rules! fp_bail {
@@ -40,6 +40,12 @@ pub enum FastPayError {
     )] PreviousTransferMustBeConfirmedFirst {
         pending_confirmation: CrossChainTransfer,
     },
+    #[fail(
+        display = "Cannot send a message while a message order is still pending confirmation: {:?}",
+        pending_confirmation
+    )] PreviousMessageMustBeConfirmedFirst {
+        pending_confirmation: CrossChainMessage,
+    },
     #[fail(display = "Transfer order was processed but no signature was produced by authority")]
     ErrorWhileProcessingTransferOrder,
     #[fail(
@@ -70,8 +76,11 @@ pub enum FastPayError {
     #[fail(display = "Invalid transfer amount: {}.", error)] InvalidTransferAmount {
         error: String,
     },
-    #[fail(display = "Wrong shard used. {}", err)]
-    WrongShard{err: String},
+    #[fail(display = "Wrong shard used, the sender belongs to shard {} of {}.", expected, num_shards)]
+    WrongShard {
+        expected: u32,
+        num_shards: u32,
+    },
     #[fail(display = "Invalid cross shard update.")]
     InvalidCrossShardUpdate,
     #[fail(display = "Cannot deserialize.")]
@@ -81,14 +90,102 @@ pub enum FastPayError {
     #[fail(display = "Network error while querying service: {:?}.", error)] ClientIoError {
         error: String,
     },
+    #[fail(display = "Serialization error occurred")]
+    SerializationError,
     #[fail(display = "Deserialization error occurred")]
     DeserializationError,
     #[fail(display = "Communication error with authority")]
     CommunicationError,
+    #[fail(display = "Could not establish a secure channel: {}", error)] HandshakeFailed {
+        error: String,
+    },
+    #[fail(
+        display = "Protocol version {} is not supported, only versions {} to {}.",
+        version, min, max
+    )] UnsupportedProtocolVersion {
+        version: u16,
+        min: u16,
+        max: u16,
+    },
+    #[fail(display = "Expected network {} but found network {}.", expected, found)] WrongNetwork {
+        expected: u32,
+        found: u32,
+    },
     #[fail(
         display = "Can't find mutable shard state for shard ID: {}.",
         shard_id
     )] ShardStateNotFound {
         shard_id: u32,
     },
+    #[fail(display = "Storage error: {}", error)] StorageError {
+        error: String,
+    },
+    #[fail(display = "Expected epoch {} but found epoch {}.", expected, found)] EpochMismatch {
+        expected: u64,
+        found: u64,
+    },
+    #[fail(display = "No committee is known for epoch {}.", epoch)] UnknownEpoch {
+        epoch: u64,
+    },
+    #[fail(display = "A committee change must leave a non-empty committee.")]
+    InvalidCommitteeChange,
+    #[fail(display = "The signer bitmap of a compact certificate does not match its signatures.")]
+    InvalidSignerBitmap,
+    #[fail(display = "The transfer expired or is being refunded, it can no longer be delivered.")]
+    TransferExpired,
+    #[fail(display = "The transfer can only be refunded after its deadline.")]
+    TransferNotExpired,
+    #[fail(display = "Could not verify the escrow: {}", error)]
+    EscrowVerificationFailed {
+        error: String,
+    },
+    #[fail(display = "The escrow {} is not on the source chain yet.", escrow_account)]
+    EscrowNotFound {
+        escrow_account: String,
+    },
+    #[fail(display = "The balance is not sufficient: {}.", current_balance)]
+    InsufficientFunding {
+        current_balance: u64,
+    },
+    #[fail(display = "Expected chain {} but found chain {}.", expected, found)]
+    WrongChain {
+        expected: u16,
+        found: u16,
+    },
+    #[fail(display = "Message payload of {} bytes exceeds the limit of {} bytes.", size, max)]
+    MessagePayloadTooLarge {
+        size: usize,
+        max: usize,
+    },
+    #[fail(display = "Shard {} did not take the cross-shard update.", shard_id)]
+    CrossShardDeliveryFailed {
+        shard_id: u32,
+    },
+}
+
+impl FastPayError {
+    /// Whether the same request may succeed later, e.g. once the authority is reachable or has
+    /// processed earlier orders of the sender. Other errors are permanent: sending the request
+    /// again gets the same answer, as for a malformed or unsupported message.
+    pub fn is_retryable(&self) -> bool {
+        use FastPayError::*;
+        matches!(
+            self,
+            PreviousTransferMustBeConfirmedFirst { .. }
+                | PreviousMessageMustBeConfirmedFirst { .. }
+                | UnexpectedTransactionIndex
+                | CertificateNotfound
+                | WrongShard { .. }
+                | ClientIoError { .. }
+                | CommunicationError
+                | ShardStateNotFound { .. }
+                | CrossShardDeliveryFailed { .. }
+                | StorageError { .. }
+                | EpochMismatch { .. }
+                | UnknownEpoch { .. }
+                | TransferNotExpired
+                | EscrowVerificationFailed { .. }
+                | EscrowNotFound { .. }
+        )
+    }
 }
@@ -0,0 +1,141 @@
+use super::{authority::EscrowVerifier, base_types::*, error::*, rpc::SvmRpcClient};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// How final the state read from an SVM chain must be.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Commitment {
+    Processed,
+    Confirmed,
+    #[default]
+    Finalized,
+}
+
+impl Commitment {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Commitment::Processed => "processed",
+            Commitment::Confirmed => "confirmed",
+            Commitment::Finalized => "finalized",
+        }
+    }
+}
+
+/// Seed of the escrow accounts of the source portal program.
+pub const ESCROW_SEED: &[u8] = b"escrow";
+/// Seed of the account the portal program signs for its escrows with.
+pub const ESCROW_AUTHORITY_SEED: &[u8] = b"escrow_authority";
+
+/// Address of the escrow token account of a transfer: the account the portal program
+/// derives from the sender and the id of the transfer. Each transfer thus has its own
+/// escrow, and a deposit cannot back any other transfer.
+pub fn escrow_address(portal_program: &Pubkey, sender: &Pubkey, interop_tx_id: &InteropTxId) -> Option<Pubkey> {
+    let seeds: [&[u8]; 3] = [ESCROW_SEED, &sender.0, &interop_tx_id.0];
+    Pubkey::find_program_address(&seeds, portal_program).map(|(address, _)| address)
+}
+
+/// Owner of the escrow token accounts: a program derived address of the portal program,
+/// since the portal itself cannot own token accounts, but can sign for its own addresses.
+pub fn escrow_authority(portal_program: &Pubkey) -> Option<Pubkey> {
+    Pubkey::find_program_address(&[ESCROW_AUTHORITY_SEED], portal_program).map(|(address, _)| address)
+}
+
+/// Whether a transfer names the escrow the portal program would have locked it in.
+pub fn is_escrow_of(portal_program: &Pubkey, transfer: &CrossChainTransfer) -> bool {
+    transfer.has_derived_id()
+        && escrow_address(portal_program, &transfer.sender, &transfer.interop_tx_id) == Some(transfer.escrow_account)
+}
+
+/// Escrow verifier reading the escrow token account of a transfer from the JSON-RPC
+/// endpoint of the source SVM chain. The balance of the escrow is enough, without reading
+/// the deposit transaction: the escrow address is derived from the sender and id of this
+/// transfer alone, so whatever it holds was locked for this transfer.
+pub struct SvmRpcEscrowVerifier {
+    client: SvmRpcClient,
+    /// Source portal program, which derives the escrow accounts
+    portal_program: Pubkey,
+    /// Owner that escrow token accounts must have, derived from the portal program
+    escrow_authority: Pubkey,
+    commitment: Commitment,
+}
+
+fn rpc_error(error: impl std::fmt::Display) -> FastPayError {
+    FastPayError::EscrowVerificationFailed {
+        error: error.to_string(),
+    }
+}
+
+impl SvmRpcEscrowVerifier {
+    pub fn new(url: String, portal_program: Pubkey, commitment: Commitment) -> Result<Self, FastPayError> {
+        let escrow_authority = escrow_authority(&portal_program).ok_or_else(|| FastPayError::ConfigurationError {
+            error: format!("No escrow authority for portal program {}", portal_program.base58()),
+        })?;
+        Ok(Self {
+            client: SvmRpcClient::new(url),
+            portal_program,
+            escrow_authority,
+            commitment,
+        })
+    }
+
+    /// Fetch an account with `getAccountInfo`, parsed by the node. Returns `Value::Null` if
+    /// the account does not exist.
+    async fn get_account_info(&self, account: &Pubkey) -> Result<Value, FastPayError> {
+        let params = json!([
+            account.base58(),
+            { "encoding": "jsonParsed", "commitment": self.commitment.as_str() }
+        ]);
+        let result = self.client.call("getAccountInfo", params).await.map_err(rpc_error)?;
+        result
+            .get("value")
+            .cloned()
+            .ok_or_else(|| rpc_error("Malformed getAccountInfo response"))
+    }
+
+    /// Check that a parsed token account holds at least the amount of the transfer, of the
+    /// right mint, on behalf of the escrow authority of the portal program.
+    fn check_token_account(&self, account: &Value, transfer: &CrossChainTransfer) -> bool {
+        let Some(info) = account.pointer("/data/parsed/info") else {
+            return false;
+        };
+        let is_token_account = account.pointer("/data/parsed/type") == Some(&json!("account"));
+        let mint = info.get("mint").and_then(Value::as_str);
+        let owner = info.get("owner").and_then(Value::as_str);
+        let state = info.get("state").and_then(Value::as_str);
+        let amount = info
+            .pointer("/tokenAmount/amount")
+            .and_then(Value::as_str)
+            .and_then(|amount| amount.parse::<u64>().ok());
+        is_token_account
+            && mint == Some(transfer.token_mint.base58().as_str())
+            && owner == Some(self.escrow_authority.base58().as_str())
+            && state == Some("initialized")
+            && amount.is_some_and(|amount| amount >= transfer.amount)
+    }
+}
+
+impl EscrowVerifier for SvmRpcEscrowVerifier {
+    fn verify_escrow<'a>(
+        &'a self,
+        transfer: &'a CrossChainTransfer,
+    ) -> BoxFuture<'a, Result<bool, FastPayError>> {
+        Box::pin(async move {
+            if !is_escrow_of(&self.portal_program, transfer) {
+                return Ok(false);
+            }
+            let account = self.get_account_info(&transfer.escrow_account).await?;
+            if account.is_null() {
+                return Err(FastPayError::EscrowNotFound {
+                    escrow_account: transfer.escrow_account.base58(),
+                });
+            }
+            Ok(self.check_token_account(&account, transfer))
+        })
+    }
+}
+
+#[cfg(test)]
+#[path = "unit_tests/escrow_tests.rs"]
+mod escrow_tests;
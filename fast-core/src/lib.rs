@@ -3,6 +3,16 @@ pub mod authority;
 pub mod message;
 pub mod downloader;
 pub mod base_types;
+pub mod channel;
 pub mod committee;
+pub mod config;
 pub mod error;
+pub mod escrow;
+#[cfg(any(test, feature = "mock-ledger"))]
+pub mod mock_ledger;
+pub mod rpc;
 pub mod serialization;
+pub mod sharding;
+pub mod storage;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
@@ -1,8 +1,8 @@
 use crate::fp_ensure;
 
-use super::{base_types::*, committee::Committee, error::*};
+use super::{base_types::*, committee::*, error::*};
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::hash::{Hash, Hasher};
 
 #[derive(Eq, Clone, Debug, Serialize, Deserialize)]
@@ -12,18 +12,185 @@ pub struct CrossChainTransferOrder {
 }
 
 #[derive(Eq, Clone, Debug, Serialize, Deserialize)]
-pub struct SignedCrossChainTransferOrder {
-    pub value: CrossChainTransferOrder,
+pub struct CrossChainMessageOrder {
+    pub message: CrossChainMessage,
+    pub signature: Signature,
+}
+
+/// Request to refund an undelivered transfer to its sender on the source chain. Carries the
+/// order signed by the user, so that anyone may ask for the refund.
+#[derive(Eq, PartialEq, Hash, Clone, Debug, Serialize, Deserialize)]
+pub struct CrossChainRefundOrder {
+    pub transfer_order: CrossChainTransferOrder,
+}
+
+/// Kind of value an authority votes for. Part of the vote, so that a vote for one kind of
+/// value cannot be passed off as a vote for another, e.g. a refund as a delivery.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize)]
+pub enum VoteKind {
+    Transfer,
+    Message,
+    Refund,
+}
+
+/// What an authority signs when it votes for a value. Its signed bytes start with the name
+/// of the type and the role of the signer, so they never read as a transfer or message signed
+/// by a user. Bound to the network and epoch of the committee, and to the shard of the
+/// authority that voted.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct Vote {
+    pub role: SignerRole,
+    pub kind: VoteKind,
+    pub network: NetworkId,
+    pub epoch: Epoch,
+    pub shard: ShardId,
+    pub digest: ContentDigest,
+}
+
+impl BcsSignable for Vote {}
+
+/// A value signed by a user, which authorities vote for and certify.
+pub trait Votable: Clone + Eq + Hash {
+    /// Tag of the votes for this kind of value.
+    const KIND: VoteKind;
+
+    /// Sender and id of the transfer or message.
+    fn key(&self) -> (Pubkey, InteropTxId);
+
+    /// Check the signature of the user.
+    fn check_signature(&self) -> Result<(), FastPayError>;
+
+    /// Digest of the content that authorities vote for.
+    fn digest(&self) -> ContentDigest;
+
+    /// What the authorities of a committee sign, from the shard that holds the sender.
+    fn vote(&self, committee: &Committee, shard: ShardId) -> Vote {
+        Vote {
+            role: SignerRole::Authority,
+            kind: Self::KIND,
+            network: committee.network,
+            epoch: committee.epoch,
+            shard,
+            digest: self.digest(),
+        }
+    }
+
+    /// What a given authority of a committee signs.
+    fn vote_of(&self, committee: &Committee, authority: &AuthorityName) -> Result<Vote, FastPayError> {
+        let shard = committee.shard_of(authority, &self.key().0)?;
+        Ok(self.vote(committee, shard))
+    }
+}
+
+/// Vote of one authority for a value.
+#[derive(Eq, Clone, Debug, Serialize, Deserialize)]
+pub struct SignedOrder<V> {
+    pub value: V,
     pub authority: AuthorityName,
+    pub epoch: Epoch,
     pub signature: Signature,
 }
 
+/// Votes of a quorum of the committee of an epoch for a value.
 #[derive(Eq, Clone, Debug, Serialize, Deserialize)]
-pub struct CertifiedCrossChainTransferOrder {
-    pub value: CrossChainTransferOrder,
+pub struct Certificate<V> {
+    pub value: V,
+    pub epoch: Epoch,
     pub signatures: Vec<(AuthorityName, Signature)>,
 }
 
+pub type SignedCrossChainTransferOrder = SignedOrder<CrossChainTransferOrder>;
+pub type CertifiedCrossChainTransferOrder = Certificate<CrossChainTransferOrder>;
+pub type SignedCrossChainMessageOrder = SignedOrder<CrossChainMessageOrder>;
+pub type CertifiedCrossChainMessageOrder = Certificate<CrossChainMessageOrder>;
+pub type SignedCrossChainRefundOrder = SignedOrder<CrossChainRefundOrder>;
+/// Lets the source portal unlock the escrow of a transfer. A transfer cannot be both
+/// delivered and refunded by honest authorities before its deadline; past the deadline, the
+/// destination portal must reject the delivery certificate.
+pub type CertifiedCrossChainRefundOrder = Certificate<CrossChainRefundOrder>;
+
+/// Certificate where signers are given by a bitmap over the positions of the committee of
+/// its epoch instead of their names, saving 32 bytes per signer. Signatures are listed by
+/// increasing committee position.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct CompactCertificate<V = CrossChainTransferOrder> {
+    pub value: V,
+    pub epoch: Epoch,
+    pub signers: Vec<u8>,
+    pub signatures: Vec<Signature>,
+}
+
+/// Answer of an authority to a message order, like `TransferOrderResponse`.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub enum MessageOrderResponse {
+    Vote(SignedCrossChainMessageOrder),
+    Certificate(CertifiedCrossChainMessageOrder),
+}
+
+/// Answer of an authority to a refund order, like `TransferOrderResponse`.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub enum RefundOrderResponse {
+    Vote(SignedCrossChainRefundOrder),
+    Certificate(CertifiedCrossChainRefundOrder),
+}
+
+/// Answer of an authority to a transfer order. Re-sending an order is idempotent: the
+/// authority returns its existing vote, or the certificate once the transfer is processed.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub enum TransferOrderResponse {
+    Vote(SignedCrossChainTransferOrder),
+    Certificate(CertifiedCrossChainTransferOrder),
+}
+
+/// Ask an authority what it knows about a transfer.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct TransferInfoRequest {
+    pub sender: Pubkey,
+    pub interop_tx_id: InteropTxId,
+}
+
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct TransferInfoResponse {
+    pub interop_tx_id: InteropTxId,
+    /// Order waiting for its certificate, if any.
+    pub pending_order: Option<CrossChainTransferOrder>,
+    /// Vote of the queried authority for the pending order, if any.
+    pub signed_order: Option<SignedCrossChainTransferOrder>,
+    /// Certificate of the transfer, once processed.
+    pub certificate: Option<CertifiedCrossChainTransferOrder>,
+}
+
+/// Ask an authority about the account of a sender.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct AccountInfoRequest {
+    pub sender: Pubkey,
+}
+
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct AccountInfoResponse {
+    pub sender: Pubkey,
+    /// Nonce expected for the next order of the sender.
+    pub next_nonce: u64,
+    /// Vote of the queried authority for the order of the sender waiting for its certificate.
+    pub pending_order: Option<SignedCrossChainTransferOrder>,
+    /// Nonce expected for the next message of the sender.
+    pub next_message_nonce: u64,
+}
+
+/// Ask an authority for the committee changes it knows past an epoch.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct CommitteeInfoRequest {
+    pub since_epoch: Epoch,
+}
+
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct CommitteeInfoResponse {
+    /// Current epoch of the queried authority.
+    pub epoch: Epoch,
+    /// Certified changes past the requested epoch, oldest first.
+    pub changes: Vec<CertifiedCommitteeChange>,
+}
+
 #[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub struct CrossChainRedeemTransaction {
     pub transfer_certificate: CertifiedCrossChainTransferOrder,
@@ -52,97 +219,170 @@ impl PartialEq for CrossChainTransferOrder {
     }
 }
 
-impl Hash for SignedCrossChainTransferOrder {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.value.hash(state);
-        self.authority.hash(state);
+impl CrossChainTransferOrder {
+    pub fn new(transfer: CrossChainTransfer, secret: &KeyPair) -> Self {
+        let signature = Signature::new(&transfer, secret);
+        Self {
+            transfer,
+            signature,
+        }
+    }
+
+    pub fn check_signature(&self) -> Result<(), FastPayError> {
+        self.signature.check(&self.transfer, self.transfer.sender)
     }
 }
 
-impl PartialEq for SignedCrossChainTransferOrder {
-    fn eq(&self, other: &Self) -> bool {
-        self.value == other.value && self.authority == other.authority
+impl Votable for CrossChainTransferOrder {
+    const KIND: VoteKind = VoteKind::Transfer;
+
+    fn key(&self) -> (Pubkey, InteropTxId) {
+        self.transfer.key()
+    }
+
+    fn check_signature(&self) -> Result<(), FastPayError> {
+        CrossChainTransferOrder::check_signature(self)
+    }
+
+    fn digest(&self) -> ContentDigest {
+        ContentDigest::new(&self.transfer)
     }
 }
 
-impl Hash for CertifiedCrossChainTransferOrder {
+impl Hash for CrossChainMessageOrder {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.value.hash(state);
-        self.signatures.len().hash(state);
-        for (name, _) in self.signatures.iter() {
-            name.hash(state);
-        }
+        self.message.hash(state);
     }
 }
 
-impl PartialEq for CertifiedCrossChainTransferOrder {
+impl PartialEq for CrossChainMessageOrder {
     fn eq(&self, other: &Self) -> bool {
-        self.value == other.value &&
-            self.signatures.len() == other.signatures.len() &&
-            self.signatures
-                .iter()
-                .map(|(name, _)| name)
-                .eq(other.signatures.iter().map(|(name, _)| name))
+        self.message == other.message
     }
 }
 
-impl CrossChainTransferOrder {
-    pub fn new(transfer: CrossChainTransfer, secret: &KeyPair) -> Self {
-        let signature = Signature::new(&transfer, secret);
+impl CrossChainMessageOrder {
+    pub fn new(message: CrossChainMessage, secret: &KeyPair) -> Self {
+        let signature = Signature::new(&message, secret);
         Self {
-            transfer,
+            message,
             signature,
         }
     }
 
     pub fn check_signature(&self) -> Result<(), FastPayError> {
-        self.signature.check(&self.transfer, self.transfer.sender)
+        self.signature.check(&self.message, self.message.sender)
+    }
+}
+
+impl Votable for CrossChainMessageOrder {
+    const KIND: VoteKind = VoteKind::Message;
+
+    fn key(&self) -> (Pubkey, InteropTxId) {
+        self.message.key()
+    }
+
+    fn check_signature(&self) -> Result<(), FastPayError> {
+        CrossChainMessageOrder::check_signature(self)
+    }
+
+    fn digest(&self) -> ContentDigest {
+        ContentDigest::new(&self.message)
     }
 }
 
-impl SignedCrossChainTransferOrder {
-    /// Use signing key to create a signed object.
-    pub fn new(value: CrossChainTransferOrder, authority: AuthorityName, secret: &KeyPair) -> Self {
-        let signature = Signature::new(&value.transfer, secret);
+impl CrossChainRefundOrder {
+    pub fn transfer(&self) -> &CrossChainTransfer {
+        &self.transfer_order.transfer
+    }
+
+    pub fn check_signature(&self) -> Result<(), FastPayError> {
+        self.transfer_order.check_signature()
+    }
+}
+
+impl Votable for CrossChainRefundOrder {
+    const KIND: VoteKind = VoteKind::Refund;
+
+    fn key(&self) -> (Pubkey, InteropTxId) {
+        self.transfer().key()
+    }
+
+    fn check_signature(&self) -> Result<(), FastPayError> {
+        CrossChainRefundOrder::check_signature(self)
+    }
+
+    fn digest(&self) -> ContentDigest {
+        ContentDigest::new(self.transfer())
+    }
+}
+
+impl<V: Hash> Hash for SignedOrder<V> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.value.hash(state);
+        self.authority.hash(state);
+    }
+}
+
+impl<V: PartialEq> PartialEq for SignedOrder<V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value && self.authority == other.authority
+    }
+}
+
+impl<V: Votable> SignedOrder<V> {
+    /// Use signing key to vote, as a member of the committee, from the shard of the sender.
+    pub fn new(value: V, authority: AuthorityName, committee: &Committee, shard: ShardId, secret: &KeyPair) -> Self {
+        let signature = Signature::new(&value.vote(committee, shard), secret);
         Self {
             value,
             authority,
+            epoch: committee.epoch,
             signature,
         }
     }
 
     /// Verify the signature and return the non-zero voting right of the authority.
     pub fn check(&self, committee: &Committee) -> Result<usize, FastPayError> {
+        fp_ensure!(
+            self.epoch == committee.epoch,
+            FastPayError::EpochMismatch { expected: committee.epoch, found: self.epoch }
+        );
         self.value.check_signature()?;
         let weight = committee.weight(&self.authority);
         fp_ensure!(weight > 0, FastPayError::UnknownSigner);
-        self.signature.check(&self.value.transfer, self.authority)?;
+        self.signature.check(&self.value.vote_of(committee, &self.authority)?, self.authority)?;
         Ok(weight)
     }
 }
 
-pub struct CrossChainSignatureAggregator<'a> {
+pub struct SignatureAggregator<'a, V> {
     committee: &'a Committee,
     weight: usize,
     used_authorities: HashSet<AuthorityName>,
-    partial: CertifiedCrossChainTransferOrder,
+    partial: Certificate<V>,
 }
 
-impl<'a> CrossChainSignatureAggregator<'a> {
+pub type CrossChainSignatureAggregator<'a> = SignatureAggregator<'a, CrossChainTransferOrder>;
+pub type MessageSignatureAggregator<'a> = SignatureAggregator<'a, CrossChainMessageOrder>;
+pub type RefundSignatureAggregator<'a> = SignatureAggregator<'a, CrossChainRefundOrder>;
+
+impl<'a, V: Votable> SignatureAggregator<'a, V> {
     /// Start aggregating signatures for the given value into a certificate.
-    pub fn try_new(value: CrossChainTransferOrder, committee: &'a Committee) -> Result<Self, FastPayError> {
+    pub fn try_new(value: V, committee: &'a Committee) -> Result<Self, FastPayError> {
         value.check_signature()?;
         Ok(Self::new_unsafe(value, committee))
     }
 
     /// Same as try_new but we don't check the order.
-    pub fn new_unsafe(value: CrossChainTransferOrder, committee: &'a Committee) -> Self {
+    pub fn new_unsafe(value: V, committee: &'a Committee) -> Self {
         Self {
             committee,
             weight: 0,
             used_authorities: HashSet::new(),
-            partial: CertifiedCrossChainTransferOrder {
+            partial: Certificate {
                 value,
+                epoch: committee.epoch,
                 signatures: Vec::new(),
             },
         }
@@ -153,8 +393,8 @@ impl<'a> CrossChainSignatureAggregator<'a> {
         &mut self,
         authority: AuthorityName,
         signature: Signature
-    ) -> Result<Option<CertifiedCrossChainTransferOrder>, FastPayError> {
-        signature.check(&self.partial.value.transfer, authority)?;
+    ) -> Result<Option<Certificate<V>>, FastPayError> {
+        signature.check(&self.partial.value.vote_of(self.committee, &authority)?, authority)?;
         // Check that each authority only appears once.
         fp_ensure!(
             !self.used_authorities.contains(&authority),
@@ -176,14 +416,40 @@ impl<'a> CrossChainSignatureAggregator<'a> {
     }
 }
 
-impl CertifiedCrossChainTransferOrder {
+impl<V: Hash> Hash for Certificate<V> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.value.hash(state);
+        self.epoch.hash(state);
+        self.signatures.len().hash(state);
+        for (name, _) in self.signatures.iter() {
+            name.hash(state);
+        }
+    }
+}
+
+impl<V: PartialEq> PartialEq for Certificate<V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value &&
+            self.epoch == other.epoch &&
+            self.signatures.len() == other.signatures.len() &&
+            self.signatures
+                .iter()
+                .map(|(name, _)| name)
+                .eq(other.signatures.iter().map(|(name, _)| name))
+    }
+}
+
+impl<V: Votable> Certificate<V> {
     pub fn key(&self) -> (Pubkey, InteropTxId) {
-        let transfer = &self.value.transfer;
-        (transfer.sender, transfer.interop_tx_id)
+        self.value.key()
     }
 
-    /// Verify the certificate.
+    /// Verify the certificate against the committee of its epoch.
     pub fn check(&self, committee: &Committee) -> Result<(), FastPayError> {
+        fp_ensure!(
+            self.epoch == committee.epoch,
+            FastPayError::EpochMismatch { expected: committee.epoch, found: self.epoch }
+        );
         // Check the quorum.
         let mut weight = 0;
         let mut used_authorities = HashSet::new();
@@ -201,10 +467,84 @@ impl CertifiedCrossChainTransferOrder {
         }
         fp_ensure!(weight >= committee.quorum_threshold(), FastPayError::CertificateRequiresQuorum);
         // All what is left is checking signatures!
-        let inner_sig = (self.value.transfer.sender, self.value.signature);
-        Signature::verify_batch(
-            &self.value.transfer,
-            std::iter::once(&inner_sig).chain(&self.signatures)
-        )
+        self.value.check_signature()?;
+        // Authorities voting from the same shard sign the same vote
+        let mut batches: BTreeMap<ShardId, Vec<(AuthorityName, Signature)>> = BTreeMap::new();
+        for (authority, signature) in self.signatures.iter() {
+            let shard = committee.shard_of(authority, &self.value.key().0)?;
+            batches.entry(shard).or_default().push((*authority, *signature));
+        }
+        for (shard, signatures) in batches.iter() {
+            Signature::verify_batch(&self.value.vote(committee, *shard), signatures)?;
+        }
+        Ok(())
     }
-}
\ No newline at end of file
+
+    /// Encode the certificate with a signer bitmap over the given committee.
+    pub fn compact(&self, committee: &Committee) -> Result<CompactCertificate<V>, FastPayError> {
+        fp_ensure!(
+            self.epoch == committee.epoch,
+            FastPayError::EpochMismatch { expected: committee.epoch, found: self.epoch }
+        );
+        let mut signatures = Vec::new();
+        for (authority, signature) in self.signatures.iter() {
+            let index = committee.position(authority).ok_or(FastPayError::UnknownSigner)?;
+            signatures.push((index, *signature));
+        }
+        signatures.sort_by_key(|(index, _)| *index);
+        let mut signers = vec![0u8; committee.voting_rights.len().div_ceil(8)];
+        for (index, _) in signatures.iter() {
+            let (byte, bit) = (index / 8, 1u8 << (index % 8));
+            fp_ensure!(signers[byte] & bit == 0, FastPayError::CertificateAuthorityReuse);
+            signers[byte] |= bit;
+        }
+        Ok(CompactCertificate {
+            value: self.value.clone(),
+            epoch: self.epoch,
+            signers,
+            signatures: signatures.into_iter().map(|(_, signature)| signature).collect(),
+        })
+    }
+}
+
+impl<V: Votable> CompactCertificate<V> {
+    /// Recover the names of the signers from the committee of the certificate's epoch.
+    pub fn expand(&self, committee: &Committee) -> Result<Certificate<V>, FastPayError> {
+        fp_ensure!(
+            self.epoch == committee.epoch,
+            FastPayError::EpochMismatch { expected: committee.epoch, found: self.epoch }
+        );
+        let size = committee.voting_rights.len();
+        fp_ensure!(
+            self.signers.len() == size.div_ceil(8),
+            FastPayError::InvalidSignerBitmap
+        );
+        let authorities: Vec<AuthorityName> = committee
+            .voting_rights
+            .keys()
+            .enumerate()
+            .filter(|(index, _)| self.signers[index / 8] & (1u8 << (index % 8)) != 0)
+            .map(|(_, name)| *name)
+            .collect();
+        // Bits past the end of the committee must be clear, and every bit needs a signature.
+        let set_bits: u32 = self.signers.iter().map(|byte| byte.count_ones()).sum();
+        fp_ensure!(
+            set_bits as usize == authorities.len() && authorities.len() == self.signatures.len(),
+            FastPayError::InvalidSignerBitmap
+        );
+        Ok(Certificate {
+            value: self.value.clone(),
+            epoch: self.epoch,
+            signatures: authorities.into_iter().zip(self.signatures.iter().copied()).collect(),
+        })
+    }
+
+    /// Verify the certificate against the committee of its epoch.
+    pub fn check(&self, committee: &Committee) -> Result<(), FastPayError> {
+        self.expand(committee)?.check(committee)
+    }
+}
+
+#[cfg(test)]
+#[path = "unit_tests/message_tests.rs"]
+mod message_tests;
@@ -0,0 +1,262 @@
+use crate::fp_ensure;
+
+use super::{authority::EscrowVerifier, base_types::*, committee::*, error::*, escrow::*, message::*};
+use futures::future::BoxFuture;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// Something that happened on a portal of a simulated chain, in order.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum PortalEvent {
+    /// Funds were locked in escrow for a transfer, signed by its sender.
+    Locked(CrossChainTransferOrder),
+    /// A certified transfer was minted to its recipient.
+    Minted(CrossChainTransfer),
+    /// A certified refund returned the escrowed funds to their sender.
+    Refunded(CrossChainTransfer),
+    /// A certified message was handed to its target program.
+    MessageDelivered(CrossChainMessage),
+}
+
+/// Funds held by the source portal for one transfer.
+#[derive(Debug, Clone)]
+struct Escrow {
+    transfer: CrossChainTransfer,
+    released: bool,
+}
+
+/// A simulated SVM chain holding token balances, with an escrow portal locking funds for
+/// outgoing transfers and a destination portal minting incoming certified transfers. Meant
+/// to run the whole user, authority, relayer and destination flow in one process.
+pub struct MockSvmChain {
+    pub chain_id: ChainId,
+    /// Address of the portal program, owner of the escrows
+    pub portal_program: Pubkey,
+    /// Current time of the chain, used to enforce transfer deadlines
+    pub clock: Timestamp,
+    /// Balances, by owner and mint
+    balances: BTreeMap<(Pubkey, Pubkey), u64>,
+    /// Escrows, by escrow account
+    escrows: HashMap<Pubkey, Escrow>,
+    /// Next nonce of the transfers locked by each sender
+    nonces: HashMap<Pubkey, u64>,
+    /// Committees known to the destination portal
+    committees: CommitteeHistory,
+    /// Transfers and messages already executed by the destination portal (replay protection)
+    delivered: HashSet<InteropTxId>,
+    events: Vec<PortalEvent>,
+}
+
+/// A simulated chain shared between the tasks of a test.
+pub type SharedMockChain = Arc<Mutex<MockSvmChain>>;
+
+impl MockSvmChain {
+    pub fn new(chain_id: ChainId, portal_program: Pubkey, committee: Committee) -> Self {
+        Self {
+            chain_id,
+            portal_program,
+            clock: current_timestamp(),
+            balances: BTreeMap::new(),
+            escrows: HashMap::new(),
+            nonces: HashMap::new(),
+            committees: CommitteeHistory::new(committee),
+            delivered: HashSet::new(),
+            events: Vec::new(),
+        }
+    }
+
+    pub fn into_shared(self) -> SharedMockChain {
+        Arc::new(Mutex::new(self))
+    }
+
+    pub fn balance(&self, owner: &Pubkey, mint: &Pubkey) -> u64 {
+        *self.balances.get(&(*owner, *mint)).unwrap_or(&0)
+    }
+
+    /// Create tokens out of thin air, e.g. to fund test users.
+    pub fn airdrop(&mut self, owner: Pubkey, mint: Pubkey, amount: u64) {
+        let balance = self.balances.entry((owner, mint)).or_insert(0);
+        *balance = balance.saturating_add(amount);
+    }
+
+    fn debit(&mut self, owner: Pubkey, mint: Pubkey, amount: u64) -> Result<(), FastPayError> {
+        let current_balance = self.balance(&owner, &mint);
+        fp_ensure!(
+            current_balance >= amount,
+            FastPayError::InsufficientFunding { current_balance }
+        );
+        self.balances.insert((owner, mint), current_balance - amount);
+        Ok(())
+    }
+
+    fn check_chain(&self, chain_id: ChainId) -> Result<(), FastPayError> {
+        fp_ensure!(
+            chain_id == self.chain_id,
+            FastPayError::WrongChain { expected: self.chain_id.0, found: chain_id.0 }
+        );
+        Ok(())
+    }
+
+    /// Events emitted by the portals, starting at the given position.
+    pub fn events_since(&self, cursor: usize) -> &[PortalEvent] {
+        self.events.get(cursor..).unwrap_or(&[])
+    }
+
+    /// Source portal: lock funds of the sender in a new escrow and emit the transfer order
+    /// signed by the sender.
+    pub fn lock(
+        &mut self,
+        sender: &KeyPair,
+        destination_chain: ChainId,
+        recipient: Pubkey,
+        token_mint: Pubkey,
+        amount: u64,
+        deadline: Timestamp,
+    ) -> Result<CrossChainTransferOrder, FastPayError> {
+        fp_ensure!(amount > 0, FastPayError::IncorrectTransferAmount);
+        let sender_key = sender.public();
+        let nonce = *self.nonces.get(&sender_key).unwrap_or(&0);
+        let interop_tx_id = InteropTxId::generate(
+            self.chain_id,
+            destination_chain,
+            sender_key,
+            recipient,
+            amount,
+            token_mint,
+            nonce,
+        );
+        // One escrow account per transfer, derived like the portal program does
+        let escrow_account = escrow_address(&self.portal_program, &sender_key, &interop_tx_id).ok_or_else(|| {
+            FastPayError::EscrowVerificationFailed {
+                error: "No escrow address for the transfer".to_string(),
+            }
+        })?;
+        self.debit(sender_key, token_mint, amount)?;
+        self.nonces.insert(sender_key, nonce + 1);
+
+        let transfer = CrossChainTransfer {
+            source_chain: self.chain_id,
+            destination_chain,
+            sender: sender_key,
+            recipient,
+            amount,
+            token_mint,
+            interop_tx_id,
+            escrow_account,
+            nonce,
+            deadline,
+        };
+        self.escrows.insert(
+            transfer.escrow_account,
+            Escrow {
+                transfer: transfer.clone(),
+                released: false,
+            },
+        );
+        let order = CrossChainTransferOrder::new(transfer, sender);
+        self.events.push(PortalEvent::Locked(order.clone()));
+        Ok(order)
+    }
+
+    /// Source portal: check that the escrow of a transfer holds its funds, with the same
+    /// binding of the escrow to the transfer as `SvmRpcEscrowVerifier`.
+    pub fn has_escrow(&self, transfer: &CrossChainTransfer) -> bool {
+        if !is_escrow_of(&self.portal_program, transfer) {
+            return false;
+        }
+        match self.escrows.get(&transfer.escrow_account) {
+            Some(escrow) => {
+                !escrow.released
+                    && escrow.transfer.interop_tx_id == transfer.interop_tx_id
+                    && escrow.transfer.token_mint == transfer.token_mint
+                    && escrow.transfer.amount >= transfer.amount
+            }
+            None => false,
+        }
+    }
+
+    /// Source portal: return escrowed funds to the sender of a transfer refunded by the
+    /// committee.
+    pub fn refund(&mut self, certificate: &CertifiedCrossChainRefundOrder) -> Result<(), FastPayError> {
+        certificate.check(self.committees.get(certificate.epoch)?)?;
+        let transfer = certificate.value.transfer();
+        self.check_chain(transfer.source_chain)?;
+        let escrow = self
+            .escrows
+            .get_mut(&transfer.escrow_account)
+            .filter(|escrow| escrow.transfer == *transfer)
+            .ok_or(FastPayError::CertificateNotfound)?;
+        fp_ensure!(!escrow.released, FastPayError::CertificateAlreadyExists);
+        escrow.released = true;
+        self.airdrop(transfer.sender, transfer.token_mint, transfer.amount);
+        self.events.push(PortalEvent::Refunded(transfer.clone()));
+        Ok(())
+    }
+
+    /// Destination portal: mint a certified transfer to its recipient, once.
+    pub fn deliver(&mut self, certificate: &CertifiedCrossChainTransferOrder) -> Result<(), FastPayError> {
+        certificate.check(self.committees.get(certificate.epoch)?)?;
+        let transfer = &certificate.value.transfer;
+        self.check_chain(transfer.destination_chain)?;
+        // Past the deadline, the transfer may be refunded instead.
+        fp_ensure!(!transfer.is_expired(self.clock), FastPayError::TransferExpired);
+        fp_ensure!(
+            self.delivered.insert(transfer.interop_tx_id),
+            FastPayError::CertificateAlreadyExists
+        );
+        self.airdrop(transfer.recipient, transfer.token_mint, transfer.amount);
+        self.events.push(PortalEvent::Minted(transfer.clone()));
+        Ok(())
+    }
+
+    /// Destination portal: same as `deliver` for a certificate in compact form.
+    pub fn deliver_compact(&mut self, certificate: &CompactCertificate) -> Result<(), FastPayError> {
+        let certificate = certificate.expand(self.committees.get(certificate.epoch)?)?;
+        self.deliver(&certificate)
+    }
+
+    /// Destination portal: hand a certified message to its target program, once.
+    pub fn deliver_message(
+        &mut self,
+        certificate: &CertifiedCrossChainMessageOrder,
+    ) -> Result<(), FastPayError> {
+        certificate.check(self.committees.get(certificate.epoch)?)?;
+        let message = &certificate.value.message;
+        self.check_chain(message.destination_chain)?;
+        fp_ensure!(
+            self.delivered.insert(message.interop_tx_id),
+            FastPayError::CertificateAlreadyExists
+        );
+        self.events.push(PortalEvent::MessageDelivered(message.clone()));
+        Ok(())
+    }
+
+    /// Both portals: follow the reconfigurations of the committee.
+    pub fn update_committee(&mut self, change: CertifiedCommitteeChange) -> Result<bool, FastPayError> {
+        self.committees.advance(change)
+    }
+}
+
+/// Authorities may check escrows directly on a simulated source chain.
+impl EscrowVerifier for SharedMockChain {
+    fn verify_escrow<'a>(
+        &'a self,
+        transfer: &'a CrossChainTransfer,
+    ) -> BoxFuture<'a, Result<bool, FastPayError>> {
+        let chain = self.lock().unwrap();
+        let result = if is_escrow_of(&chain.portal_program, transfer)
+            && !chain.escrows.contains_key(&transfer.escrow_account)
+        {
+            Err(FastPayError::EscrowNotFound {
+                escrow_account: transfer.escrow_account.base58(),
+            })
+        } else {
+            Ok(chain.has_escrow(transfer))
+        };
+        Box::pin(async move { result })
+    }
+}
+
+#[cfg(test)]
+#[path = "unit_tests/mock_ledger_tests.rs"]
+mod mock_ledger_tests;
@@ -0,0 +1,37 @@
+use failure::Error;
+use serde_json::{json, Value};
+
+/// Client of the JSON-RPC endpoint of an SVM chain, shared by the escrow verifier of the
+/// authorities and by the watchers and submitters of the relayer.
+#[derive(Clone)]
+pub struct SvmRpcClient {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl SvmRpcClient {
+    pub fn new(url: String) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url,
+        }
+    }
+
+    /// Call a method and return its result, or fail with the error answered by the node.
+    pub async fn call(&self, method: &str, params: Value) -> Result<Value, Error> {
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        let response: Value = self
+            .client
+            .post(&self.url)
+            .json(&request)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if let Some(error) = response.get("error") {
+            return Err(failure::format_err!("RPC error in {}: {}", method, error));
+        }
+        Ok(response["result"].clone())
+    }
+}
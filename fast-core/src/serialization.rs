@@ -1,6 +1,6 @@
 use bincode::{deserialize, serialize};
 use serde::{Deserialize, Serialize};
-use crate::{error::FastPayError, message::*};
+use crate::{base_types::NetworkId, committee::CertifiedCommitteeChange, error::FastPayError, message::*};
 
 /// Message types for network communication
 #[derive(Serialize, Deserialize)]
@@ -9,38 +9,269 @@ pub enum BridgeMessage {
     SignedCrossChainTransferOrder(SignedCrossChainTransferOrder),
     CertifiedCrossChainTransferOrder(CertifiedCrossChainTransferOrder),
     CrossShardUpdate(CrossShardCrossChainUpdate),
-    Error(String),
+    TransferInfoRequest(TransferInfoRequest),
+    TransferInfoResponse(TransferInfoResponse),
+    AccountInfoRequest(AccountInfoRequest),
+    AccountInfoResponse(AccountInfoResponse),
+    CommitteeChange(CertifiedCommitteeChange),
+    CommitteeInfoRequest(CommitteeInfoRequest),
+    CommitteeInfoResponse(CommitteeInfoResponse),
+    CompactCertificate(CompactCertificate),
+    CrossChainMessageOrder(CrossChainMessageOrder),
+    SignedCrossChainMessageOrder(SignedCrossChainMessageOrder),
+    CertifiedCrossChainMessageOrder(CertifiedCrossChainMessageOrder),
+    CrossChainRefundOrder(CrossChainRefundOrder),
+    SignedCrossChainRefundOrder(SignedCrossChainRefundOrder),
+    CertifiedCrossChainRefundOrder(CertifiedCrossChainRefundOrder),
+    /// Receipt of a message that needs no other answer, e.g. a certificate
+    Ack,
+    Error(FastPayError),
 }
 
-/// Serialize a message to bytes
-pub fn serialize_message(message: &BridgeMessage) -> Vec<u8> {
-    serialize(message).expect("Serialization failed")
+impl BridgeMessage {
+    /// Kind of the message, written in its envelope. Codes are never reused, so that
+    /// a node can tell which message it failed to decode.
+    pub fn kind(&self) -> u8 {
+        match self {
+            BridgeMessage::CrossChainTransferOrder(_) => 0,
+            BridgeMessage::SignedCrossChainTransferOrder(_) => 1,
+            BridgeMessage::CertifiedCrossChainTransferOrder(_) => 2,
+            BridgeMessage::CrossShardUpdate(_) => 3,
+            BridgeMessage::TransferInfoRequest(_) => 4,
+            BridgeMessage::TransferInfoResponse(_) => 5,
+            BridgeMessage::AccountInfoRequest(_) => 6,
+            BridgeMessage::AccountInfoResponse(_) => 7,
+            BridgeMessage::CommitteeChange(_) => 8,
+            BridgeMessage::CommitteeInfoRequest(_) => 9,
+            BridgeMessage::CommitteeInfoResponse(_) => 10,
+            BridgeMessage::CompactCertificate(_) => 11,
+            BridgeMessage::CrossChainMessageOrder(_) => 12,
+            BridgeMessage::SignedCrossChainMessageOrder(_) => 13,
+            BridgeMessage::CertifiedCrossChainMessageOrder(_) => 14,
+            BridgeMessage::CrossChainRefundOrder(_) => 15,
+            BridgeMessage::SignedCrossChainRefundOrder(_) => 16,
+            BridgeMessage::CertifiedCrossChainRefundOrder(_) => 17,
+            BridgeMessage::Ack => 18,
+            BridgeMessage::Error(_) => ERROR,
+        }
+    }
 }
 
-/// Deserialize bytes to a message
-pub fn deserialize_message(bytes: &[u8]) -> Result<BridgeMessage, FastPayError> {
-    deserialize(bytes).map_err(|_| FastPayError::DeserializationError)
+/// Version of the protocol spoken by this node
+pub const PROTOCOL_VERSION: u16 = 1;
+/// Oldest version of the protocol this node still understands
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+
+/// First bytes of every message
+const MAGIC: [u8; 4] = *b"FPTB";
+/// Kind of the answer to a message of an unsupported version
+const VERSION_MISMATCH: u8 = 0xff;
+/// Kind of error messages
+const ERROR: u8 = 19;
+/// Magic bytes, version, kind and network
+pub const ENVELOPE_SIZE: usize = 11;
+
+/// How a message is wrapped on the wire: the magic bytes, the protocol version and the
+/// kind of the message, then the network, little-endian. This layout is the same in
+/// every version, so that nodes of different versions can at least tell they disagree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Envelope {
+    pub version: u16,
+    pub network: NetworkId,
+}
+
+impl Envelope {
+    /// Envelope of the messages this node starts on a network
+    pub fn new(network: NetworkId) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            network,
+        }
+    }
+
+    fn header(&self, kind: u8) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(ENVELOPE_SIZE);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&self.version.to_le_bytes());
+        bytes.push(kind);
+        bytes.extend_from_slice(&self.network.to_le_bytes());
+        bytes
+    }
+}
+
+/// Split a message into its envelope, kind and body
+fn parse_envelope(bytes: &[u8]) -> Result<(Envelope, u8, &[u8]), FastPayError> {
+    if bytes.len() < ENVELOPE_SIZE || bytes[..4] != MAGIC {
+        return Err(FastPayError::DeserializationError);
+    }
+    let envelope = Envelope {
+        version: u16::from_le_bytes([bytes[4], bytes[5]]),
+        network: NetworkId::from_le_bytes([bytes[7], bytes[8], bytes[9], bytes[10]]),
+    };
+    Ok((envelope, bytes[6], &bytes[ENVELOPE_SIZE..]))
+}
+
+/// Serialize a message to bytes, in the given envelope
+pub fn serialize_message(envelope: Envelope, message: &BridgeMessage) -> Result<Vec<u8>, FastPayError> {
+    let mut bytes = envelope.header(message.kind());
+    bytes.extend(serialize(message).map_err(|_| FastPayError::SerializationError)?);
+    Ok(bytes)
+}
+
+/// Deserialize bytes to a message of the given network, along with the envelope to answer in
+pub fn open_message(
+    network: NetworkId,
+    bytes: &[u8],
+) -> Result<(Envelope, BridgeMessage), FastPayError> {
+    let (envelope, kind, body) = parse_envelope(bytes)?;
+    if kind == VERSION_MISMATCH {
+        // The peer does not speak our version, and told us which ones it does
+        if body.len() < 4 {
+            return Err(FastPayError::DeserializationError);
+        }
+        return Err(FastPayError::UnsupportedProtocolVersion {
+            version: PROTOCOL_VERSION,
+            min: u16::from_le_bytes([body[0], body[1]]),
+            max: u16::from_le_bytes([body[2], body[3]]),
+        });
+    }
+    if envelope.version < MIN_PROTOCOL_VERSION || envelope.version > PROTOCOL_VERSION {
+        return Err(FastPayError::UnsupportedProtocolVersion {
+            version: envelope.version,
+            min: MIN_PROTOCOL_VERSION,
+            max: PROTOCOL_VERSION,
+        });
+    }
+    if envelope.network != network {
+        return Err(FastPayError::WrongNetwork {
+            expected: network,
+            found: envelope.network,
+        });
+    }
+    let message: BridgeMessage = deserialize(body).map_err(|_| FastPayError::DeserializationError)?;
+    if message.kind() != kind {
+        return Err(FastPayError::DeserializationError);
+    }
+    Ok((envelope, message))
+}
+
+/// Deserialize bytes to a message of the given network
+pub fn deserialize_message(network: NetworkId, bytes: &[u8]) -> Result<BridgeMessage, FastPayError> {
+    open_message(network, bytes).map(|(_, message)| message)
+}
+
+/// Answer to a message whose version is not supported, telling which versions are.
+/// Its body is not versioned, so that any node can read it.
+pub fn serialize_version_mismatch(network: NetworkId) -> Vec<u8> {
+    let mut bytes = Envelope::new(network).header(VERSION_MISMATCH);
+    bytes.extend_from_slice(&MIN_PROTOCOL_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+    bytes
 }
 
 /// Serialize an error to a message
-pub fn serialize_error(error: &FastPayError) -> Vec<u8> {
-    let message = BridgeMessage::Error(format!("{:?}", error));
-    serialize_message(&message)
+pub fn serialize_error(envelope: Envelope, error: &FastPayError) -> Result<Vec<u8>, FastPayError> {
+    serialize_message(envelope, &BridgeMessage::Error(error.clone()))
+}
+
+pub fn serialize_ack(envelope: Envelope) -> Result<Vec<u8>, FastPayError> {
+    serialize_message(envelope, &BridgeMessage::Ack)
 }
 
 /// Helper functions for specific message types
-pub fn serialize_transfer_order(order: &CrossChainTransferOrder) -> Vec<u8> {
-    serialize_message(&BridgeMessage::CrossChainTransferOrder(order.clone()))
+pub fn serialize_transfer_order(envelope: Envelope, order: &CrossChainTransferOrder) -> Result<Vec<u8>, FastPayError> {
+    serialize_message(envelope, &BridgeMessage::CrossChainTransferOrder(order.clone()))
+}
+
+pub fn serialize_signed_order(envelope: Envelope, order: &SignedCrossChainTransferOrder) -> Result<Vec<u8>, FastPayError> {
+    serialize_message(envelope, &BridgeMessage::SignedCrossChainTransferOrder(order.clone()))
+}
+
+pub fn serialize_transfer_order_response(envelope: Envelope, response: &TransferOrderResponse) -> Result<Vec<u8>, FastPayError> {
+    match response {
+        TransferOrderResponse::Vote(order) => serialize_signed_order(envelope, order),
+        TransferOrderResponse::Certificate(order) => serialize_certified_order(envelope, order),
+    }
+}
+
+pub fn serialize_certified_order(envelope: Envelope, order: &CertifiedCrossChainTransferOrder) -> Result<Vec<u8>, FastPayError> {
+    serialize_message(envelope, &BridgeMessage::CertifiedCrossChainTransferOrder(order.clone()))
+}
+
+pub fn serialize_compact_certificate(envelope: Envelope, certificate: &CompactCertificate) -> Result<Vec<u8>, FastPayError> {
+    serialize_message(envelope, &BridgeMessage::CompactCertificate(certificate.clone()))
+}
+
+pub fn serialize_cross_shard_update(envelope: Envelope, update: &CrossShardCrossChainUpdate) -> Result<Vec<u8>, FastPayError> {
+    serialize_message(envelope, &BridgeMessage::CrossShardUpdate(update.clone()))
+}
+
+pub fn serialize_transfer_info_request(envelope: Envelope, request: &TransferInfoRequest) -> Result<Vec<u8>, FastPayError> {
+    serialize_message(envelope, &BridgeMessage::TransferInfoRequest(request.clone()))
+}
+
+pub fn serialize_transfer_info_response(envelope: Envelope, response: &TransferInfoResponse) -> Result<Vec<u8>, FastPayError> {
+    serialize_message(envelope, &BridgeMessage::TransferInfoResponse(response.clone()))
+}
+
+pub fn serialize_account_info_request(envelope: Envelope, request: &AccountInfoRequest) -> Result<Vec<u8>, FastPayError> {
+    serialize_message(envelope, &BridgeMessage::AccountInfoRequest(request.clone()))
+}
+
+pub fn serialize_account_info_response(envelope: Envelope, response: &AccountInfoResponse) -> Result<Vec<u8>, FastPayError> {
+    serialize_message(envelope, &BridgeMessage::AccountInfoResponse(response.clone()))
+}
+
+pub fn serialize_committee_change(envelope: Envelope, change: &CertifiedCommitteeChange) -> Result<Vec<u8>, FastPayError> {
+    serialize_message(envelope, &BridgeMessage::CommitteeChange(change.clone()))
+}
+
+pub fn serialize_committee_info_request(envelope: Envelope, request: &CommitteeInfoRequest) -> Result<Vec<u8>, FastPayError> {
+    serialize_message(envelope, &BridgeMessage::CommitteeInfoRequest(request.clone()))
+}
+
+pub fn serialize_committee_info_response(envelope: Envelope, response: &CommitteeInfoResponse) -> Result<Vec<u8>, FastPayError> {
+    serialize_message(envelope, &BridgeMessage::CommitteeInfoResponse(response.clone()))
+}
+
+pub fn serialize_message_order(envelope: Envelope, order: &CrossChainMessageOrder) -> Result<Vec<u8>, FastPayError> {
+    serialize_message(envelope, &BridgeMessage::CrossChainMessageOrder(order.clone()))
+}
+
+pub fn serialize_signed_message_order(envelope: Envelope, order: &SignedCrossChainMessageOrder) -> Result<Vec<u8>, FastPayError> {
+    serialize_message(envelope, &BridgeMessage::SignedCrossChainMessageOrder(order.clone()))
+}
+
+pub fn serialize_certified_message_order(envelope: Envelope, order: &CertifiedCrossChainMessageOrder) -> Result<Vec<u8>, FastPayError> {
+    serialize_message(envelope, &BridgeMessage::CertifiedCrossChainMessageOrder(order.clone()))
+}
+
+pub fn serialize_message_order_response(envelope: Envelope, response: &MessageOrderResponse) -> Result<Vec<u8>, FastPayError> {
+    match response {
+        MessageOrderResponse::Vote(order) => serialize_signed_message_order(envelope, order),
+        MessageOrderResponse::Certificate(order) => serialize_certified_message_order(envelope, order),
+    }
+}
+
+pub fn serialize_refund_order(envelope: Envelope, order: &CrossChainRefundOrder) -> Result<Vec<u8>, FastPayError> {
+    serialize_message(envelope, &BridgeMessage::CrossChainRefundOrder(order.clone()))
+}
+
+pub fn serialize_signed_refund_order(envelope: Envelope, order: &SignedCrossChainRefundOrder) -> Result<Vec<u8>, FastPayError> {
+    serialize_message(envelope, &BridgeMessage::SignedCrossChainRefundOrder(order.clone()))
 }
 
-pub fn serialize_signed_order(order: &SignedCrossChainTransferOrder) -> Vec<u8> {
-    serialize_message(&BridgeMessage::SignedCrossChainTransferOrder(order.clone()))
+pub fn serialize_certified_refund_order(envelope: Envelope, order: &CertifiedCrossChainRefundOrder) -> Result<Vec<u8>, FastPayError> {
+    serialize_message(envelope, &BridgeMessage::CertifiedCrossChainRefundOrder(order.clone()))
 }
 
-pub fn serialize_certified_order(order: &CertifiedCrossChainTransferOrder) -> Vec<u8> {
-    serialize_message(&BridgeMessage::CertifiedCrossChainTransferOrder(order.clone()))
+pub fn serialize_refund_order_response(envelope: Envelope, response: &RefundOrderResponse) -> Result<Vec<u8>, FastPayError> {
+    match response {
+        RefundOrderResponse::Vote(order) => serialize_signed_refund_order(envelope, order),
+        RefundOrderResponse::Certificate(order) => serialize_certified_refund_order(envelope, order),
+    }
 }
 
-pub fn serialize_cross_shard_update(update: &CrossShardCrossChainUpdate) -> Vec<u8> {
-    serialize_message(&BridgeMessage::CrossShardUpdate(update.clone()))
-}
\ No newline at end of file
+#[cfg(test)]
+#[path = "unit_tests/serialization_tests.rs"]
+mod serialization_tests;
@@ -0,0 +1,45 @@
+use super::{base_types::*, error::*};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+
+/// Mapping from senders to shards, shared by authorities, relayers and configuration tools.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize)]
+pub struct ShardingStrategy {
+    number_of_shards: u32,
+}
+
+impl ShardingStrategy {
+    pub fn new(number_of_shards: u32) -> Result<Self, FastPayError> {
+        if number_of_shards == 0 {
+            return Err(FastPayError::ConfigurationError {
+                error: "Number of shards must be positive".to_string(),
+            });
+        }
+        Ok(Self { number_of_shards })
+    }
+
+    pub fn number_of_shards(&self) -> u32 {
+        self.number_of_shards
+    }
+
+    /// Iterate over all the shards.
+    pub fn shard_ids(&self) -> impl Iterator<Item = ShardId> {
+        0..self.number_of_shards
+    }
+
+    /// Determine which shard should process the orders of a sender.
+    /// Hashing the full key keeps the shards balanced for any number of shards.
+    pub fn shard_of(&self, sender: &Pubkey) -> ShardId {
+        let mut hasher = Sha512::new();
+        hasher.update(b"ShardingStrategy::");
+        hasher.update(sender.0.as_ref());
+        let result = hasher.finalize();
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&result[0..8]);
+        (u64::from_le_bytes(bytes) % (self.number_of_shards as u64)) as ShardId
+    }
+}
+
+#[cfg(test)]
+#[path = "unit_tests/sharding_tests.rs"]
+mod sharding_tests;
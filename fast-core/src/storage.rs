@@ -0,0 +1,246 @@
+use super::{base_types::*, committee::CertifiedCommitteeChange, error::*, message::*};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// A durable change to the state of a shard.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ShardRecord {
+    /// The authority voted for this order.
+    Vote(CrossChainTransferOrder),
+    /// The authority processed a certificate for this transfer.
+    Certificate(CertifiedCrossChainTransferOrder),
+    /// The authority voted for this message order.
+    MessageVote(CrossChainMessageOrder),
+    /// The authority processed a certificate for this message.
+    MessageCertificate(CertifiedCrossChainMessageOrder),
+    /// The authority voted for refunding this transfer.
+    RefundVote(CrossChainRefundOrder),
+    /// The authority processed a refund certificate for this transfer.
+    RefundCertificate(CertifiedCrossChainRefundOrder),
+}
+
+/// Storage backend for the state of an authority. Shards append concurrently, so appends to
+/// different shards should not wait for each other.
+pub trait AuthorityStore: Send + Sync {
+    /// Append a record to the log of a shard. Must only return once the record is durable.
+    fn append(&self, shard_id: ShardId, record: &ShardRecord) -> Result<(), FastPayError>;
+
+    /// Read back all the records of a shard, in the order they were appended.
+    fn load(&self, shard_id: ShardId) -> Result<Vec<ShardRecord>, FastPayError>;
+
+    /// Atomically replace the records of a shard with fewer records leading to the same state.
+    fn replace(&self, shard_id: ShardId, records: &[ShardRecord]) -> Result<(), FastPayError>;
+
+    /// Append a committee change. Must only return once the change is durable.
+    fn append_committee_change(&self, change: &CertifiedCommitteeChange) -> Result<(), FastPayError>;
+
+    /// Read back all the committee changes, in the order they were appended.
+    fn load_committee_changes(&self) -> Result<Vec<CertifiedCommitteeChange>, FastPayError>;
+}
+
+fn storage_error<E: std::fmt::Display>(error: E) -> FastPayError {
+    FastPayError::StorageError {
+        error: error.to_string(),
+    }
+}
+
+/// Volatile store, for tests and local experiments.
+#[derive(Default)]
+pub struct MemoryStore {
+    records: Mutex<HashMap<ShardId, Vec<ShardRecord>>>,
+    committee_changes: Mutex<Vec<CertifiedCommitteeChange>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl AuthorityStore for MemoryStore {
+    fn append(&self, shard_id: ShardId, record: &ShardRecord) -> Result<(), FastPayError> {
+        self.records
+            .lock()
+            .unwrap()
+            .entry(shard_id)
+            .or_insert_with(Vec::new)
+            .push(record.clone());
+        Ok(())
+    }
+
+    fn load(&self, shard_id: ShardId) -> Result<Vec<ShardRecord>, FastPayError> {
+        Ok(self.records.lock().unwrap().get(&shard_id).cloned().unwrap_or_default())
+    }
+
+    fn replace(&self, shard_id: ShardId, records: &[ShardRecord]) -> Result<(), FastPayError> {
+        self.records.lock().unwrap().insert(shard_id, records.to_vec());
+        Ok(())
+    }
+
+    fn append_committee_change(&self, change: &CertifiedCommitteeChange) -> Result<(), FastPayError> {
+        self.committee_changes.lock().unwrap().push(change.clone());
+        Ok(())
+    }
+
+    fn load_committee_changes(&self) -> Result<Vec<CertifiedCommitteeChange>, FastPayError> {
+        Ok(self.committee_changes.lock().unwrap().clone())
+    }
+}
+
+/// On-disk store keeping one write-ahead log per shard, plus one for committee changes.
+///
+/// Each entry is a little-endian `u32` length followed by the bincode encoding of the
+/// record. Every append is synced to disk before returning. An incomplete entry at the end of
+/// a log (e.g. after a crash in the middle of a write) is discarded on load, whereas a complete
+/// entry that cannot be decoded is reported as an error rather than silently dropped.
+pub struct WalStore {
+    /// Directory holding the logs.
+    path: PathBuf,
+    /// Open log files, indexed by file name. Each log has its own lock, so that appends to
+    /// different logs, and their syncs to disk, do not wait for each other.
+    files: Mutex<HashMap<String, Arc<Mutex<File>>>>,
+}
+
+impl WalStore {
+    /// Open (or create) a store in the given directory.
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<Self, FastPayError> {
+        let path = path.into();
+        fs::create_dir_all(&path).map_err(storage_error)?;
+        Ok(Self {
+            path,
+            files: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn shard_log(shard_id: ShardId) -> String {
+        format!("shard_{}.wal", shard_id)
+    }
+
+    const COMMITTEE_LOG: &'static str = "committee.wal";
+
+    fn open_log(path: &Path) -> Result<File, FastPayError> {
+        OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .map_err(storage_error)
+    }
+
+    /// Sync the directory of the store, so that the logs created or renamed in it survive a
+    /// crash.
+    fn sync_dir(&self) -> Result<(), FastPayError> {
+        File::open(&self.path)
+            .and_then(|dir| dir.sync_all())
+            .map_err(storage_error)
+    }
+
+    fn file(&self, name: &str) -> Result<Arc<Mutex<File>>, FastPayError> {
+        let mut files = self.files.lock().unwrap();
+        if !files.contains_key(name) {
+            let file = Self::open_log(&self.path.join(name))?;
+            self.sync_dir()?;
+            files.insert(name.to_string(), Arc::new(Mutex::new(file)));
+        }
+        Ok(files[name].clone())
+    }
+
+    fn encode_entry<T: Serialize>(record: &T) -> Result<Vec<u8>, FastPayError> {
+        let bytes = bincode::serialize(record).map_err(storage_error)?;
+        let mut entry = Vec::with_capacity(4 + bytes.len());
+        entry.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        entry.extend_from_slice(&bytes);
+        Ok(entry)
+    }
+
+    /// Append an entry to the named log of the store, e.g. for the progress of a relayer.
+    pub fn append_entry<T: Serialize>(&self, name: &str, record: &T) -> Result<(), FastPayError> {
+        let entry = Self::encode_entry(record)?;
+        let file = self.file(name)?;
+        let mut file = file.lock().unwrap();
+        file.write_all(&entry).map_err(storage_error)?;
+        file.sync_data().map_err(storage_error)
+    }
+
+    /// Read back all the entries of the named log.
+    pub fn load_entries<T: DeserializeOwned>(&self, name: &str) -> Result<Vec<T>, FastPayError> {
+        let file = self.file(name)?;
+        let mut file = file.lock().unwrap();
+        let mut content = Vec::new();
+        file.seek(SeekFrom::Start(0)).map_err(storage_error)?;
+        file.read_to_end(&mut content).map_err(storage_error)?;
+
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while offset + 4 <= content.len() {
+            let mut len = [0u8; 4];
+            len.copy_from_slice(&content[offset..offset + 4]);
+            let end = offset + 4 + u32::from_le_bytes(len) as usize;
+            if end > content.len() {
+                break;
+            }
+            let record = bincode::deserialize(&content[offset + 4..end]).map_err(|e| {
+                storage_error(format!("Corrupted entry at offset {} of {}: {}", offset, name, e))
+            })?;
+            records.push(record);
+            offset = end;
+        }
+        if offset < content.len() {
+            // Only the last entry can be incomplete: drop it so that later appends remain
+            // readable.
+            file.set_len(offset as u64).map_err(storage_error)?;
+            file.sync_data().map_err(storage_error)?;
+        }
+        Ok(records)
+    }
+
+    /// Atomically replace the named log with the given entries, to drop the entries that no
+    /// longer matter.
+    pub fn replace_entries<T: Serialize>(&self, name: &str, records: &[T]) -> Result<(), FastPayError> {
+        let mut content = Vec::new();
+        for record in records {
+            content.extend(Self::encode_entry(record)?);
+        }
+        // Appends wait for the new log, rather than go to the replaced one
+        let log = self.file(name)?;
+        let mut log = log.lock().unwrap();
+        let tmp = self.path.join(format!("{}.tmp", name));
+        let mut file = File::create(&tmp).map_err(storage_error)?;
+        file.write_all(&content).map_err(storage_error)?;
+        file.sync_all().map_err(storage_error)?;
+        fs::rename(&tmp, self.path.join(name)).map_err(storage_error)?;
+        self.sync_dir()?;
+        *log = Self::open_log(&self.path.join(name))?;
+        Ok(())
+    }
+}
+
+impl AuthorityStore for WalStore {
+    fn append(&self, shard_id: ShardId, record: &ShardRecord) -> Result<(), FastPayError> {
+        self.append_entry(&Self::shard_log(shard_id), record)
+    }
+
+    fn load(&self, shard_id: ShardId) -> Result<Vec<ShardRecord>, FastPayError> {
+        self.load_entries(&Self::shard_log(shard_id))
+    }
+
+    fn replace(&self, shard_id: ShardId, records: &[ShardRecord]) -> Result<(), FastPayError> {
+        self.replace_entries(&Self::shard_log(shard_id), records)
+    }
+
+    fn append_committee_change(&self, change: &CertifiedCommitteeChange) -> Result<(), FastPayError> {
+        self.append_entry(Self::COMMITTEE_LOG, change)
+    }
+
+    fn load_committee_changes(&self) -> Result<Vec<CertifiedCommitteeChange>, FastPayError> {
+        self.load_entries(Self::COMMITTEE_LOG)
+    }
+}
+
+#[cfg(test)]
+#[path = "unit_tests/storage_tests.rs"]
+mod storage_tests;
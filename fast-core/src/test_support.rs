@@ -0,0 +1,91 @@
+use super::{base_types::*, message::*};
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Deadline of the transfers that must not expire during a test
+pub const NO_DEADLINE: Timestamp = 4_102_444_800; // 2100-01-01
+
+/// Transfer of tokens `[3; 32]` from chain 1 to chain 2, with its derived id. The escrow
+/// account is `[4; 32]`, not the one a portal program would derive.
+pub fn make_transfer(sender: Pubkey, recipient: Pubkey, nonce: u64, amount: u64) -> CrossChainTransfer {
+    let (source_chain, destination_chain, token_mint) = (ChainId(1), ChainId(2), Pubkey([3u8; 32]));
+    CrossChainTransfer {
+        source_chain,
+        destination_chain,
+        sender,
+        recipient,
+        amount,
+        token_mint,
+        interop_tx_id: InteropTxId::generate(
+            source_chain,
+            destination_chain,
+            sender,
+            recipient,
+            amount,
+            token_mint,
+            nonce,
+        ),
+        escrow_account: Pubkey([4u8; 32]),
+        nonce,
+        deadline: NO_DEADLINE,
+    }
+}
+
+/// Order of a transfer to `[2; 32]`, signed by its sender
+pub fn make_order(sender: &KeyPair, nonce: u64, amount: u64) -> CrossChainTransferOrder {
+    CrossChainTransferOrder::new(make_transfer(sender.public(), Pubkey([2u8; 32]), nonce, amount), sender)
+}
+
+/// Order of a message from chain 1 to program `[7; 32]` on chain 2, signed by its sender, with
+/// an id of its own for each nonce
+pub fn make_message(sender: &KeyPair, nonce: u64, payload: Vec<u8>) -> CrossChainMessageOrder {
+    let mut interop_tx_id = [6u8; 32];
+    interop_tx_id[..8].copy_from_slice(&nonce.to_le_bytes());
+    let message = CrossChainMessage {
+        source_chain: ChainId(1),
+        destination_chain: ChainId(2),
+        sender: sender.public(),
+        target_program: Pubkey([7u8; 32]),
+        payload,
+        interop_tx_id: InteropTxId(interop_tx_id),
+        nonce,
+    };
+    CrossChainMessageOrder::new(message, sender)
+}
+
+/// Serve JSON-RPC over HTTP, answering each call with the result given by `answer`, and
+/// return the URL to reach it.
+pub async fn rpc_stub<F>(answer: F) -> String
+where
+    F: Fn(&str, &Value) -> Value + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 4096];
+            // Read until the whole JSON body has arrived
+            let body = loop {
+                let n = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..n]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some((_, body)) = text.split_once("\r\n\r\n")
+                    && let Ok(body) = serde_json::from_str::<Value>(body)
+                {
+                    break body;
+                }
+            };
+            let result = answer(body["method"].as_str().unwrap(), &body["params"]);
+            let response = json!({ "jsonrpc": "2.0", "id": 1, "result": result }).to_string();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                response.len(),
+                response
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+    });
+    format!("http://{}", address)
+}
@@ -0,0 +1,661 @@
+use super::*;
+use crate::test_support::{make_message, make_transfer, NO_DEADLINE};
+
+type Inboxes = HashMap<ShardId, mpsc::UnboundedReceiver<CrossShardDelivery>>;
+
+fn init_state_with_inboxes(store: MemoryStore) -> (BridgeAuthorityState<MemoryStore>, Inboxes) {
+    let secret = KeyPair::from([1u8; 32]);
+    let name = secret.public();
+    let committee = Committee::new(0, 0, [(name, 1)].into_iter().collect(), [(name, 4)].into_iter().collect());
+    let sharding = ShardingStrategy::new(4).unwrap();
+    BridgeAuthorityState::new(name, secret, committee, sharding, store).unwrap()
+}
+
+fn init_state_with(store: MemoryStore) -> BridgeAuthorityState<MemoryStore> {
+    init_state_with_inboxes(store).0
+}
+
+fn init_state() -> BridgeAuthorityState<MemoryStore> {
+    init_state_with(MemoryStore::new())
+}
+
+fn make_order(sender: &KeyPair, nonce: u64, amount: u64, deadline: Timestamp) -> CrossChainTransferOrder {
+    let transfer = CrossChainTransfer {
+        deadline,
+        ..make_transfer(sender.public(), Pubkey([2u8; 32]), nonce, amount)
+    };
+    CrossChainTransferOrder::new(transfer, sender)
+}
+
+/// Vote for an order as if its escrow had been found
+async fn vote_for(
+    state: &BridgeAuthorityState<MemoryStore>,
+    order: &CrossChainTransferOrder
+) -> Result<TransferOrderResponse, FastPayError> {
+    let shard_id = state.get_shard_id(&order.transfer);
+    let escrow = VerifiedEscrow {
+        transfer_digest: ContentDigest::new(&order.transfer),
+    };
+    state.handle_cross_chain_transfer_order(order.clone(), shard_id, &escrow).await
+}
+
+fn certify<V: Votable>(state: &BridgeAuthorityState<MemoryStore>, order: &V) -> Certificate<V> {
+    let committees = state.committees();
+    let mut aggregator = SignatureAggregator::try_new(order.clone(), committees.current()).unwrap();
+    let vote = state.sign(order.clone());
+    aggregator.append(vote.authority, vote.signature).unwrap().unwrap()
+}
+
+async fn account_info(state: &BridgeAuthorityState<MemoryStore>, sender: &KeyPair) -> AccountInfoResponse {
+    let shard_id = state.sharding.shard_of(&sender.public());
+    state
+        .handle_account_info_request(AccountInfoRequest { sender: sender.public() }, shard_id)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_first_order_of_sender_has_nonce_zero() {
+    let state = init_state();
+    let sender = KeyPair::from([5u8; 32]);
+    assert_eq!(
+        vote_for(&state, &make_order(&sender, 1, 10, NO_DEADLINE)).await,
+        Err(FastPayError::UnexpectedTransactionIndex)
+    );
+    assert!(matches!(
+        vote_for(&state, &make_order(&sender, 0, 10, NO_DEADLINE)).await,
+        Ok(TransferOrderResponse::Vote(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_vote_locks_nonce_until_certified() {
+    let state = init_state();
+    let sender = KeyPair::from([5u8; 32]);
+    let order = make_order(&sender, 0, 10, NO_DEADLINE);
+    let Ok(TransferOrderResponse::Vote(vote)) = vote_for(&state, &order).await else {
+        panic!("Expected a vote");
+    };
+
+    // The same order gets the same vote again, but no other order of the sender
+    assert_eq!(vote_for(&state, &order).await, Ok(TransferOrderResponse::Vote(vote)));
+    let conflicting = make_order(&sender, 0, 20, NO_DEADLINE);
+    assert_eq!(
+        vote_for(&state, &conflicting).await,
+        Err(FastPayError::PreviousTransferMustBeConfirmedFirst {
+            pending_confirmation: order.transfer.clone(),
+        })
+    );
+    let next = make_order(&sender, 1, 10, NO_DEADLINE);
+    assert!(matches!(
+        vote_for(&state, &next).await,
+        Err(FastPayError::PreviousTransferMustBeConfirmedFirst { .. })
+    ));
+    assert_eq!(account_info(&state, &sender).await.pending_order.map(|vote| vote.value), Some(order.clone()));
+
+    // The certificate releases the nonce
+    let update = CrossShardCrossChainUpdate {
+        shard_id: state.sharding.shard_of(&sender.public()),
+        transfer_certificate: certify(&state, &order),
+    };
+    let certificate = update.transfer_certificate.clone();
+    state.handle_cross_shard_update(update).await.unwrap();
+    let info = account_info(&state, &sender).await;
+    assert_eq!((info.next_nonce, info.pending_order), (1, None));
+    assert_eq!(vote_for(&state, &order).await, Ok(TransferOrderResponse::Certificate(certificate)));
+    assert_eq!(
+        vote_for(&state, &conflicting).await,
+        Err(FastPayError::UnexpectedTransactionIndex)
+    );
+    assert!(matches!(vote_for(&state, &next).await, Ok(TransferOrderResponse::Vote(_))));
+}
+
+#[tokio::test]
+async fn test_last_nonce_overflows() {
+    let state = init_state();
+    let sender = KeyPair::from([5u8; 32]);
+    let update = CrossShardCrossChainUpdate {
+        shard_id: state.sharding.shard_of(&sender.public()),
+        transfer_certificate: certify(&state, &make_order(&sender, u64::MAX - 1, 10, NO_DEADLINE)),
+    };
+    state.handle_cross_shard_update(update).await.unwrap();
+    assert_eq!(account_info(&state, &sender).await.next_nonce, u64::MAX);
+    assert_eq!(
+        vote_for(&state, &make_order(&sender, u64::MAX, 10, NO_DEADLINE)).await,
+        Err(FastPayError::SequenceOverflow)
+    );
+}
+
+#[tokio::test]
+async fn test_nonce_lock_survives_restart() {
+    let state = init_state();
+    let sender = KeyPair::from([5u8; 32]);
+    let order = make_order(&sender, 0, 10, NO_DEADLINE);
+    assert!(matches!(vote_for(&state, &order).await, Ok(TransferOrderResponse::Vote(_))));
+
+    // Restart from the log of the shard
+    let shard_id = state.get_shard_id(&order.transfer);
+    let store = MemoryStore::new();
+    for record in state.store.load(shard_id).unwrap() {
+        store.append(shard_id, &record).unwrap();
+    }
+    let state = init_state_with(store);
+    assert_eq!(account_info(&state, &sender).await.pending_order.map(|vote| vote.value), Some(order.clone()));
+    assert!(matches!(
+        vote_for(&state, &make_order(&sender, 0, 20, NO_DEADLINE)).await,
+        Err(FastPayError::PreviousTransferMustBeConfirmedFirst { .. })
+    ));
+}
+
+#[tokio::test]
+async fn test_refund_vote_excludes_delivery_vote() {
+    let state = init_state();
+    let sender = KeyPair::from([5u8; 32]);
+    let order = make_order(&sender, 0, 10, NO_DEADLINE);
+    let shard_id = state.get_shard_id(&order.transfer);
+
+    // Vote to refund a transfer never voted for delivery...
+    let refund = CrossChainRefundOrder { transfer_order: order.clone() };
+    assert!(matches!(
+        state.handle_refund_order(refund, shard_id).await,
+        Ok(RefundOrderResponse::Vote(_))
+    ));
+
+    // ...then ask for the pending order of the sender, and for the transfer itself
+    let info = account_info(&state, &sender).await;
+    assert_eq!(info.pending_order, None);
+    assert_eq!(info.next_nonce, 0);
+    let request = TransferInfoRequest {
+        sender: sender.public(),
+        interop_tx_id: order.transfer.interop_tx_id,
+    };
+    assert_eq!(
+        state.handle_transfer_info_request(request, shard_id).await,
+        Err(FastPayError::CertificateNotfound)
+    );
+    assert_eq!(vote_for(&state, &order).await, Err(FastPayError::TransferExpired));
+}
+
+#[tokio::test]
+async fn test_refund_vote_withdraws_expired_delivery_vote() {
+    // The authority voted for a delivery that then expired
+    let sender = KeyPair::from([5u8; 32]);
+    let order = make_order(&sender, 0, 10, 1);
+    let store = MemoryStore::new();
+    let sharding = ShardingStrategy::new(4).unwrap();
+    let shard_id = sharding.shard_of(&sender.public());
+    store.append(shard_id, &ShardRecord::Vote(order.clone())).unwrap();
+    let state = init_state_with(store);
+    assert!(account_info(&state, &sender).await.pending_order.is_some());
+
+    let refund = CrossChainRefundOrder { transfer_order: order.clone() };
+    assert!(state.handle_refund_order(refund, shard_id).await.is_ok());
+    assert_eq!(account_info(&state, &sender).await.pending_order, None);
+    let request = TransferInfoRequest {
+        sender: sender.public(),
+        interop_tx_id: order.transfer.interop_tx_id,
+    };
+    assert!(state.handle_transfer_info_request(request, shard_id).await.is_err());
+}
+
+#[tokio::test]
+async fn test_refund_requires_expiry_after_delivery_vote() {
+    let state = init_state();
+    let sender = KeyPair::from([5u8; 32]);
+    let order = make_order(&sender, 0, 10, NO_DEADLINE);
+    let shard_id = state.get_shard_id(&order.transfer);
+    assert!(matches!(vote_for(&state, &order).await, Ok(TransferOrderResponse::Vote(_))));
+
+    let refund = CrossChainRefundOrder { transfer_order: order };
+    assert_eq!(
+        state.handle_refund_order(refund, shard_id).await,
+        Err(FastPayError::TransferNotExpired)
+    );
+}
+
+#[tokio::test]
+async fn test_refund_vote_holds_nonce_until_certified() {
+    let state = init_state();
+    let sender = KeyPair::from([5u8; 32]);
+    let order = make_order(&sender, 0, 10, NO_DEADLINE);
+    let shard_id = state.get_shard_id(&order.transfer);
+    let refund = CrossChainRefundOrder { transfer_order: order.clone() };
+    state.handle_refund_order(refund.clone(), shard_id).await.unwrap();
+
+    // Another order with the same nonce, or the next one, must wait for the refund
+    let conflicting = make_order(&sender, 0, 20, NO_DEADLINE);
+    assert!(matches!(
+        vote_for(&state, &conflicting).await,
+        Err(FastPayError::PreviousTransferMustBeConfirmedFirst { .. })
+    ));
+    let next = make_order(&sender, 1, 10, NO_DEADLINE);
+    assert!(vote_for(&state, &next).await.is_err());
+
+    let certificate = {
+        let committees = state.committees();
+        let mut aggregator = RefundSignatureAggregator::try_new(refund.clone(), committees.current()).unwrap();
+        let vote = state.sign(refund);
+        aggregator.append(vote.authority, vote.signature).unwrap().unwrap()
+    };
+    state.handle_refund_certificate(certificate, shard_id).await.unwrap();
+
+    assert_eq!(account_info(&state, &sender).await.next_nonce, 1);
+    assert!(matches!(vote_for(&state, &next).await, Ok(TransferOrderResponse::Vote(_))));
+    assert_eq!(vote_for(&state, &order).await, Err(FastPayError::TransferExpired));
+}
+
+#[tokio::test]
+async fn test_certified_transfer_is_never_refunded() {
+    let state = init_state();
+    let sender = KeyPair::from([5u8; 32]);
+    let shard_id = state.sharding.shard_of(&sender.public());
+    for (nonce, deadline) in [(0, NO_DEADLINE), (1, 1)] {
+        let update = CrossShardCrossChainUpdate {
+            shard_id,
+            transfer_certificate: certify(&state, &make_order(&sender, nonce, 10, deadline)),
+        };
+        state.handle_cross_shard_update(update).await.unwrap();
+    }
+
+    // Its certificate may have been delivered, before or after its deadline
+    for (nonce, deadline) in [(0, NO_DEADLINE), (1, 1)] {
+        let refund = CrossChainRefundOrder { transfer_order: make_order(&sender, nonce, 10, deadline) };
+        let error = state.handle_refund_order(refund, shard_id).await.unwrap_err();
+        assert_eq!(error, FastPayError::CertificateAlreadyExists);
+        assert!(!error.is_retryable());
+    }
+
+    // The nonce was released by the certificate, the sender moves on
+    assert_eq!(account_info(&state, &sender).await.next_nonce, 2);
+    let next = make_order(&sender, 2, 10, NO_DEADLINE);
+    assert!(matches!(vote_for(&state, &next).await, Ok(TransferOrderResponse::Vote(_))));
+}
+
+/// Counts the queries to the source chain, answering after a delay
+struct CountingVerifier(Arc<std::sync::atomic::AtomicUsize>);
+
+impl EscrowVerifier for CountingVerifier {
+    fn verify_escrow<'a>(
+        &'a self,
+        _transfer: &'a CrossChainTransfer
+    ) -> BoxFuture<'a, Result<bool, FastPayError>> {
+        self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        Box::pin(async {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            Ok(true)
+        })
+    }
+}
+
+#[tokio::test]
+async fn test_refund_vote_is_not_repeated_once_certified() {
+    let state = init_state();
+    let sender = KeyPair::from([5u8; 32]);
+    let shard_id = state.sharding.shard_of(&sender.public());
+    let order = make_order(&sender, 0, 10, 1);
+    let refund = CrossChainRefundOrder { transfer_order: order.clone() };
+    assert!(matches!(
+        state.handle_refund_order(refund.clone(), shard_id).await,
+        Ok(RefundOrderResponse::Vote(_))
+    ));
+
+    // The delivery gets certified all the same
+    let update = CrossShardCrossChainUpdate {
+        shard_id,
+        transfer_certificate: certify(&state, &order),
+    };
+    state.handle_cross_shard_update(update).await.unwrap();
+    assert_eq!(
+        state.handle_refund_order(refund, shard_id).await,
+        Err(FastPayError::CertificateAlreadyExists)
+    );
+}
+
+#[tokio::test]
+async fn test_escrow_cache_only_keeps_queries_in_flight() {
+    let queries = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let (_task, cache) = EscrowCache::start(CountingVerifier(queries.clone()));
+    let order = make_order(&KeyPair::from([5u8; 32]), 0, 10, NO_DEADLINE);
+
+    // Concurrent orders for the same transfer share one query
+    let (first, second) = tokio::join!(cache.verify(&order.transfer), cache.verify(&order.transfer));
+    assert!(first.is_ok() && second.is_ok());
+    assert_eq!(queries.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+    // The answer is not kept once delivered
+    assert!(cache.verify(&order.transfer).await.is_ok());
+    assert_eq!(queries.load(std::sync::atomic::Ordering::SeqCst), 2);
+}
+
+/// Finds the escrow of the transfers of 10 tokens only, answering after a delay
+struct AmountVerifier;
+
+impl EscrowVerifier for AmountVerifier {
+    fn verify_escrow<'a>(
+        &'a self,
+        transfer: &'a CrossChainTransfer
+    ) -> BoxFuture<'a, Result<bool, FastPayError>> {
+        Box::pin(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            Ok(transfer.amount == 10)
+        })
+    }
+}
+
+#[tokio::test]
+async fn test_escrow_answer_is_not_shared_with_another_transfer_of_the_same_id() {
+    let (_task, cache) = EscrowCache::start(AmountVerifier);
+    let order = make_order(&KeyPair::from([5u8; 32]), 0, 10, NO_DEADLINE);
+    let forged = CrossChainTransfer {
+        amount: 1000,
+        ..order.transfer.clone()
+    };
+
+    // The forged transfer waits for the query of the genuine one, but does not take its answer
+    let (genuine, other) = tokio::join!(cache.verify(&order.transfer), cache.verify(&forged));
+    assert!(genuine.is_ok());
+    assert!(matches!(other, Err(FastPayError::EscrowVerificationFailed { .. })));
+
+    // Asked on its own, it is refused
+    assert!(matches!(
+        cache.verify(&forged).await,
+        Err(FastPayError::InvalidTransferAmount { .. })
+    ));
+}
+
+#[tokio::test]
+async fn test_propagation_returns_once_all_shards_stored() {
+    let (state, inboxes) = init_state_with_inboxes(MemoryStore::new());
+    let state = Arc::new(state);
+    for (_, mut inbox) in inboxes {
+        let state = state.clone();
+        tokio::spawn(async move {
+            while let Some(CrossShardDelivery { update, done }) = inbox.recv().await {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                done.send(state.handle_cross_shard_update(update).await).unwrap_or(());
+            }
+        });
+    }
+    let order = make_order(&KeyPair::from([5u8; 32]), 0, 10, NO_DEADLINE);
+    state.propagate_certified_transfer(certify(&state, &order)).await.unwrap();
+
+    for shard_id in state.sharding.shard_ids() {
+        assert_eq!(state.store.load(shard_id).unwrap().len(), 1);
+        let shard_state = state.shard(shard_id).await.unwrap();
+        assert!(shard_state.processed_transfers.contains_key(&order.transfer.interop_tx_id));
+    }
+}
+
+#[tokio::test]
+async fn test_propagation_fails_without_inbox() {
+    let state = init_state();
+    let order = make_order(&KeyPair::from([5u8; 32]), 0, 10, NO_DEADLINE);
+    // Nobody stores the certificate, so it must not be acknowledged, but may be sent again
+    let error = state.propagate_certified_transfer(certify(&state, &order)).await.unwrap_err();
+    assert!(matches!(error, FastPayError::CrossShardDeliveryFailed { .. }));
+    assert!(error.is_retryable());
+}
+
+/// Restart from a copy of the logs of the shards of a state
+fn restart(state: &BridgeAuthorityState<MemoryStore>) -> BridgeAuthorityState<MemoryStore> {
+    let store = MemoryStore::new();
+    for shard_id in state.sharding.shard_ids() {
+        for record in state.store.load(shard_id).unwrap() {
+            store.append(shard_id, &record).unwrap();
+        }
+    }
+    init_state_with(store)
+}
+
+#[tokio::test]
+async fn test_restart_replaces_the_log_with_a_snapshot() {
+    let state = init_state();
+    let sender = KeyPair::from([5u8; 32]);
+    let (first, second) = (make_order(&sender, 0, 10, NO_DEADLINE), make_order(&sender, 1, 10, NO_DEADLINE));
+    let shard_id = state.get_shard_id(&first.transfer);
+    vote_for(&state, &first).await.unwrap();
+    let certificate = certify(&state, &first);
+    let update = CrossShardCrossChainUpdate {
+        shard_id,
+        transfer_certificate: certificate.clone(),
+    };
+    state.handle_cross_shard_update(update).await.unwrap();
+    vote_for(&state, &second).await.unwrap();
+    assert_eq!(state.store.load(shard_id).unwrap().len(), 3);
+
+    // The vote of the certified order is dropped
+    let state = restart(&state);
+    let records = state.store.load(shard_id).unwrap();
+    assert_eq!(records.len(), 2);
+    assert!(!records.iter().any(|record| matches!(record, ShardRecord::Vote(order) if *order == first)));
+    let info = account_info(&state, &sender).await;
+    assert_eq!((info.next_nonce, info.pending_order.map(|vote| vote.value)), (1, Some(second.clone())));
+    assert_eq!(vote_for(&state, &first).await, Ok(TransferOrderResponse::Certificate(certificate)));
+
+    // And the state is the same after another restart
+    let state = restart(&state);
+    assert_eq!(state.store.load(shard_id).unwrap().len(), 2);
+    assert_eq!(account_info(&state, &sender).await.pending_order.map(|vote| vote.value), Some(second));
+}
+
+#[tokio::test]
+async fn test_long_log_is_replaced_with_a_snapshot() {
+    let state = init_state();
+    let sender = KeyPair::from([5u8; 32]);
+    let order = make_order(&sender, 0, 10, NO_DEADLINE);
+    let shard_id = state.get_shard_id(&order.transfer);
+    vote_for(&state, &order).await.unwrap();
+    let update = CrossShardCrossChainUpdate {
+        shard_id,
+        transfer_certificate: certify(&state, &order),
+    };
+
+    // As if the log had grown long since the last snapshot
+    state.shard(shard_id).await.unwrap().log_length = MIN_RECORDS_BEFORE_SNAPSHOT;
+    state.handle_cross_shard_update(update).await.unwrap();
+    let records = state.store.load(shard_id).unwrap();
+    assert!(matches!(records[..], [ShardRecord::Certificate(_)]));
+    let shard_state = state.shard(shard_id).await.unwrap();
+    assert_eq!((shard_state.log_length, shard_state.snapshot_length), (1, 1));
+}
+
+#[tokio::test]
+async fn test_requests_to_another_shard_are_rejected() {
+    let state = init_state();
+    let sender = KeyPair::from([5u8; 32]);
+    let order = make_order(&sender, 0, 10, NO_DEADLINE);
+    let shard_id = state.get_shard_id(&order.transfer);
+    let other = (shard_id + 1) % state.sharding.number_of_shards();
+    let wrong_shard = FastPayError::WrongShard { expected: shard_id, num_shards: 4 };
+
+    let escrow = VerifiedEscrow {
+        transfer_digest: ContentDigest::new(&order.transfer),
+    };
+    let voted = state.handle_cross_chain_transfer_order(order.clone(), other, &escrow).await;
+    assert_eq!(voted, Err(wrong_shard.clone()));
+    let request = AccountInfoRequest { sender: sender.public() };
+    assert_eq!(state.handle_account_info_request(request, other).await.err(), Some(wrong_shard.clone()));
+    let request = TransferInfoRequest {
+        sender: sender.public(),
+        interop_tx_id: order.transfer.interop_tx_id,
+    };
+    assert_eq!(state.handle_transfer_info_request(request, other).await.err(), Some(wrong_shard));
+    // Nothing was recorded
+    assert!(state.store.load(other).unwrap().is_empty());
+    assert!(state.store.load(shard_id).unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_transfer_info_reports_the_vote_then_the_certificate() {
+    let state = init_state();
+    let sender = KeyPair::from([5u8; 32]);
+    let order = make_order(&sender, 0, 10, NO_DEADLINE);
+    let shard_id = state.get_shard_id(&order.transfer);
+    let request = TransferInfoRequest {
+        sender: sender.public(),
+        interop_tx_id: order.transfer.interop_tx_id,
+    };
+    assert_eq!(
+        state.handle_transfer_info_request(request.clone(), shard_id).await,
+        Err(FastPayError::CertificateNotfound)
+    );
+
+    let Ok(TransferOrderResponse::Vote(vote)) = vote_for(&state, &order).await else {
+        panic!("Expected a vote");
+    };
+    let info = state.handle_transfer_info_request(request.clone(), shard_id).await.unwrap();
+    assert_eq!(info.interop_tx_id, order.transfer.interop_tx_id);
+    assert_eq!(info.pending_order, Some(order.clone()));
+    assert_eq!(info.signed_order, Some(vote.clone()));
+    assert_eq!(info.certificate, None);
+    assert_eq!(account_info(&state, &sender).await.pending_order, Some(vote));
+
+    let certificate = certify(&state, &order);
+    let update = CrossShardCrossChainUpdate {
+        shard_id,
+        transfer_certificate: certificate.clone(),
+    };
+    state.handle_cross_shard_update(update).await.unwrap();
+    let info = state.handle_transfer_info_request(request, shard_id).await.unwrap();
+    assert_eq!((info.pending_order, info.signed_order), (None, None));
+    assert_eq!(info.certificate, Some(certificate));
+    let info = account_info(&state, &sender).await;
+    assert_eq!((info.sender, info.next_nonce, info.pending_order), (sender.public(), 1, None));
+}
+
+#[tokio::test]
+async fn test_resubmissions_get_the_stored_certificate() {
+    let state = init_state();
+    let sender = KeyPair::from([5u8; 32]);
+    let order = make_order(&sender, 0, 10, NO_DEADLINE);
+    let shard_id = state.get_shard_id(&order.transfer);
+    vote_for(&state, &order).await.unwrap();
+    let certificate = certify(&state, &order);
+    for _ in 0..2 {
+        let update = CrossShardCrossChainUpdate {
+            shard_id,
+            transfer_certificate: certificate.clone(),
+        };
+        state.handle_cross_shard_update(update).await.unwrap();
+    }
+    // Storing the certificate again changes nothing
+    assert_eq!(state.store.load(shard_id).unwrap().len(), 2);
+
+    // A relayer that lost the certificate gets it back, also after a restart
+    assert_eq!(vote_for(&state, &order).await, Ok(TransferOrderResponse::Certificate(certificate.clone())));
+    let state = restart(&state);
+    assert_eq!(vote_for(&state, &order).await, Ok(TransferOrderResponse::Certificate(certificate)));
+    assert_eq!(state.store.load(shard_id).unwrap().len(), 1);
+}
+
+async fn send_message(
+    state: &BridgeAuthorityState<MemoryStore>,
+    order: &CrossChainMessageOrder
+) -> Result<MessageOrderResponse, FastPayError> {
+    let shard_id = state.sharding.shard_of(&order.message.sender);
+    state.handle_cross_chain_message_order(order.clone(), shard_id).await
+}
+
+async fn certify_message(state: &BridgeAuthorityState<MemoryStore>, order: &CrossChainMessageOrder) {
+    let shard_id = state.sharding.shard_of(&order.message.sender);
+    state.handle_message_certificate(certify(state, order), shard_id).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_message_vote_locks_nonce_until_certified() {
+    let state = init_state();
+    let sender = KeyPair::from([5u8; 32]);
+    let order = make_message(&sender, 0, b"ping".to_vec());
+    assert_eq!(
+        send_message(&state, &make_message(&sender, 1, b"ping".to_vec())).await,
+        Err(FastPayError::UnexpectedTransactionIndex)
+    );
+    let Ok(MessageOrderResponse::Vote(vote)) = send_message(&state, &order).await else {
+        panic!("Expected a vote");
+    };
+
+    // The same order gets the same vote again, but no other message of the sender
+    assert_eq!(send_message(&state, &order).await, Ok(MessageOrderResponse::Vote(vote)));
+    assert_eq!(
+        send_message(&state, &make_message(&sender, 0, b"pong".to_vec())).await,
+        Err(FastPayError::PreviousMessageMustBeConfirmedFirst {
+            pending_confirmation: order.message.clone(),
+        })
+    );
+    let next = make_message(&sender, 1, b"ping".to_vec());
+    assert!(matches!(
+        send_message(&state, &next).await,
+        Err(FastPayError::PreviousMessageMustBeConfirmedFirst { .. })
+    ));
+    // Transfers of the sender have nonces of their own
+    assert!(matches!(
+        vote_for(&state, &make_order(&sender, 0, 10, NO_DEADLINE)).await,
+        Ok(TransferOrderResponse::Vote(_))
+    ));
+
+    // The certificate releases the nonce
+    certify_message(&state, &order).await;
+    assert_eq!(account_info(&state, &sender).await.next_message_nonce, 1);
+    assert_eq!(
+        send_message(&state, &order).await,
+        Ok(MessageOrderResponse::Certificate(certify(&state, &order)))
+    );
+    assert!(matches!(send_message(&state, &next).await, Ok(MessageOrderResponse::Vote(_))));
+}
+
+#[tokio::test]
+async fn test_message_payload_is_bounded() {
+    let state = init_state();
+    let sender = KeyPair::from([5u8; 32]);
+    let too_large = make_message(&sender, 0, vec![0; MAX_MESSAGE_PAYLOAD_SIZE + 1]);
+    assert_eq!(
+        send_message(&state, &too_large).await,
+        Err(FastPayError::MessagePayloadTooLarge {
+            size: MAX_MESSAGE_PAYLOAD_SIZE + 1,
+            max: MAX_MESSAGE_PAYLOAD_SIZE,
+        })
+    );
+    // Nothing was recorded
+    let shard_id = state.sharding.shard_of(&sender.public());
+    assert!(state.store.load(shard_id).unwrap().is_empty());
+
+    let largest = make_message(&sender, 0, vec![0; MAX_MESSAGE_PAYLOAD_SIZE]);
+    assert!(matches!(send_message(&state, &largest).await, Ok(MessageOrderResponse::Vote(_))));
+}
+
+#[tokio::test]
+async fn test_last_message_nonce_overflows() {
+    let state = init_state();
+    let sender = KeyPair::from([5u8; 32]);
+    certify_message(&state, &make_message(&sender, u64::MAX - 1, b"ping".to_vec())).await;
+    assert_eq!(account_info(&state, &sender).await.next_message_nonce, u64::MAX);
+    assert_eq!(
+        send_message(&state, &make_message(&sender, u64::MAX, b"ping".to_vec())).await,
+        Err(FastPayError::SequenceOverflow)
+    );
+}
+
+#[tokio::test]
+async fn test_message_votes_and_certificates_survive_restart() {
+    let state = init_state();
+    let sender = KeyPair::from([5u8; 32]);
+    let (first, second) = (make_message(&sender, 0, b"ping".to_vec()), make_message(&sender, 1, b"ping".to_vec()));
+    send_message(&state, &first).await.unwrap();
+    certify_message(&state, &first).await;
+    send_message(&state, &second).await.unwrap();
+
+    // Replayed from the log, then from the snapshot taken at the first restart
+    let mut state = state;
+    for _ in 0..2 {
+        state = restart(&state);
+        assert_eq!(account_info(&state, &sender).await.next_message_nonce, 1);
+        assert_eq!(
+            send_message(&state, &first).await,
+            Ok(MessageOrderResponse::Certificate(certify(&state, &first)))
+        );
+        assert!(matches!(
+            send_message(&state, &make_message(&sender, 1, b"pong".to_vec())).await,
+            Err(FastPayError::PreviousMessageMustBeConfirmedFirst { .. })
+        ));
+    }
+}
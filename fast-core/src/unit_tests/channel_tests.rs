@@ -0,0 +1,97 @@
+use super::*;
+
+fn handshake() -> (Session, Session) {
+    let client = KeyPair::from([1u8; 32]);
+    let server = KeyPair::from([2u8; 32]);
+    let initiator = Initiator::new(&client, server.public());
+    let (server_session, accept) = accept(&server, initiator.hello()).unwrap();
+    (initiator.finish(&accept).unwrap(), server_session)
+}
+
+#[test]
+fn test_sessions_authenticate_both_ends() {
+    let (client, server) = handshake();
+    assert_eq!(client.peer, KeyPair::from([2u8; 32]).public());
+    assert_eq!(server.peer, KeyPair::from([1u8; 32]).public());
+
+    let sealed = client.seal(b"header", b"request");
+    assert_eq!(server.open(b"header", &sealed), Some(b"request".to_vec()));
+    let sealed = server.seal(b"header", b"response");
+    assert_eq!(client.open(b"header", &sealed), Some(b"response".to_vec()));
+}
+
+#[test]
+fn test_replayed_message_is_rejected() {
+    let (client, server) = handshake();
+    let sealed = client.seal(b"", b"request");
+    assert!(server.open(b"", &sealed).is_some());
+    assert!(server.open(b"", &sealed).is_none());
+}
+
+#[test]
+fn test_messages_out_of_order_within_the_window() {
+    let (client, server) = handshake();
+    let first = client.seal(b"", b"first");
+    let second = client.seal(b"", b"second");
+    assert!(server.open(b"", &second).is_some());
+    assert!(server.open(b"", &first).is_some());
+    assert!(server.open(b"", &first).is_none());
+}
+
+#[test]
+fn test_message_out_of_the_window_is_rejected() {
+    let (client, server) = handshake();
+    let old = client.seal(b"", b"old");
+    for _ in 0..64 {
+        assert!(server.open(b"", &client.seal(b"", b"newer")).is_some());
+    }
+    assert!(server.open(b"", &old).is_none());
+}
+
+#[test]
+fn test_tampered_message_is_rejected() {
+    let (client, server) = handshake();
+    let mut sealed = client.seal(b"header", b"request");
+    let last = sealed.len() - 1;
+    sealed[last] ^= 1;
+    assert!(server.open(b"header", &sealed).is_none());
+
+    // Nor may the associated data change
+    let sealed = client.seal(b"header", b"request");
+    assert!(server.open(b"other header", &sealed).is_none());
+    // Tampering did not consume the counter of the message
+    assert!(server.open(b"header", &sealed).is_some());
+}
+
+#[test]
+fn test_accept_signed_by_another_key_is_rejected() {
+    let client = KeyPair::from([1u8; 32]);
+    let server = KeyPair::from([2u8; 32]);
+    let impostor = KeyPair::from([3u8; 32]);
+    let initiator = Initiator::new(&client, server.public());
+
+    // The impostor does not answer a hello meant for the server
+    let hello = initiator.hello().clone();
+    assert!(accept(&impostor, &hello).is_err());
+
+    // Nor can it answer in the name of the server
+    let binding = AcceptBinding {
+        initiator: hello.initiator,
+        responder: hello.responder,
+        initiator_ephemeral: hello.ephemeral,
+        responder_ephemeral: PublicKey::from(&ephemeral_secret()).to_bytes(),
+    };
+    let accept = Accept {
+        ephemeral: binding.responder_ephemeral,
+        signature: Signature::new(&binding, &impostor),
+    };
+    assert!(initiator.finish(&accept).is_err());
+}
+
+#[test]
+fn test_hello_signed_by_another_key_is_rejected() {
+    let server = KeyPair::from([2u8; 32]);
+    let mut hello = Initiator::new(&KeyPair::from([1u8; 32]), server.public()).hello().clone();
+    hello.initiator = KeyPair::from([3u8; 32]).public();
+    assert!(accept(&server, &hello).is_err());
+}
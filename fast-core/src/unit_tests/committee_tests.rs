@@ -0,0 +1,78 @@
+use super::*;
+
+fn committee_of(network: NetworkId, epoch: Epoch, keys: &[KeyPair]) -> Committee {
+    Committee::new(
+        network,
+        epoch,
+        keys.iter().map(|key| (key.public(), 1)).collect(),
+        keys.iter().map(|key| (key.public(), 2)).collect(),
+    )
+}
+
+fn certify(change: CommitteeChange, signers: &[KeyPair]) -> CertifiedCommitteeChange {
+    let signatures = signers
+        .iter()
+        .map(|key| (key.public(), SignedCommitteeChange::new(change.clone(), key.public(), key).signature))
+        .collect();
+    CertifiedCommitteeChange {
+        value: change,
+        signatures,
+    }
+}
+
+#[test]
+fn test_committee_change() {
+    let keys: Vec<_> = (1..=4).map(|i| KeyPair::from([i; 32])).collect();
+    let mut history = CommitteeHistory::new(committee_of(7, 0, &keys));
+    let next = committee_of(7, 1, &keys[1..]);
+    let change = CommitteeChange {
+        network: 7,
+        epoch: 1,
+        voting_rights: next.voting_rights.clone(),
+        num_shards: next.num_shards.clone(),
+    };
+
+    // Two votes out of four fall short of a quorum
+    let partial = certify(change.clone(), &keys[..2]);
+    assert_eq!(history.advance(partial), Err(FastPayError::CertificateRequiresQuorum));
+
+    let certificate = certify(change, &keys[..3]);
+    assert_eq!(history.advance(certificate.clone()), Ok(true));
+    assert_eq!(history.current(), &next);
+    assert_eq!(history.changes_since(0), vec![certificate.clone()]);
+    // Known changes are ignored
+    assert_eq!(history.advance(certificate), Ok(false));
+}
+
+#[test]
+fn test_committee_change_of_another_network() {
+    let keys: Vec<_> = (1..=4).map(|i| KeyPair::from([i; 32])).collect();
+    let committee = committee_of(7, 0, &keys);
+    let change = CommitteeChange {
+        network: 8,
+        epoch: 1,
+        voting_rights: committee.voting_rights.clone(),
+        num_shards: committee.num_shards.clone(),
+    };
+    // Even signed by the whole committee, a change for another deployment is refused
+    let certificate = certify(change, &keys);
+    assert_eq!(
+        certificate.check(&committee),
+        Err(FastPayError::WrongNetwork { expected: 7, found: 8 })
+    );
+}
+
+#[test]
+fn test_committee_change_needs_shard_counts() {
+    let keys: Vec<_> = (1..=4).map(|i| KeyPair::from([i; 32])).collect();
+    let committee = committee_of(7, 0, &keys);
+    let mut num_shards = committee.num_shards.clone();
+    num_shards.insert(keys[0].public(), 0);
+    let change = CommitteeChange {
+        network: 7,
+        epoch: 1,
+        voting_rights: committee.voting_rights.clone(),
+        num_shards,
+    };
+    assert_eq!(certify(change, &keys).check(&committee), Err(FastPayError::InvalidCommitteeChange));
+}
@@ -0,0 +1,129 @@
+use super::*;
+
+fn entry(seed: u8, host: &str, port: u16, num_shards: u32) -> AuthorityEntry {
+    AuthorityEntry {
+        name: encode_authority_name(&KeyPair::from([seed; 32]).public()),
+        host: host.to_string(),
+        port,
+        weight: 1,
+        num_shards,
+        transport: Transport::Udp,
+        security: Security::Encrypted,
+    }
+}
+
+fn committee(authorities: Vec<AuthorityEntry>) -> CommitteeConfig {
+    CommitteeConfig {
+        epoch: 0,
+        network: 7,
+        authorities,
+    }
+}
+
+fn rejection(config: &CommitteeConfig) -> String {
+    match config.validate() {
+        Err(FastPayError::ConfigurationError { error }) => error,
+        other => panic!("Expected a configuration error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_valid_committee() {
+    let config = committee(vec![
+        entry(1, "127.0.0.1", 9000, 4),
+        // Right after the shards of the first authority
+        entry(2, "127.0.0.1", 9004, 4),
+        // Same ports on another host
+        entry(3, "127.0.0.2", 9000, 4),
+        // Last shard on the last port
+        entry(4, "127.0.0.1", u16::MAX - 1, 2),
+    ]);
+    assert_eq!(config.validate(), Ok(()));
+    let committee = config.committee().unwrap();
+    assert_eq!(committee.num_shards[&KeyPair::from([1; 32]).public()], 4);
+}
+
+#[test]
+fn test_invalid_shard_counts_are_rejected() {
+    let config = committee(vec![entry(1, "127.0.0.1", 9000, 4), entry(2, "127.0.0.1", 9100, 0)]);
+    assert!(rejection(&config).contains("at least one shard"));
+
+    let config = committee(vec![entry(1, "127.0.0.1", u16::MAX - 1, 3)]);
+    assert!(rejection(&config).contains("do not fit"));
+}
+
+#[test]
+fn test_overlapping_ports_are_rejected() {
+    let config = committee(vec![entry(1, "127.0.0.1", 9000, 4), entry(2, "127.0.0.1", 9003, 4)]);
+    assert!(rejection(&config).contains("collide"));
+
+    let config = committee(vec![entry(1, "127.0.0.1", 9004, 4), entry(2, "127.0.0.1", 9000, 5)]);
+    assert!(rejection(&config).contains("collide"));
+
+    // The same host, written differently
+    let config = committee(vec![entry(1, "127.0.0.1", 9000, 4), entry(2, "localhost", 9002, 4)]);
+    assert!(rejection(&config).contains("collide"));
+
+    // A server listening on all interfaces
+    let config = committee(vec![entry(1, "0.0.0.0", 9000, 4), entry(2, "127.0.0.2", 9002, 4)]);
+    assert!(rejection(&config).contains("collide"));
+}
+
+#[test]
+fn test_duplicates_and_zero_weights_are_rejected() {
+    assert!(rejection(&committee(Vec::new())).contains("no authorities"));
+
+    let config = committee(vec![entry(1, "127.0.0.1", 9000, 4), entry(1, "127.0.0.2", 9000, 4)]);
+    assert!(rejection(&config).contains("listed twice"));
+
+    let mut weightless = entry(2, "127.0.0.1", 9100, 4);
+    weightless.weight = 0;
+    let config = committee(vec![entry(1, "127.0.0.1", 9000, 4), weightless]);
+    assert!(rejection(&config).contains("zero weight"));
+
+    let mut malformed = entry(2, "127.0.0.1", 9100, 4);
+    malformed.name.pop();
+    assert!(committee(vec![malformed]).validate().is_err());
+}
+
+#[test]
+fn test_servers_must_listen_where_the_committee_expects() {
+    let entry = entry(1, "127.0.0.1", 9000, 4);
+    assert_eq!(entry.check_listener(9000, 4), Ok(()));
+    assert!(entry.check_listener(9000, 16).is_err());
+    assert!(entry.check_listener(9001, 4).is_err());
+}
+
+#[test]
+fn test_committee_config_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("committee.json");
+    let config = committee(vec![entry(1, "127.0.0.1", 9000, 4), entry(2, "127.0.0.1", 9004, 4)]);
+    config.write(&path).unwrap();
+    let read = CommitteeConfig::read(&path).unwrap();
+    assert_eq!(read.authorities.len(), 2);
+    assert_eq!(read.network, 7);
+
+    committee(vec![entry(1, "127.0.0.1", 9000, 4), entry(2, "127.0.0.1", 9000, 4)])
+        .write(&path)
+        .unwrap();
+    assert!(CommitteeConfig::read(&path).is_err());
+}
+
+#[test]
+fn test_escrow_verification_is_only_disabled_explicitly() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("authority.json");
+    let name = encode_authority_name(&KeyPair::from([1u8; 32]).public());
+    let mut config = serde_json::json!({
+        "name": name,
+        "secret_key": hex::encode([1u8; 32]),
+        "committee": "committee.json",
+    });
+    std::fs::write(&path, config.to_string()).unwrap();
+    assert!(AuthorityConfig::read(&path).is_err());
+
+    config["escrow"] = serde_json::json!({ "type": "disabled" });
+    std::fs::write(&path, config.to_string()).unwrap();
+    assert!(matches!(AuthorityConfig::read(&path).unwrap().escrow, EscrowConfig::Disabled));
+}
@@ -0,0 +1,124 @@
+use super::*;
+use crate::test_support::{make_transfer, rpc_stub};
+
+const PORTAL: Pubkey = Pubkey([9u8; 32]);
+
+/// Transfer locked in the escrow the portal derives for it
+fn escrowed_transfer(sender: Pubkey, nonce: u64, amount: u64) -> CrossChainTransfer {
+    let transfer = make_transfer(sender, Pubkey([2u8; 32]), nonce, amount);
+    CrossChainTransfer {
+        escrow_account: escrow_address(&PORTAL, &sender, &transfer.interop_tx_id).unwrap(),
+        ..transfer
+    }
+}
+
+/// Token account of the portal holding the given amount of the mint of a transfer
+fn token_account(transfer: &CrossChainTransfer, amount: u64) -> Value {
+    token_account_of(transfer, amount, escrow_authority(&PORTAL).unwrap())
+}
+
+fn token_account_of(transfer: &CrossChainTransfer, amount: u64, owner: Pubkey) -> Value {
+    json!({
+        "data": {
+            "parsed": {
+                "type": "account",
+                "info": {
+                    "mint": transfer.token_mint.base58(),
+                    "owner": owner.base58(),
+                    "state": "initialized",
+                    "tokenAmount": { "amount": amount.to_string() },
+                }
+            }
+        }
+    })
+}
+
+/// Serve `getAccountInfo` from the given accounts, answering null for the others.
+async fn accounts_stub(accounts: Vec<(Pubkey, Value)>) -> String {
+    rpc_stub(move |method, params| {
+        assert_eq!(method, "getAccountInfo");
+        let account = params[0].as_str().unwrap();
+        let value = accounts
+            .iter()
+            .find(|(key, _)| key.base58() == account)
+            .map_or(Value::Null, |(_, value)| value.clone());
+        json!({ "value": value })
+    })
+    .await
+}
+
+fn verifier(url: String) -> SvmRpcEscrowVerifier {
+    SvmRpcEscrowVerifier::new(url, PORTAL, Commitment::Finalized).unwrap()
+}
+
+#[test]
+fn test_escrow_address_is_off_curve_and_per_transfer() {
+    let sender = KeyPair::from([1u8; 32]).public();
+    let first = escrowed_transfer(sender, 0, 100);
+    let second = escrowed_transfer(sender, 1, 100);
+    assert!(!first.escrow_account.is_on_curve());
+    assert_ne!(first.escrow_account, second.escrow_account);
+    assert!(is_escrow_of(&PORTAL, &first));
+    assert!(!is_escrow_of(&Pubkey([8u8; 32]), &first));
+}
+
+#[tokio::test]
+async fn test_escrow_of_transfer_is_accepted() {
+    let transfer = escrowed_transfer(KeyPair::from([1u8; 32]).public(), 0, 100);
+    let url = accounts_stub(vec![(transfer.escrow_account, token_account(&transfer, 100))]).await;
+    assert!(verifier(url).verify_escrow(&transfer).await.unwrap());
+}
+
+#[tokio::test]
+async fn test_short_escrow_is_rejected() {
+    let transfer = escrowed_transfer(KeyPair::from([1u8; 32]).public(), 0, 100);
+    let verifier = verifier(accounts_stub(vec![(transfer.escrow_account, token_account(&transfer, 99))]).await);
+    assert!(!verifier.verify_escrow(&transfer).await.unwrap());
+}
+
+#[tokio::test]
+async fn test_missing_escrow_may_show_up_later() {
+    let transfer = escrowed_transfer(KeyPair::from([1u8; 32]).public(), 0, 100);
+    let verifier = verifier(accounts_stub(Vec::new()).await);
+    let error = verifier.verify_escrow(&transfer).await.unwrap_err();
+    assert_eq!(
+        error,
+        FastPayError::EscrowNotFound { escrow_account: transfer.escrow_account.base58() }
+    );
+    assert!(error.is_retryable());
+}
+
+#[tokio::test]
+async fn test_escrow_must_be_owned_by_the_escrow_authority() {
+    let transfer = escrowed_transfer(KeyPair::from([1u8; 32]).public(), 0, 100);
+    assert!(!escrow_authority(&PORTAL).unwrap().is_on_curve());
+    // Neither the portal program nor the sender controls the escrow
+    for owner in [PORTAL, transfer.sender] {
+        let url = accounts_stub(vec![(transfer.escrow_account, token_account_of(&transfer, 100, owner))]).await;
+        assert!(!verifier(url).verify_escrow(&transfer).await.unwrap());
+    }
+}
+
+#[tokio::test]
+async fn test_deposit_cannot_back_another_transfer() {
+    let sender = KeyPair::from([1u8; 32]).public();
+    let funded = escrowed_transfer(sender, 0, 100);
+    let url = accounts_stub(vec![(funded.escrow_account, token_account(&funded, 100))]).await;
+    let verifier = verifier(url);
+
+    // Another transfer pointing at the funded escrow
+    let mut reused = escrowed_transfer(sender, 1, 100);
+    reused.escrow_account = funded.escrow_account;
+    assert!(!verifier.verify_escrow(&reused).await.unwrap());
+
+    // The same escrow and id, claimed by another sender
+    let mut stolen = funded.clone();
+    stolen.sender = KeyPair::from([7u8; 32]).public();
+    assert!(!verifier.verify_escrow(&stolen).await.unwrap());
+
+    // An id that is not derived from the transfer, with its matching escrow address
+    let mut forged = funded.clone();
+    forged.amount = 50;
+    forged.escrow_account = escrow_address(&PORTAL, &forged.sender, &forged.interop_tx_id).unwrap();
+    assert!(!verifier.verify_escrow(&forged).await.unwrap());
+}
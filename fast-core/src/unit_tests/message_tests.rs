@@ -0,0 +1,190 @@
+use super::*;
+use crate::test_support;
+
+/// Ten authorities, so that the signer bitmap takes two bytes, with one to three shards
+fn make_committee() -> (Committee, Vec<KeyPair>) {
+    let secrets: Vec<_> = (1..=10u8).map(|i| KeyPair::from([i; 32])).collect();
+    let committee = Committee::new(
+        0,
+        3,
+        secrets.iter().map(|secret| (secret.public(), 1)).collect(),
+        secrets.iter().zip(0..).map(|(secret, i)| (secret.public(), i % 3 + 1)).collect(),
+    );
+    (committee, secrets)
+}
+
+fn make_order() -> CrossChainTransferOrder {
+    test_support::make_order(&KeyPair::from([20u8; 32]), 0, 10)
+}
+
+fn vote(order: &CrossChainTransferOrder, committee: &Committee, secret: &KeyPair) -> SignedCrossChainTransferOrder {
+    let shard = committee.shard_of(&secret.public(), &order.transfer.sender).unwrap();
+    SignedCrossChainTransferOrder::new(order.clone(), secret.public(), committee, shard, secret)
+}
+
+/// Certificate signed by the given authorities, in the given order
+fn certify<V: Votable>(value: V, committee: &Committee, signers: &[&KeyPair]) -> Certificate<V> {
+    Certificate {
+        epoch: committee.epoch,
+        signatures: signers
+            .iter()
+            .map(|secret| {
+                let shard = committee.shard_of(&secret.public(), &value.key().0).unwrap();
+                let vote = SignedOrder::new(value.clone(), secret.public(), committee, shard, secret);
+                (vote.authority, vote.signature)
+            })
+            .collect(),
+        value,
+    }
+}
+
+#[test]
+fn test_certificate_from_authorities_with_different_shards() {
+    let (committee, secrets) = make_committee();
+    let order = make_order();
+    let mut aggregator = CrossChainSignatureAggregator::try_new(order.clone(), &committee).unwrap();
+    let mut certificate = None;
+    for secret in &secrets[..7] {
+        let vote = vote(&order, &committee, secret);
+        certificate = aggregator.append(vote.authority, vote.signature).unwrap();
+    }
+    assert_eq!(certificate.unwrap().check(&committee), Ok(()));
+}
+
+#[test]
+fn test_vote_is_bound_to_network_and_shard() {
+    let (committee, secrets) = make_committee();
+    let order = make_order();
+    let secret = &secrets[2];
+    let vote = vote(&order, &committee, secret);
+    assert_eq!(vote.check(&committee), Ok(1));
+
+    // The same vote does not count for another deployment
+    let mut other_network = committee.clone();
+    other_network.network = 1;
+    assert!(matches!(vote.check(&other_network), Err(FastPayError::InvalidSignature { .. })));
+
+    // Nor when signed from a shard that does not hold the sender
+    let wrong_shard = SignedCrossChainTransferOrder::new(
+        order.clone(),
+        secret.public(),
+        &committee,
+        committee.shard_of(&secret.public(), &order.transfer.sender).unwrap() + 1,
+        secret,
+    );
+    assert!(matches!(wrong_shard.check(&committee), Err(FastPayError::InvalidSignature { .. })));
+}
+
+#[test]
+fn test_vote_is_not_a_user_signature() {
+    let (committee, secrets) = make_committee();
+    let order = make_order();
+    let secret = &secrets[0];
+    let vote = vote(&order, &committee, secret);
+    assert!(vote.signature.check(&order.transfer, secret.public()).is_err());
+
+    // Nor does the same content signed in another role
+    let shard = committee.shard_of(&secret.public(), &order.transfer.sender).unwrap();
+    let as_user = Vote { role: SignerRole::User, ..order.vote(&committee, shard) };
+    assert!(vote.signature.check(&as_user, secret.public()).is_err());
+}
+
+#[test]
+fn test_compact_certificate_round_trip() {
+    let (committee, secrets) = make_committee();
+    // Out of committee order, and across both bytes of the bitmap
+    let signers = [&secrets[9], &secrets[0], &secrets[8], &secrets[3], &secrets[5], &secrets[7], &secrets[2]];
+    let certificate = certify(make_order(), &committee, &signers);
+    certificate.check(&committee).unwrap();
+
+    let compact = certificate.compact(&committee).unwrap();
+    assert_eq!(compact.signers.len(), 2);
+    assert_eq!(compact.signatures.len(), signers.len());
+    compact.check(&committee).unwrap();
+
+    let mut expanded = compact.expand(&committee).unwrap();
+    expanded.check(&committee).unwrap();
+    let mut signatures = certificate.signatures.clone();
+    signatures.sort_by_key(|(name, _)| committee.position(name));
+    assert_eq!(expanded.signatures, signatures);
+    expanded.signatures = certificate.signatures.clone();
+    assert_eq!(expanded, certificate);
+
+    // Names are replaced by the bitmap
+    let bitmap = bincode::serialized_size(&compact.signers).unwrap();
+    assert_eq!(
+        bincode::serialized_size(&compact).unwrap() + 32 * signers.len() as u64,
+        bincode::serialized_size(&certificate).unwrap() + bitmap
+    );
+}
+
+#[test]
+fn test_compact_message_certificate_round_trip() {
+    let (committee, secrets) = make_committee();
+    let order = test_support::make_message(&KeyPair::from([20u8; 32]), 0, b"ping".to_vec());
+    let signers: Vec<_> = secrets.iter().take(7).collect();
+    let certificate = certify(order, &committee, &signers);
+
+    let compact = certificate.compact(&committee).unwrap();
+    compact.check(&committee).unwrap();
+    let mut expanded = compact.expand(&committee).unwrap();
+    expanded.signatures.sort_by_key(|(name, _)| secrets.iter().position(|secret| secret.public() == *name));
+    assert_eq!(expanded, certificate);
+}
+
+#[test]
+fn test_compact_certificate_needs_its_committee() {
+    let (committee, secrets) = make_committee();
+    let signers: Vec<_> = secrets.iter().take(7).collect();
+    let certificate = certify(make_order(), &committee, &signers);
+    let compact = certificate.compact(&committee).unwrap();
+
+    let next = Committee::new(0, 4, committee.voting_rights.clone(), committee.num_shards.clone());
+    assert_eq!(
+        compact.expand(&next),
+        Err(FastPayError::EpochMismatch { expected: 4, found: 3 })
+    );
+    let stranger = KeyPair::from([30u8; 32]);
+    let mut foreign = certificate.clone();
+    foreign.signatures[0].0 = stranger.public();
+    assert_eq!(foreign.compact(&committee), Err(FastPayError::UnknownSigner));
+}
+
+#[test]
+fn test_compact_certificate_rejects_malformed_bitmap() {
+    let (committee, secrets) = make_committee();
+    let signers: Vec<_> = secrets.iter().take(7).collect();
+    let compact = certify(make_order(), &committee, &signers).compact(&committee).unwrap();
+
+    let mut short = compact.clone();
+    short.signers.pop();
+    assert_eq!(short.expand(&committee), Err(FastPayError::InvalidSignerBitmap));
+
+    // A bit past the end of the committee, with a signature for it
+    let mut overflow = compact.clone();
+    overflow.signers[1] |= 0x80;
+    overflow.signatures.push(compact.signatures[0]);
+    assert_eq!(overflow.expand(&committee), Err(FastPayError::InvalidSignerBitmap));
+
+    let mut missing = compact.clone();
+    missing.signatures.pop();
+    assert_eq!(missing.expand(&committee), Err(FastPayError::InvalidSignerBitmap));
+}
+
+#[test]
+fn test_compact_certificate_rejects_moved_signatures() {
+    let (committee, secrets) = make_committee();
+    let signers: Vec<_> = secrets.iter().take(7).collect();
+    let compact = certify(make_order(), &committee, &signers).compact(&committee).unwrap();
+
+    // Each signature is checked against the name at its position
+    let mut swapped = compact.clone();
+    swapped.signatures.swap(0, 1);
+    assert!(swapped.check(&committee).is_err());
+
+    // Without a quorum
+    let mut thin = compact;
+    thin.signers = vec![0b0001_1111, 0];
+    thin.signatures.truncate(5);
+    assert_eq!(thin.check(&committee), Err(FastPayError::CertificateRequiresQuorum));
+}
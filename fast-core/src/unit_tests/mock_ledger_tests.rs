@@ -0,0 +1,161 @@
+use super::*;
+use crate::{authority::*, sharding::ShardingStrategy, storage::MemoryStore, test_support::NO_DEADLINE};
+
+const SOURCE: ChainId = ChainId(1);
+const DESTINATION: ChainId = ChainId(2);
+const MINT: Pubkey = Pubkey([3u8; 32]);
+
+/// A committee of four authorities, each with two shards, and both chains of a transfer
+struct Setup {
+    committee: Committee,
+    authorities: Vec<BridgeAuthorityState<MemoryStore>>,
+    source: SharedMockChain,
+    destination: MockSvmChain,
+    escrow: EscrowCache,
+}
+
+fn setup() -> Setup {
+    let secrets: Vec<_> = (1..=4u8).map(|i| KeyPair::from([i; 32])).collect();
+    let committee = Committee::new(
+        0,
+        0,
+        secrets.iter().map(|secret| (secret.public(), 1)).collect(),
+        secrets.iter().map(|secret| (secret.public(), 2)).collect(),
+    );
+    let authorities = secrets
+        .into_iter()
+        .map(|secret| {
+            let sharding = ShardingStrategy::new(2).unwrap();
+            BridgeAuthorityState::new(secret.public(), secret, committee.clone(), sharding, MemoryStore::new())
+                .unwrap()
+                .0
+        })
+        .collect();
+    let portal = Pubkey([9u8; 32]);
+    let source = MockSvmChain::new(SOURCE, portal, committee.clone()).into_shared();
+    let destination = MockSvmChain::new(DESTINATION, portal, committee.clone());
+    let (_, escrow) = EscrowCache::start(source.clone());
+    Setup {
+        committee,
+        authorities,
+        source,
+        destination,
+        escrow,
+    }
+}
+
+impl Setup {
+    /// Collect the votes of the authorities for a transfer until a quorum certifies it
+    async fn certify(&self, order: &CrossChainTransferOrder) -> CertifiedCrossChainTransferOrder {
+        let mut aggregator = CrossChainSignatureAggregator::try_new(order.clone(), &self.committee).unwrap();
+        for authority in &self.authorities {
+            let shard_id = authority.get_shard_id(&order.transfer);
+            assert!(authority.check_cross_chain_transfer_order(order, shard_id).await.unwrap().is_none());
+            let verified = self.escrow.verify(&order.transfer).await.unwrap();
+            let response = authority
+                .handle_cross_chain_transfer_order(order.clone(), shard_id, &verified)
+                .await
+                .unwrap();
+            let TransferOrderResponse::Vote(vote) = response else {
+                panic!("Expected a vote");
+            };
+            if let Some(certificate) = aggregator.append(vote.authority, vote.signature).unwrap() {
+                return certificate;
+            }
+        }
+        panic!("No quorum");
+    }
+
+    /// Collect the refund votes of the authorities until a quorum certifies the refund
+    async fn certify_refund(&self, order: &CrossChainTransferOrder) -> CertifiedCrossChainRefundOrder {
+        let refund = CrossChainRefundOrder {
+            transfer_order: order.clone(),
+        };
+        let mut aggregator = RefundSignatureAggregator::try_new(refund.clone(), &self.committee).unwrap();
+        for authority in &self.authorities {
+            let shard_id = authority.get_shard_id(&order.transfer);
+            let response = authority.handle_refund_order(refund.clone(), shard_id).await.unwrap();
+            let RefundOrderResponse::Vote(vote) = response else {
+                panic!("Expected a vote");
+            };
+            if let Some(certificate) = aggregator.append(vote.authority, vote.signature).unwrap() {
+                return certificate;
+            }
+        }
+        panic!("No quorum");
+    }
+}
+
+#[tokio::test]
+async fn test_lock_certify_deliver() {
+    let mut setup = setup();
+    let user = KeyPair::from([7u8; 32]);
+    let recipient = Pubkey([8u8; 32]);
+    let order = {
+        let mut source = setup.source.lock().unwrap();
+        source.airdrop(user.public(), MINT, 1000);
+        source.lock(&user, DESTINATION, recipient, MINT, 600, NO_DEADLINE).unwrap()
+    };
+    assert_eq!(setup.source.lock().unwrap().balance(&user.public(), &MINT), 400);
+
+    let certificate = setup.certify(&order).await;
+    let compact = certificate.compact(&setup.committee).unwrap();
+    setup.destination.deliver_compact(&compact).unwrap();
+    assert_eq!(setup.destination.balance(&recipient, &MINT), 600);
+    assert_eq!(setup.destination.events_since(0), &[PortalEvent::Minted(order.transfer.clone())]);
+
+    // Delivered once only
+    assert_eq!(
+        setup.destination.deliver_compact(&compact),
+        Err(FastPayError::CertificateAlreadyExists)
+    );
+
+    // Nor can the escrow be refunded once the authorities certified the delivery
+    for authority in &setup.authorities {
+        let shard_id = authority.get_shard_id(&order.transfer);
+        let update = CrossShardCrossChainUpdate {
+            shard_id,
+            transfer_certificate: certificate.clone(),
+        };
+        authority.handle_cross_shard_update(update).await.unwrap();
+    }
+    let refund = CrossChainRefundOrder {
+        transfer_order: order.clone(),
+    };
+    let authority = &setup.authorities[0];
+    assert!(authority
+        .handle_refund_order(refund, authority.get_shard_id(&order.transfer))
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_lock_expire_refund() {
+    let setup = setup();
+    let user = KeyPair::from([7u8; 32]);
+    let recipient = Pubkey([8u8; 32]);
+    let deadline = current_timestamp() - 1;
+    let order = {
+        let mut source = setup.source.lock().unwrap();
+        source.airdrop(user.public(), MINT, 1000);
+        source.lock(&user, DESTINATION, recipient, MINT, 600, deadline).unwrap()
+    };
+
+    // Past the deadline, authorities refuse to vote for the delivery
+    let authority = &setup.authorities[0];
+    let shard_id = authority.get_shard_id(&order.transfer);
+    assert_eq!(
+        authority.check_cross_chain_transfer_order(&order, shard_id).await,
+        Err(FastPayError::TransferExpired)
+    );
+
+    let certificate = setup.certify_refund(&order).await;
+    setup.source.lock().unwrap().refund(&certificate).unwrap();
+    let source = setup.source.lock().unwrap();
+    assert_eq!(source.balance(&user.public(), &MINT), 1000);
+    assert!(!source.has_escrow(&order.transfer));
+    assert_eq!(
+        source.events_since(1),
+        &[PortalEvent::Refunded(order.transfer.clone())]
+    );
+}
@@ -0,0 +1,110 @@
+use super::*;
+use crate::base_types::Pubkey;
+
+const NETWORK: NetworkId = 7;
+
+fn request() -> AccountInfoRequest {
+    AccountInfoRequest { sender: Pubkey([5u8; 32]) }
+}
+
+#[test]
+fn test_message_round_trip() {
+    let bytes = serialize_account_info_request(Envelope::new(NETWORK), &request()).unwrap();
+    assert_eq!(&bytes[..4], b"FPTB");
+    let (envelope, message) = open_message(NETWORK, &bytes).unwrap();
+    assert_eq!(envelope, Envelope::new(NETWORK));
+    assert!(matches!(message, BridgeMessage::AccountInfoRequest(request) if request.sender == Pubkey([5u8; 32])));
+}
+
+#[test]
+fn test_message_of_another_network_is_rejected() {
+    let bytes = serialize_account_info_request(Envelope::new(NETWORK + 1), &request()).unwrap();
+    assert_eq!(
+        deserialize_message(NETWORK, &bytes).err(),
+        Some(FastPayError::WrongNetwork { expected: NETWORK, found: NETWORK + 1 })
+    );
+}
+
+#[test]
+fn test_unsupported_version_is_rejected() {
+    for version in [MIN_PROTOCOL_VERSION - 1, PROTOCOL_VERSION + 1] {
+        let envelope = Envelope { version, network: NETWORK };
+        let bytes = serialize_account_info_request(envelope, &request()).unwrap();
+        assert_eq!(
+            deserialize_message(NETWORK, &bytes).err(),
+            Some(FastPayError::UnsupportedProtocolVersion {
+                version,
+                min: MIN_PROTOCOL_VERSION,
+                max: PROTOCOL_VERSION,
+            })
+        );
+    }
+}
+
+#[test]
+fn test_version_mismatch_answer_names_supported_versions() {
+    let bytes = serialize_version_mismatch(NETWORK);
+    assert_eq!(
+        deserialize_message(NETWORK, &bytes).err(),
+        Some(FastPayError::UnsupportedProtocolVersion {
+            version: PROTOCOL_VERSION,
+            min: MIN_PROTOCOL_VERSION,
+            max: PROTOCOL_VERSION,
+        })
+    );
+}
+
+#[test]
+fn test_malformed_envelope_is_rejected() {
+    let bytes = serialize_account_info_request(Envelope::new(NETWORK), &request()).unwrap();
+    assert_eq!(
+        deserialize_message(NETWORK, &bytes[..ENVELOPE_SIZE - 1]).err(),
+        Some(FastPayError::DeserializationError)
+    );
+
+    let mut wrong_magic = bytes.clone();
+    wrong_magic[0] ^= 1;
+    assert_eq!(
+        deserialize_message(NETWORK, &wrong_magic).err(),
+        Some(FastPayError::DeserializationError)
+    );
+
+    // A body that does not match the kind of the envelope
+    let mut wrong_kind = bytes;
+    wrong_kind[6] = BridgeMessage::Ack.kind();
+    assert_eq!(
+        deserialize_message(NETWORK, &wrong_kind).err(),
+        Some(FastPayError::DeserializationError)
+    );
+}
+
+#[test]
+fn test_typed_error_round_trip() {
+    let error = FastPayError::WrongShard { expected: 3, num_shards: 4 };
+    let bytes = serialize_error(Envelope::new(NETWORK), &error).unwrap();
+    assert!(matches!(
+        deserialize_message(NETWORK, &bytes),
+        Ok(BridgeMessage::Error(found)) if found == error
+    ));
+}
+
+#[test]
+fn test_error_classification() {
+    for error in [
+        FastPayError::WrongShard { expected: 0, num_shards: 2 },
+        FastPayError::CommunicationError,
+        FastPayError::CertificateNotfound,
+        FastPayError::UnknownEpoch { epoch: 3 },
+    ] {
+        assert!(error.is_retryable(), "{:?} should be retryable", error);
+    }
+    for error in [
+        FastPayError::DeserializationError,
+        FastPayError::HandshakeFailed { error: "bad signature".into() },
+        FastPayError::UnsupportedProtocolVersion { version: 9, min: 1, max: 1 },
+        FastPayError::InvalidSignature { error: "bad signature".into() },
+        FastPayError::TransferExpired,
+    ] {
+        assert!(!error.is_retryable(), "{:?} should be permanent", error);
+    }
+}
@@ -0,0 +1,41 @@
+use super::*;
+
+#[test]
+fn test_shard_count_must_be_positive() {
+    assert!(ShardingStrategy::new(0).is_err());
+    let sharding = ShardingStrategy::new(3).unwrap();
+    assert_eq!(sharding.number_of_shards(), 3);
+    assert_eq!(sharding.shard_ids().collect::<Vec<_>>(), [0, 1, 2]);
+}
+
+#[test]
+fn test_senders_spread_over_the_configured_shards() {
+    for number_of_shards in [1, 3, 16, 17, 100] {
+        let sharding = ShardingStrategy::new(number_of_shards).unwrap();
+        let mut counts = vec![0; number_of_shards as usize];
+        for i in 0..(number_of_shards * 100) {
+            let mut key = [0u8; 32];
+            key[..4].copy_from_slice(&i.to_le_bytes());
+            let shard_id = sharding.shard_of(&Pubkey(key));
+            assert!(shard_id < number_of_shards);
+            // The same sender always goes to the same shard
+            assert_eq!(sharding.shard_of(&Pubkey(key)), shard_id);
+            counts[shard_id as usize] += 1;
+        }
+        // Senders differing in their first byte only are not all sent to one shard
+        assert!(counts.iter().all(|count| *count > 50), "{:?}", counts);
+    }
+}
+
+#[test]
+fn test_whole_key_decides_the_shard() {
+    let sharding = ShardingStrategy::new(16).unwrap();
+    let shards: std::collections::HashSet<_> = (0..64u8)
+        .map(|i| {
+            let mut key = [7u8; 32];
+            key[31] = i;
+            sharding.shard_of(&Pubkey(key))
+        })
+        .collect();
+    assert!(shards.len() > 1);
+}
@@ -0,0 +1,185 @@
+use failure::Error;
+use fast_core::{ base_types::*, config::*, error::FastPayError, message::*, sharding::ShardingStrategy };
+use futures::stream::{ self, StreamExt };
+use log::info;
+use std::sync::Arc;
+use std::time::{ Duration, Instant };
+use structopt::StructOpt;
+
+use crate::network::{ AuthorityShardClient, NetworkClients };
+
+#[derive(Debug, StructOpt)]
+pub struct BenchOpt {
+    /// Committee configuration file
+    #[structopt(long)]
+    committee: String,
+
+    /// Index of the benchmarked authority in the committee
+    #[structopt(long, default_value = "0")]
+    authority: usize,
+
+    /// Requests to send
+    #[structopt(long, default_value = "10000")]
+    requests: usize,
+
+    /// Requests in flight at the same time
+    #[structopt(long, default_value = "256")]
+    concurrency: usize,
+
+    /// Requests to send: `transfer` for transfer orders of distinct senders, which the
+    /// authority votes for, or `info` for lookups of unknown transfers, which it refuses
+    #[structopt(long, default_value = "transfer")]
+    kind: BenchKind,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum BenchKind {
+    Transfer,
+    Info,
+}
+
+impl std::str::FromStr for BenchKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "transfer" => Ok(BenchKind::Transfer),
+            "info" => Ok(BenchKind::Info),
+            _ => Err(failure::format_err!("Unknown request kind {}, expected transfer or info", s)),
+        }
+    }
+}
+
+/// Request prepared ahead of the run, so that only the authority is measured
+enum BenchRequest {
+    Transfer(CrossChainTransferOrder),
+    Info(TransferInfoRequest),
+}
+
+impl BenchRequest {
+    fn new(kind: BenchKind) -> Self {
+        let sender = KeyPair::from(rand::random());
+        let transfer = CrossChainTransfer {
+            source_chain: ChainId(1),
+            destination_chain: ChainId(2),
+            sender: sender.public(),
+            recipient: Pubkey(rand::random()),
+            amount: 1000,
+            token_mint: Pubkey([4u8; 32]),
+            interop_tx_id: InteropTxId(rand::random()),
+            escrow_account: Pubkey(rand::random()),
+            nonce: 0,
+            deadline: 4_102_444_800, // 2100-01-01
+        };
+        match kind {
+            BenchKind::Transfer => BenchRequest::Transfer(CrossChainTransferOrder::new(transfer, &sender)),
+            BenchKind::Info => BenchRequest::Info(TransferInfoRequest {
+                sender: transfer.sender,
+                interop_tx_id: transfer.interop_tx_id,
+            }),
+        }
+    }
+
+    fn sender(&self) -> &Pubkey {
+        match self {
+            BenchRequest::Transfer(order) => &order.transfer.sender,
+            BenchRequest::Info(request) => &request.sender,
+        }
+    }
+
+    /// Send the request and classify the answer of the authority
+    async fn send(&self, shard: &AuthorityShardClient) -> Outcome {
+        let result = match self {
+            BenchRequest::Transfer(order) => shard.send_transfer_order(order).await.map(|_| ()),
+            BenchRequest::Info(request) => shard.get_transfer_info(request).await.map(|_| ()),
+        };
+        match result {
+            Ok(()) => Outcome::Accepted,
+            Err(FastPayError::ClientIoError { .. }) | Err(FastPayError::CommunicationError) => Outcome::Unanswered,
+            Err(_) => Outcome::Refused,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Accepted,
+    /// The authority answered with an error
+    Refused,
+    /// No answer came back, even after retrying
+    Unanswered,
+}
+
+/// Measure the throughput and latency of one authority under concurrent requests
+pub async fn run_bench(opt: BenchOpt) -> Result<(), Error> {
+    let committee_config = CommitteeConfig::read(&opt.committee)?;
+    let entry = committee_config.authorities.get(opt.authority).ok_or_else(|| {
+        failure::format_err!("The committee has no authority {}", opt.authority)
+    })?;
+    let name = entry.authority_name()?;
+    let sharding = ShardingStrategy::new(entry.num_shards)?;
+
+    // The benchmark only needs a throwaway identity
+    let clients = NetworkClients::new(Arc::new(KeyPair::from(rand::random()))).await?;
+    let client = clients.get(entry.transport, entry.security);
+    let shards = sharding
+        .shard_ids()
+        .map(|shard_id| {
+            let address = entry.shard_address(shard_id)?;
+            Ok(AuthorityShardClient::new(name, address, committee_config.network, client.clone()))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    info!("Preparing {} {:?} requests", opt.requests, opt.kind);
+    let requests: Vec<_> = (0..opt.requests).map(|_| BenchRequest::new(opt.kind)).collect();
+
+    // Open the channels to every shard before measuring
+    for shard in &shards {
+        BenchRequest::new(BenchKind::Info).send(shard).await;
+    }
+
+    info!(
+        "Sending {} requests to authority {}, {} at a time",
+        opt.requests,
+        opt.authority,
+        opt.concurrency
+    );
+    let start = Instant::now();
+    let mut results: Vec<(Outcome, Duration)> = stream::iter(&requests)
+        .map(|request| {
+            let shard = &shards[sharding.shard_of(request.sender()) as usize];
+            async move {
+                let sent = Instant::now();
+                let outcome = request.send(shard).await;
+                (outcome, sent.elapsed())
+            }
+        })
+        .buffer_unordered(opt.concurrency.max(1))
+        .collect()
+        .await;
+    let elapsed = start.elapsed();
+
+    let count = |outcome: Outcome| results.iter().filter(|(o, _)| *o == outcome).count();
+    let (refused, unanswered) = (count(Outcome::Refused), count(Outcome::Unanswered));
+    results.sort_by_key(|(_, latency)| *latency);
+    let percentile = |p: usize| {
+        results
+            .get(((results.len() * p) / 100).min(results.len().saturating_sub(1)))
+            .map_or(Duration::ZERO, |(_, latency)| *latency)
+    };
+    info!(
+        "{} requests in {:.2?}: {:.0} requests/s, {} refused, {} unanswered",
+        results.len(),
+        elapsed,
+        (results.len() as f64) / elapsed.as_secs_f64(),
+        refused,
+        unanswered
+    );
+    info!(
+        "Latency: p50 {:.2?}, p99 {:.2?}, max {:.2?}",
+        percentile(50),
+        percentile(99),
+        percentile(100)
+    );
+    Ok(())
+}
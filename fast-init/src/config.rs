@@ -1,11 +1,9 @@
-use fast_core::base_types::*;
+use fast_core::{base_types::*, config::*};
 use failure::Error;
 use log::info;
 use rand::rngs::OsRng;
 use rand::TryRngCore;
-use serde::{ Deserialize, Serialize };
-use std::fs::{ self, File };
-use std::io::BufWriter;
+use std::fs;
 use std::path::Path;
 use structopt::StructOpt;
 
@@ -31,30 +29,37 @@ pub struct BridgeConfigGenOpt {
     #[structopt(long, default_value = "1000")]
     port_step: u16,
 
+    /// Identifier of the bridge deployment, distinguishing e.g. a testnet from the mainnet
+    #[structopt(long, default_value = "0")]
+    network: NetworkId,
+
+    /// Transport of the authorities: udp or tcp
+    #[structopt(long, default_value = "udp")]
+    transport: String,
+
+    /// Let anyone talk to the authorities in cleartext, for local tests
+    #[structopt(long)]
+    plaintext: bool,
+
     /// Output directory
     #[structopt(long, default_value = "./bridge_config")]
     output_dir: String,
-}
 
-#[derive(Debug, Serialize, Deserialize)]
-struct AuthorityConfig {
-    name: String,
-    secret_key: String,
-    committee: String,
-}
+    /// JSON-RPC endpoint of the source chain, to verify escrows
+    #[structopt(long)]
+    escrow_rpc_url: Option<String>,
 
-#[derive(Debug, Serialize, Deserialize)]
-struct CommitteeConfig {
-    authorities: Vec<AuthorityEntry>,
-}
+    /// Base58 address of the source portal program owning escrow accounts
+    #[structopt(long)]
+    portal_program: Option<String>,
 
-#[derive(Debug, Serialize, Deserialize)]
-struct AuthorityEntry {
-    name: String,
-    host: String,
-    port: u16,
-    weight: u64,
-    num_shards: u32,
+    /// Commitment level of escrow checks
+    #[structopt(long, default_value = "finalized")]
+    escrow_commitment: String,
+
+    /// Let the authorities certify every transfer without checking its escrow, for local tests
+    #[structopt(long)]
+    disable_escrow_verification: bool,
 }
 
 fn generate_keypair() -> (Pubkey, [u8; 32]) {
@@ -67,21 +72,50 @@ fn generate_keypair() -> (Pubkey, [u8; 32]) {
     (public, secret)
 }
 
-/// Encode a public key as a hex string
-fn encode_public_key(key: &Pubkey) -> String {
-    hex::encode(key.0)
-}
-
 /// Encode a secret key as a hex string
 fn encode_secret_key(key: &[u8; 32]) -> String {
     hex::encode(key)
 }
 
+/// How authorities check escrows: through the given endpoint and portal program, or not at
+/// all if asked explicitly.
+fn escrow_config(opt: &BridgeConfigGenOpt) -> Result<EscrowConfig, Error> {
+    match (&opt.escrow_rpc_url, &opt.portal_program, opt.disable_escrow_verification) {
+        (Some(url), Some(portal_program), false) => {
+            decode_address(portal_program)?;
+            Ok(EscrowConfig::SvmRpc {
+                url: url.clone(),
+                portal_program: portal_program.clone(),
+                commitment: serde_json::from_value(opt.escrow_commitment.clone().into())?,
+            })
+        }
+        (None, None, true) => Ok(EscrowConfig::Disabled),
+        (_, _, true) => Err(failure::format_err!(
+            "--disable-escrow-verification cannot be combined with --escrow-rpc-url or --portal-program"
+        )),
+        (None, Some(_), false) => Err(failure::format_err!("--portal-program requires --escrow-rpc-url")),
+        (Some(_), None, false) => Err(failure::format_err!("--escrow-rpc-url requires --portal-program")),
+        (None, None, false) => Err(failure::format_err!(
+            "Escrows are verified through --escrow-rpc-url and --portal-program, \
+             or not at all with --disable-escrow-verification"
+        )),
+    }
+}
+
 /// Generate bridge configuration
 pub async fn generate_bridge_config(opt: BridgeConfigGenOpt) -> Result<(), Error> {
     // Create output directory
     fs::create_dir_all(&opt.output_dir)?;
 
+    let escrow = escrow_config(&opt)?;
+
+    let transport: Transport = serde_json::from_value(opt.transport.clone().into())?;
+    let security = if opt.plaintext {
+        Security::Plaintext
+    } else {
+        Security::Encrypted
+    };
+
     // Generate authorities
     let mut authority_entries = Vec::new();
 
@@ -91,45 +125,51 @@ pub async fn generate_bridge_config(opt: BridgeConfigGenOpt) -> Result<(), Error
 
         // Create authority entry
         let authority_entry = AuthorityEntry {
-            name: encode_public_key(&public_key),
+            name: encode_authority_name(&public_key),
             host: opt.host.clone(),
             port: opt.base_port + (i as u16) * opt.port_step,
             weight: 1,
             num_shards: opt.num_shards,
+            transport,
+            security,
         };
 
         authority_entries.push(authority_entry);
 
         // Create authority config
         let authority_config = AuthorityConfig {
-            name: encode_public_key(&public_key),
+            name: encode_authority_name(&public_key),
             secret_key: encode_secret_key(&secret_key),
             committee: Path::new(&opt.output_dir)
                 .join("committee.json")
                 .to_str()
                 .unwrap()
                 .to_string(),
+            escrow: escrow.clone(),
         };
 
         // Save authority config
         let config_path = Path::new(&opt.output_dir).join(format!("authority_{}.json", i));
-        let file = File::create(config_path)?;
-        let writer = BufWriter::new(file);
-        serde_json::to_writer_pretty(writer, &authority_config)?;
+        authority_config.write(config_path)?;
     }
 
     // Create committee config
     let committee_config = CommitteeConfig {
+        epoch: 0,
+        network: opt.network,
         authorities: authority_entries,
     };
 
-    // Save committee config
+    // Check the committee like authorities and relayers will, then save it
+    committee_config.validate()?;
     let committee_path = Path::new(&opt.output_dir).join("committee.json");
-    let file = File::create(committee_path)?;
-    let writer = BufWriter::new(file);
-    serde_json::to_writer_pretty(writer, &committee_config)?;
+    committee_config.write(committee_path)?;
 
     info!("Bridge configuration generated in: {}", opt.output_dir);
 
     Ok(())
 }
+
+#[cfg(test)]
+#[path = "unit_tests/config_tests.rs"]
+mod config_tests;
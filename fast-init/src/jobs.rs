@@ -0,0 +1,77 @@
+use failure::Error;
+use fast_core::{base_types::*, message::*, storage::WalStore};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::submitter::Submission;
+
+/// Step reached by the relayer for a transfer or a message, in order.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Serialize, Deserialize)]
+pub enum JobState {
+    /// The transfer was seen on its source chain
+    Observed,
+    /// Votes of a quorum of authorities were received
+    VotesCollected,
+    /// A certificate was formed or recovered
+    Certified,
+    /// The certificate was sent to the destination chain
+    Submitted,
+    /// The destination chain confirmed the delivery
+    Confirmed,
+    /// The authorities received the certificate, the job is over
+    Propagated,
+}
+
+/// A durable change to a relayer job, written before the relayer acts on it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum JobRecord {
+    Observed {
+        order: CrossChainTransferOrder,
+        observed_at: Timestamp,
+    },
+    Vote(SignedCrossChainTransferOrder),
+    Certified(CertifiedCrossChainTransferOrder),
+    /// The certificate of a transfer, message or refund was sent to its portal
+    Submitted(InteropTxId, Submission),
+    /// The last submission failed, for the given number of times in total
+    SubmissionFailed(InteropTxId, u32),
+    Confirmed(InteropTxId),
+    Propagated(InteropTxId),
+    /// The transfer will be refunded instead of delivered
+    RefundRequested(InteropTxId),
+    /// The transfer was refunded, or the transfer or message given up on
+    Closed(InteropTxId),
+    MessageObserved(CrossChainMessageOrder),
+    MessageCertified(CertifiedCrossChainMessageOrder),
+    /// A quorum agreed to refund the transfer, to be submitted to the source chain
+    RefundCertified(CertifiedCrossChainRefundOrder),
+}
+
+/// Write-ahead log of the relayer jobs, kept like the logs of the authorities.
+pub struct JobLog {
+    store: WalStore,
+}
+
+impl JobLog {
+    const LOG: &'static str = "jobs.wal";
+
+    pub fn open(state_dir: &Path) -> Result<Self, Error> {
+        Ok(Self {
+            store: WalStore::open(state_dir.join("jobs"))?,
+        })
+    }
+
+    /// Make a record durable.
+    pub fn append(&mut self, record: &JobRecord) -> Result<(), Error> {
+        Ok(self.store.append_entry(Self::LOG, record)?)
+    }
+
+    pub fn load(&mut self) -> Result<Vec<JobRecord>, Error> {
+        Ok(self.store.load_entries(Self::LOG)?)
+    }
+
+    /// Replace the log with records leading to the same jobs.
+    pub fn compact(&mut self, records: &[JobRecord]) -> Result<(), Error> {
+        Ok(self.store.replace_entries(Self::LOG, records)?)
+    }
+}
@@ -3,12 +3,19 @@ use log::info;
 use structopt::StructOpt;
 use tokio::runtime::Runtime;
 
+mod bench;
 mod config;
+mod jobs;
 mod relayer;
 mod server;
 mod network;
+mod reconfig;
+mod submitter;
+mod watcher;
 
+use bench::{ run_bench, BenchOpt };
 use config::{ generate_bridge_config, BridgeConfigGenOpt };
+use reconfig::{ reconfigure, sign_committee_change, ReconfigureOpt, SignCommitteeChangeOpt };
 use relayer::{ run_relayer, RelayerOpt };
 use server::{ run_bridge_server, BridgeServerOpt };
 
@@ -35,6 +42,18 @@ enum Command {
     /// Generate bridge configuration
     #[structopt(name = "generate-config")]
     GenerateConfig(BridgeConfigGenOpt),
+
+    /// Sign the hand-over to the committee of the next epoch
+    #[structopt(name = "sign-committee-change")]
+    SignCommitteeChange(SignCommitteeChangeOpt),
+
+    /// Certify the hand-over to the committee of the next epoch and push it to the authorities
+    #[structopt(name = "reconfigure")]
+    Reconfigure(ReconfigureOpt),
+
+    /// Measure the throughput of an authority under concurrent requests
+    #[structopt(name = "bench")]
+    Bench(BenchOpt),
 }
 
 fn main() -> Result<(), Error> {
@@ -68,6 +87,18 @@ fn main() -> Result<(), Error> {
             info!("Generating bridge configuration");
             runtime.block_on(generate_bridge_config(config_opt))?;
         }
+        Command::SignCommitteeChange(sign_opt) => {
+            info!("Signing committee change");
+            runtime.block_on(sign_committee_change(sign_opt))?;
+        }
+        Command::Reconfigure(reconfigure_opt) => {
+            info!("Reconfiguring bridge committee");
+            runtime.block_on(reconfigure(reconfigure_opt))?;
+        }
+        Command::Bench(bench_opt) => {
+            info!("Benchmarking bridge authority");
+            runtime.block_on(run_bench(bench_opt))?;
+        }
     }
 
     Ok(())
@@ -1,121 +1,1471 @@
-use fast_core::{ base_types::*, error::*, message::*, serialization::* };
-use log::{ error, info };
-use std::net::SocketAddr;
-use tokio::net::UdpSocket;
+use fast_core::channel::{ self, Accept, Hello, Initiator, Session };
+use fast_core::{ base_types::*, committee::*, config::{ Security, Transport }, error::*, message::*, serialization::* };
+use futures::future::BoxFuture;
+use log::{ debug, error, info };
+use sha2::{ Digest, Sha256 };
+use std::collections::{ BTreeMap, BTreeSet, HashMap };
+use std::net::{ IpAddr, SocketAddr };
+use std::sync::atomic::{ AtomicBool, AtomicU64, Ordering };
+use std::sync::{ Arc, Mutex };
+use std::time::{ Duration, Instant };
+use tokio::io::{ AsyncRead, AsyncReadExt, AsyncWriteExt };
+use tokio::net::{ TcpListener, TcpStream, UdpSocket };
+use tokio::sync::{ mpsc, oneshot, Semaphore };
+use tokio::task::JoinHandle;
 
 const DEFAULT_BUFFER_SIZE: usize = 65536;
 
-/// UDP client for communicating with authority shards
+/// Largest frame accepted over TCP
+const MAX_FRAME_SIZE: usize = 16 << 20;
+
+/// Time to wait for a response before sending a request again
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(500);
+/// Number of times a request is sent before giving up
+const DEFAULT_ATTEMPTS: u32 = 4;
+
+/// Requests a server processes at the same time, unless configured otherwise
+const DEFAULT_MAX_CONCURRENCY: usize = 256;
+
+/// Encrypted channels kept by a UDP server, the least recently used being dropped first
+const MAX_CHANNELS: usize = 4096;
+/// Encrypted channels kept for one source address, so that a flood of handshakes from one
+/// host only drops its own channels
+const MAX_CHANNELS_PER_SOURCE: usize = 64;
+/// Connections served by a TCP server at the same time. Further connections wait in the
+/// backlog of the listener.
+const MAX_CONNECTIONS: usize = 4096;
+/// Connections served for one source address, so that one host cannot hold them all. Further
+/// connections from that host are closed right away.
+const MAX_CONNECTIONS_PER_SOURCE: usize = 64;
+/// Lifetime of the cookies of a server, which accepts those of the current and of the
+/// previous period
+const COOKIE_PERIOD: Duration = Duration::from_secs(60);
+const COOKIE_SIZE: usize = 32;
+
+/// Identifier of a request, sent in front of each datagram and echoed in front of its response
+pub type RequestId = u64;
+
+const REQUEST_ID_SIZE: usize = std::mem::size_of::<RequestId>();
+
+fn wrap(request_id: RequestId, payload: &[u8]) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(REQUEST_ID_SIZE + payload.len());
+    datagram.extend_from_slice(&request_id.to_le_bytes());
+    datagram.extend_from_slice(payload);
+    datagram
+}
+
+fn unwrap(datagram: &[u8]) -> Option<(RequestId, &[u8])> {
+    if datagram.len() < REQUEST_ID_SIZE {
+        return None;
+    }
+    let (request_id, payload) = datagram.split_at(REQUEST_ID_SIZE);
+    Some((RequestId::from_le_bytes(request_id.try_into().ok()?), payload))
+}
+
+/// TCP frame: a little-endian `u32` length, then the body
+fn frame(body: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(4 + body.len());
+    frame.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    frame.extend_from_slice(body);
+    frame
+}
+
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Vec<u8>, std::io::Error> {
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len).await?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Invalid frame length {}", len),
+        ));
+    }
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).await?;
+    Ok(body)
+}
+
+/// Kinds of the datagrams of encrypted UDP channels. Each datagram starts with its kind and
+/// the session id chosen by the client.
+/// A hello starts with the last cookie of the server, all zeros at first.
+const HELLO: u8 = 0;
+const ACCEPT: u8 = 1;
+const DATA: u8 = 2;
+/// The server does not know the session, which must be established again. Not
+/// authenticated, so the worst a forged one can do is to cause a new handshake.
+const UNKNOWN_SESSION: u8 = 3;
+/// The server only takes a hello with this cookie, proving that the client receives at its
+/// address. It keeps no state and runs no key exchange until then. Encrypted TCP connections
+/// open the same way, with frames starting with these kinds instead of datagrams.
+const COOKIE: u8 = 4;
+
+const HEADER_SIZE: usize = 1 + 8;
+
+fn secure_datagram(kind: u8, session_id: u64, body: &[u8]) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(HEADER_SIZE + body.len());
+    datagram.push(kind);
+    datagram.extend_from_slice(&session_id.to_le_bytes());
+    datagram.extend_from_slice(body);
+    datagram
+}
+
+/// Encrypt a message, authenticating the header of its datagram along with it
+fn sealed_datagram(session: &Session, session_id: u64, message: &[u8]) -> Vec<u8> {
+    let header = secure_datagram(DATA, session_id, &[]);
+    let sealed = session.seal(&header, message);
+    secure_datagram(DATA, session_id, &sealed)
+}
+
+fn parse_secure_datagram(datagram: &[u8]) -> Option<(u8, u64, &[u8])> {
+    if datagram.len() < HEADER_SIZE {
+        return None;
+    }
+    let session_id = u64::from_le_bytes(datagram[1..HEADER_SIZE].try_into().ok()?);
+    Some((datagram[0], session_id, &datagram[HEADER_SIZE..]))
+}
+
+/// Sender of a request, as seen by a server
+#[derive(Debug, Clone, Copy)]
+pub struct Peer {
+    pub address: SocketAddr,
+    /// Identity proven by the sender, or `None` in plaintext mode. Servers take requests from
+    /// any identity: the handler decides what each one may do.
+    pub identity: Option<Pubkey>,
+}
+
+impl std::fmt::Display for Peer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.identity {
+            Some(identity) => write!(f, "{} ({})", identity.base58(), self.address),
+            None => write!(f, "{} (plaintext)", self.address),
+        }
+    }
+}
+
+/// Handler of the messages received by a server, returning the response to send back, if any
+pub type Handler = Arc<dyn Fn(Peer, Vec<u8>) -> BoxFuture<'static, Option<Vec<u8>>> + Send + Sync>;
+
+/// Client side of a transport, able to carry concurrent requests to any number of shards.
+pub trait NetworkClient: Send + Sync {
+    /// Send a message to a shard of `authority` and receive its response
+    fn send_recv(
+        &self,
+        authority: AuthorityName,
+        addr: SocketAddr,
+        data: Vec<u8>
+    ) -> BoxFuture<'_, Result<Vec<u8>, FastPayError>>;
+}
+
+/// Server side of a transport, listening for the messages to one shard.
+pub trait NetworkServer: Send + Sync {
+    /// Process incoming messages. Responses carry the request id of their request.
+    fn run(&self, handler: Handler) -> BoxFuture<'_, Result<(), std::io::Error>>;
+}
+
+/// How a server processes the messages it receives
+#[derive(Debug, Clone, Copy)]
+pub struct ServerConfig {
+    /// Requests processed at the same time. Further datagrams wait in the socket buffer, and
+    /// further frames in their connection.
+    pub max_concurrency: usize,
+    /// Sockets receiving the datagrams of a UDP server, sharing its address with SO_REUSEPORT
+    /// if more than one
+    pub udp_sockets: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrency: DEFAULT_MAX_CONCURRENCY,
+            udp_sockets: 1,
+        }
+    }
+}
+
+/// Listen on an address with the given transport. Clients must prove their identity over an
+/// encrypted channel if the server has an `identity`, and may send plaintext otherwise. Any
+/// identity is accepted, and handed to the handler with each request.
+pub async fn bind(
+    transport: Transport,
+    addr: SocketAddr,
+    identity: Option<Arc<KeyPair>>,
+    config: ServerConfig
+) -> Result<Box<dyn NetworkServer>, std::io::Error> {
+    Ok(match transport {
+        Transport::Udp => Box::new(UdpServer::new(addr, identity, config).await?),
+        Transport::Tcp => Box::new(TcpServer::new(addr, identity, config).await?),
+    })
+}
+
+/// One client per transport and security mode, each shared by all the shards reached with it
+#[derive(Clone)]
+pub struct NetworkClients {
+    udp: Arc<UdpClient>,
+    tcp: Arc<TcpClient>,
+    secure_udp: Arc<UdpClient>,
+    secure_tcp: Arc<TcpClient>,
+}
+
+impl NetworkClients {
+    /// Create the clients, proving `identity` to the shards over encrypted channels
+    pub async fn new(identity: Arc<KeyPair>) -> Result<Self, std::io::Error> {
+        Ok(Self {
+            udp: Arc::new(UdpClient::new(None).await?),
+            tcp: Arc::new(TcpClient::new(None)),
+            secure_udp: Arc::new(UdpClient::new(Some(identity.clone())).await?),
+            secure_tcp: Arc::new(TcpClient::new(Some(identity))),
+        })
+    }
+
+    pub fn get(&self, transport: Transport, security: Security) -> Arc<dyn NetworkClient> {
+        match (transport, security) {
+            (Transport::Udp, Security::Plaintext) => self.udp.clone(),
+            (Transport::Tcp, Security::Plaintext) => self.tcp.clone(),
+            (Transport::Udp, Security::Encrypted) => self.secure_udp.clone(),
+            (Transport::Tcp, Security::Encrypted) => self.secure_tcp.clone(),
+        }
+    }
+}
+
+/// Calls waiting for an answer, by peer and request or session id
+type Waiting<T> = Mutex<HashMap<(SocketAddr, u64), oneshot::Sender<T>>>;
+
+/// Requests waiting for their response
+type PendingRequests = Arc<Waiting<Vec<u8>>>;
+
+/// Forgets a call when its caller stops waiting, e.g. on timeout
+struct PendingGuard<'a, T> {
+    pending: &'a Waiting<T>,
+    key: (SocketAddr, u64),
+}
+
+impl<T> Drop for PendingGuard<'_, T> {
+    fn drop(&mut self) {
+        self.pending.lock().unwrap().remove(&self.key);
+    }
+}
+
+/// Answer of a server to a hello
+enum HandshakeReply {
+    Cookie([u8; COOKIE_SIZE]),
+    Accept(Accept),
+}
+
+/// Channel in use with a shard, and its session id
+type SessionSlot = Arc<tokio::sync::Mutex<Option<(u64, Arc<Session>)>>>;
+
+/// Encrypted channels of a UDP client, one per shard at a time
+struct UdpChannels {
+    identity: Arc<KeyPair>,
+    /// Established channels, by shard and session id
+    sessions: Mutex<HashMap<(SocketAddr, u64), Arc<Session>>>,
+    /// Channel in use with each shard, established by one request at a time
+    current: Mutex<HashMap<SocketAddr, SessionSlot>>,
+    /// Handshakes waiting for the answer of a shard
+    handshakes: Waiting<HandshakeReply>,
+    /// Wakes up the requests when a shard lost a channel, to retry them right away
+    lost: tokio::sync::Notify,
+}
+
+impl UdpChannels {
+    fn new(identity: Arc<KeyPair>) -> Self {
+        Self {
+            identity,
+            sessions: Mutex::new(HashMap::new()),
+            current: Mutex::new(HashMap::new()),
+            handshakes: Mutex::new(HashMap::new()),
+            lost: tokio::sync::Notify::new(),
+        }
+    }
+
+    /// Return the channel with a shard, after a handshake if there is none yet
+    async fn session(
+        &self,
+        socket: &UdpSocket,
+        authority: AuthorityName,
+        addr: SocketAddr,
+        timeout: Duration,
+        attempts: u32
+    ) -> Result<(u64, Arc<Session>), FastPayError> {
+        let slot = self.current.lock().unwrap().entry(addr).or_default().clone();
+        let mut slot = slot.lock().await;
+        if let Some((session_id, session)) = &*slot
+            && !session.is_closed()
+            && session.peer == authority
+        {
+            return Ok((*session_id, session.clone()));
+        }
+
+        let session_id = rand::random();
+        let initiator = Initiator::new(&self.identity, authority);
+        let _guard = PendingGuard {
+            pending: &self.handshakes,
+            key: (addr, session_id),
+        };
+
+        let hello = bincode::serialize(initiator.hello()).expect("Serialization should not fail");
+        let mut cookie = [0; COOKIE_SIZE];
+        let mut remaining = attempts;
+        let mut refreshed = false;
+        while remaining > 0 {
+            let (sender, receiver) = oneshot::channel();
+            self.handshakes.lock().unwrap().insert((addr, session_id), sender);
+            let datagram = secure_datagram(HELLO, session_id, &[&cookie[..], &hello].concat());
+            socket.send_to(&datagram, addr).await.map_err(|_| FastPayError::CommunicationError)?;
+            match tokio::time::timeout(timeout, receiver).await {
+                Ok(Ok(HandshakeReply::Accept(accept))) => {
+                    let session = Arc::new(initiator.finish(&accept)?);
+                    let mut sessions = self.sessions.lock().unwrap();
+                    if let Some((previous, _)) = slot.replace((session_id, session.clone())) {
+                        sessions.remove(&(addr, previous));
+                    }
+                    sessions.insert((addr, session_id), session.clone());
+                    return Ok((session_id, session));
+                }
+                // Sent again right away with the new cookie, at most once per attempt
+                Ok(Ok(HandshakeReply::Cookie(new_cookie))) if !refreshed && new_cookie != cookie => {
+                    cookie = new_cookie;
+                    refreshed = true;
+                    continue;
+                }
+                Ok(Ok(HandshakeReply::Cookie(new_cookie))) => cookie = new_cookie,
+                Ok(Err(_)) => return Err(FastPayError::CommunicationError),
+                Err(_) => (),
+            }
+            refreshed = false;
+            remaining -= 1;
+        }
+        Err(FastPayError::ClientIoError {
+            error: format!("No handshake with {} after {} attempts", addr, attempts),
+        })
+    }
+
+    /// Decrypt a response, or take in an answer to a handshake
+    fn receive(&self, addr: SocketAddr, datagram: &[u8]) -> Option<(RequestId, Vec<u8>)> {
+        let (kind, session_id, body) = parse_secure_datagram(datagram)?;
+        match kind {
+            ACCEPT | COOKIE => {
+                let reply = match kind {
+                    ACCEPT => HandshakeReply::Accept(bincode::deserialize(body).ok()?),
+                    _ => HandshakeReply::Cookie(body.try_into().ok()?),
+                };
+                if let Some(sender) = self.handshakes.lock().unwrap().remove(&(addr, session_id)) {
+                    let _ = sender.send(reply);
+                }
+                None
+            }
+            DATA => {
+                let session = self.sessions.lock().unwrap().get(&(addr, session_id)).cloned()?;
+                let message = session.open(&datagram[..HEADER_SIZE], body)?;
+                let (request_id, payload) = unwrap(&message)?;
+                Some((request_id, payload.to_vec()))
+            }
+            UNKNOWN_SESSION => {
+                if let Some(session) = self.sessions.lock().unwrap().remove(&(addr, session_id)) {
+                    debug!("Shard {} lost session {}, establishing a new one", addr, session_id);
+                    session.close();
+                    self.lost.notify_waiters();
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+/// UDP client for communicating with authority shards. Responses are matched to their
+/// request by sender and request id, so that one client can serve concurrent requests to
+/// any number of shards.
 pub struct UdpClient {
-    socket: UdpSocket,
-    buffer_size: usize,
+    socket: Arc<UdpSocket>,
+    pending: PendingRequests,
+    next_request_id: AtomicU64,
+    timeout: Duration,
+    attempts: u32,
+    receiver: JoinHandle<()>,
+    /// Encrypted channels, or `None` in plaintext mode
+    channels: Option<Arc<UdpChannels>>,
 }
 
 impl UdpClient {
-    pub async fn new() -> Result<Self, std::io::Error> {
-        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    /// Create a client proving `identity` to the shards, or sending plaintext if `None`
+    pub async fn new(identity: Option<Arc<KeyPair>>) -> Result<Self, std::io::Error> {
+        Self::with_timeout(identity, DEFAULT_TIMEOUT, DEFAULT_ATTEMPTS).await
+    }
+
+    /// Create a client sending each request up to `attempts` times, `timeout` apart
+    pub async fn with_timeout(
+        identity: Option<Arc<KeyPair>>,
+        timeout: Duration,
+        attempts: u32
+    ) -> Result<Self, std::io::Error> {
+        let socket = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+        let pending = PendingRequests::default();
+        let channels = identity.map(|identity| Arc::new(UdpChannels::new(identity)));
+        let receiver = tokio::spawn(Self::receive(socket.clone(), pending.clone(), channels.clone()));
         Ok(Self {
             socket,
-            buffer_size: DEFAULT_BUFFER_SIZE,
+            pending,
+            // Random start, so that late responses to a previous client are not matched
+            next_request_id: AtomicU64::new(rand::random()),
+            timeout,
+            attempts: attempts.max(1),
+            receiver,
+            channels,
         })
     }
 
-    /// Send a message and receive a response
+    /// Hand each response over to the request waiting for it
+    async fn receive(
+        socket: Arc<UdpSocket>,
+        pending: PendingRequests,
+        channels: Option<Arc<UdpChannels>>
+    ) {
+        let mut buffer = vec![0; DEFAULT_BUFFER_SIZE];
+        loop {
+            let (len, addr) = match socket.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                Err(e) => {
+                    // e.g. the port of a previous destination is closed
+                    debug!("Failed to receive response: {}", e);
+                    continue;
+                }
+            };
+            let response = match &channels {
+                None => unwrap(&buffer[..len]).map(|(request_id, payload)| (request_id, payload.to_vec())),
+                Some(channels) => channels.receive(addr, &buffer[..len]),
+            };
+            let Some((request_id, payload)) = response else {
+                continue;
+            };
+            match pending.lock().unwrap().remove(&(addr, request_id)) {
+                Some(sender) => {
+                    let _ = sender.send(payload);
+                }
+                None => debug!("Discarding unmatched response {} from {}", request_id, addr),
+            }
+        }
+    }
+
+    fn new_request_id(&self) -> RequestId {
+        self.next_request_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Send a message and receive its response, sending it again if the response is late.
+    /// Servers must therefore handle requests idempotently.
     pub async fn send_recv(
         &self,
+        authority: AuthorityName,
         addr: SocketAddr,
         data: Vec<u8>
     ) -> Result<Vec<u8>, FastPayError> {
-        // Send the data
-        self.socket.send_to(&data, addr).await.map_err(|_| FastPayError::CommunicationError)?;
+        let request_id = self.new_request_id();
+        let (sender, mut receiver) = oneshot::channel();
+        self.pending.lock().unwrap().insert((addr, request_id), sender);
+        let _guard = PendingGuard {
+            pending: &self.pending,
+            key: (addr, request_id),
+        };
+
+        let message = wrap(request_id, &data);
+        for _ in 0..self.attempts {
+            let (datagram, session) = match &self.channels {
+                None => (message.clone(), None),
+                Some(channels) => {
+                    // Each attempt is sealed anew, on a new channel if the shard lost the last one
+                    let (session_id, session) = channels
+                        .session(&self.socket, authority, addr, self.timeout, self.attempts)
+                        .await?;
+                    (sealed_datagram(&session, session_id, &message), Some(session))
+                }
+            };
+            self.socket.send_to(&datagram, addr).await.map_err(|_| FastPayError::CommunicationError)?;
+
+            let deadline = tokio::time::Instant::now() + self.timeout;
+            loop {
+                let lost = async {
+                    match &self.channels {
+                        Some(channels) => channels.lost.notified().await,
+                        None => std::future::pending().await,
+                    }
+                };
+                tokio::select! {
+                    response = &mut receiver => {
+                        return response.map_err(|_| FastPayError::CommunicationError);
+                    }
+                    _ = tokio::time::sleep_until(deadline) => break,
+                    _ = lost => {
+                        if session.as_ref().is_some_and(|session| session.is_closed()) {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        Err(FastPayError::ClientIoError {
+            error: format!("No response from {} after {} attempts", addr, self.attempts),
+        })
+    }
+}
+
+impl NetworkClient for UdpClient {
+    fn send_recv(
+        &self,
+        authority: AuthorityName,
+        addr: SocketAddr,
+        data: Vec<u8>
+    ) -> BoxFuture<'_, Result<Vec<u8>, FastPayError>> {
+        Box::pin(UdpClient::send_recv(self, authority, addr, data))
+    }
+}
+
+impl Drop for UdpClient {
+    fn drop(&mut self) {
+        self.receiver.abort();
+    }
+}
+
+/// Encrypted channel of a UDP server with a client
+struct ServerChannel {
+    session: Arc<Session>,
+    /// Ephemeral key of the hello that opened the channel, to recognize retransmissions
+    hello: [u8; 32],
+    /// Answer to the hello, sent again for retransmissions
+    accept: Vec<u8>,
+    /// Position of the last use of the channel, in the order of the table
+    last_used: u64,
+}
+
+/// What to do with a datagram received by an encrypted UDP server
+enum Received {
+    Request(u64, Arc<Session>, RequestId, Vec<u8>),
+    Reply(Vec<u8>),
+    Nothing,
+}
+
+/// Key of the channel of a UDP server: client address and session id
+type ChannelKey = (SocketAddr, u64);
+
+/// Encrypted channels of a UDP server, with their order of use
+#[derive(Default)]
+struct ChannelTable {
+    channels: HashMap<ChannelKey, ServerChannel>,
+    /// Channels from the least to the most recently used
+    order: BTreeMap<u64, ChannelKey>,
+    /// Last uses of the channels of each source address
+    sources: HashMap<IpAddr, BTreeSet<u64>>,
+    next_use: u64,
+}
+
+impl ChannelTable {
+    fn get(&self, key: &ChannelKey) -> Option<&ServerChannel> {
+        self.channels.get(key)
+    }
+
+    /// Move a channel to the most recently used position
+    fn touch(&mut self, key: &ChannelKey) {
+        let Some(channel) = self.channels.get_mut(key) else {
+            return;
+        };
+        let last_used = self.next_use;
+        self.next_use += 1;
+        self.order.remove(&channel.last_used);
+        self.order.insert(last_used, *key);
+        let uses = self.sources.entry(key.0.ip()).or_default();
+        uses.remove(&channel.last_used);
+        uses.insert(last_used);
+        channel.last_used = last_used;
+    }
 
-        // Receive the response
-        let mut buffer = vec![0; self.buffer_size];
-        let (len, _) = self.socket
-            .recv_from(&mut buffer).await
-            .map_err(|_| FastPayError::CommunicationError)?;
+    /// Add a channel, dropping the least recently used one of its source address, or else of
+    /// the server, if either has too many
+    fn insert(&mut self, key: ChannelKey, mut channel: ServerChannel) {
+        self.remove(&key);
+        let source_oldest = self.sources.get(&key.0.ip()).filter(|uses| uses.len() >= MAX_CHANNELS_PER_SOURCE);
+        let oldest = match source_oldest {
+            Some(uses) => uses.first().copied(),
+            None if self.channels.len() >= MAX_CHANNELS => self.order.keys().next().copied(),
+            None => None,
+        };
+        if let Some(oldest) = oldest.and_then(|oldest| self.order.get(&oldest).copied()) {
+            self.remove(&oldest);
+        }
+
+        channel.last_used = self.next_use;
+        self.next_use += 1;
+        self.order.insert(channel.last_used, key);
+        self.sources.entry(key.0.ip()).or_default().insert(channel.last_used);
+        self.channels.insert(key, channel);
+    }
+
+    fn remove(&mut self, key: &ChannelKey) {
+        let Some(channel) = self.channels.remove(key) else {
+            return;
+        };
+        self.order.remove(&channel.last_used);
+        if let Some(uses) = self.sources.get_mut(&key.0.ip()) {
+            uses.remove(&channel.last_used);
+            if uses.is_empty() {
+                self.sources.remove(&key.0.ip());
+            }
+        }
+    }
+}
+
+/// Cookies of a server, bound to the address and session of a client
+struct Cookies {
+    /// Key of the cookies, chosen anew by each server
+    key: [u8; 32],
+    started: Instant,
+}
+
+impl Cookies {
+    fn new() -> Self {
+        Self {
+            key: rand::random(),
+            started: Instant::now(),
+        }
+    }
 
-        Ok(buffer[..len].to_vec())
+    /// Cookie of a client for the given period, which only the server can compute
+    fn cookie(&self, addr: SocketAddr, session_id: u64, period: u64) -> [u8; COOKIE_SIZE] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.key);
+        hasher.update(addr.to_string());
+        hasher.update(session_id.to_le_bytes());
+        hasher.update(period.to_le_bytes());
+        hasher.finalize().into()
     }
+
+    fn current_period(&self) -> u64 {
+        self.started.elapsed().as_secs() / COOKIE_PERIOD.as_secs()
+    }
+
+    /// Cookie to send to a client now
+    fn current(&self, addr: SocketAddr, session_id: u64) -> [u8; COOKIE_SIZE] {
+        self.cookie(addr, session_id, self.current_period())
+    }
+
+    fn is_valid(&self, addr: SocketAddr, session_id: u64, cookie: &[u8]) -> bool {
+        let period = self.current_period();
+        cookie == self.cookie(addr, session_id, period)
+            || (period > 0 && cookie == self.cookie(addr, session_id, period - 1))
+    }
+}
+
+/// Encrypted channels of a UDP server, by client address and session id. Key exchanges and
+/// decryption run outside of the lock of the table.
+struct ServerChannels {
+    table: Mutex<ChannelTable>,
+    cookies: Cookies,
+}
+
+impl ServerChannels {
+    fn new() -> Self {
+        Self {
+            table: Mutex::new(ChannelTable::default()),
+            cookies: Cookies::new(),
+        }
+    }
+
+    fn receive(&self, identity: &KeyPair, addr: SocketAddr, datagram: &[u8]) -> Received {
+        let Some((kind, session_id, body)) = parse_secure_datagram(datagram) else {
+            debug!("Discarding malformed datagram from {}", addr);
+            return Received::Nothing;
+        };
+        match kind {
+            HELLO => {
+                let Some((cookie, hello)) = body.split_at_checked(COOKIE_SIZE) else {
+                    debug!("Discarding malformed hello from {}", addr);
+                    return Received::Nothing;
+                };
+                let Ok(hello) = bincode::deserialize::<Hello>(hello) else {
+                    debug!("Discarding malformed hello from {}", addr);
+                    return Received::Nothing;
+                };
+                if let Some(accept) = self.retransmitted_accept(addr, session_id, &hello) {
+                    return Received::Reply(accept);
+                }
+                if !self.cookies.is_valid(addr, session_id, cookie) {
+                    let cookie = self.cookies.current(addr, session_id);
+                    return Received::Reply(secure_datagram(COOKIE, session_id, &cookie));
+                }
+                let (session, accept) = match channel::accept(identity, &hello) {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        debug!("Rejected handshake from {}: {}", addr, e);
+                        return Received::Nothing;
+                    }
+                };
+                let accept = bincode::serialize(&accept).expect("Serialization should not fail");
+                let accept = secure_datagram(ACCEPT, session_id, &accept);
+                let mut table = self.table.lock().unwrap();
+                // The same hello may have been accepted meanwhile, and its answer sent
+                if let Some(channel) = table.get(&(addr, session_id))
+                    && channel.hello == hello.ephemeral
+                {
+                    return Received::Reply(channel.accept.clone());
+                }
+                table.insert((addr, session_id), ServerChannel {
+                    session: Arc::new(session),
+                    hello: hello.ephemeral,
+                    accept: accept.clone(),
+                    last_used: 0,
+                });
+                Received::Reply(accept)
+            }
+            DATA => {
+                let session = self.table.lock().unwrap().get(&(addr, session_id)).map(|channel| channel.session.clone());
+                let Some(session) = session else {
+                    return Received::Reply(secure_datagram(UNKNOWN_SESSION, session_id, &[]));
+                };
+                let Some(message) = session.open(&datagram[..HEADER_SIZE], body) else {
+                    debug!("Discarding forged or replayed datagram from {}", addr);
+                    return Received::Nothing;
+                };
+                self.table.lock().unwrap().touch(&(addr, session_id));
+                match unwrap(&message) {
+                    Some((request_id, data)) => Received::Request(session_id, session, request_id, data.to_vec()),
+                    None => Received::Nothing,
+                }
+            }
+            _ => {
+                // e.g. a plaintext request
+                debug!("Discarding datagram of unknown kind {} from {}", kind, addr);
+                Received::Nothing
+            }
+        }
+    }
+
+    /// Answer already sent to a hello, if the client sent it again
+    fn retransmitted_accept(&self, addr: SocketAddr, session_id: u64, hello: &Hello) -> Option<Vec<u8>> {
+        let table = self.table.lock().unwrap();
+        let channel = table.get(&(addr, session_id))?;
+        (channel.hello == hello.ephemeral).then(|| channel.accept.clone())
+    }
+}
+
+/// Receive buffers of a UDP server, reused from one datagram to the next
+struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    buffer_size: usize,
+}
+
+impl BufferPool {
+    fn new(buffer_size: usize) -> Arc<Self> {
+        Arc::new(Self {
+            buffers: Mutex::new(Vec::new()),
+            buffer_size,
+        })
+    }
+
+    /// Take a buffer from the pool, or allocate one if all are in use
+    fn take(self: &Arc<Self>) -> PooledBuffer {
+        let buffer = self.buffers
+            .lock()
+            .unwrap()
+            .pop()
+            .unwrap_or_else(|| vec![0; self.buffer_size]);
+        PooledBuffer {
+            buffer,
+            pool: self.clone(),
+        }
+    }
+}
+
+/// Buffer taken from a pool, which it goes back to when dropped
+struct PooledBuffer {
+    buffer: Vec<u8>,
+    pool: Arc<BufferPool>,
+}
+
+impl std::ops::Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl std::ops::DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        let buffer = std::mem::take(&mut self.buffer);
+        self.pool.buffers.lock().unwrap().push(buffer);
+    }
+}
+
+/// Bind a UDP socket sharing its address with the other sockets bound the same way, the
+/// kernel spreading the incoming datagrams among them
+#[cfg(unix)]
+fn bind_reuse_port(addr: SocketAddr) -> Result<UdpSocket, std::io::Error> {
+    use net2::unix::UnixUdpBuilderExt;
+
+    let builder = match addr {
+        SocketAddr::V4(_) => net2::UdpBuilder::new_v4()?,
+        SocketAddr::V6(_) => net2::UdpBuilder::new_v6()?,
+    };
+    builder.reuse_address(true)?;
+    builder.reuse_port(true)?;
+    let socket = builder.bind(addr)?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket)
 }
 
-/// UDP server for handling authority requests
+#[cfg(not(unix))]
+fn bind_reuse_port(_addr: SocketAddr) -> Result<UdpSocket, std::io::Error> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "SO_REUSEPORT is not available"))
+}
+
+/// UDP server for handling authority requests. Each socket has its own receive loop, and
+/// requests are processed concurrently, their responses being sent back as they complete.
 pub struct UdpServer {
-    socket: UdpSocket,
+    sockets: Vec<Arc<UdpSocket>>,
     buffer_size: usize,
+    /// Identity proven to clients, or `None` in plaintext mode
+    identity: Option<Arc<KeyPair>>,
+    max_concurrency: usize,
 }
 
 impl UdpServer {
-    pub async fn new(addr: SocketAddr) -> Result<Self, std::io::Error> {
-        let socket = UdpSocket::bind(addr).await?;
-        info!("Server listening on {}", addr);
+    pub async fn new(
+        addr: SocketAddr,
+        identity: Option<Arc<KeyPair>>,
+        config: ServerConfig
+    ) -> Result<Self, std::io::Error> {
+        let sockets = if config.udp_sockets > 1 {
+            (0..config.udp_sockets)
+                .map(|_| bind_reuse_port(addr).map(Arc::new))
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            vec![Arc::new(UdpSocket::bind(addr).await?)]
+        };
+        info!("Server listening on {} ({} sockets)", addr, sockets.len());
         Ok(Self {
-            socket,
+            sockets,
             buffer_size: DEFAULT_BUFFER_SIZE,
+            identity,
+            max_concurrency: config.max_concurrency.max(1),
         })
     }
 
-    /// Start the server and process incoming messages
-    pub async fn run<F>(&self, handler: F) -> Result<(), std::io::Error>
-        where F: Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync + 'static
-    {
-        let mut buffer = vec![0; self.buffer_size];
+    /// Start the server and process incoming messages, up to `max_concurrency` at a time
+    pub async fn run(&self, handler: Handler) -> Result<(), std::io::Error> {
+        let pipeline = UdpPipeline {
+            identity: self.identity.clone(),
+            channels: Arc::new(ServerChannels::new()),
+            handler,
+            permits: Arc::new(Semaphore::new(self.max_concurrency)),
+            buffers: BufferPool::new(self.buffer_size),
+        };
+        let receivers = self.sockets
+            .iter()
+            .map(|socket| pipeline.clone().receive(socket.clone()));
+        futures::future::join_all(receivers).await;
+        Ok(())
+    }
+}
+
+impl NetworkServer for UdpServer {
+    fn run(&self, handler: Handler) -> BoxFuture<'_, Result<(), std::io::Error>> {
+        Box::pin(UdpServer::run(self, handler))
+    }
+}
+
+/// State shared by the receive loops of a UDP server and the requests they spawn
+#[derive(Clone)]
+struct UdpPipeline {
+    identity: Option<Arc<KeyPair>>,
+    channels: Arc<ServerChannels>,
+    handler: Handler,
+    /// One permit per request being processed
+    permits: Arc<Semaphore>,
+    buffers: Arc<BufferPool>,
+}
 
+impl UdpPipeline {
+    /// Receive the datagrams of one socket, each processed in its own task. Receiving waits
+    /// for a permit, so that datagrams queue in the socket buffer once the server is busy.
+    async fn receive(self, socket: Arc<UdpSocket>) {
         loop {
-            match self.socket.recv_from(&mut buffer).await {
-                Ok((len, addr)) => {
-                    let data = &buffer[..len];
-                    if let Some(response) = handler(data) {
-                        if let Err(e) = self.socket.send_to(&response, addr).await {
+            let permit = self.permits.clone().acquire_owned().await.expect("Permits are never closed");
+            let mut buffer = self.buffers.take();
+            let (len, addr) = match socket.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                Err(e) => {
+                    error!("Failed to receive data: {}", e);
+                    continue;
+                }
+            };
+            let pipeline = self.clone();
+            let socket = socket.clone();
+            tokio::spawn(async move {
+                pipeline.process(&socket, buffer, len, addr).await;
+                drop(permit);
+            });
+        }
+    }
+
+    /// Process one datagram and send back its response, if any
+    async fn process(&self, socket: &UdpSocket, buffer: PooledBuffer, len: usize, addr: SocketAddr) {
+        let datagram = &buffer[..len];
+        let (session, request_id, data) = match &self.identity {
+            None => match unwrap(datagram) {
+                Some((request_id, data)) => (None, request_id, data.to_vec()),
+                None => {
+                    debug!("Discarding malformed request from {}", addr);
+                    return;
+                }
+            },
+            Some(identity) => {
+                let received = self.channels.receive(identity, addr, datagram);
+                match received {
+                    Received::Request(session_id, session, request_id, data) => {
+                        (Some((session_id, session)), request_id, data)
+                    }
+                    Received::Reply(reply) => {
+                        if let Err(e) = socket.send_to(&reply, addr).await {
                             error!("Failed to send response: {}", e);
                         }
+                        return;
+                    }
+                    Received::Nothing => return,
+                }
+            }
+        };
+        // The request is copied out, so the buffer can serve the next datagram
+        drop(buffer);
+
+        let peer = Peer {
+            address: addr,
+            identity: session.as_ref().map(|(_, session)| session.peer),
+        };
+        let Some(response) = (self.handler)(peer, data).await else {
+            return;
+        };
+        let response = wrap(request_id, &response);
+        let datagram = match &session {
+            None => response,
+            Some((session_id, session)) => sealed_datagram(session, *session_id, &response),
+        };
+        if let Err(e) = socket.send_to(&datagram, addr).await {
+            error!("Failed to send response: {}", e);
+        }
+    }
+}
+
+/// Connection of a TCP client to one shard, carrying any number of concurrent requests.
+/// Whole frames are queued to a writer task, so that a cancelled request never leaves a
+/// partial frame on the stream.
+struct TcpConnection {
+    frames: mpsc::UnboundedSender<Vec<u8>>,
+    pending: PendingRequests,
+    /// Encrypted channel, or `None` in plaintext mode
+    session: Option<Arc<Session>>,
+    closed: Arc<AtomicBool>,
+    tasks: [JoinHandle<()>; 2],
+}
+
+impl TcpConnection {
+    /// Connect to a shard, and prove `identity` to it if given
+    async fn connect(
+        addr: SocketAddr,
+        authority: AuthorityName,
+        identity: Option<&KeyPair>
+    ) -> Result<Self, std::io::Error> {
+        let mut stream = TcpStream::connect(addr).await?;
+        let _ = stream.set_nodelay(true);
+        let session = match identity {
+            None => None,
+            Some(identity) => {
+                let invalid = |e: String| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+                let initiator = Initiator::new(identity, authority);
+                let hello = bincode::serialize(initiator.hello()).expect("Serialization should not fail");
+                let mut cookie = [0; COOKIE_SIZE];
+                let mut refreshed = false;
+                let accept: Accept = loop {
+                    stream.write_all(&frame(&[&cookie[..], &hello].concat())).await?;
+                    let reply = read_frame(&mut stream).await?;
+                    match reply.split_first() {
+                        Some((&ACCEPT, accept)) => break bincode::deserialize(accept).map_err(|e| invalid(e.to_string()))?,
+                        // Sent again with the cookie of the server, at most once
+                        Some((&COOKIE, new_cookie)) if !refreshed => {
+                            cookie = new_cookie.try_into().map_err(|_| invalid("Invalid cookie".to_string()))?;
+                            refreshed = true;
+                        }
+                        _ => return Err(invalid(format!("Unexpected answer to the hello of {}", addr))),
+                    }
+                };
+                let session = initiator
+                    .finish(&accept)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::PermissionDenied, e.to_string()))?;
+                Some(Arc::new(session))
+            }
+        };
+        Ok(Self::new(stream, addr, session))
+    }
+
+    fn new(stream: TcpStream, addr: SocketAddr, session: Option<Arc<Session>>) -> Self {
+        let (mut reader, mut writer) = stream.into_split();
+        let (frames, mut outgoing) = mpsc::unbounded_channel::<Vec<u8>>();
+        let pending = PendingRequests::default();
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let writer_task = {
+            let (pending, closed) = (pending.clone(), closed.clone());
+            tokio::spawn(async move {
+                while let Some(frame) = outgoing.recv().await {
+                    if let Err(e) = writer.write_all(&frame).await {
+                        debug!("Failed to write to {}: {}", addr, e);
+                        break;
+                    }
+                }
+                Self::close(&pending, &closed);
+            })
+        };
+        let reader_task = {
+            let (pending, closed, session) = (pending.clone(), closed.clone(), session.clone());
+            tokio::spawn(async move {
+                loop {
+                    let body = match read_frame(&mut reader).await {
+                        Ok(body) => body,
+                        Err(e) => {
+                            debug!("Connection to {} closed: {}", addr, e);
+                            break;
+                        }
+                    };
+                    let message = match &session {
+                        None => Some(body),
+                        Some(session) => session.open(&[], &body),
+                    };
+                    let Some((request_id, payload)) = message.as_deref().and_then(unwrap) else {
+                        debug!("Closing connection to {} after an invalid frame", addr);
+                        break;
+                    };
+                    match pending.lock().unwrap().remove(&(addr, request_id)) {
+                        Some(sender) => {
+                            let _ = sender.send(payload.to_vec());
+                        }
+                        None => debug!("Discarding unmatched response {} from {}", request_id, addr),
                     }
                 }
+                Self::close(&pending, &closed);
+            })
+        };
+
+        Self {
+            frames,
+            pending,
+            session,
+            closed,
+            tasks: [writer_task, reader_task],
+        }
+    }
+
+    /// Fail the waiting requests, and prevent new ones
+    fn close(pending: &PendingRequests, closed: &AtomicBool) {
+        let mut pending = pending.lock().unwrap();
+        closed.store(true, Ordering::Relaxed);
+        pending.clear();
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for TcpConnection {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// TCP client for communicating with authority shards, keeping one connection per shard.
+/// Responses are matched to their request by request id, as with UDP.
+pub struct TcpClient {
+    connections: Mutex<HashMap<SocketAddr, Arc<TcpConnection>>>,
+    next_request_id: AtomicU64,
+    timeout: Duration,
+    /// Identity proven to the shards, or `None` in plaintext mode
+    identity: Option<Arc<KeyPair>>,
+}
+
+impl TcpClient {
+    /// Create a client proving `identity` to the shards, or sending plaintext if `None`
+    pub fn new(identity: Option<Arc<KeyPair>>) -> Self {
+        Self::with_timeout(identity, DEFAULT_TIMEOUT * DEFAULT_ATTEMPTS)
+    }
+
+    /// Create a client waiting up to `timeout` for each connection and each response
+    pub fn with_timeout(identity: Option<Arc<KeyPair>>, timeout: Duration) -> Self {
+        Self {
+            connections: Mutex::new(HashMap::new()),
+            next_request_id: AtomicU64::new(rand::random()),
+            timeout,
+            identity,
+        }
+    }
+
+    /// Reuse the connection to a shard, or open a new one if there is none or it broke
+    async fn connection(
+        &self,
+        authority: AuthorityName,
+        addr: SocketAddr
+    ) -> Result<Arc<TcpConnection>, FastPayError> {
+        if let Some(connection) = self.connections.lock().unwrap().get(&addr)
+            && !connection.is_closed()
+            && connection.session.as_ref().is_none_or(|session| session.peer == authority)
+        {
+            return Ok(connection.clone());
+        }
+        let connect = TcpConnection::connect(addr, authority, self.identity.as_deref());
+        let connection = tokio::time::timeout(self.timeout, connect)
+            .await
+            .map_err(|_| FastPayError::ClientIoError {
+                error: format!("Timeout connecting to {}", addr),
+            })?
+            .map_err(|e| FastPayError::ClientIoError {
+                error: format!("Failed to connect to {}: {}", addr, e),
+            })?;
+        let connection = Arc::new(connection);
+        self.connections.lock().unwrap().insert(addr, connection.clone());
+        Ok(connection)
+    }
+
+    /// Send a message and receive its response
+    pub async fn send_recv(
+        &self,
+        authority: AuthorityName,
+        addr: SocketAddr,
+        data: Vec<u8>
+    ) -> Result<Vec<u8>, FastPayError> {
+        let connection = self.connection(authority, addr).await?;
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = oneshot::channel();
+        {
+            let mut pending = connection.pending.lock().unwrap();
+            if connection.is_closed() {
+                return Err(FastPayError::CommunicationError);
+            }
+            pending.insert((addr, request_id), sender);
+        }
+        let _guard = PendingGuard {
+            pending: &connection.pending,
+            key: (addr, request_id),
+        };
+
+        let message = wrap(request_id, &data);
+        let body = match &connection.session {
+            None => message,
+            Some(session) => session.seal(&[], &message),
+        };
+        connection.frames.send(frame(&body)).map_err(|_| FastPayError::CommunicationError)?;
+        match tokio::time::timeout(self.timeout, receiver).await {
+            Ok(response) => response.map_err(|_| FastPayError::CommunicationError),
+            Err(_) => Err(FastPayError::ClientIoError {
+                error: format!("No response from {} within {:?}", addr, self.timeout),
+            }),
+        }
+    }
+}
+
+impl NetworkClient for TcpClient {
+    fn send_recv(
+        &self,
+        authority: AuthorityName,
+        addr: SocketAddr,
+        data: Vec<u8>
+    ) -> BoxFuture<'_, Result<Vec<u8>, FastPayError>> {
+        Box::pin(TcpClient::send_recv(self, authority, addr, data))
+    }
+}
+
+/// Number of connections served for each source host
+type SourceCounts = Arc<Mutex<HashMap<IpAddr, usize>>>;
+
+/// Connection counted against the limit of its source host until dropped
+struct SourceSlot {
+    sources: SourceCounts,
+    ip: IpAddr,
+}
+
+impl SourceSlot {
+    /// Count a connection from `ip`, unless it already has `limit` of them
+    fn take(sources: &SourceCounts, ip: IpAddr, limit: usize) -> Option<Self> {
+        let mut counts = sources.lock().unwrap();
+        let count = counts.entry(ip).or_default();
+        if *count >= limit {
+            return None;
+        }
+        *count += 1;
+        Some(Self {
+            sources: sources.clone(),
+            ip,
+        })
+    }
+}
+
+impl Drop for SourceSlot {
+    fn drop(&mut self) {
+        let mut counts = self.sources.lock().unwrap();
+        if let Some(count) = counts.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// TCP server for handling authority requests. The requests of a connection are processed
+/// concurrently, and their responses sent back as they complete. In encrypted mode, a
+/// connection opens with a cookie exchange before the key exchange, as with UDP, and any
+/// client completing it is served: the handler checks the identity where it matters.
+pub struct TcpServer {
+    listener: TcpListener,
+    /// Identity proven to clients, or `None` in plaintext mode
+    identity: Option<Arc<KeyPair>>,
+    /// One permit per request being processed, across all connections
+    permits: Arc<Semaphore>,
+    /// One permit per connection being served
+    connections: Arc<Semaphore>,
+    sources: SourceCounts,
+    max_connections_per_source: usize,
+    cookies: Arc<Cookies>,
+}
+
+impl TcpServer {
+    pub async fn new(
+        addr: SocketAddr,
+        identity: Option<Arc<KeyPair>>,
+        config: ServerConfig
+    ) -> Result<Self, std::io::Error> {
+        let listener = TcpListener::bind(addr).await?;
+        info!("Server listening on {} (TCP)", addr);
+        Ok(Self {
+            listener,
+            identity,
+            permits: Arc::new(Semaphore::new(config.max_concurrency.max(1))),
+            connections: Arc::new(Semaphore::new(MAX_CONNECTIONS)),
+            sources: SourceCounts::default(),
+            max_connections_per_source: MAX_CONNECTIONS_PER_SOURCE,
+            cookies: Arc::new(Cookies::new()),
+        })
+    }
+
+    /// Start the server and process incoming connections
+    pub async fn run(&self, handler: Handler) -> Result<(), std::io::Error> {
+        loop {
+            // Leave further connections in the backlog while the server is full
+            let connection = self.connections.clone().acquire_owned().await.expect("Permits are never closed");
+            match self.listener.accept().await {
+                Ok((stream, address)) => {
+                    let Some(source) = SourceSlot::take(&self.sources, address.ip(), self.max_connections_per_source) else {
+                        debug!("Refusing connection from {}: too many from its host", address);
+                        continue;
+                    };
+                    let _ = stream.set_nodelay(true);
+                    let (handler, identity, permits) = (handler.clone(), self.identity.clone(), self.permits.clone());
+                    let cookies = self.cookies.clone();
+                    tokio::spawn(async move {
+                        Self::serve(stream, address, handler, identity, permits, cookies).await;
+                        drop((connection, source));
+                    });
+                }
                 Err(e) => {
-                    error!("Failed to receive data: {}", e);
+                    error!("Failed to accept connection: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Answer the hello that opens an encrypted connection, once it carries a cookie. Cookies
+    /// are bound to the address of the connection, with no session id.
+    async fn accept(
+        stream: &mut TcpStream,
+        address: SocketAddr,
+        identity: &KeyPair,
+        cookies: &Cookies
+    ) -> Result<Session, std::io::Error> {
+        let invalid = |e: String| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+        let mut refreshed = false;
+        loop {
+            let body = read_frame(stream).await?;
+            let (cookie, hello) = body
+                .split_at_checked(COOKIE_SIZE)
+                .ok_or_else(|| invalid("Truncated hello".to_string()))?;
+            if !cookies.is_valid(address, 0, cookie) {
+                if refreshed {
+                    return Err(invalid("Invalid cookie".to_string()));
                 }
+                refreshed = true;
+                stream.write_all(&frame(&[&[COOKIE][..], &cookies.current(address, 0)].concat())).await?;
+                continue;
             }
+            let hello: Hello = bincode::deserialize(hello).map_err(|e| invalid(e.to_string()))?;
+            let (session, accept) = channel::accept(identity, &hello).map_err(|e| invalid(e.to_string()))?;
+            let accept = bincode::serialize(&accept).expect("Serialization should not fail");
+            stream.write_all(&frame(&[&[ACCEPT][..], &accept].concat())).await?;
+            return Ok(session);
+        }
+    }
+
+    async fn serve(
+        mut stream: TcpStream,
+        address: SocketAddr,
+        handler: Handler,
+        identity: Option<Arc<KeyPair>>,
+        permits: Arc<Semaphore>,
+        cookies: Arc<Cookies>
+    ) {
+        let session = match identity {
+            None => None,
+            Some(identity) => {
+                let handshake = Self::accept(&mut stream, address, &identity, &cookies);
+                match tokio::time::timeout(DEFAULT_TIMEOUT * DEFAULT_ATTEMPTS, handshake).await {
+                    Ok(Ok(session)) => Some(Arc::new(session)),
+                    Ok(Err(e)) => {
+                        debug!("Rejected handshake from {}: {}", address, e);
+                        return;
+                    }
+                    Err(_) => {
+                        debug!("No handshake from {}", address);
+                        return;
+                    }
+                }
+            }
+        };
+        let peer = Peer {
+            address,
+            identity: session.as_ref().map(|session| session.peer),
+        };
+
+        let (mut reader, mut writer) = stream.into_split();
+        let (responses, mut outgoing) = mpsc::unbounded_channel::<Vec<u8>>();
+        let writer_task = tokio::spawn(async move {
+            while let Some(frame) = outgoing.recv().await {
+                if let Err(e) = writer.write_all(&frame).await {
+                    debug!("Failed to send response to {}: {}", address, e);
+                    break;
+                }
+            }
+        });
+
+        loop {
+            let body = match read_frame(&mut reader).await {
+                Ok(body) => body,
+                Err(e) => {
+                    if e.kind() != std::io::ErrorKind::UnexpectedEof {
+                        debug!("Closing connection from {}: {}", peer, e);
+                    }
+                    break;
+                }
+            };
+            let message = match &session {
+                None => Some(body),
+                Some(session) => session.open(&[], &body),
+            };
+            let Some((request_id, data)) = message.as_deref().and_then(unwrap) else {
+                debug!("Closing connection from {} after an invalid frame", peer);
+                break;
+            };
+            // Stop reading the connection while the server is busy
+            let permit = permits.clone().acquire_owned().await.expect("Permits are never closed");
+            let (handler, responses, session) = (handler.clone(), responses.clone(), session.clone());
+            let data = data.to_vec();
+            tokio::spawn(async move {
+                let _permit = permit;
+                if let Some(response) = handler(peer, data).await {
+                    let response = wrap(request_id, &response);
+                    let body = match &session {
+                        None => response,
+                        Some(session) => session.seal(&[], &response),
+                    };
+                    let _ = responses.send(frame(&body));
+                }
+            });
         }
+
+        // Answer the requests still being processed before closing
+        drop(responses);
+        let _ = writer_task.await;
+    }
+}
+
+impl NetworkServer for TcpServer {
+    fn run(&self, handler: Handler) -> BoxFuture<'_, Result<(), std::io::Error>> {
+        Box::pin(TcpServer::run(self, handler))
     }
 }
 
 /// Authority client for a specific shard
 pub struct AuthorityShardClient {
-    client: UdpClient,
+    client: Arc<dyn NetworkClient>,
     address: SocketAddr,
-    _authority: AuthorityName,
+    authority: AuthorityName,
+    envelope: Envelope,
 }
 
 impl AuthorityShardClient {
-    pub async fn new(
-        _authority: AuthorityName,
-        address: SocketAddr
-    ) -> Result<Self, std::io::Error> {
-        let client = UdpClient::new().await?;
-
-        Ok(Self {
+    /// Create a client for a shard of `network`, sharing the transport `client` with other shards
+    pub fn new(
+        authority: AuthorityName,
+        address: SocketAddr,
+        network: NetworkId,
+        client: Arc<dyn NetworkClient>
+    ) -> Self {
+        Self {
             client,
             address,
-            _authority,
-        })
+            authority,
+            envelope: Envelope::new(network),
+        }
     }
 
     /// Send a transfer order to the authority
     pub async fn send_transfer_order(
         &self,
         order: &CrossChainTransferOrder
-    ) -> Result<SignedCrossChainTransferOrder, FastPayError> {
+    ) -> Result<TransferOrderResponse, FastPayError> {
         // Serialize and send the order
-        let request = serialize_transfer_order(order);
-        let response_bytes = self.client.send_recv(self.address, request).await?;
+        let request = serialize_transfer_order(self.envelope, order)?;
+        let response_bytes = self.client.send_recv(self.authority, self.address, request).await?;
 
         // Deserialize the response
-        match deserialize_message(&response_bytes)? {
-            BridgeMessage::SignedCrossChainTransferOrder(signed_order) => Ok(signed_order),
-            BridgeMessage::Error(error) => {
-                error!("Authority returned error: {}", error);
+        match deserialize_message(self.envelope.network, &response_bytes)? {
+            BridgeMessage::SignedCrossChainTransferOrder(signed_order) => {
+                Ok(TransferOrderResponse::Vote(signed_order))
+            }
+            BridgeMessage::CertifiedCrossChainTransferOrder(certificate) => {
+                Ok(TransferOrderResponse::Certificate(certificate))
+            }
+            // The authority received the order and refused to vote for it
+            BridgeMessage::Error(error) => Err(error),
+            _ => {
+                error!("Unexpected response from authority");
                 Err(FastPayError::CommunicationError)
             }
+        }
+    }
+
+    /// Ask the authority for its vote and the certificate of a transfer
+    pub async fn get_transfer_info(
+        &self,
+        request: &TransferInfoRequest
+    ) -> Result<TransferInfoResponse, FastPayError> {
+        let request = serialize_transfer_info_request(self.envelope, request)?;
+        let response_bytes = self.client.send_recv(self.authority, self.address, request).await?;
+
+        match deserialize_message(self.envelope.network, &response_bytes)? {
+            BridgeMessage::TransferInfoResponse(response) => Ok(response),
+            BridgeMessage::Error(error) => Err(error),
             _ => {
                 error!("Unexpected response from authority");
                 Err(FastPayError::CommunicationError)
@@ -128,11 +1478,116 @@ impl AuthorityShardClient {
         &self,
         order: &CertifiedCrossChainTransferOrder
     ) -> Result<(), FastPayError> {
-        // Serialize and send the order
-        let request = serialize_certified_order(order);
-        let _ = self.client.send_recv(self.address, request).await?;
+        let request = serialize_certified_order(self.envelope, order)?;
+        self.send_acknowledged(request).await
+    }
 
-        // We don't expect a response for this message
-        Ok(())
+    /// Send a message that the authority only acknowledges
+    async fn send_acknowledged(&self, request: Vec<u8>) -> Result<(), FastPayError> {
+        let response_bytes = self.client.send_recv(self.authority, self.address, request).await?;
+
+        match deserialize_message(self.envelope.network, &response_bytes)? {
+            BridgeMessage::Ack => Ok(()),
+            BridgeMessage::Error(error) => Err(error),
+            _ => {
+                error!("Unexpected response from authority");
+                Err(FastPayError::CommunicationError)
+            }
+        }
+    }
+
+    /// Ask the authority to vote for refunding a transfer
+    pub async fn send_refund_order(
+        &self,
+        order: &CrossChainRefundOrder
+    ) -> Result<RefundOrderResponse, FastPayError> {
+        let request = serialize_refund_order(self.envelope, order)?;
+        let response_bytes = self.client.send_recv(self.authority, self.address, request).await?;
+
+        match deserialize_message(self.envelope.network, &response_bytes)? {
+            BridgeMessage::SignedCrossChainRefundOrder(signed_order) => {
+                Ok(RefundOrderResponse::Vote(signed_order))
+            }
+            BridgeMessage::CertifiedCrossChainRefundOrder(certificate) => {
+                Ok(RefundOrderResponse::Certificate(certificate))
+            }
+            BridgeMessage::Error(error) => Err(error),
+            _ => {
+                error!("Unexpected response from authority");
+                Err(FastPayError::CommunicationError)
+            }
+        }
+    }
+
+    /// Send a refund certificate to the shard of the sender of the transfer
+    pub async fn send_refund_certificate(
+        &self,
+        certificate: &CertifiedCrossChainRefundOrder
+    ) -> Result<(), FastPayError> {
+        self.send_acknowledged(serialize_certified_refund_order(self.envelope, certificate)?).await
+    }
+
+    /// Send a message order to the authority
+    pub async fn send_message_order(
+        &self,
+        order: &CrossChainMessageOrder
+    ) -> Result<MessageOrderResponse, FastPayError> {
+        let request = serialize_message_order(self.envelope, order)?;
+        let response_bytes = self.client.send_recv(self.authority, self.address, request).await?;
+
+        match deserialize_message(self.envelope.network, &response_bytes)? {
+            BridgeMessage::SignedCrossChainMessageOrder(signed_order) => {
+                Ok(MessageOrderResponse::Vote(signed_order))
+            }
+            BridgeMessage::CertifiedCrossChainMessageOrder(certificate) => {
+                Ok(MessageOrderResponse::Certificate(certificate))
+            }
+            BridgeMessage::Error(error) => Err(error),
+            _ => {
+                error!("Unexpected response from authority");
+                Err(FastPayError::CommunicationError)
+            }
+        }
+    }
+
+    /// Send the certificate of a message to the shard of its sender
+    pub async fn send_message_certificate(
+        &self,
+        certificate: &CertifiedCrossChainMessageOrder
+    ) -> Result<(), FastPayError> {
+        self.send_acknowledged(serialize_certified_message_order(self.envelope, certificate)?).await
+    }
+
+    /// Ask the authority for the committee changes past an epoch
+    pub async fn get_committee_info(
+        &self,
+        since_epoch: Epoch
+    ) -> Result<CommitteeInfoResponse, FastPayError> {
+        let request = serialize_committee_info_request(self.envelope, &CommitteeInfoRequest { since_epoch })?;
+        self.committee_info(request).await
+    }
+
+    /// Hand the authority over to the committee of the next epoch
+    pub async fn send_committee_change(
+        &self,
+        change: &CertifiedCommitteeChange
+    ) -> Result<CommitteeInfoResponse, FastPayError> {
+        self.committee_info(serialize_committee_change(self.envelope, change)?).await
+    }
+
+    async fn committee_info(&self, request: Vec<u8>) -> Result<CommitteeInfoResponse, FastPayError> {
+        let response_bytes = self.client.send_recv(self.authority, self.address, request).await?;
+        match deserialize_message(self.envelope.network, &response_bytes)? {
+            BridgeMessage::CommitteeInfoResponse(response) => Ok(response),
+            BridgeMessage::Error(error) => Err(error),
+            _ => {
+                error!("Unexpected response from authority");
+                Err(FastPayError::CommunicationError)
+            }
+        }
     }
 }
+
+#[cfg(test)]
+#[path = "unit_tests/network_tests.rs"]
+mod network_tests;
@@ -0,0 +1,157 @@
+use failure::Error;
+use fast_core::{base_types::*, committee::*, config::*};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::sync::Arc;
+use structopt::StructOpt;
+
+use crate::network::{AuthorityShardClient, NetworkClients};
+
+#[derive(Debug, StructOpt)]
+pub struct SignCommitteeChangeOpt {
+    /// Path to the configuration of the signing authority
+    #[structopt(long)]
+    config: String,
+
+    /// Committee configuration file of the next epoch
+    #[structopt(long)]
+    next_committee: String,
+
+    /// Where to write the vote
+    #[structopt(long)]
+    output: String,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ReconfigureOpt {
+    /// Committee configuration file of the current epoch
+    #[structopt(long)]
+    committee: String,
+
+    /// Committee configuration file of the next epoch
+    #[structopt(long)]
+    next_committee: String,
+
+    /// Votes of the current committee, as written by `sign-committee-change`
+    #[structopt(long, required = true)]
+    votes: Vec<String>,
+}
+
+/// Vote of an authority for a committee change, as stored on disk
+#[derive(Debug, Serialize, Deserialize)]
+struct CommitteeChangeVote {
+    epoch: Epoch,
+    authority: String,
+    signature: String,
+}
+
+/// Read the committee change described by a committee configuration file
+fn load_committee_change(config: &CommitteeConfig) -> Result<CommitteeChange, Error> {
+    let committee = config.committee()?;
+    Ok(CommitteeChange {
+        network: committee.network,
+        epoch: committee.epoch,
+        voting_rights: committee.voting_rights,
+        num_shards: committee.num_shards,
+    })
+}
+
+/// Sign the hand-over to the next committee with the key of one authority
+pub async fn sign_committee_change(opt: SignCommitteeChangeOpt) -> Result<(), Error> {
+    let config = AuthorityConfig::read(&opt.config)?;
+    let secret = config.key_pair()?;
+    let name = config.authority_name()?;
+    let change = load_committee_change(&CommitteeConfig::read(&opt.next_committee)?)?;
+
+    let signed = SignedCommitteeChange::new(change, name, &secret);
+    let vote = CommitteeChangeVote {
+        epoch: signed.value.epoch,
+        authority: config.name.trim().to_string(),
+        signature: hex::encode(signed.signature.0.to_bytes()),
+    };
+    let writer = BufWriter::new(File::create(&opt.output)?);
+    serde_json::to_writer_pretty(writer, &vote)?;
+
+    info!("Signed committee change to epoch {} into {}", vote.epoch, opt.output);
+    Ok(())
+}
+
+/// Certify the hand-over to the next committee and push it to all authorities
+pub async fn reconfigure(opt: ReconfigureOpt) -> Result<(), Error> {
+    let committee_config = CommitteeConfig::read(&opt.committee)?;
+    let next_committee_config = CommitteeConfig::read(&opt.next_committee)?;
+    let committee = committee_config.committee()?;
+    let change = load_committee_change(&next_committee_config)?;
+    if next_committee_config.network != committee_config.network {
+        return Err(failure::format_err!(
+            "The next committee is on network {}, not {}",
+            next_committee_config.network,
+            committee_config.network
+        ));
+    }
+
+    // Collect the votes of the current committee
+    let mut signatures = Vec::new();
+    for path in &opt.votes {
+        let vote: CommitteeChangeVote = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        if vote.epoch != change.epoch {
+            return Err(failure::format_err!(
+                "Vote {} is for epoch {}, not {}",
+                path,
+                vote.epoch,
+                change.epoch
+            ));
+        }
+        let bytes = hex::decode(vote.signature.trim())?;
+        if bytes.len() != 64 {
+            return Err(failure::format_err!("Invalid signature length in {}", path));
+        }
+        let mut signature = [0u8; 64];
+        signature.copy_from_slice(&bytes);
+        let authority = decode_authority_name(&vote.authority)?;
+        let signature = Signature::from(signature);
+        signature.check(&change, authority)?;
+        signatures.push((authority, signature));
+    }
+    let certificate = CertifiedCommitteeChange {
+        value: change,
+        signatures,
+    };
+    certificate.check(&committee)?;
+
+    // Push the change to the authorities of both epochs
+    let entries = committee_config
+        .authorities
+        .iter()
+        .chain(&next_committee_config.authorities);
+    // The change is certified by the committee, so the operator needs no lasting identity
+    let clients = NetworkClients::new(Arc::new(KeyPair::from(rand::random()))).await?;
+    let mut notified = HashSet::new();
+    for entry in entries {
+        let name = entry.authority_name()?;
+        if !notified.insert(name) {
+            continue;
+        }
+        let client = clients.get(entry.transport, entry.security);
+        // The shards of an authority share its committees, so shard 0 speaks for all of them
+        let shard = AuthorityShardClient::new(
+            name,
+            entry.shard_address(0)?,
+            committee_config.network,
+            client,
+        );
+        match shard.send_committee_change(&certificate).await {
+            Ok(response) => info!(
+                "Authority {:?} is at epoch {}",
+                name.base58(),
+                response.epoch
+            ),
+            Err(e) => error!("Failed to reconfigure authority {:?}: {:?}", name.base58(), e),
+        }
+    }
+
+    Ok(())
+}
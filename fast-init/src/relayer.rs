@@ -1,16 +1,19 @@
 use failure::Error;
-use fast_core::{base_types::*, committee::Committee, message::*};
+use fast_core::{base_types::*, committee::*, config::*, error::FastPayError, message::*, sharding::ShardingStrategy};
+use futures::future::join_all;
 use log::{error, info};
-use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap};
-use std::fs::File;
-use std::io::BufReader;
-use std::net::SocketAddr;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use structopt::StructOpt;
 use tokio::time::sleep;
 
-use crate::network::AuthorityShardClient;
+use crate::jobs::{JobLog, JobRecord, JobState};
+use crate::network::{AuthorityShardClient, NetworkClients};
+use crate::submitter::{DestinationSubmitter, PortalInstruction, Submission, SubmissionStatus, SubmitterSpec};
+use crate::watcher::{SourceCursors, SourceOrder, SourceWatcher, WatcherSpec};
 
 #[derive(Debug, StructOpt)]
 pub struct RelayerOpt {
@@ -29,20 +32,52 @@ pub struct RelayerOpt {
     /// Polling interval in milliseconds
     #[structopt(long, default_value = "1000")]
     polling_interval: u64,
-}
 
-#[derive(Debug, Serialize, Deserialize)]
-struct CommitteeConfig {
-    authorities: Vec<AuthorityEntry>,
-}
+    /// Source of transfer orders, one per source chain: `file:<CHAIN>:<PATH>`, `stdin:<CHAIN>`,
+    /// `http:<CHAIN>:<ADDRESS>`, `svm:<CHAIN>:<PORTAL_PROGRAM>` (read from the source RPC) or
+    /// `demo:<CHAIN>`
+    #[structopt(long = "watch", required = true)]
+    watch: Vec<WatcherSpec>,
+
+    /// Directory where the relayer keeps its progress
+    #[structopt(long, default_value = "relayer_state")]
+    state_dir: PathBuf,
+
+    /// Where to deliver certificates: `file:<PATH>` or `svm:<PORTAL_PROGRAM>` (sent to the
+    /// destination RPC). Defaults to `destination.jsonl` in the state directory
+    #[structopt(long)]
+    submit_to: Option<SubmitterSpec>,
+
+    /// Where to submit refunds: `file:<PATH>` or `svm:<PORTAL_PROGRAM>` (sent to the source
+    /// RPC). Defaults to `source.jsonl` in the state directory
+    #[structopt(long)]
+    refund_to: Option<SubmitterSpec>,
+
+    /// Key pair paying for destination and refund transactions, as written by `solana-keygen`
+    #[structopt(long)]
+    payer: Option<PathBuf>,
+
+    /// Commitment level at which deliveries are confirmed
+    #[structopt(long, default_value = "finalized")]
+    destination_commitment: String,
+
+    /// Commitment level at which refunds are confirmed
+    #[structopt(long, default_value = "finalized")]
+    source_commitment: String,
+
+    /// Time allowed to authorities to answer a transfer order, in milliseconds
+    #[structopt(long, default_value = "2000")]
+    request_timeout: u64,
 
-#[derive(Debug, Serialize, Deserialize)]
-struct AuthorityEntry {
-    name: String,
-    host: String,
-    port: u16,
-    weight: u64,
-    num_shards: u32,
+    /// Time allowed to authorities for the transfers of a given source chain:
+    /// `<CHAIN>:<MILLISECONDS>`
+    #[structopt(long = "chain-timeout")]
+    chain_timeouts: Vec<ChainTimeout>,
+
+    /// Key pair proving the identity of the relayer to the authorities, as written by
+    /// `solana-keygen`. Defaults to `identity.json` in the state directory, created if missing
+    #[structopt(long)]
+    identity: Option<PathBuf>,
 }
 
 /// Pending transfer state
@@ -50,204 +85,837 @@ struct PendingTransfer {
     order: CrossChainTransferOrder,
     signed_orders: HashMap<AuthorityName, SignedCrossChainTransferOrder>,
     weight: usize,
-    start_time: Instant,
+    /// Certificate returned by an authority that already processed the transfer
+    certificate: Option<CertifiedCrossChainTransferOrder>,
+    observed_at: Timestamp,
+    delivery: Delivery,
+    /// When to ask again the authorities that did not vote
+    retries: HashMap<AuthorityName, AuthorityRetry>,
+}
+
+/// Pending message state
+struct PendingMessage {
+    order: CrossChainMessageOrder,
+    certificate: Option<CertifiedCrossChainMessageOrder>,
+    delivery: Delivery,
+}
+
+/// Pending refund state
+struct PendingRefund {
+    order: CrossChainRefundOrder,
+    certificate: Option<CertifiedCrossChainRefundOrder>,
+    /// When the transfer was seen on its source chain
+    observed_at: Timestamp,
+    /// Submission of the certificate to the source portal
+    delivery: Delivery,
+}
+
+/// Progress of a transfer, message or refund towards its delivery
+struct Delivery {
+    state: JobState,
+    /// Certificate sent to the destination chain, waiting for confirmation
+    submission: Option<Submission>,
+    submission_failures: u32,
+    /// Authorities that stored the certificate, not asked again. Sending the certificate
+    /// twice is harmless, so this is not recorded.
+    acked: HashSet<AuthorityName>,
+    /// Rounds of sending the certificate to the authorities once the delivery is confirmed
+    propagation_attempts: u32,
+}
+
+impl Delivery {
+    fn new() -> Self {
+        Self {
+            state: JobState::Observed,
+            submission: None,
+            submission_failures: 0,
+            acked: HashSet::new(),
+            propagation_attempts: 0,
+        }
+    }
+
+    /// Records leading to this progress, once the certificate is recorded
+    fn records(&self, id: InteropTxId) -> Vec<JobRecord> {
+        let mut records = Vec::new();
+        if self.submission_failures > 0 {
+            records.push(JobRecord::SubmissionFailed(id, self.submission_failures));
+        }
+        if let Some(submission) = &self.submission {
+            records.push(JobRecord::Submitted(id, submission.clone()));
+        }
+        if self.state == JobState::Confirmed {
+            records.push(JobRecord::Confirmed(id));
+        }
+        records
+    }
+}
+
+/// Retry schedule of the requests to one authority
+struct AuthorityRetry {
+    attempts: u32,
+    next_attempt: Instant,
+    /// The authority refused to vote for the order, see `is_rejection`
+    rejected: bool,
+}
+
+/// Whether an error of an authority means that it will never vote for a transfer order.
+/// Retryable errors do not, and neither do permanent errors of the exchange itself, e.g. an
+/// unsupported protocol version, which say nothing about the order.
+fn is_rejection(error: &FastPayError) -> bool {
+    use FastPayError::*;
+    !error.is_retryable()
+        && !matches!(
+            error,
+            HandshakeFailed { .. }
+                | UnsupportedProtocolVersion { .. }
+                | WrongNetwork { .. }
+                | InvalidDecoding
+                | SerializationError
+                | DeserializationError
+                | UnexpectedMessage
+                | ConfigurationError { .. }
+        )
+}
+
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(500);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+impl AuthorityRetry {
+    fn new() -> Self {
+        Self {
+            attempts: 0,
+            next_attempt: Instant::now(),
+            rejected: false,
+        }
+    }
+
+    /// Wait exponentially longer after each attempt, with jitter so that relayers retrying
+    /// at the same time spread out
+    fn schedule(&mut self) {
+        self.attempts += 1;
+        let delay = INITIAL_RETRY_DELAY
+            .saturating_mul(1 << self.attempts.min(16))
+            .min(MAX_RETRY_DELAY);
+        self.next_attempt = Instant::now() + delay.mul_f64(rand::random_range(0.5..1.5));
+    }
+}
+
+/// Time allowed for authorities to answer about the transfers of a chain, e.g. to account
+/// for escrow checks on a slow chain: `<CHAIN>:<MILLISECONDS>`
+#[derive(Debug, Clone)]
+struct ChainTimeout(ChainId, Duration);
+
+impl FromStr for ChainTimeout {
+    type Err = Error;
+
+    fn from_str(spec: &str) -> Result<Self, Error> {
+        let (chain_id, millis) = spec
+            .split_once(':')
+            .ok_or_else(|| failure::format_err!("Invalid chain timeout {:?}", spec))?;
+        Ok(Self(ChainId(chain_id.parse()?), Duration::from_millis(millis.parse()?)))
+    }
+}
+
+/// Request timeouts, by source chain
+pub struct Timeouts {
+    pub default: Duration,
+    pub chains: HashMap<ChainId, Duration>,
+}
+
+impl Timeouts {
+    fn request_timeout(&self, chain_id: ChainId) -> Duration {
+        *self.chains.get(&chain_id).unwrap_or(&self.default)
+    }
+}
+
+/// Failed submissions of a certificate after which the relayer gives up on it
+const MAX_SUBMISSION_FAILURES: u32 = 10;
+
+/// Rounds of propagation after which a certificate stored by a quorum is not sent to the
+/// other authorities anymore
+const MAX_PROPAGATION_ATTEMPTS: u32 = 10;
+
+/// Finished jobs remembered, so that orders reported again are not delivered twice. Orders
+/// are only reported again around a restart, or by clients of an intake retrying.
+const MAX_FINISHED_JOBS: usize = 100_000;
+
+/// Records appended to the job log after which it is compacted
+const COMPACTION_INTERVAL: usize = 10_000;
+
+/// Transfers and messages delivered, refunded or given up on, the oldest forgotten first
+#[derive(Default)]
+struct FinishedJobs {
+    ids: HashSet<InteropTxId>,
+    order: VecDeque<InteropTxId>,
+}
+
+impl FinishedJobs {
+    fn insert(&mut self, id: InteropTxId) {
+        if !self.ids.insert(id) {
+            return;
+        }
+        self.order.push_back(id);
+        if self.order.len() > MAX_FINISHED_JOBS
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.ids.remove(&oldest);
+        }
+    }
+
+    fn contains(&self, id: &InteropTxId) -> bool {
+        self.ids.contains(id)
+    }
+
+    /// Oldest first
+    fn iter(&self) -> impl Iterator<Item = &InteropTxId> {
+        self.order.iter()
+    }
+}
+
+/// Clients for all the shards of one authority
+struct AuthorityClients {
+    name: AuthorityName,
+    /// Where the authority listens, kept to follow it to another number of shards
+    entry: AuthorityEntry,
+    sharding: ShardingStrategy,
+    shards: Vec<AuthorityShardClient>,
+}
+
+impl AuthorityClients {
+    /// Create clients for each shard of an authority, routed like the authority does
+    fn new(entry: &AuthorityEntry, network: NetworkId, clients: &NetworkClients) -> Result<Self, Error> {
+        let name = entry.authority_name()?;
+        let sharding = entry.sharding()?;
+        let client = clients.get(entry.transport, entry.security);
+        let mut shards = Vec::new();
+        for shard_id in sharding.shard_ids() {
+            shards.push(AuthorityShardClient::new(
+                name,
+                entry.shard_address(shard_id)?,
+                network,
+                client.clone(),
+            ));
+        }
+        Ok(Self {
+            name,
+            entry: entry.clone(),
+            sharding,
+            shards,
+        })
+    }
+
+    /// Pick the shard of this authority in charge of a transfer
+    fn shard_for(&self, transfer: &CrossChainTransfer) -> (ShardId, &AuthorityShardClient) {
+        let shard_id = transfer.shard_id(&self.sharding);
+        (shard_id, &self.shards[shard_id as usize])
+    }
+
+    /// Pick the shard of this authority in charge of a message
+    fn shard_for_message(&self, message: &CrossChainMessage) -> (ShardId, &AuthorityShardClient) {
+        let shard_id = message.shard_id(&self.sharding);
+        (shard_id, &self.shards[shard_id as usize])
+    }
 }
 
 /// Bridge relayer
 pub struct Relayer {
-    committee: Committee,
-    authority_clients: HashMap<ShardId, Vec<AuthorityShardClient>>,
+    committee_path: String,
+    committees: CommitteeHistory,
+    /// Bridge deployment of the committee
+    network: NetworkId,
+    authority_clients: Vec<AuthorityClients>,
+    /// Transports shared by the clients of all the shards
+    clients: NetworkClients,
     pending_transfers: HashMap<InteropTxId, PendingTransfer>,
-    _source_rpc: String,
-    _destination_rpc: String,
+    pending_messages: HashMap<InteropTxId, PendingMessage>,
+    /// Transfers that could not be delivered, to be refunded on their source chain
+    pending_refunds: HashMap<InteropTxId, PendingRefund>,
+    finished_jobs: FinishedJobs,
+    /// Durable progress of the transfers
+    jobs: JobLog,
+    /// Records appended since the job log was last compacted
+    appended_records: usize,
+    /// Sources of new transfers, with the position reached in each of them
+    watchers: Vec<Box<dyn SourceWatcher>>,
+    cursors: SourceCursors,
+    submitter: Box<dyn DestinationSubmitter>,
+    /// Submits refund certificates to the source portal
+    refund_submitter: Box<dyn DestinationSubmitter>,
+    timeouts: Timeouts,
     polling_interval: Duration,
-    last_poll: Option<Instant>,
 }
 
 impl Relayer {
     /// Create a new relayer
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         committee_path: &str,
-        _source_rpc: String,
-        _destination_rpc: String,
+        source_rpc: String,
         polling_interval: Duration,
+        watch: &[WatcherSpec],
+        state_dir: PathBuf,
+        submitter: Box<dyn DestinationSubmitter>,
+        refund_submitter: Box<dyn DestinationSubmitter>,
+        timeouts: Timeouts,
+        identity: KeyPair,
     ) -> Result<Self, Error> {
         // Load committee configuration
-        let config = load_committee_config(committee_path)?;
+        let config = CommitteeConfig::read(committee_path)?;
 
-        // Create committee
-        let mut voting_rights = BTreeMap::new();
-        let mut authority_clients = HashMap::new();
+        // A chain watched twice would have its cursor moved by both sources
+        let chains: HashSet<_> = watch.iter().map(WatcherSpec::chain_id).collect();
+        if chains.len() != watch.len() {
+            return Err(failure::format_err!("Each source chain must be watched only once"));
+        }
+        std::fs::create_dir_all(&state_dir)?;
+        let jobs = JobLog::open(&state_dir)?;
+        let cursors = SourceCursors::load(state_dir.join("cursors.json"))?;
+        let mut watchers = Vec::new();
+        for spec in watch {
+            let cursor = cursors.get(spec.chain_id());
+            info!(
+                "Watching chain {} from {:?}, cursor {}",
+                spec.chain_id().0,
+                spec,
+                cursor
+            );
+            watchers.push(spec.start(&source_rpc, &state_dir, cursor).await?);
+        }
 
-        // Create authority clients for each shard
+        // Create authority clients for each shard, sharing one client per transport
+        let clients = NetworkClients::new(Arc::new(identity)).await?;
+        let mut authority_clients = Vec::new();
         for entry in &config.authorities {
-            let name = decode_authority_name(&entry.name)?;
-            let authority_name = name.into();
+            authority_clients.push(AuthorityClients::new(entry, config.network, &clients)?);
+        }
 
-            // Add to voting rights
-            voting_rights.insert(authority_name, entry.weight as usize);
+        let mut relayer = Self {
+            committee_path: committee_path.to_string(),
+            committees: CommitteeHistory::new(config.committee()?),
+            network: config.network,
+            authority_clients,
+            clients,
+            pending_transfers: HashMap::new(),
+            pending_messages: HashMap::new(),
+            pending_refunds: HashMap::new(),
+            finished_jobs: FinishedJobs::default(),
+            jobs,
+            appended_records: 0,
+            watchers,
+            cursors,
+            submitter,
+            refund_submitter,
+            timeouts,
+            polling_interval,
+        };
+        relayer.recover()?;
+        Ok(relayer)
+    }
 
-            // Create clients for each shard
-            for i in 0..entry.num_shards {
-                let shard_id = i as ShardId;
-                let port = entry.port + (shard_id as u16);
-                let addr = format!("{}:{}", entry.host, port);
-                let addr: SocketAddr = addr.parse()?;
+    /// Rebuild the jobs from their log, and compact it.
+    fn recover(&mut self) -> Result<(), Error> {
+        for record in self.jobs.load()? {
+            self.apply(record);
+        }
+        self.compact()?;
+        info!(
+            "Recovered {} pending transfers, {} pending messages and {} pending refunds",
+            self.pending_transfers.len(),
+            self.pending_messages.len(),
+            self.pending_refunds.len()
+        );
+        Ok(())
+    }
 
-                let client = AuthorityShardClient::new(authority_name, addr).await?;
+    /// Replace the job log with the records leading to the current jobs.
+    fn compact(&mut self) -> Result<(), Error> {
+        let snapshot = self.snapshot();
+        self.jobs.compact(&snapshot)?;
+        self.appended_records = 0;
+        Ok(())
+    }
 
-                // Add to clients map
-                authority_clients
-                    .entry(shard_id)
-                    .or_insert_with(Vec::new)
-                    .push(client);
+    /// Records leading to the current jobs.
+    fn snapshot(&self) -> Vec<JobRecord> {
+        let mut records = Vec::new();
+        for (id, pending) in &self.pending_transfers {
+            records.push(JobRecord::Observed {
+                order: pending.order.clone(),
+                observed_at: pending.observed_at,
+            });
+            records.extend(pending.signed_orders.values().cloned().map(JobRecord::Vote));
+            if let Some(certificate) = &pending.certificate {
+                records.push(JobRecord::Certified(certificate.clone()));
+            }
+            records.extend(pending.delivery.records(*id));
+        }
+        for (id, pending) in &self.pending_messages {
+            records.push(JobRecord::MessageObserved(pending.order.clone()));
+            if let Some(certificate) = &pending.certificate {
+                records.push(JobRecord::MessageCertified(certificate.clone()));
             }
+            records.extend(pending.delivery.records(*id));
         }
+        for (id, pending) in &self.pending_refunds {
+            records.push(JobRecord::Observed {
+                order: pending.order.transfer_order.clone(),
+                observed_at: pending.observed_at,
+            });
+            records.push(JobRecord::RefundRequested(*id));
+            if let Some(certificate) = &pending.certificate {
+                records.push(JobRecord::RefundCertified(certificate.clone()));
+            }
+            records.extend(pending.delivery.records(*id));
+        }
+        // Remembered so that orders reported again are not delivered twice
+        records.extend(self.finished_jobs.iter().copied().map(JobRecord::Closed));
+        records
+    }
 
-        let committee = Committee::new(voting_rights);
-        Ok(Self {
-            committee,
-            authority_clients,
-            pending_transfers: HashMap::new(),
-            _source_rpc,
-            _destination_rpc,
-            polling_interval,
-            last_poll: None,
-        })
+    /// Make a change to a job durable, then apply it.
+    fn record(&mut self, record: JobRecord) -> Result<(), Error> {
+        self.jobs.append(&record)?;
+        self.appended_records += 1;
+        self.apply(record);
+        Ok(())
+    }
+
+    fn apply(&mut self, record: JobRecord) {
+        match record {
+            JobRecord::Observed { order, observed_at } => {
+                let id = order.transfer.interop_tx_id;
+                self.pending_transfers.entry(id).or_insert(PendingTransfer {
+                    order,
+                    signed_orders: HashMap::new(),
+                    weight: 0,
+                    certificate: None,
+                    observed_at,
+                    delivery: Delivery::new(),
+                    retries: HashMap::new(),
+                });
+            }
+            JobRecord::Vote(vote) => {
+                // Votes of another epoch cannot be part of a certificate
+                let committee = self.committees.current();
+                if vote.epoch != committee.epoch {
+                    return;
+                }
+                if let Some(pending) = self.pending_transfers.get_mut(&vote.value.transfer.interop_tx_id)
+                    && !pending.signed_orders.contains_key(&vote.authority)
+                {
+                    pending.weight += committee.weight(&vote.authority);
+                    pending.signed_orders.insert(vote.authority, vote);
+                    if pending.weight >= committee.quorum_threshold() {
+                        pending.delivery.state = pending.delivery.state.max(JobState::VotesCollected);
+                    }
+                }
+            }
+            JobRecord::Certified(certificate) => {
+                let id = certificate.value.transfer.interop_tx_id;
+                // A certified transfer is delivered, never refunded
+                if let Some(refund) = self.pending_refunds.remove(&id) {
+                    self.pending_transfers.insert(id, PendingTransfer {
+                        order: refund.order.transfer_order,
+                        signed_orders: HashMap::new(),
+                        weight: 0,
+                        certificate: None,
+                        observed_at: refund.observed_at,
+                        delivery: Delivery::new(),
+                        retries: HashMap::new(),
+                    });
+                }
+                if let Some(pending) = self.pending_transfers.get_mut(&id) {
+                    pending.certificate = Some(certificate);
+                    pending.delivery.state = pending.delivery.state.max(JobState::Certified);
+                }
+            }
+            JobRecord::Submitted(id, submission) => {
+                if let Some(delivery) = self.delivery_mut(&id) {
+                    delivery.submission = Some(submission);
+                    delivery.state = delivery.state.max(JobState::Submitted);
+                }
+            }
+            JobRecord::SubmissionFailed(id, failures) => {
+                if let Some(delivery) = self.delivery_mut(&id) {
+                    delivery.submission = None;
+                    delivery.submission_failures = failures;
+                    delivery.state = JobState::Certified;
+                }
+            }
+            JobRecord::Confirmed(id) => {
+                if let Some(delivery) = self.delivery_mut(&id) {
+                    delivery.state = JobState::Confirmed;
+                }
+            }
+            JobRecord::RefundRequested(id) => {
+                if let Some(pending) = self.pending_transfers.remove(&id) {
+                    let refund = PendingRefund {
+                        order: CrossChainRefundOrder {
+                            transfer_order: pending.order,
+                        },
+                        certificate: None,
+                        observed_at: pending.observed_at,
+                        delivery: Delivery::new(),
+                    };
+                    self.pending_refunds.insert(id, refund);
+                }
+            }
+            JobRecord::Propagated(id) | JobRecord::Closed(id) => {
+                self.pending_transfers.remove(&id);
+                self.pending_messages.remove(&id);
+                self.pending_refunds.remove(&id);
+                self.finished_jobs.insert(id);
+            }
+            JobRecord::MessageObserved(order) => {
+                let id = order.message.interop_tx_id;
+                self.pending_messages.entry(id).or_insert(PendingMessage {
+                    order,
+                    certificate: None,
+                    delivery: Delivery::new(),
+                });
+            }
+            JobRecord::MessageCertified(certificate) => {
+                if let Some(pending) = self.pending_messages.get_mut(&certificate.value.message.interop_tx_id) {
+                    pending.certificate = Some(certificate);
+                    pending.delivery.state = pending.delivery.state.max(JobState::Certified);
+                }
+            }
+            JobRecord::RefundCertified(certificate) => {
+                if let Some(pending) = self.pending_refunds.get_mut(&certificate.value.transfer().interop_tx_id) {
+                    pending.certificate = Some(certificate);
+                    pending.delivery.state = pending.delivery.state.max(JobState::Certified);
+                }
+            }
+        }
+    }
+
+    /// Progress of the delivery of a transfer, message or refund
+    fn delivery(&self, id: &InteropTxId) -> Option<&Delivery> {
+        if let Some(pending) = self.pending_transfers.get(id) {
+            return Some(&pending.delivery);
+        }
+        if let Some(pending) = self.pending_messages.get(id) {
+            return Some(&pending.delivery);
+        }
+        self.pending_refunds.get(id).map(|pending| &pending.delivery)
+    }
+
+    fn delivery_mut(&mut self, id: &InteropTxId) -> Option<&mut Delivery> {
+        if let Some(pending) = self.pending_transfers.get_mut(id) {
+            return Some(&mut pending.delivery);
+        }
+        if let Some(pending) = self.pending_messages.get_mut(id) {
+            return Some(&mut pending.delivery);
+        }
+        self.pending_refunds.get_mut(id).map(|pending| &mut pending.delivery)
+    }
+
+    /// Submitter of the certificate of a job: refunds go to the source chain, the others to
+    /// the destination chain
+    fn submitter(&self, id: &InteropTxId) -> &dyn DestinationSubmitter {
+        if self.pending_refunds.contains_key(id) {
+            self.refund_submitter.as_ref()
+        } else {
+            self.submitter.as_ref()
+        }
+    }
+
+    /// Whether an order was already seen
+    fn is_known(&self, id: &InteropTxId) -> bool {
+        self.pending_transfers.contains_key(id)
+            || self.pending_messages.contains_key(id)
+            || self.pending_refunds.contains_key(id)
+            || self.finished_jobs.contains(id)
+    }
+
+    /// Send again the recorded submissions, since one recorded just before a crash may never
+    /// have been sent. The same submission is delivered at most once.
+    async fn resend_submissions(&self) {
+        let ids = self.pending_transfers.keys().chain(self.pending_messages.keys());
+        for id in ids.chain(self.pending_refunds.keys()) {
+            if let Some(delivery) = self.delivery(id)
+                && let Some(submission) = &delivery.submission
+                && delivery.state == JobState::Submitted
+                && let Err(e) = self.submitter(id).send(submission).await
+            {
+                error!("Failed to send submission {} again: {}", submission.id, e);
+            }
+        }
     }
 
     /// Run the relayer
     pub async fn run(&mut self) -> Result<(), Error> {
         info!("Starting bridge relayer");
+        self.resend_submissions().await;
 
         loop {
             // Poll for new transfers
             info!("==============================================================================");
             info!("Polling source chain...");
-            self.poll_source_chain().await?;
+            if let Err(e) = self.poll_source_chain().await {
+                error!("Failed to process new transfers, retrying: {}", e);
+            }
 
             // Check for completed transfers
             info!("==============================================================================");
             info!("Checking pending transfers...");
-            self.check_pending_transfers().await?;
+            if let Err(e) = self.check_pending_transfers().await {
+                error!("Failed to advance pending transfers, retrying: {}", e);
+            }
+            if let Err(e) = self.check_pending_messages().await {
+                error!("Failed to advance pending messages, retrying: {}", e);
+            }
+
+            if self.appended_records >= COMPACTION_INTERVAL
+                && let Err(e) = self.compact()
+            {
+                error!("Failed to compact the job log: {}", e);
+            }
 
             // Wait for next polling interval
             sleep(self.polling_interval).await;
         }
     }
 
-    /// Poll source chain for new transfers
+    /// Poll the source chains for new transfers
     async fn poll_source_chain(&mut self) -> Result<(), Error> {
-        // In a real implementation, this would connect to the source chain
-        // and look for new escrow events
-
-        let now = Instant::now();
-        if self.last_poll.is_none()
-            || now.duration_since(self.last_poll.unwrap()) > Duration::from_secs(10)
-        {
-            self.last_poll = Some(now);
-
-            // Create a dummy transfer
-            let interop_tx_id = InteropTxId([1u8; 32]);
-
-            if !self.pending_transfers.contains_key(&interop_tx_id) {
-                if !self.pending_transfers.contains_key(&interop_tx_id) {
-                    // Create a real signature using the sender's keypair
-                    let sender_secret = [2u8; 32];
-                    let sender_keypair = KeyPair::from(sender_secret);
-                    let sender_pubkey = sender_keypair.public();
-                    let first_byte = sender_pubkey.0[0];
-                    info!(
-                        "Sender public key: {:?} maps to --> Shard: {}",
-                        sender_keypair.public().base58(),
-                        (first_byte as u32) % 16
-                    );
-                    let transfer = CrossChainTransfer {
-                        source_chain: ChainId(1),
-                        destination_chain: ChainId(2),
-                        sender: sender_pubkey,
-                        recipient: Pubkey([3u8; 32]),
-                        amount: 1000,
-                        token_mint: Pubkey([4u8; 32]),
-                        interop_tx_id,
-                        escrow_account: Pubkey([5u8; 32]),
-                        nonce: 0,
-                    };
-                    let signature = Signature::new(&transfer, &sender_keypair);
-
-                    let order = CrossChainTransferOrder {
-                        transfer,
-                        signature,
-                    };
+        // Watchers are taken while their orders are processed, and put back even if that fails
+        let mut watchers = std::mem::take(&mut self.watchers);
+        let mut result = Ok(());
+        for watcher in &mut watchers {
+            result = self.poll_watcher(watcher.as_mut()).await;
+            if result.is_err() {
+                break;
+            }
+        }
+        self.watchers = watchers;
+        result
+    }
 
-                    info!(
-                        "Generated dummy transfer with ID: {:?}",
-                        interop_tx_id.base58()
-                    );
-                    // Process the transfer
-                    self.process_transfer(order).await?;
+    /// Process the new orders of one source chain
+    async fn poll_watcher(&mut self, watcher: &mut dyn SourceWatcher) -> Result<(), Error> {
+        let chain_id = watcher.chain_id();
+        let batch = match watcher.poll(self.cursors.get(chain_id)).await {
+            Ok(batch) => batch,
+            Err(e) => {
+                error!("Failed to poll source chain {}: {}", chain_id.0, e);
+                return Ok(());
+            }
+        };
+        for (cursor, order) in batch.orders {
+            let interop_tx_id = order.interop_tx_id();
+            if order.source_chain() != chain_id {
+                error!(
+                    "Ignoring order {:?} from chain {} reported for chain {}",
+                    interop_tx_id.base58(),
+                    order.source_chain().0,
+                    chain_id.0
+                );
+            } else if !self.is_known(&interop_tx_id) {
+                match order {
+                    SourceOrder::Transfer(order) => {
+                        info!(
+                            "New transfer on chain {} with ID: {:?}",
+                            chain_id.0,
+                            interop_tx_id.base58()
+                        );
+                        self.process_transfer(order).await?;
+                    }
+                    SourceOrder::Message(order) => {
+                        info!(
+                            "New message on chain {} with ID: {:?}",
+                            chain_id.0,
+                            interop_tx_id.base58()
+                        );
+                        self.record(JobRecord::MessageObserved(order))?;
+                    }
                 }
             }
+            // Past this point the order is in the job log
+            self.cursors.set(chain_id, cursor)?;
         }
-
-        Ok(())
+        self.cursors.set(chain_id, batch.cursor)
     }
 
     /// Check pending transfers for completion
     async fn check_pending_transfers(&mut self) -> Result<(), Error> {
-        let now = Instant::now();
-        let mut completed = Vec::new();
-        let mut timed_out = Vec::new();
+        let now = current_timestamp();
+        let mut abandoned = Vec::new();
 
         // Check each pending transfer
-        for (id, pending) in &self.pending_transfers {
+        let ids: Vec<_> = self.pending_transfers.keys().copied().collect();
+        for id in ids {
+            let pending = &self.pending_transfers[&id];
             info!(
                 "Checking pending transfer {:?}, weight: {}/{}",
                 id.base58(),
                 pending.weight,
-                self.committee.quorum_threshold()
+                self.committees.current().quorum_threshold()
             );
 
-            // Check if we have a quorum
-            if pending.weight >= self.committee.quorum_threshold() {
+            // Check if we have a quorum, unless a certificate was recovered from an authority
+            if pending.certificate.is_none() && pending.weight >= self.committees.current().quorum_threshold() {
                 info!("Quorum threshold reached, attempting to create certificate");
-                // Create a certificate
-                if let Some(certificate) = self.create_certificate(pending)? {
-                    info!(
-                        "Certificate created successfully with {} signatures",
-                        certificate.signatures.len()
-                    );
-                    // Submit to destination chain
-                    self.submit_to_destination(&certificate).await?;
+                match self.create_certificate(pending) {
+                    Ok(certificate) => {
+                        info!(
+                            "Certificate created successfully with {} signatures",
+                            certificate.signatures.len()
+                        );
+                        self.record(JobRecord::Certified(certificate))?;
+                    }
+                    Err(invalid) => {
+                        error!("Failed to create certificate despite having enough weight");
+                        self.drop_votes(&id, &invalid);
+                    }
+                }
+            }
 
-                    // Propagate to all authorities
-                    self.propagate_to_authorities(&certificate).await?;
+            if self.pending_transfers.get(&id).is_some_and(|pending| pending.certificate.is_none()) {
+                self.request_missing_votes(&id).await?;
+            }
 
-                    // Mark as completed
-                    completed.push(*id);
-                } else {
-                    error!("Failed to create certificate despite having enough weight");
+            let Some(pending) = self.pending_transfers.get(&id) else {
+                continue;
+            };
+            if pending.certificate.is_some() {
+                if pending.delivery.state != JobState::Confirmed
+                    && pending.delivery.submission_failures >= MAX_SUBMISSION_FAILURES
+                {
+                    // Never refunded, since the certificate may have been delivered all the
+                    // same, e.g. by another relayer
+                    let expired = pending.order.transfer.is_expired(now);
+                    match self.is_executed(&id).await {
+                        Ok(true) => {
+                            info!("Transfer {:?} was delivered by another submission", id.base58());
+                            self.record(JobRecord::Confirmed(id))?;
+                        }
+                        Ok(false) if expired => {
+                            // The destination portal no longer delivers it past its deadline
+                            error!(
+                                "Certified transfer {:?} expired undelivered, its funds stay in escrow",
+                                id.base58()
+                            );
+                            self.record(JobRecord::Closed(id))?;
+                        }
+                        Ok(false) => (),
+                        Err(e) => error!("Failed to check the delivery of {:?}: {}", id.base58(), e),
+                    }
+                } else if (pending.delivery.state == JobState::Confirmed || self.advance_submission(&id).await?)
+                    && self.propagate(&id).await
+                {
+                    // Only completed once the destination chain confirmed the delivery, and
+                    // the authorities stored the certificate
+                    self.record(JobRecord::Propagated(id))?;
                 }
-            } else if
-            // Check for timeout (5 minutes)
-            now.duration_since(pending.start_time) > Duration::from_secs(300) {
-                timed_out.push(*id);
+            } else if pending.order.transfer.is_expired(now) {
+                error!("Transfer expired, requesting a refund: {:?}", id.base58());
+                abandoned.push(id);
+            } else if self.is_rejected(pending) {
+                error!("Transfer rejected by more than f authorities, requesting a refund: {:?}", id.base58());
+                abandoned.push(id);
             }
         }
 
-        // Remove completed and timed out transfers
-        for id in completed {
-            self.pending_transfers.remove(&id);
+        // Funds stay locked in escrow until the transfer is refunded
+        for id in abandoned {
+            self.record(JobRecord::RefundRequested(id))?;
         }
 
-        for id in timed_out {
-            error!("Transfer timed out: {:?}", id);
-            self.pending_transfers.remove(&id);
-        }
+        self.check_pending_refunds().await
+    }
+
+    /// Certify pending refunds, then submit them to the source portal until it confirms
+    /// returning the funds. Refunds are never given up on, the funds would stay in escrow.
+    async fn check_pending_refunds(&mut self) -> Result<(), Error> {
+        let ids: Vec<_> = self.pending_refunds.keys().copied().collect();
+        for id in ids {
+            let pending = &self.pending_refunds[&id];
+            if let Err(e) = pending.order.check_signature() {
+                // Nobody can refund an order its sender did not sign
+                error!("Dropping refund of {:?} with an invalid order: {}", id.base58(), e);
+                self.record(JobRecord::Closed(id))?;
+                continue;
+            }
+            if pending.certificate.is_none() {
+                // Authorities that voted for delivery only agree to refund past the deadline,
+                // so keep asking until there is a certificate
+                let Some(certificate) = self.request_refund(pending.order.clone()).await? else {
+                    continue;
+                };
+                self.record(JobRecord::RefundCertified(certificate))?;
+                // Let the sender move on to its next order meanwhile
+                self.release_nonce(&id).await;
+            }
 
+            if (self.pending_refunds[&id].delivery.state == JobState::Confirmed || self.advance_submission(&id).await?)
+                && self.propagate(&id).await
+            {
+                // Sent again in case the relayer stopped right after certifying the refund
+                self.record(JobRecord::Closed(id))?;
+            }
+        }
         Ok(())
     }
 
+    /// Collect votes for refunding a transfer, until they form a certificate. A transfer that
+    /// turns out to be certified is delivered instead.
+    async fn request_refund(
+        &mut self,
+        order: CrossChainRefundOrder,
+    ) -> Result<Option<CertifiedCrossChainRefundOrder>, Error> {
+        let mut votes = Vec::new();
+        let mut certified_by = Vec::new();
+        for authority in &self.authority_clients {
+            let (_, client) = authority.shard_for(order.transfer());
+            match client.send_refund_order(&order).await {
+                Ok(RefundOrderResponse::Certificate(certificate)) => {
+                    if self.is_valid_certificate(&certificate, authority.name) {
+                        return Ok(Some(certificate));
+                    }
+                }
+                Ok(RefundOrderResponse::Vote(vote)) => votes.push(vote),
+                Err(FastPayError::CertificateAlreadyExists) => certified_by.push(authority.name),
+                Err(e) => error!(
+                    "Authority {:?} refused to refund: {:?}",
+                    authority.name.base58(),
+                    e
+                ),
+            }
+        }
+        for name in certified_by {
+            if let Some(certificate) = self.fetch_certificate(name, order.transfer()).await {
+                info!(
+                    "Authority {:?} certified the transfer, delivering it instead of refunding it",
+                    name.base58()
+                );
+                self.handle_certificate(certificate)?;
+                if !self.pending_refunds.contains_key(&order.transfer().interop_tx_id) {
+                    return Ok(None);
+                }
+            }
+        }
+        if let Some(vote) = votes.iter().find(|vote| vote.epoch > self.committees.current().epoch) {
+            self.refresh_committee(vote.authority).await;
+        }
+
+        // The signature of the order was checked by the caller
+        let committee = self.committees.current();
+        let mut aggregator = RefundSignatureAggregator::new_unsafe(order, committee);
+        for vote in votes.iter().filter(|vote| vote.epoch == committee.epoch) {
+            match aggregator.append(vote.authority, vote.signature) {
+                Ok(Some(certificate)) => return Ok(Some(certificate)),
+                Ok(None) => (),
+                Err(e) => error!(
+                    "Invalid refund vote from {:?}: {:?}",
+                    vote.authority.base58(),
+                    e
+                ),
+            }
+        }
+        Ok(None)
+    }
+
     /// Process a new transfer
     async fn process_transfer(&mut self, order: CrossChainTransferOrder) -> Result<(), Error> {
         let interop_tx_id = order.transfer.interop_tx_id;
@@ -262,60 +930,265 @@ impl Relayer {
         );
         info!(
             "Committee voting rights: {} members",
-            self.committee.voting_rights.len()
+            self.committees.current().voting_rights.len()
         );
 
         // Create a new pending transfer
-        let pending = PendingTransfer {
+        self.record(JobRecord::Observed {
             order: order.clone(),
-            signed_orders: HashMap::new(),
-            weight: 0,
-            start_time: Instant::now(),
+            observed_at: current_timestamp(),
+        })?;
+        self.request_missing_votes(&interop_tx_id).await
+    }
+
+    /// Ask for their vote on a transfer the authorities that did not vote yet, did not reject
+    /// the transfer, and are due for another attempt
+    async fn request_missing_votes(&mut self, id: &InteropTxId) -> Result<(), Error> {
+        let pending = &self.pending_transfers[id];
+        let order = &pending.order;
+        let timeout = self.timeouts.request_timeout(order.transfer.source_chain);
+        let now = Instant::now();
+        let due = self.authority_clients.iter().filter(|authority| {
+            !pending.signed_orders.contains_key(&authority.name)
+                && pending
+                    .retries
+                    .get(&authority.name)
+                    .is_none_or(|retry| !retry.rejected && retry.next_attempt <= now)
+        });
+
+        // Send to the shard in charge of this transfer at every due authority, concurrently
+        let responses = join_all(due.map(|authority| async move {
+            let (shard_id, client) = authority.shard_for(&order.transfer);
+            info!(
+                "Sending transfer order to authority {:?}, shard {}",
+                authority.name.base58(),
+                shard_id
+            );
+            let response = tokio::time::timeout(timeout, client.send_transfer_order(order))
+                .await
+                .unwrap_or(Err(FastPayError::CommunicationError));
+            (authority.name, response)
+        }))
+        .await;
+
+        let mut signed_orders = Vec::new();
+        let mut certificate = None;
+        let mut reroutes = Vec::new();
+        let mut certified_by = Vec::new();
+        let pending = self.pending_transfers.get_mut(id).unwrap();
+        for (name, response) in responses {
+            let retry = pending.retries.entry(name).or_insert_with(AuthorityRetry::new);
+            match response {
+                Ok(TransferOrderResponse::Certificate(cert)) => {
+                    info!(
+                        "Authority {:?} already processed the transfer, recovered its certificate",
+                        name.base58()
+                    );
+                    certificate = Some(cert);
+                }
+                Ok(TransferOrderResponse::Vote(signed_order)) => {
+                    info!(
+                        "Received signed order from authority: {:?} from relayer",
+                        signed_order.authority.base58()
+                    );
+                    signed_orders.push(signed_order);
+                    // Only asked again if the vote turns out to be invalid
+                    retry.schedule();
+                }
+                Err(FastPayError::WrongShard { expected, num_shards })
+                    if self
+                        .authority_clients
+                        .iter()
+                        .any(|a| a.name == name && a.sharding.number_of_shards() != num_shards) =>
+                {
+                    // Asked again right away, once routed like the authority does
+                    error!(
+                        "Authority {:?} expects the transfer on shard {} of {}, re-routing",
+                        name.base58(),
+                        expected,
+                        num_shards
+                    );
+                    reroutes.push((name, num_shards));
+                }
+                Err(FastPayError::CertificateAlreadyExists) => {
+                    info!(
+                        "Authority {:?} already certified the transfer, fetching the certificate",
+                        name.base58()
+                    );
+                    certified_by.push(name);
+                    retry.schedule();
+                }
+                Err(e) if is_rejection(&e) => {
+                    error!("Authority {:?} rejected the transfer: {}", name.base58(), e);
+                    retry.rejected = true;
+                }
+                Err(e) => {
+                    retry.schedule();
+                    error!(
+                        "Error sending to authority {:?} (attempt {}): {:?}",
+                        name.base58(),
+                        retry.attempts,
+                        e
+                    );
+                }
+            }
+        }
+        let transfer = pending.order.transfer.clone();
+        for (name, num_shards) in reroutes {
+            self.reshard(name, num_shards);
+        }
+        for name in certified_by {
+            if certificate.is_none() {
+                certificate = self.fetch_certificate(name, &transfer).await;
+            }
+        }
+        info!("Received {} signed orders", signed_orders.len());
+        for signed_order in signed_orders {
+            self.handle_signed_order(signed_order).await?;
+        }
+        if let Some(certificate) = certificate {
+            self.handle_certificate(certificate)?;
+        }
+
+        Ok(())
+    }
+
+    /// Follow an authority to the number of shards it actually runs
+    fn reshard(&mut self, name: AuthorityName, num_shards: u32) {
+        let Some(authority) = self.authority_clients.iter_mut().find(|a| a.name == name) else {
+            return;
         };
+        let mut entry = authority.entry.clone();
+        entry.num_shards = num_shards;
+        match AuthorityClients::new(&entry, self.network, &self.clients) {
+            Ok(clients) => *authority = clients,
+            Err(e) => error!("Failed to re-route to authority {}: {:?}", entry.name, e),
+        }
+    }
 
-        self.pending_transfers.insert(interop_tx_id, pending);
+    /// Fetch the certificate of a transfer from an authority that processed it
+    async fn fetch_certificate(
+        &self,
+        name: AuthorityName,
+        transfer: &CrossChainTransfer,
+    ) -> Option<CertifiedCrossChainTransferOrder> {
+        let authority = self.authority_clients.iter().find(|a| a.name == name)?;
+        let (_, client) = authority.shard_for(transfer);
+        let request = TransferInfoRequest {
+            sender: transfer.sender,
+            interop_tx_id: transfer.interop_tx_id,
+        };
+        match client.get_transfer_info(&request).await {
+            Ok(response) => response.certificate,
+            Err(e) => {
+                error!("Failed to fetch certificate from {:?}: {:?}", name.base58(), e);
+                None
+            }
+        }
+    }
+
+    /// Whether more than f authorities rejected a transfer, so that it can never be certified
+    fn is_rejected(&self, pending: &PendingTransfer) -> bool {
+        let committee = self.committees.current();
+        let rejected_weight: usize = pending
+            .retries
+            .iter()
+            .filter(|(_, retry)| retry.rejected)
+            .map(|(name, _)| committee.weight(name))
+            .sum();
+        rejected_weight >= committee.validity_threshold()
+    }
+
+    /// Certify pending messages, then deliver them to their target program like transfers
+    async fn check_pending_messages(&mut self) -> Result<(), Error> {
+        let ids: Vec<_> = self.pending_messages.keys().copied().collect();
+        for id in ids {
+            let pending = &self.pending_messages[&id];
+            if let Err(e) = pending.order.check_signature() {
+                error!("Dropping message {:?} with an invalid order: {}", id.base58(), e);
+                self.record(JobRecord::Closed(id))?;
+                continue;
+            }
+            if pending.certificate.is_none() {
+                let order = pending.order.clone();
+                match self.certify_message(order).await {
+                    Some(certificate) => self.record(JobRecord::MessageCertified(certificate))?,
+                    None => continue,
+                }
+            }
 
-        // Determine which shard should handle this transfer
-        let shard_id = get_shard_id(&order.transfer, self.authority_clients.len() as u32); // In a real implementation, this would be based on the transfer
+            let pending = &self.pending_messages[&id];
+            if pending.delivery.state != JobState::Confirmed
+                && pending.delivery.submission_failures >= MAX_SUBMISSION_FAILURES
+            {
+                // Messages are not refunded, the sender may only send another one
+                error!("Giving up on message {:?}", id.base58());
+                self.record(JobRecord::Closed(id))?;
+            } else if (pending.delivery.state == JobState::Confirmed || self.advance_submission(&id).await?)
+                && self.propagate(&id).await
+            {
+                // Only completed once the destination chain confirmed the delivery, and the
+                // authorities released the nonce of the sender
+                self.record(JobRecord::Propagated(id))?;
+            }
+        }
+        Ok(())
+    }
 
+    /// Collect votes for a message until they form a certificate
+    async fn certify_message(&mut self, order: CrossChainMessageOrder) -> Option<CertifiedCrossChainMessageOrder> {
+        let interop_tx_id = order.message.interop_tx_id;
         info!(
-            "Processing transfer for shard {}, authority count: {}",
-            shard_id,
-            self.authority_clients.get(&shard_id).map_or(0, |v| v.len())
+            "Collecting votes for message with ID: {:?}",
+            interop_tx_id.base58()
         );
 
-        // Get clients for this shard
-        if let Some(clients) = self.authority_clients.get(&shard_id) {
-            let mut signed_orders = Vec::new();
-            // Send to all authorities for this shard
-            for client in clients {
-                info!("Sending transfer order to authority");
-                match client.send_transfer_order(&order).await {
-                    Ok(signed_order) => {
-                        info!(
-                            "Received signed order from authority: {:?} from relayer",
-                            signed_order.authority.base58()
-                        );
-                        signed_orders.push(signed_order);
-                        info!("Signed order added, current count: {}", signed_orders.len());
-                    }
-                    Err(e) => {
-                        // Log error but continue with other authorities
-                        error!("Error sending to authority: {:?}", e);
+        let mut votes = Vec::new();
+        for authority in &self.authority_clients {
+            let (_, client) = authority.shard_for_message(&order.message);
+            match client.send_message_order(&order).await {
+                Ok(MessageOrderResponse::Certificate(certificate)) => {
+                    // Recovered from an authority that already processed the message
+                    if self.is_valid_certificate(&certificate, authority.name) {
+                        return Some(certificate);
                     }
                 }
+                Ok(MessageOrderResponse::Vote(vote)) => votes.push(vote),
+                Err(e) => error!(
+                    "Error sending message to authority {:?}: {:?}",
+                    authority.name.base58(),
+                    e
+                ),
             }
-            info!(
-                "Received {} signed orders from shard {}",
-                signed_orders.len(),
-                shard_id
-            );
-            for signed_order in signed_orders {
-                self.handle_signed_order(signed_order).await?;
-            }
+        }
+        if let Some(vote) = votes.iter().find(|vote| vote.epoch > self.committees.current().epoch) {
+            self.refresh_committee(vote.authority).await;
         }
 
-        Ok(())
+        let committee = self.committees.current();
+        let mut aggregator = match MessageSignatureAggregator::try_new(order, committee) {
+            Ok(aggregator) => aggregator,
+            Err(e) => {
+                error!("Invalid order for message {:?}: {}", interop_tx_id.base58(), e);
+                return None;
+            }
+        };
+        for vote in votes.iter().filter(|vote| vote.epoch == committee.epoch) {
+            match aggregator.append(vote.authority, vote.signature) {
+                Ok(Some(certificate)) => return Some(certificate),
+                Ok(None) => (),
+                Err(e) => error!(
+                    "Invalid vote for message from {:?}: {:?}",
+                    vote.authority.base58(),
+                    e
+                ),
+            }
+        }
+        error!(
+            "Not enough votes to certify message {:?}",
+            interop_tx_id.base58()
+        );
+        None
     }
 
     /// Handle a signed order from an authority
@@ -323,8 +1196,13 @@ impl Relayer {
         &mut self,
         signed_order: SignedCrossChainTransferOrder,
     ) -> Result<(), Error> {
+        // Catch up with the committee if the authority moved to a later epoch
+        if signed_order.epoch > self.committees.current().epoch {
+            self.refresh_committee(signed_order.authority).await;
+        }
+
         // Check the signature
-        if signed_order.check(&self.committee).is_err() {
+        if signed_order.check(self.committees.current()).is_err() {
             error!(
                 "Signature verification failed for order from {:?}",
                 signed_order.authority
@@ -335,33 +1213,120 @@ impl Relayer {
         let interop_tx_id = signed_order.value.transfer.interop_tx_id;
         let authority = signed_order.authority;
 
-        // Get the pending transfer
-        if let Some(pending) = self.pending_transfers.get_mut(&interop_tx_id) {
-            // Add the signed order if not already present
-            if !pending.signed_orders.contains_key(&authority) {
-                // Add weight
-                pending.weight += self.committee.weight(&authority);
+        // Add the signed order to the pending transfer if not already present
+        let Some(pending) = self.pending_transfers.get(&interop_tx_id) else {
+            return Ok(());
+        };
+        if pending.signed_orders.contains_key(&authority) {
+            return Ok(());
+        }
+        self.record(JobRecord::Vote(signed_order))?;
+        info!(
+            "Added signature from authority {:?}, weight now {}/{}",
+            authority.base58(),
+            self.pending_transfers[&interop_tx_id].weight,
+            self.committees.current().quorum_threshold()
+        );
 
-                info!(
-                    "Added signature from authority {:?}, weight now {}/{}",
-                    authority.base58(),
-                    pending.weight,
-                    self.committee.quorum_threshold()
-                );
+        Ok(())
+    }
 
-                // Add signed order
-                pending.signed_orders.insert(authority, signed_order);
+    /// Fetch and verify the committee changes known to an authority
+    async fn refresh_committee(&mut self, name: AuthorityName) {
+        let Some(authority) = self.authority_clients.iter().find(|a| a.name == name) else {
+            return;
+        };
+        let current_epoch = self.committees.current().epoch;
+        let response = match authority.shards[0].get_committee_info(current_epoch).await {
+            Ok(response) => response,
+            Err(e) => {
+                error!("Failed to fetch committee changes from {:?}: {:?}", name.base58(), e);
+                return;
+            }
+        };
+        for change in response.changes {
+            if let Err(e) = self.committees.advance(change) {
+                error!("Rejected committee change from {:?}: {:?}", name.base58(), e);
+                break;
             }
         }
 
+        if self.committees.current().epoch == current_epoch {
+            return;
+        }
+        info!("Moved to committee of epoch {}", self.committees.current().epoch);
+
+        // Operators list joining authorities in the committee file, so look for new members
+        match CommitteeConfig::read(&self.committee_path) {
+            Ok(config) if config.network != self.network => error!(
+                "Committee configuration moved to network {}, not following it",
+                config.network
+            ),
+            Ok(config) => {
+                for entry in &config.authorities {
+                    let known = entry
+                        .authority_name()
+                        .map(|name| self.authority_clients.iter().any(|a| a.name == name))
+                        .unwrap_or(true);
+                    if known {
+                        continue;
+                    }
+                    match AuthorityClients::new(entry, self.network, &self.clients) {
+                        Ok(clients) => self.authority_clients.push(clients),
+                        Err(e) => error!("Failed to connect to authority {}: {:?}", entry.name, e),
+                    }
+                }
+            }
+            Err(e) => error!("Failed to reload committee configuration: {:?}", e),
+        }
+
+        let committee = self.committees.current();
+        // Votes of the previous epoch cannot be part of a certificate anymore.
+        for pending in self.pending_transfers.values_mut() {
+            pending
+                .signed_orders
+                .retain(|_, signed| signed.epoch == committee.epoch);
+            pending.weight = pending
+                .signed_orders
+                .keys()
+                .map(|name| committee.weight(name))
+                .sum();
+        }
+    }
+
+    /// Handle a certificate returned by an authority for an already processed transfer
+    fn handle_certificate(&mut self, certificate: CertifiedCrossChainTransferOrder) -> Result<(), Error> {
+        let committee = match self.committees.get(certificate.epoch) {
+            Ok(committee) => committee,
+            Err(e) => {
+                error!("Certificate returned by authority is from an unknown epoch: {:?}", e);
+                return Ok(());
+            }
+        };
+        if let Err(e) = certificate.check(committee) {
+            error!("Invalid certificate returned by authority: {:?}", e);
+            return Ok(());
+        }
+        let interop_tx_id = certificate.value.transfer.interop_tx_id;
+        let uncertified = match self.pending_transfers.get(&interop_tx_id) {
+            Some(pending) => pending.order == certificate.value && pending.certificate.is_none(),
+            // Being refunded, e.g. because the transfer expired before this relayer saw a quorum
+            None => self.pending_refunds.get(&interop_tx_id).is_some_and(|pending| {
+                pending.order.transfer_order == certificate.value && pending.certificate.is_none()
+            }),
+        };
+        if uncertified {
+            self.record(JobRecord::Certified(certificate))?;
+        }
         Ok(())
     }
 
-    /// Create a certificate from a pending transfer
+    /// Create a certificate from a pending transfer. Returns the authorities whose vote could
+    /// not be added if the others do not reach a quorum.
     fn create_certificate(
         &self,
         pending: &PendingTransfer,
-    ) -> Result<Option<CertifiedCrossChainTransferOrder>, Error> {
+    ) -> Result<CertifiedCrossChainTransferOrder, Vec<AuthorityName>> {
         info!(
             "Attempting to create certificate with {} signatures",
             pending.signed_orders.len()
@@ -369,21 +1334,23 @@ impl Relayer {
 
         // Create a signature aggregator
         let mut aggregator =
-            CrossChainSignatureAggregator::new_unsafe(pending.order.clone(), &self.committee);
+            CrossChainSignatureAggregator::new_unsafe(
+                pending.order.clone(),
+                self.committees.current(),
+            );
 
-        // Track whether we've created a certificate
-        let mut certificate = None;
+        // Votes that do not verify, e.g. from Byzantine authorities
+        let mut invalid = Vec::new();
 
         // Add all signatures
         for (name, signed) in &pending.signed_orders {
-            match aggregator.append(*name, signed.signature.clone()) {
+            match aggregator.append(*name, signed.signature) {
                 Ok(Some(cert)) => {
                     info!(
                         "Certificate created with signature from {:?}",
                         name.base58()
                     );
-                    certificate = Some(cert);
-                    break; // Exit the loop once we have a certificate
+                    return Ok(cert);
                 }
                 Ok(None) => {
                     info!(
@@ -397,85 +1364,224 @@ impl Relayer {
                         name.base58(),
                         e
                     );
-                    return Err(e.into());
+                    invalid.push(*name);
                 }
             }
         }
-        Ok(certificate)
+        Err(invalid)
     }
 
-    /// Submit a certificate to the destination chain
-    async fn submit_to_destination(
-        &self,
-        certificate: &CertifiedCrossChainTransferOrder,
-    ) -> Result<(), Error> {
-        // In a real implementation, this would submit the certificate
-        // to the destination chain
+    /// Check a certificate returned by an authority against the committee of its epoch
+    fn is_valid_certificate<V: Votable>(&self, certificate: &Certificate<V>, name: AuthorityName) -> bool {
+        let checked = match self.committees.get(certificate.epoch) {
+            Ok(committee) => certificate.check(committee),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = &checked {
+            error!("Invalid certificate returned by authority {:?}: {:?}", name.base58(), e);
+        }
+        checked.is_ok()
+    }
 
-        info!(
-            "Submitting certificate to destination chain: {:?}",
-            certificate.value.transfer.interop_tx_id.base58()
-        );
+    /// Forget the votes of some authorities for a transfer, so that they are asked again
+    fn drop_votes(&mut self, id: &InteropTxId, names: &[AuthorityName]) {
+        let committee = self.committees.current();
+        if let Some(pending) = self.pending_transfers.get_mut(id) {
+            for name in names {
+                if pending.signed_orders.remove(name).is_some() {
+                    pending.weight -= committee.weight(name);
+                    pending.retries.remove(name);
+                }
+            }
+        }
+    }
 
-        Ok(())
+    /// Portal instruction delivering the certificate of a transfer, message or refund.
+    /// Transactions are small, so certificates are sent in compact form.
+    fn delivery_instruction(&self, id: &InteropTxId) -> Result<PortalInstruction, Error> {
+        if let Some(certificate) = self.pending_transfers.get(id).and_then(|pending| pending.certificate.as_ref()) {
+            let compact = certificate.compact(self.committees.get(certificate.epoch)?)?;
+            return Ok(PortalInstruction::DeliverTransfer(compact));
+        }
+        if let Some(certificate) = self.pending_messages.get(id).and_then(|pending| pending.certificate.as_ref()) {
+            let compact = certificate.compact(self.committees.get(certificate.epoch)?)?;
+            return Ok(PortalInstruction::DeliverMessage(compact));
+        }
+        if let Some(certificate) = self.pending_refunds.get(id).and_then(|pending| pending.certificate.as_ref()) {
+            let compact = certificate.compact(self.committees.get(certificate.epoch)?)?;
+            return Ok(PortalInstruction::Refund(compact));
+        }
+        Err(failure::format_err!("No certificate to deliver for {:?}", id.base58()))
     }
 
-    /// Propagate a certificate to all authorities for cross-shard updates
-    async fn propagate_to_authorities(
-        &self,
-        certificate: &CertifiedCrossChainTransferOrder,
-    ) -> Result<(), Error> {
-        info!(
-            "Propagating certificate to {} authority shards",
-            self.authority_clients.len()
-        );
+    /// Submit the certificate of a transfer or message to the destination chain, or of a
+    /// refund to the source chain, or follow up on its submission. Returns whether the
+    /// delivery is confirmed.
+    async fn advance_submission(&mut self, id: &InteropTxId) -> Result<bool, Error> {
+        let delivery = self
+            .delivery(id)
+            .ok_or_else(|| failure::format_err!("No pending delivery for {:?}", id.base58()))?;
+        let status = match &delivery.submission {
+            None => {
+                let instruction = self.delivery_instruction(id)?;
+                info!(
+                    "Submitting certificate to chain {}: {:?} ({} bytes)",
+                    instruction.chain_id().0,
+                    id.base58(),
+                    bincode::serialized_size(&instruction)?
+                );
+                match self.submitter(id).prepare(&instruction).await {
+                    Ok(submission) => {
+                        // Recorded first, so that after a crash the same submission is sent
+                        // again instead of a new one
+                        self.record(JobRecord::Submitted(*id, submission.clone()))?;
+                        match self.submitter(id).send(&submission).await {
+                            Ok(()) => {
+                                info!("Certificate {:?} submitted as {}", id.base58(), submission.id);
+                                return Ok(false);
+                            }
+                            Err(e) => SubmissionStatus::Failed(e.to_string()),
+                        }
+                    }
+                    Err(e) => SubmissionStatus::Failed(e.to_string()),
+                }
+            }
+            Some(submission) => match self.submitter(id).status(submission).await {
+                Ok(status) => status,
+                Err(e) => {
+                    // The submission may still land, ask again later
+                    error!("Failed to check submission {}: {}", submission.id, e);
+                    SubmissionStatus::Pending
+                }
+            },
+        };
 
-        // Send to all authorities (all shards)
-        for (shard_id, clients) in &self.authority_clients {
-            info!(
-                "Sending to shard {} with {} authorities",
-                shard_id,
-                clients.len()
-            );
-            for client in clients {
-                if let Err(e) = client.send_certified_order(certificate).await {
-                    error!("Error propagating certificate to authority: {:?}", e);
-                } else {
-                    info!(
-                        "Certificate propagated successfully to authority in shard {}",
-                        shard_id
-                    );
+        match status {
+            SubmissionStatus::Confirmed => {
+                info!("Delivery of {:?} confirmed", id.base58());
+                self.record(JobRecord::Confirmed(*id))?;
+                return Ok(true);
+            }
+            SubmissionStatus::Pending => (),
+            SubmissionStatus::Failed(_) | SubmissionStatus::Expired => {
+                let failures = self.delivery(id).map_or(0, |delivery| delivery.submission_failures) + 1;
+                error!(
+                    "Submission of {:?} failed ({} times): {:?}",
+                    id.base58(),
+                    failures,
+                    status
+                );
+                self.record(JobRecord::SubmissionFailed(*id, failures))?;
+                if failures >= MAX_SUBMISSION_FAILURES && !self.pending_refunds.contains_key(id) {
+                    error!("Giving up on delivering {:?}", id.base58());
+                    // Let the sender move on to its next order meanwhile
+                    self.release_nonce(id).await;
                 }
             }
         }
-
-        Ok(())
+        Ok(false)
     }
-}
-
-/// Load committee configuration from file
-fn load_committee_config(path: &str) -> Result<CommitteeConfig, Error> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let config: CommitteeConfig = serde_json::from_reader(reader)?;
-    Ok(config)
-}
 
-/// Decode authority name from string
-fn decode_authority_name(name: &str) -> Result<Pubkey, Error> {
-    let bytes = hex::decode(name.trim())?;
-    if bytes.len() != 32 {
-        return Err(failure::format_err!("Invalid public key length"));
+    /// Whether the portal executed the certificate of a job, whichever submission carried it
+    async fn is_executed(&self, id: &InteropTxId) -> Result<bool, Error> {
+        let instruction = self.delivery_instruction(id)?;
+        self.submitter(id).is_executed(&instruction).await
     }
 
-    let mut key_bytes = [0u8; 32];
-    key_bytes.copy_from_slice(&bytes);
+        /// Send the certificate of a transfer, message or refund to the shard of its sender at
+    /// every authority that did not store it yet, releasing its nonce. The shard stores it in
+    /// the other shards of its authority before answering. Returns the answer of each
+    /// authority.
+    async fn release_nonce(&self, id: &InteropTxId) -> Vec<(AuthorityName, Result<(), FastPayError>)> {
+        let Some(delivery) = self.delivery(id) else {
+            return Vec::new();
+        };
+        let due = self
+            .authority_clients
+            .iter()
+            .filter(|authority| !delivery.acked.contains(&authority.name));
+        join_all(due.map(|authority| async move {
+            let (shard_id, result) = if let Some(pending) = self.pending_transfers.get(id)
+                && let Some(certificate) = &pending.certificate
+            {
+                let (shard_id, client) = authority.shard_for(&certificate.value.transfer);
+                (shard_id, client.send_certified_order(certificate).await)
+            } else if let Some(pending) = self.pending_messages.get(id)
+                && let Some(certificate) = &pending.certificate
+            {
+                let (shard_id, client) = authority.shard_for_message(&certificate.value.message);
+                (shard_id, client.send_message_certificate(certificate).await)
+            } else if let Some(pending) = self.pending_refunds.get(id)
+                && let Some(certificate) = &pending.certificate
+            {
+                let (shard_id, client) = authority.shard_for(certificate.value.transfer());
+                (shard_id, client.send_refund_certificate(certificate).await)
+            } else {
+                return (authority.name, Err(FastPayError::CertificateNotfound));
+            };
+            if let Err(e) = &result {
+                error!(
+                    "Error sending certificate to shard {} of authority {:?}: {:?}",
+                    shard_id,
+                    authority.name.base58(),
+                    e
+                );
+            }
+            (authority.name, result)
+        }))
+        .await
+    }
 
-    Ok(Pubkey(key_bytes))
+    /// Send the certificate of a confirmed delivery to the authorities, until all of them
+    /// stored it, or a quorum did and the others had enough attempts. Returns whether the
+    /// job is over.
+    async fn propagate(&mut self, id: &InteropTxId) -> bool {
+        let results = self.release_nonce(id).await;
+        let committee = self.committees.current();
+        let Some(delivery) = self.delivery(id) else {
+            return false;
+        };
+        let mut acked = delivery.acked.clone();
+        acked.extend(results.into_iter().filter(|(_, result)| result.is_ok()).map(|(name, _)| name));
+        let missing: Vec<_> = self
+            .authority_clients
+            .iter()
+            .filter(|authority| !acked.contains(&authority.name))
+            .map(|authority| authority.name.base58())
+            .collect();
+        let has_quorum = acked.iter().map(|name| committee.weight(name)).sum::<usize>() >= committee.quorum_threshold();
+
+        let delivery = self.delivery_mut(id).expect("The delivery was just found");
+        delivery.acked = acked;
+        delivery.propagation_attempts += 1;
+        if missing.is_empty() {
+            return true;
+        }
+        if has_quorum && delivery.propagation_attempts >= MAX_PROPAGATION_ATTEMPTS {
+            error!(
+                "Certificate of {:?} stored by a quorum, giving up on authorities {:?}",
+                id.base58(),
+                missing
+            );
+            return true;
+        }
+        info!(
+            "Certificate of {:?} not stored yet by authorities {:?}",
+            id.base58(),
+            missing
+        );
+        false
+    }
 }
 
-fn get_shard_id(transfer: &CrossChainTransfer, _num_shards: u32) -> ShardId {
-    (transfer.sender.0[0] as u32) % 16
+/// Identity of the relayer, created on first start
+fn load_identity(path: &Path) -> Result<KeyPair, Error> {
+    if path.exists() {
+        return Ok(read_key_pair_file(path)?);
+    }
+    let identity = KeyPair::from(rand::random());
+    write_key_pair_file(path, &identity)?;
+    Ok(identity)
 }
 
 /// Run the relayer with the given options
@@ -486,13 +1592,53 @@ pub async fn run_relayer(opt: RelayerOpt) -> Result<(), Error> {
 
     let polling_interval = Duration::from_millis(opt.polling_interval);
 
+    let submit_to = opt
+        .submit_to
+        .unwrap_or_else(|| SubmitterSpec::File(opt.state_dir.join("destination.jsonl")));
+    let submitter = submit_to.start(
+        &opt.destination_rpc,
+        opt.payer.as_deref(),
+        serde_json::from_value(opt.destination_commitment.into())?,
+    )?;
+    let refund_to = opt
+        .refund_to
+        .unwrap_or_else(|| SubmitterSpec::File(opt.state_dir.join("source.jsonl")));
+    let refund_submitter = refund_to.start(
+        &opt.source_rpc,
+        opt.payer.as_deref(),
+        serde_json::from_value(opt.source_commitment.into())?,
+    )?;
+
+    std::fs::create_dir_all(&opt.state_dir)?;
+    let identity = match &opt.identity {
+        Some(path) => read_key_pair_file(path)?,
+        None => load_identity(&opt.state_dir.join("identity.json"))?,
+    };
+    info!("Relayer identity: {}", identity.public().base58());
+
     let mut relayer = Relayer::new(
         &opt.committee,
         opt.source_rpc,
-        opt.destination_rpc,
         polling_interval,
+        &opt.watch,
+        opt.state_dir,
+        submitter,
+        refund_submitter,
+        Timeouts {
+            default: Duration::from_millis(opt.request_timeout),
+            chains: opt
+                .chain_timeouts
+                .iter()
+                .map(|ChainTimeout(chain_id, timeout)| (*chain_id, *timeout))
+                .collect(),
+        },
+        identity,
     )
     .await?;
 
     relayer.run().await
 }
+
+#[cfg(test)]
+#[path = "unit_tests/relayer_tests.rs"]
+mod relayer_tests;
@@ -1,18 +1,17 @@
 use failure::Error;
 use fast_core::{
-    authority::*, base_types::*, committee::Committee, error::*, message::*, serialization::*,
+    authority::*, base_types::*, config::*, error::FastPayError, escrow::SvmRpcEscrowVerifier,
+    message::*, serialization::*, sharding::ShardingStrategy, storage::WalStore,
 };
-use log::{error, info};
-use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
-use std::fs::File;
-use std::io::BufReader;
+use futures::FutureExt;
+use log::{debug, error, info, warn};
 use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
+use std::path::Path;
+use std::sync::Arc;
 use structopt::StructOpt;
 use tokio::sync::mpsc;
 
-use crate::network::UdpServer;
+use crate::network::{self, Handler, NetworkServer, Peer, ServerConfig};
 
 #[derive(Debug, StructOpt)]
 pub struct BridgeServerOpt {
@@ -31,221 +30,302 @@ pub struct BridgeServerOpt {
     /// Number of shards to run
     #[structopt(long, default_value = "16")]
     num_shards: u32,
-}
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct AuthorityConfig {
-    /// Authority name (public key)
-    pub name: String,
+    /// Directory for the write-ahead logs (one sub-directory per authority)
+    #[structopt(long, default_value = "./bridge_db")]
+    db_dir: String,
 
-    /// Path to authority secret key file
-    pub secret_key: String,
+    /// Requests each shard processes at the same time
+    #[structopt(long, default_value = "256")]
+    max_concurrent_requests: usize,
 
-    /// Committee members file
-    pub committee: String,
+    /// UDP sockets receiving the requests of each shard, sharing its port with SO_REUSEPORT
+    #[structopt(long, default_value = "1")]
+    udp_sockets: usize,
 }
 
-/// Simple escrow verifier that always returns true
-pub struct DummyEscrowVerifier;
-
-impl EscrowVerifier for DummyEscrowVerifier {
-    fn verify_escrow(&self, _transfer: &CrossChainTransfer) -> Result<bool, FastPayError> {
-        Ok(true)
-    }
-}
+type AuthorityState = BridgeAuthorityState<WalStore>;
 
 /// Run a bridge authority server with the given options
 pub async fn run_bridge_server(opt: BridgeServerOpt) -> Result<(), Error> {
     // Load authority configuration
-    let config = load_authority_config(&opt.config)?;
+    let config = AuthorityConfig::read(&opt.config)?;
     // Load committee configuration
-    let committee = load_committee(&config.committee)?;
+    let committee_config = CommitteeConfig::read(&config.committee)?;
+    let committee = committee_config.committee()?;
 
     // Load authority secret key
-    let secret = load_secret_key(&config.secret_key)?;
+    let secret = config.key_pair()?;
     // Create authority name from public key
-    let name = decode_authority_name(&config.name)?.into();
-
-    // Create escrow verifier
-    let escrow_verifier = DummyEscrowVerifier;
+    let name = config.authority_name()?;
+
+    // Relayers route according to the committee configuration, so we must listen the same way
+    let entry = committee_config.entry(&name).ok_or_else(|| {
+        failure::format_err!("Authority {} is not listed in {}", config.name, config.committee)
+    })?;
+    entry.check_listener(opt.port, opt.num_shards)?;
+
+    // Shards prove the identity of the authority to their clients, unless in plaintext mode
+    let identity = match entry.security {
+        Security::Encrypted => Some(Arc::new(config.key_pair()?)),
+        Security::Plaintext => {
+            warn!("Shards accept plaintext requests from anyone");
+            None
+        }
+    };
 
-    // Create bridge authority state
-    let (authority_state, cross_shard_receiver) =
-        BridgeAuthorityState::new(name, secret, committee, opt.num_shards, escrow_verifier);
+    // Map senders to shards
+    let sharding = ShardingStrategy::new(opt.num_shards)?;
 
-    // Create shared authority state
-    let shared_authority = Arc::new(Mutex::new(authority_state));
+    // Start the escrow verifier, shared by all shards
+    let (_escrow_task, escrow) = match &config.escrow {
+        EscrowConfig::Disabled => {
+            warn!("Escrow verification is disabled, every transfer will be certified");
+            EscrowCache::start(DummyEscrowVerifier)
+        }
+        EscrowConfig::SvmRpc {
+            url,
+            portal_program,
+            commitment,
+        } => {
+            info!("Verifying escrows through {} at {} commitment", url, commitment.as_str());
+            let portal_program = decode_address(portal_program)?;
+            EscrowCache::start(SvmRpcEscrowVerifier::new(url.clone(), portal_program, *commitment)?)
+        }
+    };
 
-    // Create cross-shard handler
-    let cross_shard_task =
-        handle_cross_shard_updates(shared_authority.clone(), cross_shard_receiver);
+    // Open the write-ahead logs of this authority
+    let db_path = Path::new(&opt.db_dir).join(config.name.trim());
+    let store = WalStore::open(&db_path)?;
+
+    // Create bridge authority state, recovering the shards from disk
+    let (authority_state, cross_shard_receivers) = BridgeAuthorityState::new(
+        name,
+        secret,
+        committee,
+        sharding,
+        store,
+    )?;
+    info!("Recovered authority state from {}", db_path.display());
+
+    // Create shared authority state. Shards lock their own state only.
+    let shared_authority = Arc::new(authority_state);
+
+    // Let each shard process the certificates propagated to it
+    let cross_shard_tasks: Vec<_> = cross_shard_receivers
+        .into_iter()
+        .map(|(shard_id, receiver)| {
+            tokio::spawn(handle_cross_shard_updates(shared_authority.clone(), shard_id, receiver))
+        })
+        .collect();
 
     // Create and run shard servers
+    let server_config = ServerConfig {
+        max_concurrency: opt.max_concurrent_requests,
+        udp_sockets: opt.udp_sockets,
+    };
     let mut server_tasks = Vec::new();
 
-    for i in 0..opt.num_shards {
-        let shard_id = i as ShardId;
+    for shard_id in sharding.shard_ids() {
         let authority = shared_authority.clone();
-        let addr = format!("{}:{}", opt.host, opt.port + (i as u16));
+        let addr = format!("{}:{}", opt.host, opt.port + (shard_id as u16));
         let addr: SocketAddr = addr.parse()?;
-
-        let server_task = run_shard_server(shard_id, authority, addr);
+        let server = network::bind(entry.transport, addr, identity.clone(), server_config).await?;
+
+        let server_task = run_shard_server(
+            shard_id,
+            authority,
+            escrow.clone(),
+            committee_config.network,
+            identity.as_ref().map(|identity| identity.public()),
+            server,
+            addr,
+        );
         server_tasks.push(server_task);
     }
 
     // Wait for all shard servers to complete
     let _shard_results = futures::future::join_all(server_tasks).await;
-    // Await on cross-shard updates
-    cross_shard_task.await?;
+    for task in cross_shard_tasks {
+        task.abort();
+    }
 
     Ok(())
 }
 
 /// Run a server for a specific shard
-async fn run_shard_server(
+pub(crate) async fn run_shard_server(
     shard_id: ShardId,
-    authority: Arc<Mutex<BridgeAuthorityState<DummyEscrowVerifier>>>,
+    authority: Arc<AuthorityState>,
+    escrow: EscrowCache,
+    network: NetworkId,
+    // Only the authority itself may update its shards
+    own_identity: Option<Pubkey>,
+    server: Box<dyn NetworkServer>,
     addr: SocketAddr,
 ) -> Result<(), Error> {
-    let server = UdpServer::new(addr).await?;
-
     info!("Starting shard server {} on {}", shard_id, addr);
 
-    server
-        .run(move |data| {
-            let authority = authority.clone();
-
-            match deserialize_message(data) {
-                Ok(BridgeMessage::CrossChainTransferOrder(order)) => {
-                    // Handle transfer order
-                    let mut state = authority.lock().unwrap();
-                    match state.handle_cross_chain_transfer_order(order, shard_id) {
-                        Ok(signed_order) => {
+    let handler: Handler = Arc::new(move |peer: Peer, data| {
+        let authority = authority.clone();
+        let escrow = escrow.clone();
+
+        async move {
+            debug!("Request to shard {} from {}", shard_id, peer);
+            let (envelope, message) = match open_message(network, &data) {
+                Ok(opened) => opened,
+                Err(FastPayError::UnsupportedProtocolVersion { version, .. }) => {
+                    warn!(
+                        "Request to shard {} from {} in unsupported version {}",
+                        shard_id, peer, version
+                    );
+                    return Some(serialize_version_mismatch(network));
+                }
+                Err(e) => {
+                    debug!("Undecodable request to shard {} from {}: {}", shard_id, peer, e);
+                    return serialize_error(Envelope::new(network), &e).ok();
+                }
+            };
+            let answer = match message {
+                BridgeMessage::CrossChainTransferOrder(order) => {
+                    // Handle transfer order. Answer right away if no new vote is needed,
+                    // otherwise verify the escrow without holding the lock.
+                    let checked = authority.check_cross_chain_transfer_order(&order, shard_id).await;
+                    let result = match checked {
+                        Ok(Some(response)) => Ok(response),
+                        Ok(None) => match escrow.verify(&order.transfer).await {
+                            Ok(verified) => {
+                                authority.handle_cross_chain_transfer_order(order, shard_id, &verified).await
+                            }
+                            Err(e) => Err(e),
+                        },
+                        Err(e) => Err(e),
+                    };
+                    match result {
+                        Ok(response) => {
                             // logs for debug
-                            info!(
-                                "Received signed order from authority: {:?}",
-                                signed_order.authority.base58()
-                            );
-                            Some(serialize_signed_order(&signed_order))
+                            if let TransferOrderResponse::Certificate(_) = &response {
+                                info!("Returning certificate of already processed transfer");
+                            }
+                            serialize_transfer_order_response(envelope, &response).map(Some)
                         }
-                        Err(e) => Some(serialize_error(&e)),
+                        Err(e) => Err(e),
                     }
                 }
-                Ok(BridgeMessage::CrossShardUpdate(update)) => {
-                    // Handle cross-shard update
-                    let mut state = authority.lock().unwrap();
-                    match state.handle_cross_shard_update(update) {
-                        Ok(_) => {
+                BridgeMessage::CrossShardUpdate(update) => {
+                    if own_identity.is_none() || peer.identity != own_identity {
+                        warn!("Rejected cross-shard update from {}", peer);
+                        Err(FastPayError::InvalidCrossShardUpdate)
+                    } else if update.shard_id != shard_id {
+                        warn!("Cross-shard update for shard {} sent to shard {}", update.shard_id, shard_id);
+                        Err(FastPayError::WrongShard {
+                            expected: update.shard_id,
+                            num_shards: authority.sharding.number_of_shards(),
+                        })
+                    } else {
+                        // Handle cross-shard update. No response needed.
+                        authority.handle_cross_shard_update(update).await.map(|_| {
                             info!("Handled cross-shard update for shard {}", shard_id);
                             None
-                        } // No response needed
-                        Err(e) => Some(serialize_error(&e)),
+                        })
                     }
                 }
-                Ok(BridgeMessage::CertifiedCrossChainTransferOrder(cert)) => {
-                    // Handle certified transfer order (propagate to all shards)
-                    let state = authority.lock().unwrap();
-                    match state.propagate_certified_transfer(cert) {
-                        Ok(_) => None, // No response needed
-                        Err(e) => Some(serialize_error(&e)),
+                BridgeMessage::CertifiedCrossChainTransferOrder(cert) => {
+                    // Propagate to all shards, and only acknowledge once they all stored it
+                    match authority.propagate_certified_transfer(cert).await {
+                        Ok(_) => serialize_ack(envelope).map(Some),
+                        Err(e) => Err(e),
                     }
                 }
-                Ok(_) => {
-                    // Unexpected message type
-                    None
+                BridgeMessage::CompactCertificate(cert) => {
+                    match authority.propagate_compact_certificate(cert).await {
+                        Ok(_) => serialize_ack(envelope).map(Some),
+                        Err(e) => Err(e),
+                    }
+                }
+                BridgeMessage::CrossChainRefundOrder(order) => {
+                    match authority.handle_refund_order(order, shard_id).await {
+                        Ok(response) => serialize_refund_order_response(envelope, &response).map(Some),
+                        Err(e) => Err(e),
+                    }
+                }
+                BridgeMessage::CertifiedCrossChainRefundOrder(cert) => {
+                    match authority.handle_refund_certificate(cert, shard_id).await {
+                        Ok(_) => serialize_ack(envelope).map(Some),
+                        Err(e) => Err(e),
+                    }
+                }
+                BridgeMessage::CrossChainMessageOrder(order) => {
+                    match authority.handle_cross_chain_message_order(order, shard_id).await {
+                        Ok(response) => serialize_message_order_response(envelope, &response).map(Some),
+                        Err(e) => Err(e),
+                    }
+                }
+                BridgeMessage::CertifiedCrossChainMessageOrder(cert) => {
+                    match authority.handle_message_certificate(cert, shard_id).await {
+                        Ok(_) => serialize_ack(envelope).map(Some),
+                        Err(e) => Err(e),
+                    }
+                }
+                BridgeMessage::TransferInfoRequest(request) => {
+                    match authority.handle_transfer_info_request(request, shard_id).await {
+                        Ok(response) => serialize_transfer_info_response(envelope, &response).map(Some),
+                        Err(e) => Err(e),
+                    }
                 }
-                Err(_) => {
-                    // Deserialization error
+                BridgeMessage::CommitteeChange(change) => {
+                    match authority.handle_committee_change(change).await {
+                        Ok(response) => {
+                            info!("Committee is now at epoch {}", response.epoch);
+                            serialize_committee_info_response(envelope, &response).map(Some)
+                        }
+                        Err(e) => Err(e),
+                    }
+                }
+                BridgeMessage::CommitteeInfoRequest(request) => {
+                    let response = authority.handle_committee_info_request(request);
+                    serialize_committee_info_response(envelope, &response).map(Some)
+                }
+                BridgeMessage::AccountInfoRequest(request) => {
+                    match authority.handle_account_info_request(request, shard_id).await {
+                        Ok(response) => serialize_account_info_response(envelope, &response).map(Some),
+                        Err(e) => Err(e),
+                    }
+                }
+                // Responses and other messages a shard does not serve
+                _ => Err(FastPayError::UnexpectedMessage),
+            };
+            let answer = match answer {
+                Ok(answer) => Ok(answer),
+                Err(e) => serialize_error(envelope, &e).map(Some),
+            };
+            match answer {
+                Ok(answer) => answer,
+                Err(e) => {
+                    error!("Could not answer request to shard {} from {}: {}", shard_id, peer, e);
                     None
                 }
             }
-        })
-        .await?;
-
-    Ok(())
-}
-
-/// Handle cross-shard updates
-async fn handle_cross_shard_updates(
-    authority: Arc<Mutex<BridgeAuthorityState<DummyEscrowVerifier>>>,
-    mut receiver: mpsc::UnboundedReceiver<CrossShardCrossChainUpdate>,
-) -> Result<(), Error> {
-    while let Some(update) = receiver.recv().await {
-        let mut state = authority.lock().unwrap();
-        if let Err(e) = state.handle_cross_shard_update(update) {
-            error!("Error handling cross-shard update: {:?}", e);
         }
-    }
+        .boxed()
+    });
+    server.run(handler).await?;
 
     Ok(())
 }
 
-/// Load authority configuration from file
-fn load_authority_config(path: &str) -> Result<AuthorityConfig, Error> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let config: AuthorityConfig = serde_json::from_reader(reader)?;
-    Ok(config)
-}
-
-/// Load committee configuration from file
-fn load_committee(path: &str) -> Result<Committee, Error> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let config: serde_json::Value = serde_json::from_reader(reader)?;
-
-    // Parse committee configuration
-    let mut voting_rights = BTreeMap::new();
-
-    if let Some(authorities) = config.get("authorities").and_then(|v| v.as_array()) {
-        for authority in authorities {
-            if let (Some(name), Some(weight)) = (
-                authority.get("name").and_then(|v| v.as_str()),
-                authority.get("weight").and_then(|v| v.as_u64()),
-            ) {
-                let name = decode_authority_name(name)?;
-                voting_rights.insert(name.into(), weight as usize);
-            }
+/// Handle the cross-shard updates sent to one shard, in the order they were sent, and report
+/// back once each is stored
+pub(crate) async fn handle_cross_shard_updates(
+    authority: Arc<AuthorityState>,
+    shard_id: ShardId,
+    mut receiver: mpsc::UnboundedReceiver<CrossShardDelivery>,
+) {
+    while let Some(CrossShardDelivery { update, done }) = receiver.recv().await {
+        let result = authority.handle_cross_shard_update(update).await;
+        if let Err(e) = &result {
+            error!("Error handling cross-shard update for shard {}: {:?}", shard_id, e);
         }
+        done.send(result).unwrap_or(());
     }
-
-    Ok(Committee::new(voting_rights))
-}
-
-/// Load secret key from string or file
-fn load_secret_key(key_or_path: &str) -> Result<KeyPair, Error> {
-    let secret_key_str = if key_or_path.contains('/') && std::path::Path::new(key_or_path).exists()
-    {
-        // This looks like a file path, try to read from file
-        let file = File::open(key_or_path)?;
-        let reader = BufReader::new(file);
-        serde_json::from_reader(reader)?
-    } else {
-        // This looks like a direct secret key string
-        key_or_path.to_string()
-    };
-
-    // Parse secret key
-    let bytes = hex::decode(secret_key_str.trim())?;
-    if bytes.len() != 32 {
-        return Err(failure::format_err!("Invalid secret key length"));
-    }
-
-    let mut key_bytes = [0u8; 32];
-    key_bytes.copy_from_slice(&bytes);
-
-    Ok(KeyPair::from(key_bytes))
-}
-
-/// Decode authority name from string
-fn decode_authority_name(name: &str) -> Result<Pubkey, Error> {
-    let bytes = hex::decode(name.trim())?;
-    if bytes.len() != 32 {
-        return Err(failure::format_err!("Invalid public key length"));
-    }
-
-    let mut key_bytes = [0u8; 32];
-    key_bytes.copy_from_slice(&bytes);
-
-    Ok(Pubkey(key_bytes))
 }
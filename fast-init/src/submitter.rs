@@ -0,0 +1,477 @@
+use base64::Engine;
+use failure::Error;
+use fast_core::{
+    base_types::*,
+    config::{decode_address, read_key_pair_file},
+    escrow::{escrow_authority, Commitment},
+    message::*,
+    rpc::SvmRpcClient,
+};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Seed of the state account of a portal program, which records the delivered transfers.
+pub const PORTAL_STATE_SEED: &[u8] = b"portal";
+/// Seed of the receipt account a portal program creates for each transfer, message or refund
+/// it executes, so that it executes each of them once.
+pub const RECEIPT_SEED: &[u8] = b"receipt";
+/// Seed of the vault token accounts a portal program delivers transfers from, one per mint.
+pub const VAULT_SEED: &[u8] = b"vault";
+/// SPL token program, `TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA`.
+pub const TOKEN_PROGRAM: Pubkey = Pubkey([
+    6, 221, 246, 225, 215, 101, 161, 147, 217, 203, 225, 70, 206, 235, 121, 172, 28, 180, 133, 237, 95, 91, 55, 145,
+    58, 140, 245, 133, 126, 255, 0, 169,
+]);
+
+/// Account passed to a portal instruction.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct AccountMeta {
+    pub pubkey: Pubkey,
+    pub writable: bool,
+}
+
+impl AccountMeta {
+    fn writable(pubkey: Pubkey) -> Self {
+        Self { pubkey, writable: true }
+    }
+
+    fn readonly(pubkey: Pubkey) -> Self {
+        Self { pubkey, writable: false }
+    }
+}
+
+fn program_address(seeds: &[&[u8]], portal_program: &Pubkey) -> Result<Pubkey, Error> {
+    Pubkey::find_program_address(seeds, portal_program)
+        .map(|(address, _)| address)
+        .ok_or_else(|| failure::format_err!("No program address for portal program {}", portal_program.base58()))
+}
+
+/// Instruction of a portal program, encoded with bincode as instruction data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum PortalInstruction {
+    /// Mint a certified transfer to its recipient
+    DeliverTransfer(CompactCertificate),
+    /// Hand a certified message to its target program
+    DeliverMessage(CompactCertificate<CrossChainMessageOrder>),
+    /// Return the escrowed funds of a transfer to its sender, on the source chain
+    Refund(CompactCertificate<CrossChainRefundOrder>),
+}
+
+impl PortalInstruction {
+    /// Transfer or message the instruction is about
+    pub fn interop_tx_id(&self) -> InteropTxId {
+        match self {
+            PortalInstruction::DeliverTransfer(certificate) => certificate.value.transfer.interop_tx_id,
+            PortalInstruction::DeliverMessage(certificate) => certificate.value.message.interop_tx_id,
+            PortalInstruction::Refund(certificate) => certificate.value.transfer().interop_tx_id,
+        }
+    }
+
+    /// Chain whose portal executes the instruction
+    pub fn chain_id(&self) -> ChainId {
+        match self {
+            PortalInstruction::DeliverTransfer(certificate) => certificate.value.transfer.destination_chain,
+            PortalInstruction::DeliverMessage(certificate) => certificate.value.message.destination_chain,
+            PortalInstruction::Refund(certificate) => certificate.value.transfer().source_chain,
+        }
+    }
+
+    /// Receipt account the portal program creates when it executes the instruction
+    pub fn receipt(&self, portal_program: &Pubkey) -> Result<Pubkey, Error> {
+        program_address(&[RECEIPT_SEED, &self.interop_tx_id().0], portal_program)
+    }
+
+    /// Accounts the portal program uses to execute the instruction, in the order it expects
+    /// them, after the payer. The vaults and escrows of the portal are owned by its escrow
+    /// authority, which the portal signs for.
+    pub fn accounts(&self, portal_program: &Pubkey) -> Result<Vec<AccountMeta>, Error> {
+        let state = AccountMeta::writable(program_address(&[PORTAL_STATE_SEED], portal_program)?);
+        let receipt = AccountMeta::writable(self.receipt(portal_program)?);
+        let authority = escrow_authority(portal_program)
+            .ok_or_else(|| failure::format_err!("No escrow authority for portal program {}", portal_program.base58()))?;
+        Ok(match self {
+            PortalInstruction::DeliverTransfer(certificate) => {
+                let transfer = &certificate.value.transfer;
+                let vault = program_address(&[VAULT_SEED, &transfer.token_mint.0], portal_program)?;
+                vec![
+                    state,
+                    receipt,
+                    AccountMeta::writable(transfer.recipient),
+                    AccountMeta::readonly(transfer.token_mint),
+                    AccountMeta::writable(vault),
+                    AccountMeta::readonly(authority),
+                    AccountMeta::readonly(TOKEN_PROGRAM),
+                ]
+            }
+            PortalInstruction::DeliverMessage(certificate) => {
+                vec![state, receipt, AccountMeta::readonly(certificate.value.message.target_program)]
+            }
+            PortalInstruction::Refund(certificate) => {
+                let transfer = certificate.value.transfer();
+                vec![
+                    state,
+                    receipt,
+                    AccountMeta::writable(transfer.sender),
+                    AccountMeta::writable(transfer.escrow_account),
+                    AccountMeta::readonly(authority),
+                    AccountMeta::readonly(TOKEN_PROGRAM),
+                ]
+            }
+        })
+    }
+}
+
+/// An instruction ready to be sent to the destination chain, e.g. as a signed transaction.
+/// Sending the same submission several times delivers the certificate at most once.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Submission {
+    /// Identifier on the destination chain, e.g. a transaction signature
+    pub id: String,
+    /// Past this block height, a submission that did not land never will
+    pub last_valid_height: u64,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SubmissionStatus {
+    /// Not yet at the required commitment
+    Pending,
+    /// Executed at the required commitment
+    Confirmed,
+    /// Executed with an error
+    Failed(String),
+    /// Dropped before execution
+    Expired,
+}
+
+/// Delivers certificates to the portal program of the destination chain.
+pub trait DestinationSubmitter: Send + Sync {
+    /// Build a transaction for a portal instruction, ready to be sent
+    fn prepare<'a>(&'a self, instruction: &'a PortalInstruction) -> BoxFuture<'a, Result<Submission, Error>>;
+
+    /// Send a prepared submission
+    fn send<'a>(&'a self, submission: &'a Submission) -> BoxFuture<'a, Result<(), Error>>;
+
+    /// Check how far a submission went
+    fn status<'a>(&'a self, submission: &'a Submission) -> BoxFuture<'a, Result<SubmissionStatus, Error>>;
+
+    /// Whether the portal executed the instruction, carried by any submission, e.g. one of
+    /// another relayer
+    fn is_executed<'a>(&'a self, instruction: &'a PortalInstruction) -> BoxFuture<'a, Result<bool, Error>>;
+}
+
+/// Where to submit certificates, as given on the command line: `file:<PATH>` or
+/// `svm:<PORTAL_PROGRAM>`.
+#[derive(Debug, Clone)]
+pub enum SubmitterSpec {
+    File(PathBuf),
+    Svm(Pubkey),
+}
+
+impl FromStr for SubmitterSpec {
+    type Err = Error;
+
+    fn from_str(spec: &str) -> Result<Self, Error> {
+        match spec.split_once(':') {
+            Some(("file", path)) => Ok(SubmitterSpec::File(PathBuf::from(path))),
+            Some(("svm", portal_program)) => Ok(SubmitterSpec::Svm(decode_address(portal_program)?)),
+            _ => Err(failure::format_err!("Invalid destination {:?}", spec)),
+        }
+    }
+}
+
+impl SubmitterSpec {
+    /// Create the submitter. SVM transactions are paid by the key pair in `payer`, a JSON
+    /// array of 64 bytes as written by `solana-keygen`.
+    pub fn start(
+        &self,
+        destination_rpc: &str,
+        payer: Option<&Path>,
+        commitment: Commitment,
+    ) -> Result<Box<dyn DestinationSubmitter>, Error> {
+        Ok(match self {
+            SubmitterSpec::File(path) => Box::new(FileSubmitter::new(path.clone())),
+            SubmitterSpec::Svm(portal_program) => {
+                let payer = payer.ok_or_else(|| failure::format_err!("Submitting to an SVM chain requires a payer"))?;
+                Box::new(SvmRpcSubmitter::new(
+                    destination_rpc.to_string(),
+                    *portal_program,
+                    read_key_pair_file(payer)?,
+                    commitment,
+                ))
+            }
+        })
+    }
+}
+
+/// Appends the portal instructions to a file, one JSON line each, for another process to
+/// submit. A submission is confirmed once its line is in the file, synced to disk; a
+/// submission whose line is missing, e.g. because the append failed, is sent again.
+pub struct FileSubmitter {
+    path: PathBuf,
+}
+
+impl FileSubmitter {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+impl DestinationSubmitter for FileSubmitter {
+    fn prepare<'a>(&'a self, instruction: &'a PortalInstruction) -> BoxFuture<'a, Result<Submission, Error>> {
+        Box::pin(async move {
+            let id = instruction.interop_tx_id().base58();
+            let data = bincode::serialize(instruction)?;
+            let mut line = serde_json::to_vec(&json!({
+                "interop_tx_id": id,
+                "chain": instruction.chain_id().0,
+                "instruction": base64::engine::general_purpose::STANDARD.encode(data),
+            }))?;
+            line.push(b'\n');
+            Ok(Submission {
+                id,
+                last_valid_height: 0,
+                payload: line,
+            })
+        })
+    }
+
+    fn send<'a>(&'a self, submission: &'a Submission) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let mut file = fs::OpenOptions::new().create(true).append(true).open(&self.path)?;
+            file.write_all(&submission.payload)?;
+            file.sync_data()?;
+            Ok(())
+        })
+    }
+
+    fn status<'a>(&'a self, submission: &'a Submission) -> BoxFuture<'a, Result<SubmissionStatus, Error>> {
+        Box::pin(async move {
+            let file = match fs::File::open(&self.path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(SubmissionStatus::Expired),
+                Err(e) => return Err(e.into()),
+            };
+            let line = submission.payload.strip_suffix(b"\n").unwrap_or(&submission.payload);
+            for written in BufReader::new(file).split(b'\n') {
+                if written? == line {
+                    return Ok(SubmissionStatus::Confirmed);
+                }
+            }
+            Ok(SubmissionStatus::Expired)
+        })
+    }
+
+    fn is_executed<'a>(&'a self, instruction: &'a PortalInstruction) -> BoxFuture<'a, Result<bool, Error>> {
+        Box::pin(async move {
+            let file = match fs::File::open(&self.path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+                Err(e) => return Err(e.into()),
+            };
+            let id = instruction.interop_tx_id().base58();
+            for written in BufReader::new(file).split(b'\n') {
+                let Ok(line) = serde_json::from_slice::<Value>(&written?) else {
+                    continue;
+                };
+                if line["interop_tx_id"] == id.as_str() && line["chain"] == instruction.chain_id().0 {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        })
+    }
+}
+
+/// Sends portal instructions in transactions to the JSON-RPC endpoint of the destination
+/// SVM chain, and follows them until the chosen commitment.
+pub struct SvmRpcSubmitter {
+    client: SvmRpcClient,
+    portal_program: Pubkey,
+    payer: KeyPair,
+    commitment: Commitment,
+}
+
+impl SvmRpcSubmitter {
+    pub fn new(url: String, portal_program: Pubkey, payer: KeyPair, commitment: Commitment) -> Self {
+        Self {
+            client: SvmRpcClient::new(url),
+            portal_program,
+            payer,
+            commitment,
+        }
+    }
+
+    /// Legacy transaction calling the portal with a single instruction, paid and signed by
+    /// the payer.
+    fn transaction(&self, instruction: &PortalInstruction, recent_blockhash: &Pubkey) -> Result<Vec<u8>, Error> {
+        let data = bincode::serialize(instruction)?;
+        let accounts = instruction.accounts(&self.portal_program)?;
+        let message = compile_message(&self.payer.public(), &self.portal_program, &accounts, &data, recent_blockhash);
+
+        let mut transaction = Vec::new();
+        push_compact_u16(&mut transaction, 1);
+        transaction.extend_from_slice(&self.payer.sign_bytes(&message));
+        transaction.extend_from_slice(&message);
+        Ok(transaction)
+    }
+}
+
+/// Legacy message of a single instruction of `program`, whose accounts are the payer then
+/// `accounts`. The keys are ordered as the runtime requires: the payer, the only signer, then
+/// the writable accounts, then the read-only ones, the program last.
+fn compile_message(
+    payer: &Pubkey,
+    program: &Pubkey,
+    accounts: &[AccountMeta],
+    data: &[u8],
+    recent_blockhash: &Pubkey,
+) -> Vec<u8> {
+    let mut writable = Vec::new();
+    let mut readonly = Vec::new();
+    for account in accounts.iter().filter(|account| account.pubkey != *payer) {
+        if account.writable {
+            readonly.retain(|key| *key != account.pubkey);
+            if !writable.contains(&account.pubkey) {
+                writable.push(account.pubkey);
+            }
+        } else if !writable.contains(&account.pubkey) && !readonly.contains(&account.pubkey) {
+            readonly.push(account.pubkey);
+        }
+    }
+    readonly.retain(|key| key != program);
+    readonly.push(*program);
+    let keys: Vec<Pubkey> = std::iter::once(*payer).chain(writable).chain(readonly.iter().copied()).collect();
+    let index = |key: &Pubkey| keys.iter().position(|k| k == key).expect("Every account has a key") as u8;
+
+    let mut message = vec![
+        1, // signatures required: the payer
+        0, // read-only signed accounts
+        readonly.len() as u8,
+    ];
+    push_compact_u16(&mut message, keys.len());
+    for key in &keys {
+        message.extend_from_slice(&key.0);
+    }
+    message.extend_from_slice(&recent_blockhash.0);
+    push_compact_u16(&mut message, 1);
+    message.push(index(program));
+    push_compact_u16(&mut message, accounts.len() + 1);
+    message.push(0);
+    message.extend(accounts.iter().map(|account| index(&account.pubkey)));
+    push_compact_u16(&mut message, data.len());
+    message.extend_from_slice(data);
+    message
+}
+
+/// Length prefix of the arrays of an SVM transaction.
+fn push_compact_u16(buffer: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buffer.push(byte);
+            return;
+        }
+        buffer.push(byte | 0x80);
+    }
+}
+
+fn commitment_rank(status: &str) -> u8 {
+    match status {
+        "processed" => 0,
+        "confirmed" => 1,
+        "finalized" => 2,
+        _ => 0,
+    }
+}
+
+impl DestinationSubmitter for SvmRpcSubmitter {
+    fn prepare<'a>(&'a self, instruction: &'a PortalInstruction) -> BoxFuture<'a, Result<Submission, Error>> {
+        Box::pin(async move {
+            let latest = self
+                .client
+                .call("getLatestBlockhash", json!([{ "commitment": self.commitment.as_str() }]))
+                .await?;
+            let blockhash = decode_address(latest["value"]["blockhash"].as_str().unwrap_or_default())?;
+            let last_valid_height = latest["value"]["lastValidBlockHeight"]
+                .as_u64()
+                .ok_or_else(|| failure::format_err!("Malformed getLatestBlockhash response"))?;
+
+            let transaction = self.transaction(instruction, &blockhash)?;
+            // The first signature identifies the transaction
+            let id = bs58::encode(&transaction[1..65]).into_string();
+            Ok(Submission {
+                id,
+                last_valid_height,
+                payload: transaction,
+            })
+        })
+    }
+
+    fn send<'a>(&'a self, submission: &'a Submission) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let options = json!({ "encoding": "base64", "preflightCommitment": self.commitment.as_str() });
+            let encoded = base64::engine::general_purpose::STANDARD.encode(&submission.payload);
+            self.client.call("sendTransaction", json!([encoded, options])).await?;
+            Ok(())
+        })
+    }
+
+    fn status<'a>(&'a self, submission: &'a Submission) -> BoxFuture<'a, Result<SubmissionStatus, Error>> {
+        Box::pin(async move {
+            let statuses = self
+                .client
+                .call(
+                    "getSignatureStatuses",
+                    json!([[submission.id], { "searchTransactionHistory": true }]),
+                )
+                .await?;
+            let status = &statuses["value"][0];
+            if status.is_null() {
+                let height = self
+                    .client
+                    .call("getBlockHeight", json!([{ "commitment": self.commitment.as_str() }]))
+                    .await?
+                    .as_u64()
+                    .unwrap_or(0);
+                if height > submission.last_valid_height {
+                    return Ok(SubmissionStatus::Expired);
+                }
+                return Ok(SubmissionStatus::Pending);
+            }
+            if !status["err"].is_null() {
+                return Ok(SubmissionStatus::Failed(status["err"].to_string()));
+            }
+            let reached = commitment_rank(status["confirmationStatus"].as_str().unwrap_or_default());
+            if reached >= commitment_rank(self.commitment.as_str()) {
+                Ok(SubmissionStatus::Confirmed)
+            } else {
+                Ok(SubmissionStatus::Pending)
+            }
+        })
+    }
+
+    fn is_executed<'a>(&'a self, instruction: &'a PortalInstruction) -> BoxFuture<'a, Result<bool, Error>> {
+        Box::pin(async move {
+            let receipt = instruction.receipt(&self.portal_program)?;
+            let account = self
+                .client
+                .call(
+                    "getAccountInfo",
+                    json!([receipt.base58(), { "commitment": self.commitment.as_str(), "encoding": "base64" }]),
+                )
+                .await?;
+            Ok(!account["value"].is_null())
+        })
+    }
+}
+
+#[cfg(test)]
+#[path = "unit_tests/submitter_tests.rs"]
+mod submitter_tests;
@@ -0,0 +1,19 @@
+use super::*;
+
+fn escrow(args: &[&str]) -> Result<EscrowConfig, Error> {
+    let opt = BridgeConfigGenOpt::from_iter(["generate-config"].iter().chain(args));
+    escrow_config(&opt)
+}
+
+#[test]
+fn test_escrow_verification_needs_an_endpoint_and_a_portal() {
+    let portal = Pubkey([9u8; 32]).base58();
+    let verified = escrow(&["--escrow-rpc-url", "http://localhost:8899", "--portal-program", &portal]).unwrap();
+    assert!(matches!(verified, EscrowConfig::SvmRpc { portal_program, .. } if portal_program == portal));
+    assert!(matches!(escrow(&["--disable-escrow-verification"]).unwrap(), EscrowConfig::Disabled));
+
+    assert!(escrow(&[]).is_err());
+    assert!(escrow(&["--portal-program", &portal]).is_err());
+    assert!(escrow(&["--escrow-rpc-url", "http://localhost:8899"]).is_err());
+    assert!(escrow(&["--portal-program", &portal, "--disable-escrow-verification"]).is_err());
+}
@@ -0,0 +1,436 @@
+use super::*;
+
+fn address(host: u8, port: u16) -> SocketAddr {
+    SocketAddr::from(([10, 0, 0, host], port))
+}
+
+/// A session shared by all the channels of a table, which only tests its bookkeeping
+fn session() -> Arc<Session> {
+    let client = KeyPair::from([1u8; 32]);
+    let server = KeyPair::from([2u8; 32]);
+    let initiator = Initiator::new(&client, server.public());
+    let (session, _) = channel::accept(&server, initiator.hello()).unwrap();
+    Arc::new(session)
+}
+
+fn server_channel(session: &Arc<Session>) -> ServerChannel {
+    ServerChannel {
+        session: session.clone(),
+        hello: [0; 32],
+        accept: Vec::new(),
+        last_used: 0,
+    }
+}
+
+#[test]
+fn test_least_recently_used_channel_is_dropped() {
+    let session = session();
+    let mut table = ChannelTable::default();
+    let hosts = MAX_CHANNELS / MAX_CHANNELS_PER_SOURCE;
+    for i in 0..MAX_CHANNELS {
+        let key = (address((i % hosts) as u8, (i / hosts) as u16), 0);
+        table.insert(key, server_channel(&session));
+    }
+    let (first, second) = ((address(0, 0), 0), (address(1, 0), 0));
+    table.touch(&first);
+
+    table.insert((address(200, 0), 0), server_channel(&session));
+    assert_eq!(table.channels.len(), MAX_CHANNELS);
+    assert!(table.get(&first).is_some());
+    assert!(table.get(&second).is_none());
+    assert_eq!(table.order.len(), MAX_CHANNELS);
+}
+
+#[test]
+fn test_handshake_flood_only_drops_channels_of_its_source() {
+    let session = session();
+    let mut table = ChannelTable::default();
+    let client = (address(1, 1000), 7);
+    table.insert(client, server_channel(&session));
+    for session_id in 0..10 * MAX_CHANNELS as u64 {
+        table.insert((address(2, 2000), session_id), server_channel(&session));
+    }
+    assert!(table.get(&client).is_some());
+    assert_eq!(table.channels.len(), MAX_CHANNELS_PER_SOURCE + 1);
+    assert_eq!(table.sources[&address(2, 0).ip()].len(), MAX_CHANNELS_PER_SOURCE);
+
+    table.remove(&client);
+    assert!(!table.sources.contains_key(&client.0.ip()));
+}
+
+#[test]
+fn test_hello_without_cookie_keeps_no_state() {
+    let identity = KeyPair::from([2u8; 32]);
+    let initiator = Initiator::new(&KeyPair::from([1u8; 32]), identity.public());
+    let hello = bincode::serialize(initiator.hello()).unwrap();
+    let channels = ServerChannels::new();
+    let addr = address(1, 1000);
+
+    let datagram = secure_datagram(HELLO, 7, &[&[0; COOKIE_SIZE][..], &hello].concat());
+    let Received::Reply(reply) = channels.receive(&identity, addr, &datagram) else {
+        panic!("Expected a cookie");
+    };
+    let (kind, session_id, cookie) = parse_secure_datagram(&reply).unwrap();
+    assert_eq!((kind, session_id), (COOKIE, 7));
+    assert!(channels.table.lock().unwrap().channels.is_empty());
+
+    // The cookie is bound to the address and session of the client
+    let datagram = secure_datagram(HELLO, 7, &[cookie, &hello].concat());
+    assert!(matches!(
+        channels.receive(&identity, address(2, 1000), &datagram),
+        Received::Reply(reply) if reply[0] == COOKIE
+    ));
+    assert!(matches!(
+        channels.receive(&identity, addr, &datagram),
+        Received::Reply(reply) if reply[0] == ACCEPT
+    ));
+    assert!(channels.table.lock().unwrap().get(&(addr, 7)).is_some());
+}
+
+#[tokio::test]
+async fn test_encrypted_udp_round_trip() {
+    let identity = Arc::new(KeyPair::from([2u8; 32]));
+    let server = UdpServer::new("127.0.0.1:0".parse().unwrap(), Some(identity.clone()), ServerConfig::default())
+        .await
+        .unwrap();
+    let addr = server.sockets[0].local_addr().unwrap();
+    let handler: Handler = Arc::new(|peer: Peer, data: Vec<u8>| {
+        Box::pin(async move { Some([peer.identity.unwrap().0.to_vec(), data].concat()) })
+    });
+    tokio::spawn(async move { server.run(handler).await });
+
+    let client_identity = Arc::new(KeyPair::from([1u8; 32]));
+    let client = UdpClient::new(Some(client_identity.clone())).await.unwrap();
+    let response = client.send_recv(identity.public(), addr, b"ping".to_vec()).await.unwrap();
+    assert_eq!(response, [client_identity.public().0.to_vec(), b"ping".to_vec()].concat());
+}
+
+/// Answers each request with its payload, after the number of milliseconds in its first byte,
+/// counting how many requests are processed at the same time
+fn delayed_echo(in_flight: Arc<AtomicU64>, most_in_flight: Arc<AtomicU64>) -> Handler {
+    Arc::new(move |_peer: Peer, data: Vec<u8>| {
+        let (in_flight, most_in_flight) = (in_flight.clone(), most_in_flight.clone());
+        Box::pin(async move {
+            let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            most_in_flight.fetch_max(current, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(data[0] as u64)).await;
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            Some(data)
+        })
+    })
+}
+
+async fn udp_server(config: ServerConfig, handler: Handler) -> SocketAddr {
+    let server = UdpServer::new("127.0.0.1:0".parse().unwrap(), None, config).await.unwrap();
+    let addr = server.sockets[0].local_addr().unwrap();
+    tokio::spawn(async move { server.run(handler).await });
+    addr
+}
+
+async fn tcp_server(config: ServerConfig, handler: Handler) -> SocketAddr {
+    let server = TcpServer::new("127.0.0.1:0".parse().unwrap(), None, config).await.unwrap();
+    let addr = server.listener.local_addr().unwrap();
+    tokio::spawn(async move { server.run(handler).await });
+    addr
+}
+
+/// Send a slow request then a fast one at the same time, returning the order of the answers
+async fn answer_order(client: &dyn NetworkClient, addr: SocketAddr) -> Vec<u8> {
+    let answers = Mutex::new(Vec::new());
+    let request = |delay: u8| {
+        let answers = &answers;
+        async move {
+            let response = client.send_recv(Pubkey([0u8; 32]), addr, vec![delay]).await.unwrap();
+            answers.lock().unwrap().push(response[0]);
+        }
+    };
+    tokio::join!(request(200), request(0));
+    answers.into_inner().unwrap()
+}
+
+#[tokio::test]
+async fn test_udp_fast_request_is_not_held_by_slow_one() {
+    let counters = (Arc::new(AtomicU64::new(0)), Arc::new(AtomicU64::new(0)));
+    let addr = udp_server(ServerConfig::default(), delayed_echo(counters.0, counters.1)).await;
+    let client = UdpClient::new(None).await.unwrap();
+    assert_eq!(answer_order(&client, addr).await, [0, 200]);
+}
+
+#[tokio::test]
+async fn test_tcp_fast_request_is_not_held_by_slow_one() {
+    let counters = (Arc::new(AtomicU64::new(0)), Arc::new(AtomicU64::new(0)));
+    let addr = tcp_server(ServerConfig::default(), delayed_echo(counters.0, counters.1)).await;
+    // Both requests share one connection
+    let client = TcpClient::new(None);
+    assert_eq!(answer_order(&client, addr).await, [0, 200]);
+}
+
+#[tokio::test]
+async fn test_udp_server_bounds_concurrent_requests() {
+    let (in_flight, most_in_flight) = (Arc::new(AtomicU64::new(0)), Arc::new(AtomicU64::new(0)));
+    let config = ServerConfig {
+        max_concurrency: 2,
+        udp_sockets: 1,
+    };
+    let addr = udp_server(config, delayed_echo(in_flight, most_in_flight.clone())).await;
+    let client = UdpClient::new(None).await.unwrap();
+    let requests = (0..8).map(|_| client.send_recv(Pubkey([0u8; 32]), addr, vec![20]));
+    for response in futures::future::join_all(requests).await {
+        assert_eq!(response.unwrap(), [20]);
+    }
+    assert_eq!(most_in_flight.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn test_typed_error_reaches_the_client() {
+    let network = 7;
+    let handler: Handler = Arc::new(move |_peer: Peer, data: Vec<u8>| {
+        Box::pin(async move {
+            let (envelope, _) = open_message(network, &data).unwrap();
+            let error = FastPayError::WrongShard { expected: 3, num_shards: 4 };
+            Some(serialize_error(envelope, &error).unwrap())
+        })
+    });
+    let addr = udp_server(ServerConfig::default(), handler).await;
+    let client = AuthorityShardClient::new(
+        Pubkey([0u8; 32]),
+        addr,
+        network,
+        Arc::new(UdpClient::new(None).await.unwrap()),
+    );
+    let request = TransferInfoRequest {
+        sender: Pubkey([1u8; 32]),
+        interop_tx_id: InteropTxId([2u8; 32]),
+    };
+    let error = client.get_transfer_info(&request).await.unwrap_err();
+    assert_eq!(error, FastPayError::WrongShard { expected: 3, num_shards: 4 });
+    assert!(error.is_retryable());
+}
+
+#[tokio::test]
+async fn test_handshake_gives_up_on_endless_cookies() {
+    // A server answering every hello with a new cookie
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    let hellos = Arc::new(AtomicU64::new(0));
+    let counter = hellos.clone();
+    tokio::spawn(async move {
+        let mut buffer = vec![0; DEFAULT_BUFFER_SIZE];
+        while let Ok((len, client)) = socket.recv_from(&mut buffer).await {
+            let (kind, session_id, _) = parse_secure_datagram(&buffer[..len]).unwrap();
+            assert_eq!(kind, HELLO);
+            counter.fetch_add(1, Ordering::SeqCst);
+            let cookie: [u8; COOKIE_SIZE] = rand::random();
+            let _ = socket.send_to(&secure_datagram(COOKIE, session_id, &cookie), client).await;
+        }
+    });
+
+    let identity = Some(Arc::new(KeyPair::from([1u8; 32])));
+    let client = UdpClient::with_timeout(identity, Duration::from_millis(50), 3).await.unwrap();
+    let response = tokio::time::timeout(
+        Duration::from_secs(5),
+        client.send_recv(Pubkey([2u8; 32]), addr, b"ping".to_vec()),
+    )
+    .await
+    .expect("The handshake should give up");
+    assert!(response.is_err());
+    // Each attempt sends one hello, then one more with the cookie it got
+    assert_eq!(hellos.load(Ordering::SeqCst), 6);
+}
+
+/// Plaintext UDP shard answering each datagram with `answer`, given the number of datagrams
+/// received so far. Returns its address.
+async fn raw_udp_shard<F>(answer: F) -> SocketAddr
+where
+    F: Fn(usize, RequestId, &[u8]) -> Vec<Vec<u8>> + Send + 'static,
+{
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buffer = vec![0; DEFAULT_BUFFER_SIZE];
+        let mut received = 0;
+        while let Ok((len, client)) = socket.recv_from(&mut buffer).await {
+            received += 1;
+            let (request_id, payload) = unwrap(&buffer[..len]).unwrap();
+            for datagram in answer(received, request_id, payload) {
+                let _ = socket.send_to(&datagram, client).await;
+            }
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn test_udp_request_is_sent_again_until_answered() {
+    // The first datagram is lost
+    let addr = raw_udp_shard(|received, request_id, payload| {
+        if received == 1 {
+            Vec::new()
+        } else {
+            vec![wrap(request_id, &[payload, &[received as u8]].concat())]
+        }
+    })
+    .await;
+    let client = UdpClient::with_timeout(None, Duration::from_millis(50), 3).await.unwrap();
+    let response = client.send_recv(Pubkey([0u8; 32]), addr, b"ping".to_vec()).await.unwrap();
+    assert_eq!(response, b"ping\x02");
+    assert!(client.pending.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_udp_request_gives_up_after_its_attempts() {
+    let received = Arc::new(AtomicU64::new(0));
+    let counter = received.clone();
+    let addr = raw_udp_shard(move |_, _, _| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Vec::new()
+    })
+    .await;
+    let client = UdpClient::with_timeout(None, Duration::from_millis(20), 3).await.unwrap();
+    let response = client.send_recv(Pubkey([0u8; 32]), addr, b"ping".to_vec()).await;
+    assert!(matches!(response, Err(FastPayError::ClientIoError { .. })));
+    assert_eq!(received.load(Ordering::SeqCst), 3);
+    assert!(client.pending.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_udp_responses_are_matched_to_their_request() {
+    // Each request is answered late: a response to another request id comes first
+    let addr = raw_udp_shard(|_, request_id, payload| {
+        vec![
+            wrap(request_id.wrapping_add(1000), b"unmatched"),
+            b"short".to_vec(),
+            wrap(request_id, payload),
+        ]
+    })
+    .await;
+    let client = UdpClient::with_timeout(None, Duration::from_millis(500), 1).await.unwrap();
+    let requests = (0..20u8).map(|i| {
+        let client = &client;
+        async move { (i, client.send_recv(Pubkey([0u8; 32]), addr, vec![i]).await.unwrap()) }
+    });
+    // Concurrent requests share the socket of the client
+    for (i, response) in futures::future::join_all(requests).await {
+        assert_eq!(response, [i]);
+    }
+}
+
+#[tokio::test]
+async fn test_frames_round_trip_and_bound_their_length() {
+    let (mut client, mut server) = tokio::io::duplex(1 << 16);
+    let body = vec![7u8; 100_000];
+    let writer = tokio::spawn(async move {
+        client.write_all(&frame(&body)).await.unwrap();
+        client.write_all(&frame(&[])).await.unwrap();
+        client.write_all(&((MAX_FRAME_SIZE + 1) as u32).to_le_bytes()).await.unwrap();
+        client
+    });
+    assert_eq!(read_frame(&mut server).await.unwrap(), vec![7u8; 100_000]);
+    assert!(read_frame(&mut server).await.unwrap().is_empty());
+    let error = read_frame(&mut server).await.unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+    // A frame cut short is an error, not a shorter message
+    let mut client = writer.await.unwrap();
+    client.write_all(&frame(b"ping")[..6]).await.unwrap();
+    drop(client);
+    assert!(read_frame(&mut server).await.is_err());
+}
+
+#[tokio::test]
+async fn test_tcp_carries_messages_larger_than_a_datagram() {
+    let handler: Handler = Arc::new(|_peer: Peer, data: Vec<u8>| Box::pin(async move { Some(data) }));
+    let addr = tcp_server(ServerConfig::default(), handler).await;
+    let client = TcpClient::new(None);
+    let message: Vec<u8> = (0..4 * DEFAULT_BUFFER_SIZE).map(|i| i as u8).collect();
+    let response = client.send_recv(Pubkey([0u8; 32]), addr, message.clone()).await.unwrap();
+    assert_eq!(response, message);
+}
+
+#[tokio::test]
+async fn test_tcp_connection_is_reused_until_it_breaks() {
+    let handler: Handler = Arc::new(|_peer: Peer, data: Vec<u8>| Box::pin(async move { Some(data) }));
+    let addr = tcp_server(ServerConfig::default(), handler).await;
+    let client = TcpClient::new(None);
+    client.send_recv(Pubkey([0u8; 32]), addr, b"one".to_vec()).await.unwrap();
+    let first = client.connections.lock().unwrap()[&addr].clone();
+    client.send_recv(Pubkey([0u8; 32]), addr, b"two".to_vec()).await.unwrap();
+    assert!(Arc::ptr_eq(&first, &client.connections.lock().unwrap()[&addr]));
+
+    // A broken connection is replaced
+    TcpConnection::close(&first.pending, &first.closed);
+    assert_eq!(client.send_recv(Pubkey([0u8; 32]), addr, b"three".to_vec()).await.unwrap(), b"three");
+    assert!(!Arc::ptr_eq(&first, &client.connections.lock().unwrap()[&addr]));
+}
+
+#[tokio::test]
+async fn test_encrypted_tcp_round_trip() {
+    let identity = Arc::new(KeyPair::from([2u8; 32]));
+    let server = TcpServer::new("127.0.0.1:0".parse().unwrap(), Some(identity.clone()), ServerConfig::default())
+        .await
+        .unwrap();
+    let addr = server.listener.local_addr().unwrap();
+    let handler: Handler = Arc::new(|peer: Peer, data: Vec<u8>| {
+        Box::pin(async move { Some([peer.identity.unwrap().0.to_vec(), data].concat()) })
+    });
+    tokio::spawn(async move { server.run(handler).await });
+
+    let client_identity = Arc::new(KeyPair::from([1u8; 32]));
+    let client = TcpClient::new(Some(client_identity.clone()));
+    let response = client.send_recv(identity.public(), addr, b"ping".to_vec()).await.unwrap();
+    assert_eq!(response, [client_identity.public().0.to_vec(), b"ping".to_vec()].concat());
+}
+
+#[tokio::test]
+async fn test_tcp_hello_without_cookie_gets_a_cookie() {
+    let identity = Arc::new(KeyPair::from([2u8; 32]));
+    let server = TcpServer::new("127.0.0.1:0".parse().unwrap(), Some(identity.clone()), ServerConfig::default())
+        .await
+        .unwrap();
+    let addr = server.listener.local_addr().unwrap();
+    let handler: Handler = Arc::new(|_peer: Peer, data: Vec<u8>| Box::pin(async move { Some(data) }));
+    tokio::spawn(async move { server.run(handler).await });
+
+    let client_identity = KeyPair::from([1u8; 32]);
+    let initiator = Initiator::new(&client_identity, identity.public());
+    let hello = bincode::serialize(initiator.hello()).unwrap();
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(&frame(&[&[0; COOKIE_SIZE][..], &hello].concat())).await.unwrap();
+    let reply = read_frame(&mut stream).await.unwrap();
+    assert_eq!(reply[0], COOKIE);
+
+    // The key exchange only runs once the hello carries the cookie
+    stream.write_all(&frame(&[&reply[1..], &hello].concat())).await.unwrap();
+    let reply = read_frame(&mut stream).await.unwrap();
+    assert_eq!(reply[0], ACCEPT);
+    assert!(initiator.finish(&bincode::deserialize(&reply[1..]).unwrap()).is_ok());
+}
+
+#[tokio::test]
+async fn test_tcp_connections_are_bounded_per_source() {
+    let mut server = TcpServer::new("127.0.0.1:0".parse().unwrap(), None, ServerConfig::default()).await.unwrap();
+    server.max_connections_per_source = 1;
+    let addr = server.listener.local_addr().unwrap();
+    let handler: Handler = Arc::new(|_peer: Peer, data: Vec<u8>| Box::pin(async move { Some(data) }));
+    tokio::spawn(async move { server.run(handler).await });
+
+    let client = TcpClient::new(None);
+    client.send_recv(Pubkey([0u8; 32]), addr, b"one".to_vec()).await.unwrap();
+    let mut refused = TcpStream::connect(addr).await.unwrap();
+    let _ = refused.write_all(&frame(&wrap(1, b"two"))).await;
+    assert!(read_frame(&mut refused).await.is_err());
+
+    // The host may connect again once its connection is closed
+    drop(client);
+    let client = TcpClient::with_timeout(None, Duration::from_millis(200));
+    let mut answered = false;
+    for _ in 0..20 {
+        if client.send_recv(Pubkey([0u8; 32]), addr, b"three".to_vec()).await.is_ok() {
+            answered = true;
+            break;
+        }
+        client.connections.lock().unwrap().clear();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(answered);
+}
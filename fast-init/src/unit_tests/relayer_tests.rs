@@ -0,0 +1,837 @@
+use super::*;
+use crate::network::{Handler, Peer, ServerConfig, UdpServer};
+use crate::server::{handle_cross_shard_updates, run_shard_server};
+use crate::watcher::{JsonLinesWatcher, SourceBatch};
+use fast_core::authority::{BridgeAuthorityState, EscrowCache};
+use fast_core::mock_ledger::{MockSvmChain, PortalEvent, SharedMockChain};
+use fast_core::serialization::*;
+use fast_core::storage::WalStore;
+use fast_core::test_support::{self, NO_DEADLINE};
+use futures::future::BoxFuture;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+
+const NETWORK: NetworkId = 7;
+
+/// How a stub authority answers transfer orders
+#[derive(Clone)]
+enum Answer {
+    Vote,
+    Refuse(FastPayError),
+}
+
+/// Shard of an authority, answering certificates with an ack if it `stores` them, transfer
+/// orders as set by the test, refund orders and transfer info requests according to the
+/// certificate it has, if any, and other requests with an error
+struct Stub {
+    secret: KeyPair,
+    committee: Committee,
+    stores: bool,
+    answer: Mutex<Answer>,
+    certificate: Mutex<Option<CertifiedCrossChainTransferOrder>>,
+    /// Transfer orders received
+    orders: Mutex<usize>,
+}
+
+impl Stub {
+    fn answer(&self, envelope: Envelope, message: BridgeMessage) -> Option<Vec<u8>> {
+        match message {
+            BridgeMessage::CertifiedCrossChainTransferOrder(_) if self.stores => serialize_ack(envelope).ok(),
+            BridgeMessage::CrossChainTransferOrder(order) => {
+                *self.orders.lock().unwrap() += 1;
+                let answer = self.answer.lock().unwrap().clone();
+                match answer {
+                    Answer::Vote => {
+                        let name = self.secret.public();
+                        let shard = self.committee.shard_of(&name, &order.transfer.sender).unwrap();
+                        let vote = SignedOrder::new(order, name, &self.committee, shard, &self.secret);
+                        serialize_transfer_order_response(envelope, &TransferOrderResponse::Vote(vote)).ok()
+                    }
+                    Answer::Refuse(error) => serialize_error(envelope, &error).ok(),
+                }
+            }
+            BridgeMessage::CrossChainRefundOrder(_) if self.certificate.lock().unwrap().is_some() => {
+                serialize_error(envelope, &FastPayError::CertificateAlreadyExists).ok()
+            }
+            BridgeMessage::TransferInfoRequest(request) => {
+                let response = TransferInfoResponse {
+                    interop_tx_id: request.interop_tx_id,
+                    pending_order: None,
+                    signed_order: None,
+                    certificate: self.certificate.lock().unwrap().clone(),
+                };
+                serialize_transfer_info_response(envelope, &response).ok()
+            }
+            _ => serialize_error(envelope, &FastPayError::CommunicationError).ok(),
+        }
+    }
+}
+
+/// Serve a stub authority on a free port, and return the port
+async fn authority_stub(stub: Arc<Stub>) -> u16 {
+    let port = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let server = UdpServer::new(([127, 0, 0, 1], port).into(), None, ServerConfig::default())
+        .await
+        .unwrap();
+    let handler: Handler = Arc::new(move |_peer: Peer, data: Vec<u8>| {
+        let stub = stub.clone();
+        Box::pin(async move {
+            let (envelope, message) = open_message(NETWORK, &data).ok()?;
+            stub.answer(envelope, message)
+        })
+    });
+    tokio::spawn(async move { server.run(handler).await });
+    port
+}
+
+/// What the relayer asked of a submitter, the status of each submission, `Pending` unless set
+/// by the test, and the jobs executed by submissions of others
+#[derive(Default)]
+struct MockChain {
+    prepared: Vec<InteropTxId>,
+    sent: Vec<String>,
+    statuses: HashMap<String, SubmissionStatus>,
+    executed: HashSet<InteropTxId>,
+}
+
+#[derive(Clone, Default)]
+struct MockSubmitter(Arc<Mutex<MockChain>>);
+
+/// Id of the `attempt`-th submission of a job by the mock submitter
+fn submission_id(id: &InteropTxId, attempt: usize) -> String {
+    format!("{}-{}", id.base58(), attempt)
+}
+
+impl DestinationSubmitter for MockSubmitter {
+    fn prepare<'a>(&'a self, instruction: &'a PortalInstruction) -> BoxFuture<'a, Result<Submission, Error>> {
+        Box::pin(async move {
+            let mut chain = self.0.lock().unwrap();
+            let id = instruction.interop_tx_id();
+            chain.prepared.push(id);
+            let attempt = chain.prepared.iter().filter(|prepared| **prepared == id).count();
+            Ok(Submission {
+                id: submission_id(&id, attempt),
+                last_valid_height: 0,
+                payload: Vec::new(),
+            })
+        })
+    }
+
+    fn send<'a>(&'a self, submission: &'a Submission) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            self.0.lock().unwrap().sent.push(submission.id.clone());
+            Ok(())
+        })
+    }
+
+    fn status<'a>(&'a self, submission: &'a Submission) -> BoxFuture<'a, Result<SubmissionStatus, Error>> {
+        Box::pin(async move {
+            let chain = self.0.lock().unwrap();
+            Ok(chain.statuses.get(&submission.id).cloned().unwrap_or(SubmissionStatus::Pending))
+        })
+    }
+
+    fn is_executed<'a>(&'a self, instruction: &'a PortalInstruction) -> BoxFuture<'a, Result<bool, Error>> {
+        Box::pin(async move { Ok(self.0.lock().unwrap().executed.contains(&instruction.interop_tx_id())) })
+    }
+}
+
+/// Four authorities of one shard each, and the state directory of a relayer
+struct Setup {
+    dir: tempfile::TempDir,
+    secrets: Vec<KeyPair>,
+    committee: Committee,
+    stubs: Vec<Arc<Stub>>,
+    submitter: MockSubmitter,
+}
+
+impl Setup {
+    /// Only the first `storing` authorities store the certificates they are sent. None of
+    /// them answers transfer orders until told to.
+    async fn new(storing: usize) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let secrets: Vec<_> = (1..=4u8).map(|i| KeyPair::from([i; 32])).collect();
+        let committee = Committee::new(
+            NETWORK,
+            0,
+            secrets.iter().map(|secret| (secret.public(), 1)).collect(),
+            secrets.iter().map(|secret| (secret.public(), 1)).collect(),
+        );
+        let mut authorities = Vec::new();
+        let mut stubs = Vec::new();
+        for (i, secret) in secrets.iter().enumerate() {
+            let stub = Arc::new(Stub {
+                secret: KeyPair::from([i as u8 + 1; 32]),
+                committee: committee.clone(),
+                stores: i < storing,
+                answer: Mutex::new(Answer::Refuse(FastPayError::CommunicationError)),
+                certificate: Mutex::new(None),
+                orders: Mutex::new(0),
+            });
+            authorities.push(AuthorityEntry {
+                name: encode_authority_name(&secret.public()),
+                host: "127.0.0.1".to_string(),
+                port: authority_stub(stub.clone()).await,
+                weight: 1,
+                num_shards: 1,
+                transport: Transport::Udp,
+                security: Security::Plaintext,
+            });
+            stubs.push(stub);
+        }
+        let config = CommitteeConfig {
+            epoch: 0,
+            network: NETWORK,
+            authorities,
+        };
+        config.write(dir.path().join("committee.json")).unwrap();
+        assert_eq!(config.committee().unwrap(), committee);
+        Self {
+            committee,
+            dir,
+            secrets,
+            stubs,
+            submitter: MockSubmitter::default(),
+        }
+    }
+
+    fn state_dir(&self) -> PathBuf {
+        self.dir.path().join("state")
+    }
+
+    /// Append records to the job log, as a previous run of the relayer would have
+    fn record(&self, records: &[JobRecord]) {
+        std::fs::create_dir_all(self.state_dir()).unwrap();
+        let mut log = JobLog::open(&self.state_dir()).unwrap();
+        for record in records {
+            log.append(record).unwrap();
+        }
+    }
+
+    async fn start(&self) -> Relayer {
+        Relayer::new(
+            self.dir.path().join("committee.json").to_str().unwrap(),
+            String::new(),
+            Duration::ZERO,
+            &[],
+            self.state_dir(),
+            Box::new(self.submitter.clone()),
+            Box::new(MockSubmitter::default()),
+            Timeouts {
+                default: Duration::from_millis(500),
+                chains: HashMap::new(),
+            },
+            KeyPair::from([50u8; 32]),
+        )
+        .await
+        .unwrap()
+    }
+
+    fn vote(&self, order: &CrossChainTransferOrder, secret: &KeyPair) -> SignedCrossChainTransferOrder {
+        let shard = self.committee.shard_of(&secret.public(), &order.transfer.sender).unwrap();
+        SignedOrder::new(order.clone(), secret.public(), &self.committee, shard, secret)
+    }
+
+    /// Certificate of the first three authorities, a quorum
+    fn certify(&self, order: &CrossChainTransferOrder) -> CertifiedCrossChainTransferOrder {
+        Certificate {
+            value: order.clone(),
+            epoch: 0,
+            signatures: self.secrets[..3]
+                .iter()
+                .map(|secret| (secret.public(), self.vote(order, secret).signature))
+                .collect(),
+        }
+    }
+}
+
+fn make_order(nonce: u64) -> CrossChainTransferOrder {
+    test_support::make_order(&KeyPair::from([20u8; 32]), nonce, 10)
+}
+
+/// Same order, past its deadline
+fn expired(order: &CrossChainTransferOrder) -> CrossChainTransferOrder {
+    let transfer = CrossChainTransfer {
+        deadline: 1,
+        ..order.transfer.clone()
+    };
+    CrossChainTransferOrder::new(transfer, &KeyPair::from([20u8; 32]))
+}
+
+fn observed(order: &CrossChainTransferOrder) -> JobRecord {
+    JobRecord::Observed {
+        order: order.clone(),
+        observed_at: 0,
+    }
+}
+
+fn state(relayer: &Relayer, id: &InteropTxId) -> Option<JobState> {
+    relayer.pending_transfers.get(id).map(|pending| pending.delivery.state)
+}
+
+#[tokio::test]
+async fn test_restart_resumes_each_job_from_its_last_step() {
+    let setup = Setup::new(4).await;
+    let orders: Vec<_> = (0..6).map(make_order).collect();
+    let ids: Vec<_> = orders.iter().map(|order| order.transfer.interop_tx_id).collect();
+    let submission = |i: usize| Submission {
+        id: format!("before-restart-{}", i),
+        last_valid_height: 0,
+        payload: Vec::new(),
+    };
+    let mut records: Vec<_> = orders.iter().map(observed).collect();
+    records.extend(setup.secrets[..3].iter().map(|secret| JobRecord::Vote(setup.vote(&orders[1], secret))));
+    records.extend(orders[2..].iter().map(|order| JobRecord::Certified(setup.certify(order))));
+    records.extend((3..6).map(|i| JobRecord::Submitted(ids[i], submission(i))));
+    records.extend([JobRecord::Confirmed(ids[4]), JobRecord::Confirmed(ids[5])]);
+    records.push(JobRecord::Propagated(ids[5]));
+    setup.record(&records);
+
+    let mut relayer = setup.start().await;
+    let states: Vec<_> = ids.iter().map(|id| state(&relayer, id)).collect();
+    assert_eq!(
+        states,
+        [
+            Some(JobState::Observed),
+            Some(JobState::VotesCollected),
+            Some(JobState::Certified),
+            Some(JobState::Submitted),
+            Some(JobState::Confirmed),
+            None,
+        ]
+    );
+    assert!(relayer.finished_jobs.contains(&ids[5]));
+    assert_eq!(relayer.pending_transfers[&ids[3]].delivery.submission, Some(submission(3)));
+
+    // The submission recorded before the restart is sent again, not prepared anew
+    relayer.resend_submissions().await;
+    assert_eq!(setup.submitter.0.lock().unwrap().sent, ["before-restart-3"]);
+    assert!(setup.submitter.0.lock().unwrap().prepared.is_empty());
+
+    relayer.check_pending_transfers().await.unwrap();
+    // Only the jobs that were never submitted are
+    let mut prepared = setup.submitter.0.lock().unwrap().prepared.clone();
+    prepared.sort();
+    let mut expected = vec![ids[1], ids[2]];
+    expected.sort();
+    assert_eq!(prepared, expected);
+    assert_eq!(setup.submitter.0.lock().unwrap().sent.len(), 3);
+    assert_eq!(state(&relayer, &ids[0]), Some(JobState::Observed));
+    assert_eq!(state(&relayer, &ids[3]), Some(JobState::Submitted));
+    // Confirmed before the restart, then stored by every authority
+    assert_eq!(state(&relayer, &ids[4]), None);
+    assert!(relayer.finished_jobs.contains(&ids[4]));
+}
+
+#[tokio::test]
+async fn test_failed_submission_is_prepared_again_until_confirmed() {
+    let setup = Setup::new(4).await;
+    let order = make_order(0);
+    let id = order.transfer.interop_tx_id;
+    setup.record(&[observed(&order), JobRecord::Certified(setup.certify(&order))]);
+    let mut relayer = setup.start().await;
+
+    relayer.check_pending_transfers().await.unwrap();
+    assert_eq!(state(&relayer, &id), Some(JobState::Submitted));
+    let failed = SubmissionStatus::Failed("custom program error".to_string());
+    setup.submitter.0.lock().unwrap().statuses.insert(submission_id(&id, 1), failed);
+
+    // The failure is recorded, then a new submission made
+    relayer.check_pending_transfers().await.unwrap();
+    assert_eq!(state(&relayer, &id), Some(JobState::Certified));
+    assert_eq!(relayer.pending_transfers[&id].delivery.submission_failures, 1);
+    relayer.check_pending_transfers().await.unwrap();
+    assert_eq!(state(&relayer, &id), Some(JobState::Submitted));
+    assert_eq!(setup.submitter.0.lock().unwrap().sent, [submission_id(&id, 1), submission_id(&id, 2)]);
+
+    let confirmed = SubmissionStatus::Confirmed;
+    setup.submitter.0.lock().unwrap().statuses.insert(submission_id(&id, 2), confirmed);
+    relayer.check_pending_transfers().await.unwrap();
+    assert!(relayer.finished_jobs.contains(&id));
+
+    // Still over after a restart
+    drop(relayer);
+    let relayer = setup.start().await;
+    assert!(relayer.pending_transfers.is_empty());
+    assert!(relayer.is_known(&id));
+}
+
+#[tokio::test]
+async fn test_confirmed_job_waits_for_the_authorities_to_store_its_certificate() {
+    // The last authority never stores the certificate
+    let setup = Setup::new(3).await;
+    let order = make_order(0);
+    let id = order.transfer.interop_tx_id;
+    let certificate = setup.certify(&order);
+    setup.record(&[observed(&order), JobRecord::Certified(certificate), JobRecord::Confirmed(id)]);
+    let mut relayer = setup.start().await;
+
+    for _ in 1..MAX_PROPAGATION_ATTEMPTS {
+        relayer.check_pending_transfers().await.unwrap();
+        assert_eq!(state(&relayer, &id), Some(JobState::Confirmed));
+    }
+    let acked = &relayer.pending_transfers[&id].delivery.acked;
+    assert_eq!(acked.len(), 3);
+    assert!(!acked.contains(&setup.secrets[3].public()));
+
+    // Given up on the last authority, since a quorum has the certificate
+    relayer.check_pending_transfers().await.unwrap();
+    assert!(relayer.finished_jobs.contains(&id));
+}
+
+#[tokio::test]
+async fn test_confirmed_job_without_a_quorum_of_acks_is_not_over() {
+    let setup = Setup::new(2).await;
+    let order = make_order(0);
+    let id = order.transfer.interop_tx_id;
+    let certificate = setup.certify(&order);
+    setup.record(&[observed(&order), JobRecord::Certified(certificate), JobRecord::Confirmed(id)]);
+    let mut relayer = setup.start().await;
+
+    for _ in 0..=MAX_PROPAGATION_ATTEMPTS {
+        relayer.check_pending_transfers().await.unwrap();
+    }
+    assert_eq!(state(&relayer, &id), Some(JobState::Confirmed));
+
+    // Propagated is never recorded, so the job is taken up again after a restart
+    drop(relayer);
+    let relayer = setup.start().await;
+    assert_eq!(state(&relayer, &id), Some(JobState::Confirmed));
+}
+
+#[tokio::test]
+async fn test_compaction_keeps_when_transfers_were_observed() {
+    let setup = Setup::new(4).await;
+    let (order, refunded) = (make_order(0), make_order(1));
+    let refunded_id = refunded.transfer.interop_tx_id;
+    setup.record(&[
+        JobRecord::Observed {
+            order: order.clone(),
+            observed_at: 1_000,
+        },
+        JobRecord::Observed {
+            order: refunded,
+            observed_at: 2_000,
+        },
+        JobRecord::RefundRequested(refunded_id),
+    ]);
+
+    let mut relayer = setup.start().await;
+    relayer.compact().unwrap();
+    drop(relayer);
+    let relayer = setup.start().await;
+    assert_eq!(relayer.pending_transfers[&order.transfer.interop_tx_id].observed_at, 1_000);
+    assert_eq!(relayer.pending_refunds[&refunded_id].observed_at, 2_000);
+}
+
+#[test]
+fn test_finished_jobs_forget_the_oldest() {
+    let mut finished = FinishedJobs::default();
+    let id = |i: usize| {
+        let mut bytes = [0u8; 32];
+        bytes[..8].copy_from_slice(&(i as u64).to_le_bytes());
+        InteropTxId(bytes)
+    };
+    for i in 0..=MAX_FINISHED_JOBS {
+        finished.insert(id(i));
+    }
+    finished.insert(id(1));
+    assert!(!finished.contains(&id(0)));
+    assert!(finished.contains(&id(1)));
+    assert!(finished.contains(&id(MAX_FINISHED_JOBS)));
+    assert_eq!(finished.iter().count(), MAX_FINISHED_JOBS);
+    assert_eq!(finished.iter().next(), Some(&id(1)));
+}
+
+#[test]
+fn test_retries_back_off_exponentially() {
+    let mut retry = AuthorityRetry::new();
+    for attempt in 1..=10u32 {
+        let before = Instant::now();
+        retry.schedule();
+        assert_eq!(retry.attempts, attempt);
+        let delay = INITIAL_RETRY_DELAY.saturating_mul(1 << attempt).min(MAX_RETRY_DELAY);
+        // With jitter of half the delay either way
+        assert!(retry.next_attempt >= before + delay / 2);
+        assert!(retry.next_attempt <= Instant::now() + delay.mul_f64(1.5));
+    }
+}
+
+fn orders_received(setup: &Setup) -> Vec<usize> {
+    setup.stubs.iter().map(|stub| *stub.orders.lock().unwrap()).collect()
+}
+
+#[tokio::test]
+async fn test_votes_are_only_asked_again_of_the_missing_authorities() {
+    let setup = Setup::new(4).await;
+    for stub in &setup.stubs[..2] {
+        *stub.answer.lock().unwrap() = Answer::Vote;
+    }
+    let order = make_order(0);
+    let id = order.transfer.interop_tx_id;
+    let mut relayer = setup.start().await;
+    relayer.process_transfer(order).await.unwrap();
+    assert_eq!(orders_received(&setup), [1, 1, 1, 1]);
+    assert_eq!(relayer.pending_transfers[&id].signed_orders.len(), 2);
+
+    // Nobody is due before the backoff delay
+    relayer.request_missing_votes(&id).await.unwrap();
+    assert_eq!(orders_received(&setup), [1, 1, 1, 1]);
+
+    // Then only the authorities that did not vote are asked again
+    let now = Instant::now();
+    for retry in relayer.pending_transfers.get_mut(&id).unwrap().retries.values_mut() {
+        retry.next_attempt = now;
+    }
+    *setup.stubs[2].answer.lock().unwrap() = Answer::Vote;
+    relayer.request_missing_votes(&id).await.unwrap();
+    assert_eq!(orders_received(&setup), [1, 1, 2, 2]);
+    assert_eq!(relayer.pending_transfers[&id].signed_orders.len(), 3);
+    assert_eq!(relayer.pending_transfers[&id].retries[&setup.secrets[3].public()].attempts, 2);
+
+    // Which is a quorum
+    relayer.check_pending_transfers().await.unwrap();
+    assert!(relayer.pending_transfers[&id].certificate.is_some());
+    assert_eq!(orders_received(&setup), [1, 1, 2, 2]);
+}
+
+#[tokio::test]
+async fn test_failed_exchanges_are_not_rejections() {
+    let setup = Setup::new(4).await;
+    let errors = [
+        FastPayError::UnsupportedProtocolVersion { version: 2, min: 1, max: 1 },
+        FastPayError::WrongNetwork { expected: 8, found: NETWORK },
+        FastPayError::UnexpectedTransactionIndex,
+    ];
+    for (stub, error) in setup.stubs.iter().zip(errors) {
+        *stub.answer.lock().unwrap() = Answer::Refuse(error);
+    }
+    let order = make_order(0);
+    let id = order.transfer.interop_tx_id;
+    let mut relayer = setup.start().await;
+    relayer.process_transfer(order).await.unwrap();
+
+    // The relayer cannot talk to these authorities for now, but they may vote later
+    relayer.check_pending_transfers().await.unwrap();
+    assert!(relayer.pending_transfers[&id].retries.values().all(|retry| !retry.rejected));
+    assert_eq!(state(&relayer, &id), Some(JobState::Observed));
+    assert!(relayer.pending_refunds.is_empty());
+}
+
+#[tokio::test]
+async fn test_lagging_authorities_vote_once_they_see_the_escrow() {
+    let setup = Setup::new(4).await;
+    let order = make_order(0);
+    let id = order.transfer.interop_tx_id;
+    let not_found = FastPayError::EscrowNotFound {
+        escrow_account: order.transfer.escrow_account.base58(),
+    };
+    *setup.stubs[0].answer.lock().unwrap() = Answer::Vote;
+    for stub in &setup.stubs[1..] {
+        *stub.answer.lock().unwrap() = Answer::Refuse(not_found.clone());
+    }
+    let mut relayer = setup.start().await;
+    relayer.process_transfer(order).await.unwrap();
+
+    // More than f authorities have not seen the lock yet, which is no reason to refund
+    relayer.check_pending_transfers().await.unwrap();
+    assert!(relayer.pending_transfers[&id].retries.values().all(|retry| !retry.rejected));
+    assert!(relayer.pending_refunds.is_empty());
+
+    // Once their source RPC catches up, they vote
+    for stub in &setup.stubs[1..] {
+        *stub.answer.lock().unwrap() = Answer::Vote;
+    }
+    let now = Instant::now();
+    for retry in relayer.pending_transfers.get_mut(&id).unwrap().retries.values_mut() {
+        retry.next_attempt = now;
+    }
+    relayer.request_missing_votes(&id).await.unwrap();
+    relayer.check_pending_transfers().await.unwrap();
+    assert!(relayer.pending_transfers[&id].certificate.is_some());
+}
+
+#[tokio::test]
+async fn test_transfer_is_abandoned_once_more_than_f_authorities_reject_it() {
+    let setup = Setup::new(4).await;
+    *setup.stubs[0].answer.lock().unwrap() = Answer::Refuse(FastPayError::TransferExpired);
+    let order = make_order(0);
+    let id = order.transfer.interop_tx_id;
+    let mut relayer = setup.start().await;
+    relayer.process_transfer(order).await.unwrap();
+
+    // A single authority may be faulty, so the others are still asked
+    relayer.check_pending_transfers().await.unwrap();
+    assert_eq!(state(&relayer, &id), Some(JobState::Observed));
+    assert!(relayer.pending_transfers[&id].retries[&setup.secrets[0].public()].rejected);
+
+    let now = Instant::now();
+    for retry in relayer.pending_transfers.get_mut(&id).unwrap().retries.values_mut() {
+        retry.next_attempt = now;
+    }
+    *setup.stubs[1].answer.lock().unwrap() = Answer::Refuse(FastPayError::TransferExpired);
+    relayer.request_missing_votes(&id).await.unwrap();
+    // Rejections are final
+    assert_eq!(orders_received(&setup), [1, 2, 2, 2]);
+
+    // Then the funds are refunded
+    relayer.check_pending_transfers().await.unwrap();
+    assert!(!relayer.pending_transfers.contains_key(&id));
+    assert!(relayer.pending_refunds.contains_key(&id));
+}
+
+#[tokio::test]
+async fn test_certified_transfer_is_never_refunded() {
+    let setup = Setup::new(4).await;
+    let (undelivered, delivered) = (expired(&make_order(0)), expired(&make_order(1)));
+    let mut records = Vec::new();
+    for order in [&undelivered, &delivered] {
+        records.extend([
+            observed(order),
+            JobRecord::Certified(setup.certify(order)),
+            JobRecord::SubmissionFailed(order.transfer.interop_tx_id, MAX_SUBMISSION_FAILURES),
+        ]);
+    }
+    setup.record(&records);
+    let (undelivered, delivered) = (undelivered.transfer.interop_tx_id, delivered.transfer.interop_tx_id);
+    setup.submitter.0.lock().unwrap().executed.insert(delivered);
+    let mut relayer = setup.start().await;
+
+    // Both expired after too many failed submissions, but one was delivered meanwhile
+    relayer.check_pending_transfers().await.unwrap();
+    assert!(relayer.pending_refunds.is_empty());
+    assert!(relayer.finished_jobs.contains(&undelivered));
+    assert_eq!(state(&relayer, &delivered), Some(JobState::Confirmed));
+    relayer.check_pending_transfers().await.unwrap();
+    assert!(relayer.pending_refunds.is_empty());
+    assert!(relayer.finished_jobs.contains(&delivered));
+    assert!(setup.submitter.0.lock().unwrap().prepared.is_empty());
+}
+
+#[tokio::test]
+async fn test_refund_of_a_certified_transfer_turns_into_its_delivery() {
+    let setup = Setup::new(4).await;
+    let order = expired(&make_order(0));
+    let id = order.transfer.interop_tx_id;
+    setup.record(&[observed(&order), JobRecord::RefundRequested(id)]);
+    // Another relayer got the transfer certified
+    *setup.stubs[2].certificate.lock().unwrap() = Some(setup.certify(&order));
+    let mut relayer = setup.start().await;
+    assert!(relayer.pending_refunds.contains_key(&id));
+
+    relayer.check_pending_transfers().await.unwrap();
+    assert!(relayer.pending_refunds.is_empty());
+    assert_eq!(state(&relayer, &id), Some(JobState::Certified));
+    relayer.check_pending_transfers().await.unwrap();
+    assert_eq!(setup.submitter.0.lock().unwrap().prepared, [id]);
+
+    // Still a delivery after a restart
+    drop(relayer);
+    let relayer = setup.start().await;
+    assert!(relayer.pending_refunds.is_empty());
+    assert_eq!(state(&relayer, &id), Some(JobState::Submitted));
+}
+
+#[tokio::test]
+async fn test_watchers_are_kept_when_processing_their_orders_fails() {
+    let setup = Setup::new(4).await;
+    let order = make_order(0);
+    let line = format!("{}\n", serde_json::to_string(&order).unwrap());
+    let path = setup.dir.path().join("orders.jsonl");
+    std::fs::write(&path, &line).unwrap();
+    let mut relayer = setup.start().await;
+    relayer.watchers.push(Box::new(JsonLinesWatcher::new(ChainId(1), path)));
+
+    // The cursor of the order cannot be saved
+    let missing = setup.dir.path().join("missing").join("cursors.json");
+    let cursors = std::mem::replace(&mut relayer.cursors, SourceCursors::load(missing).unwrap());
+    assert!(relayer.poll_source_chain().await.is_err());
+    assert_eq!(relayer.watchers.len(), 1);
+
+    // The next poll reads the order again, and moves past it
+    relayer.cursors = cursors;
+    relayer.poll_source_chain().await.unwrap();
+    assert!(relayer.is_known(&order.transfer.interop_tx_id));
+    assert_eq!(relayer.cursors.get(ChainId(1)), line.len() as u64);
+}
+
+/// Reports the orders locked on the portal of a simulated chain, positioned by its events
+struct MockSvmWatcher(SharedMockChain);
+
+impl SourceWatcher for MockSvmWatcher {
+    fn chain_id(&self) -> ChainId {
+        self.0.lock().unwrap().chain_id
+    }
+
+    fn poll(&mut self, cursor: u64) -> BoxFuture<'_, Result<SourceBatch, Error>> {
+        let chain = self.0.lock().unwrap();
+        let events = chain.events_since(cursor as usize);
+        let orders = (cursor + 1..)
+            .zip(events)
+            .filter_map(|(position, event)| match event {
+                PortalEvent::Locked(order) => Some((position, SourceOrder::Transfer(order.clone()))),
+                _ => None,
+            })
+            .collect();
+        let batch = SourceBatch {
+            orders,
+            cursor: cursor + events.len() as u64,
+        };
+        Box::pin(async move { Ok(batch) })
+    }
+}
+
+/// Executes deliveries on the portal of a simulated chain as soon as they are sent
+struct MockSvmSubmitter(SharedMockChain);
+
+impl MockSvmSubmitter {
+    fn is_minted(&self, id: &InteropTxId) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .events_since(0)
+            .iter()
+            .any(|event| matches!(event, PortalEvent::Minted(transfer) if transfer.interop_tx_id == *id))
+    }
+}
+
+impl DestinationSubmitter for MockSvmSubmitter {
+    fn prepare<'a>(&'a self, instruction: &'a PortalInstruction) -> BoxFuture<'a, Result<Submission, Error>> {
+        Box::pin(async move {
+            Ok(Submission {
+                id: instruction.interop_tx_id().base58(),
+                last_valid_height: 0,
+                payload: bincode::serialize(instruction)?,
+            })
+        })
+    }
+
+    fn send<'a>(&'a self, submission: &'a Submission) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            match bincode::deserialize(&submission.payload)? {
+                PortalInstruction::DeliverTransfer(certificate) => {
+                    Ok(self.0.lock().unwrap().deliver_compact(&certificate)?)
+                }
+                instruction => Err(failure::format_err!("Unexpected instruction {:?}", instruction)),
+            }
+        })
+    }
+
+    fn status<'a>(&'a self, submission: &'a Submission) -> BoxFuture<'a, Result<SubmissionStatus, Error>> {
+        Box::pin(async move {
+            let instruction: PortalInstruction = bincode::deserialize(&submission.payload)?;
+            if self.is_minted(&instruction.interop_tx_id()) {
+                Ok(SubmissionStatus::Confirmed)
+            } else {
+                Ok(SubmissionStatus::Expired)
+            }
+        })
+    }
+
+    fn is_executed<'a>(&'a self, instruction: &'a PortalInstruction) -> BoxFuture<'a, Result<bool, Error>> {
+        Box::pin(async move { Ok(self.is_minted(&instruction.interop_tx_id())) })
+    }
+}
+
+/// Serve a shard of an authority as `fast-init server` does, checking escrows on the given
+/// chain, and return its port
+async fn authority_server(secret: KeyPair, committee: Committee, source: SharedMockChain, dir: &Path) -> u16 {
+    let (authority, receivers) = BridgeAuthorityState::new(
+        secret.public(),
+        secret,
+        committee,
+        ShardingStrategy::new(1).unwrap(),
+        WalStore::open(dir).unwrap(),
+    )
+    .unwrap();
+    let authority = Arc::new(authority);
+    for (shard_id, receiver) in receivers {
+        tokio::spawn(handle_cross_shard_updates(authority.clone(), shard_id, receiver));
+    }
+    let (_, escrow) = EscrowCache::start(source);
+    let port = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let address: SocketAddr = ([127, 0, 0, 1], port).into();
+    let server = UdpServer::new(address, None, ServerConfig::default()).await.unwrap();
+    tokio::spawn(run_shard_server(0, authority, escrow, NETWORK, None, Box::new(server), address));
+    port
+}
+
+#[tokio::test]
+async fn test_locked_transfer_is_minted_on_the_destination_chain() {
+    let dir = tempfile::tempdir().unwrap();
+    let secrets: Vec<_> = (1..=4u8).map(|i| KeyPair::from([i; 32])).collect();
+    let committee = Committee::new(
+        NETWORK,
+        0,
+        secrets.iter().map(|secret| (secret.public(), 1)).collect(),
+        secrets.iter().map(|secret| (secret.public(), 1)).collect(),
+    );
+    let portal = Pubkey([9u8; 32]);
+    let source = MockSvmChain::new(ChainId(1), portal, committee.clone()).into_shared();
+    let destination = MockSvmChain::new(ChainId(2), portal, committee.clone()).into_shared();
+
+    let mut authorities = Vec::new();
+    for (i, secret) in secrets.iter().enumerate() {
+        let db = dir.path().join(format!("authority-{}", i));
+        let port = authority_server(KeyPair::from([i as u8 + 1; 32]), committee.clone(), source.clone(), &db).await;
+        authorities.push(AuthorityEntry {
+            name: encode_authority_name(&secret.public()),
+            host: "127.0.0.1".to_string(),
+            port,
+            weight: 1,
+            num_shards: 1,
+            transport: Transport::Udp,
+            security: Security::Plaintext,
+        });
+    }
+    let committee_path = dir.path().join("committee.json");
+    CommitteeConfig {
+        epoch: 0,
+        network: NETWORK,
+        authorities,
+    }
+    .write(&committee_path)
+    .unwrap();
+
+    let mut relayer = Relayer::new(
+        committee_path.to_str().unwrap(),
+        String::new(),
+        Duration::ZERO,
+        &[],
+        dir.path().join("state"),
+        Box::new(MockSvmSubmitter(destination.clone())),
+        Box::new(MockSubmitter::default()),
+        Timeouts {
+            default: Duration::from_millis(500),
+            chains: HashMap::new(),
+        },
+        KeyPair::from([50u8; 32]),
+    )
+    .await
+    .unwrap();
+    relayer.watchers.push(Box::new(MockSvmWatcher(source.clone())));
+
+    // A user locks funds on the source chain
+    let (user, recipient, mint) = (KeyPair::from([20u8; 32]), Pubkey([2u8; 32]), Pubkey([3u8; 32]));
+    let order = {
+        let mut source = source.lock().unwrap();
+        source.airdrop(user.public(), mint, 100);
+        source.lock(&user, ChainId(2), recipient, mint, 60, NO_DEADLINE).unwrap()
+    };
+    let id = order.transfer.interop_tx_id;
+
+    // The relayer picks the order up, gets it certified by the authorities, and delivers it
+    relayer.poll_source_chain().await.unwrap();
+    assert!(relayer.is_known(&id));
+    for _ in 0..10 {
+        if relayer.finished_jobs.contains(&id) {
+            break;
+        }
+        relayer.check_pending_transfers().await.unwrap();
+    }
+    assert!(relayer.finished_jobs.contains(&id));
+    assert_eq!(source.lock().unwrap().balance(&user.public(), &mint), 40);
+    let destination = destination.lock().unwrap();
+    assert_eq!(destination.balance(&recipient, &mint), 60);
+    assert_eq!(destination.events_since(0), &[PortalEvent::Minted(order.transfer)]);
+}
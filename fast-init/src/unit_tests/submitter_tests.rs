@@ -0,0 +1,207 @@
+use super::*;
+use fast_core::test_support::{make_order, make_transfer, rpc_stub};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+const PORTAL: Pubkey = Pubkey([9u8; 32]);
+
+/// Certificate whose signatures are not looked at by the submitters
+fn compact<V>(value: V) -> CompactCertificate<V> {
+    CompactCertificate {
+        value,
+        epoch: 0,
+        signers: vec![0],
+        signatures: Vec::new(),
+    }
+}
+
+fn delivery(recipient: Pubkey) -> PortalInstruction {
+    let sender = KeyPair::from([20u8; 32]);
+    PortalInstruction::DeliverTransfer(compact(CrossChainTransferOrder::new(make_transfer(sender.public(), recipient, 0, 10), &sender)))
+}
+
+fn submitter(url: String) -> SvmRpcSubmitter {
+    SvmRpcSubmitter::new(url, PORTAL, KeyPair::from([5u8; 32]), Commitment::Confirmed)
+}
+
+fn push_len(buffer: &mut Vec<u8>, len: usize) {
+    assert!((128..1 << 14).contains(&len));
+    buffer.extend_from_slice(&[(len & 0x7f) as u8 | 0x80, (len >> 7) as u8]);
+}
+
+#[test]
+fn test_delivery_transaction_lists_its_accounts() {
+    let submitter = submitter(String::new());
+    let payer = submitter.payer.public();
+    let instruction = delivery(Pubkey([2u8; 32]));
+    let blockhash = Pubkey([7u8; 32]);
+    let transaction = submitter.transaction(&instruction, &blockhash).unwrap();
+
+    let state = Pubkey::find_program_address(&[PORTAL_STATE_SEED], &PORTAL).unwrap().0;
+    let receipt = instruction.receipt(&PORTAL).unwrap();
+    let vault = Pubkey::find_program_address(&[VAULT_SEED, &[3u8; 32]], &PORTAL).unwrap().0;
+    let authority = escrow_authority(&PORTAL).unwrap();
+    let data = bincode::serialize(&instruction).unwrap();
+    // Header, then the payer, the writable accounts, the read-only ones and the program
+    let mut message = vec![1, 0, 4, 9];
+    for key in [payer, state, receipt, Pubkey([2u8; 32]), vault, Pubkey([3u8; 32]), authority, TOKEN_PROGRAM, PORTAL] {
+        message.extend_from_slice(&key.0);
+    }
+    message.extend_from_slice(&blockhash.0);
+    // One instruction of the portal, with its accounts in the order the portal expects them
+    message.extend_from_slice(&[1, 8, 8, 0, 1, 2, 3, 5, 4, 6, 7]);
+    push_len(&mut message, data.len());
+    message.extend_from_slice(&data);
+
+    let mut expected = vec![1];
+    expected.extend_from_slice(&submitter.payer.sign_bytes(&message));
+    expected.extend_from_slice(&message);
+    assert_eq!(transaction, expected);
+}
+
+#[test]
+fn test_refund_transaction_lists_the_escrow() {
+    let sender = KeyPair::from([20u8; 32]);
+    let order = make_order(&sender, 0, 10);
+    let instruction = PortalInstruction::Refund(compact(CrossChainRefundOrder { transfer_order: order }));
+    let accounts = instruction.accounts(&PORTAL).unwrap();
+    assert_eq!(
+        accounts[1..],
+        [
+            AccountMeta::writable(instruction.receipt(&PORTAL).unwrap()),
+            AccountMeta::writable(sender.public()),
+            AccountMeta::writable(Pubkey([4u8; 32])),
+            AccountMeta::readonly(escrow_authority(&PORTAL).unwrap()),
+            AccountMeta::readonly(TOKEN_PROGRAM),
+        ]
+    );
+}
+
+#[test]
+fn test_accounts_are_listed_once() {
+    let submitter = submitter(String::new());
+    let payer = submitter.payer.public();
+    // Paying for a transfer to oneself
+    let instruction = delivery(payer);
+    let transaction = submitter.transaction(&instruction, &Pubkey([7u8; 32])).unwrap();
+    let message = &transaction[65..];
+    assert_eq!(message[..4], [1, 0, 4, 8]);
+    let instruction_start = 4 + 8 * 32 + 32;
+    // The recipient is the payer, at index 0
+    assert_eq!(message[instruction_start..instruction_start + 11], [1, 7, 8, 0, 1, 2, 0, 4, 3, 5, 6]);
+}
+
+#[tokio::test]
+async fn test_file_submission_is_confirmed_once_appended() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = FileSubmitter::new(dir.path().join("instructions"));
+    let first = file.prepare(&delivery(Pubkey([2u8; 32]))).await.unwrap();
+    let second = file.prepare(&delivery(Pubkey([6u8; 32]))).await.unwrap();
+    assert_eq!(file.status(&first).await.unwrap(), SubmissionStatus::Expired);
+
+    file.send(&first).await.unwrap();
+    assert_eq!(file.status(&first).await.unwrap(), SubmissionStatus::Confirmed);
+    assert_eq!(file.status(&second).await.unwrap(), SubmissionStatus::Expired);
+    file.send(&second).await.unwrap();
+    assert_eq!(file.status(&second).await.unwrap(), SubmissionStatus::Confirmed);
+
+    // Whichever submission carried it
+    let other = FileSubmitter::new(dir.path().join("instructions"));
+    assert!(other.is_executed(&delivery(Pubkey([2u8; 32]))).await.unwrap());
+    let sender = KeyPair::from([21u8; 32]);
+    let order = make_order(&sender, 0, 10);
+    let undelivered = PortalInstruction::DeliverTransfer(compact(order));
+    assert!(!other.is_executed(&undelivered).await.unwrap());
+}
+
+/// Chain whose block height, signature statuses and receipt accounts are set by the test
+#[derive(Default)]
+struct Chain {
+    height: u64,
+    statuses: VecDeque<Value>,
+    sent: Vec<String>,
+    receipts: Vec<String>,
+}
+
+async fn chain_stub(chain: Arc<Mutex<Chain>>) -> String {
+    rpc_stub(move |method, params| {
+        let mut chain = chain.lock().unwrap();
+        match method {
+            "getLatestBlockhash" => json!({
+                "value": { "blockhash": Pubkey([7u8; 32]).base58(), "lastValidBlockHeight": 100 }
+            }),
+            "sendTransaction" => {
+                chain.sent.push(params[0].as_str().unwrap().to_string());
+                Value::Null
+            }
+            "getSignatureStatuses" => json!({ "value": [chain.statuses.pop_front().unwrap_or(Value::Null)] }),
+            "getBlockHeight" => json!(chain.height),
+            "getAccountInfo" => {
+                let receipt = params[0].as_str().unwrap();
+                if chain.receipts.iter().any(|known| known == receipt) {
+                    json!({ "value": { "data": ["", "base64"], "lamports": 1 } })
+                } else {
+                    json!({ "value": null })
+                }
+            }
+            _ => panic!("Unexpected call of {}", method),
+        }
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_svm_submission_waits_for_its_commitment() {
+    let chain = Arc::new(Mutex::new(Chain::default()));
+    let submitter = submitter(chain_stub(chain.clone()).await);
+    let submission = submitter.prepare(&delivery(Pubkey([2u8; 32]))).await.unwrap();
+    assert_eq!(submission.last_valid_height, 100);
+    assert_eq!(submission.id, bs58::encode(&submission.payload[1..65]).into_string());
+
+    // Sending again sends the same transaction
+    submitter.send(&submission).await.unwrap();
+    submitter.send(&submission).await.unwrap();
+    let encoded = base64::engine::general_purpose::STANDARD.encode(&submission.payload);
+    assert_eq!(chain.lock().unwrap().sent, [encoded.clone(), encoded]);
+
+    chain.lock().unwrap().height = 50;
+    chain.lock().unwrap().statuses.extend([
+        Value::Null,
+        json!({ "err": null, "confirmationStatus": "processed" }),
+        json!({ "err": null, "confirmationStatus": "confirmed" }),
+    ]);
+    assert_eq!(submitter.status(&submission).await.unwrap(), SubmissionStatus::Pending);
+    assert_eq!(submitter.status(&submission).await.unwrap(), SubmissionStatus::Pending);
+    assert_eq!(submitter.status(&submission).await.unwrap(), SubmissionStatus::Confirmed);
+}
+
+#[tokio::test]
+async fn test_svm_submission_fails_or_expires() {
+    let chain = Arc::new(Mutex::new(Chain::default()));
+    let submitter = submitter(chain_stub(chain.clone()).await);
+    let submission = submitter.prepare(&delivery(Pubkey([2u8; 32]))).await.unwrap();
+
+    chain
+        .lock()
+        .unwrap()
+        .statuses
+        .push_back(json!({ "err": { "InstructionError": [0, "Custom"] }, "confirmationStatus": "confirmed" }));
+    assert!(matches!(
+        submitter.status(&submission).await.unwrap(),
+        SubmissionStatus::Failed(error) if error.contains("InstructionError")
+    ));
+
+    // Not found, and past its last valid height
+    chain.lock().unwrap().height = 101;
+    assert_eq!(submitter.status(&submission).await.unwrap(), SubmissionStatus::Expired);
+}
+
+#[tokio::test]
+async fn test_svm_execution_is_read_from_the_receipt() {
+    let chain = Arc::new(Mutex::new(Chain::default()));
+    let submitter = submitter(chain_stub(chain.clone()).await);
+    let instruction = delivery(Pubkey([2u8; 32]));
+    assert!(!submitter.is_executed(&instruction).await.unwrap());
+    chain.lock().unwrap().receipts.push(instruction.receipt(&PORTAL).unwrap().base58());
+    assert!(submitter.is_executed(&instruction).await.unwrap());
+}
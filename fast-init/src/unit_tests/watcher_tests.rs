@@ -0,0 +1,165 @@
+use super::*;
+use fast_core::test_support::{self, rpc_stub};
+
+fn make_order(nonce: u64) -> CrossChainTransferOrder {
+    test_support::make_order(&KeyPair::from([20u8; 32]), nonce, 10)
+}
+
+fn nonces(batch: &SourceBatch) -> Vec<(u64, u64)> {
+    batch
+        .orders
+        .iter()
+        .map(|(position, order)| match order {
+            SourceOrder::Transfer(order) => (*position, order.transfer.nonce),
+            SourceOrder::Message(order) => (*position, order.message.nonce),
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_file_watcher_restarts_from_its_stored_cursor() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("orders.jsonl");
+    let mut file = fs::File::create(&path).unwrap();
+    for nonce in 0..3 {
+        writeln!(file, "{}", serde_json::to_string(&SourceOrder::Transfer(make_order(nonce))).unwrap()).unwrap();
+    }
+    // Not yet complete
+    write!(file, "{{\"transfer\"").unwrap();
+
+    let mut cursors = SourceCursors::load(dir.path().join("cursors.json")).unwrap();
+    let mut watcher = JsonLinesWatcher::new(ChainId(1), path.clone());
+    let batch = watcher.poll(cursors.get(ChainId(1))).await.unwrap();
+    assert_eq!(batch.orders.len(), 3);
+    assert_eq!(batch.cursor, fs::metadata(&path).unwrap().len() - 11);
+    // Stop after processing the first order
+    cursors.set(ChainId(1), batch.orders[0].0).unwrap();
+    drop(watcher);
+
+    let cursors = SourceCursors::load(dir.path().join("cursors.json")).unwrap();
+    assert_eq!(cursors.get(ChainId(1)), batch.orders[0].0);
+    assert_eq!(cursors.get(ChainId(2)), 0);
+    let mut watcher = JsonLinesWatcher::new(ChainId(1), path.clone());
+    let again = watcher.poll(cursors.get(ChainId(1))).await.unwrap();
+    assert_eq!(nonces(&again), nonces(&batch)[1..]);
+    assert_eq!(again.cursor, batch.cursor);
+}
+
+#[tokio::test]
+async fn test_demo_watcher_restarts_from_its_stored_cursor() {
+    let mut watcher = DemoWatcher(ChainId(u16::MAX));
+    let batch = watcher.poll(0).await.unwrap();
+    assert_eq!(nonces(&batch), [(1, 0), (2, 0)]);
+    assert!(matches!(&batch.orders[0].1, SourceOrder::Transfer(order) if order.transfer.destination_chain == ChainId(0)));
+    assert!(matches!(&batch.orders[1].1, SourceOrder::Message(_)));
+
+    let batch = watcher.poll(1).await.unwrap();
+    assert!(matches!(&batch.orders[..], [(2, SourceOrder::Message(_))]));
+    assert!(watcher.poll(2).await.unwrap().orders.is_empty());
+}
+
+#[tokio::test]
+async fn test_stdin_cannot_resume_from_a_stored_cursor() {
+    let dir = tempfile::tempdir().unwrap();
+    let spec = WatcherSpec::Stdin(ChainId(1));
+    assert!(spec.start("", dir.path(), 3).await.is_err());
+}
+
+/// Transactions of the portal, oldest first: slot, whether it failed, and the nonce of the
+/// order it logs
+type Transactions = Arc<Mutex<Vec<(u64, bool, u64)>>>;
+
+/// Serve the signatures and the logs of the given transactions over JSON-RPC, counting the
+/// calls.
+async fn portal_stub(transactions: Transactions, calls: Arc<Mutex<Vec<String>>>) -> String {
+    rpc_stub(move |method, params| {
+        let transactions = transactions.lock().unwrap().clone();
+        let result = match method {
+            "getSignaturesForAddress" => {
+                let limit = params[1]["limit"].as_u64().unwrap() as usize;
+                let before = params[1]["before"].as_str().map(|before| before.parse::<usize>().unwrap());
+                let newest = before.unwrap_or(transactions.len());
+                let page: Vec<_> = (0..newest)
+                    .rev()
+                    .take(limit)
+                    .map(|index| {
+                        let (slot, failed, _) = transactions[index];
+                        let err = if failed { json!({ "InstructionError": [0, "Custom"] }) } else { Value::Null };
+                        json!({ "signature": index.to_string(), "slot": slot, "err": err })
+                    })
+                    .collect();
+                json!(page)
+            }
+            "getTransaction" => {
+                let index: usize = params[0].as_str().unwrap().parse().unwrap();
+                let data = bincode::serialize(&make_order(transactions[index].2)).unwrap();
+                let log = format!("Program data: {}", base64::engine::general_purpose::STANDARD.encode(data));
+                json!({ "meta": { "logMessages": ["Program log: deposit", log] } })
+            }
+            _ => panic!("Unexpected call of {}", method),
+        };
+        calls.lock().unwrap().push(method.to_string());
+        result
+    })
+    .await
+}
+
+fn svm_watcher(url: String) -> SvmLogWatcher {
+    let mut watcher = SvmLogWatcher::new(ChainId(1), url, Pubkey([9u8; 32]).base58());
+    watcher.page_size = 2;
+    watcher.max_pages = 2;
+    watcher.max_transactions = 2;
+    watcher
+}
+
+#[tokio::test]
+async fn test_svm_watcher_restarts_from_its_stored_cursor() {
+    let transactions: Transactions =
+        Arc::new(Mutex::new(vec![(3, false, 0), (5, false, 1), (5, true, 2), (5, false, 3), (8, false, 4)]));
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let url = portal_stub(transactions.clone(), calls.clone()).await;
+
+    // Resume after slot 3, reading at most two transactions per poll
+    let mut watcher = svm_watcher(url.clone());
+    watcher.max_pages = 10;
+    let batch = watcher.poll(3).await.unwrap();
+    // The failed transaction is skipped, and slot 5 is not passed before all its orders are read
+    assert_eq!(nonces(&batch), [(4, 1), (5, 3)]);
+    assert_eq!(batch.cursor, 5);
+    let batch = watcher.poll(batch.cursor).await.unwrap();
+    assert_eq!(nonces(&batch), [(8, 4)]);
+    assert_eq!(batch.cursor, 8);
+
+    // A restarted watcher only reads what is past its cursor
+    transactions.lock().unwrap().push((9, false, 5));
+    let mut watcher = svm_watcher(url);
+    watcher.max_pages = 10;
+    let batch = watcher.poll(8).await.unwrap();
+    assert_eq!(nonces(&batch), [(9, 5)]);
+    let batch = watcher.poll(9).await.unwrap();
+    assert!(batch.orders.is_empty());
+    assert_eq!(batch.cursor, 9);
+}
+
+#[tokio::test]
+async fn test_svm_watcher_walks_back_over_several_polls() {
+    let transactions: Transactions = Arc::new(Mutex::new((0..9).map(|nonce| (10 + nonce, false, nonce)).collect()));
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let url = portal_stub(transactions.clone(), calls.clone()).await;
+
+    let mut watcher = svm_watcher(url);
+    let mut cursor = 0;
+    let mut read = Vec::new();
+    for _ in 0..10 {
+        calls.lock().unwrap().clear();
+        let batch = watcher.poll(cursor).await.unwrap();
+        // Two pages of signatures and two transactions at most
+        let calls = calls.lock().unwrap();
+        assert!(calls.iter().filter(|method| *method == "getSignaturesForAddress").count() <= 2);
+        assert!(calls.iter().filter(|method| *method == "getTransaction").count() <= 2);
+        read.extend(nonces(&batch).into_iter().map(|(_, nonce)| nonce));
+        cursor = batch.cursor;
+    }
+    assert_eq!(read, (0..9).collect::<Vec<_>>());
+    assert_eq!(cursor, 18);
+}
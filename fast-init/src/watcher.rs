@@ -0,0 +1,657 @@
+use base64::Engine;
+use failure::Error;
+use fast_core::{base_types::*, message::*, rpc::SvmRpcClient};
+use futures::future::BoxFuture;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::io::{SeekFrom, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+/// An order signed by a user of a source chain. In JSON, told apart by its `transfer` or
+/// `message` field.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SourceOrder {
+    Transfer(CrossChainTransferOrder),
+    Message(CrossChainMessageOrder),
+}
+
+impl SourceOrder {
+    pub fn source_chain(&self) -> ChainId {
+        match self {
+            SourceOrder::Transfer(order) => order.transfer.source_chain,
+            SourceOrder::Message(order) => order.message.source_chain,
+        }
+    }
+
+    pub fn interop_tx_id(&self) -> InteropTxId {
+        match self {
+            SourceOrder::Transfer(order) => order.transfer.interop_tx_id,
+            SourceOrder::Message(order) => order.message.interop_tx_id,
+        }
+    }
+
+    pub fn check_signature(&self) -> Result<(), Error> {
+        match self {
+            SourceOrder::Transfer(order) => Ok(order.check_signature()?),
+            SourceOrder::Message(order) => Ok(order.check_signature()?),
+        }
+    }
+}
+
+/// Orders read from a source chain past a cursor.
+pub struct SourceBatch {
+    /// New orders, each with the cursor to resume from once it is processed
+    pub orders: Vec<(u64, SourceOrder)>,
+    /// Cursor to resume from once the whole batch is processed
+    pub cursor: u64,
+}
+
+/// Source of the transfer and message orders of one chain. Positions in the source are given by an
+/// opaque cursor, which the relayer persists so that restarts neither miss nor repeat orders.
+pub trait SourceWatcher: Send {
+    /// Chain whose orders are reported
+    fn chain_id(&self) -> ChainId;
+
+    /// Read the orders past the given cursor
+    fn poll(&mut self, cursor: u64) -> BoxFuture<'_, Result<SourceBatch, Error>>;
+}
+
+/// How to watch a source chain, as given on the command line:
+/// `file:<CHAIN>:<PATH>`, `stdin:<CHAIN>`, `http:<CHAIN>:<ADDRESS>`, `svm:<CHAIN>:<PORTAL_PROGRAM>`
+/// or `demo:<CHAIN>`.
+#[derive(Debug, Clone)]
+pub enum WatcherSpec {
+    File(ChainId, PathBuf),
+    Stdin(ChainId),
+    Http(ChainId, SocketAddr),
+    Svm(ChainId, String),
+    Demo(ChainId),
+}
+
+impl FromStr for WatcherSpec {
+    type Err = Error;
+
+    fn from_str(spec: &str) -> Result<Self, Error> {
+        let mut parts = spec.splitn(3, ':');
+        let kind = parts.next().unwrap_or_default();
+        let chain_id = ChainId(parts
+            .next()
+            .ok_or_else(|| failure::format_err!("Missing chain id in source {:?}", spec))?
+            .parse()?);
+        let argument = parts.next();
+        match (kind, argument) {
+            ("file", Some(path)) => Ok(WatcherSpec::File(chain_id, PathBuf::from(path))),
+            ("stdin", None) => Ok(WatcherSpec::Stdin(chain_id)),
+            ("http", Some(address)) => Ok(WatcherSpec::Http(chain_id, address.parse()?)),
+            ("svm", Some(portal_program)) => Ok(WatcherSpec::Svm(chain_id, portal_program.to_string())),
+            ("demo", None) => Ok(WatcherSpec::Demo(chain_id)),
+            _ => Err(failure::format_err!("Invalid source {:?}", spec)),
+        }
+    }
+}
+
+impl WatcherSpec {
+    pub fn chain_id(&self) -> ChainId {
+        match self {
+            WatcherSpec::File(chain_id, _)
+            | WatcherSpec::Stdin(chain_id)
+            | WatcherSpec::Http(chain_id, _)
+            | WatcherSpec::Svm(chain_id, _)
+            | WatcherSpec::Demo(chain_id) => *chain_id,
+        }
+    }
+
+    /// Create the watcher, to resume from `cursor`. HTTP intakes journal what they receive
+    /// under `state_dir`.
+    pub async fn start(
+        &self,
+        source_rpc: &str,
+        state_dir: &Path,
+        cursor: u64,
+    ) -> Result<Box<dyn SourceWatcher>, Error> {
+        Ok(match self {
+            WatcherSpec::File(chain_id, path) => Box::new(JsonLinesWatcher::new(*chain_id, path.clone())),
+            WatcherSpec::Stdin(chain_id) => {
+                if cursor != 0 {
+                    return Err(failure::format_err!(
+                        "The standard input cannot resume chain {} from cursor {}",
+                        chain_id.0,
+                        cursor
+                    ));
+                }
+                Box::new(StdinWatcher::new(*chain_id))
+            }
+            WatcherSpec::Http(chain_id, address) => {
+                let journal = state_dir.join(format!("intake_{}.jsonl", chain_id.0));
+                Box::new(HttpIntakeWatcher::start(*chain_id, *address, journal).await?)
+            }
+            WatcherSpec::Svm(chain_id, portal_program) => Box::new(SvmLogWatcher::new(
+                *chain_id,
+                source_rpc.to_string(),
+                portal_program.clone(),
+            )),
+            WatcherSpec::Demo(chain_id) => Box::new(DemoWatcher(*chain_id)),
+        })
+    }
+}
+
+/// Cursors of the source chains, saved to disk after each update.
+pub struct SourceCursors {
+    path: PathBuf,
+    cursors: BTreeMap<u16, u64>,
+}
+
+impl SourceCursors {
+    pub fn load(path: PathBuf) -> Result<Self, Error> {
+        let cursors = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { path, cursors })
+    }
+
+    pub fn get(&self, chain_id: ChainId) -> u64 {
+        *self.cursors.get(&chain_id.0).unwrap_or(&0)
+    }
+
+    /// Move the cursor of a chain, replacing the file atomically.
+    pub fn set(&mut self, chain_id: ChainId, cursor: u64) -> Result<(), Error> {
+        if self.cursors.insert(chain_id.0, cursor) == Some(cursor) {
+            return Ok(());
+        }
+        let tmp = self.path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(&serde_json::to_vec(&self.cursors)?)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Orders given as JSON lines appended to a file. The cursor is a byte offset, and a last
+/// line without a newline is left for the next poll.
+pub struct JsonLinesWatcher {
+    chain_id: ChainId,
+    path: PathBuf,
+}
+
+impl JsonLinesWatcher {
+    pub fn new(chain_id: ChainId, path: PathBuf) -> Self {
+        Self { chain_id, path }
+    }
+}
+
+impl SourceWatcher for JsonLinesWatcher {
+    fn chain_id(&self) -> ChainId {
+        self.chain_id
+    }
+
+    fn poll(&mut self, cursor: u64) -> BoxFuture<'_, Result<SourceBatch, Error>> {
+        Box::pin(async move {
+            let mut file = match tokio::fs::File::open(&self.path).await {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    return Ok(SourceBatch { orders: Vec::new(), cursor });
+                }
+                Err(e) => return Err(e.into()),
+            };
+            file.seek(SeekFrom::Start(cursor)).await?;
+            let mut data = Vec::new();
+            file.read_to_end(&mut data).await?;
+
+            let mut offset = cursor;
+            let mut orders = Vec::new();
+            for line in data.split_inclusive(|byte| *byte == b'\n') {
+                if !line.ends_with(b"\n") {
+                    break;
+                }
+                offset += line.len() as u64;
+                if line.trim_ascii().is_empty() {
+                    continue;
+                }
+                match serde_json::from_slice(line) {
+                    Ok(order) => orders.push((offset, order)),
+                    Err(e) => error!("Skipping malformed order in {}: {}", self.path.display(), e),
+                }
+            }
+            Ok(SourceBatch { orders, cursor: offset })
+        })
+    }
+}
+
+/// Orders given as JSON lines on the standard input. A feed cannot be resumed, so positions
+/// in it are never saved and the cursor stays at 0: after a restart, the whole feed is read
+/// again, and the orders already in the job log are skipped like any order reported twice.
+pub struct StdinWatcher {
+    chain_id: ChainId,
+    lines: mpsc::UnboundedReceiver<String>,
+}
+
+impl StdinWatcher {
+    pub fn new(chain_id: ChainId) -> Self {
+        let (sender, lines) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut reader = BufReader::new(tokio::io::stdin()).lines();
+            while let Ok(Some(line)) = reader.next_line().await {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Self { chain_id, lines }
+    }
+}
+
+impl SourceWatcher for StdinWatcher {
+    fn chain_id(&self) -> ChainId {
+        self.chain_id
+    }
+
+    fn poll(&mut self, _cursor: u64) -> BoxFuture<'_, Result<SourceBatch, Error>> {
+        Box::pin(async move {
+            let mut orders = Vec::new();
+            while let Ok(line) = self.lines.try_recv() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str(&line) {
+                    Ok(order) => orders.push((0, order)),
+                    Err(e) => error!("Skipping malformed order on stdin: {}", e),
+                }
+            }
+            Ok(SourceBatch { orders, cursor: 0 })
+        })
+    }
+}
+
+/// Largest order body the intake accepts, well above the size of a signed order
+const MAX_INTAKE_BODY: usize = 16 * 1024;
+
+/// Longest request line or header the intake reads
+const MAX_INTAKE_LINE: u64 = 8 * 1024;
+
+/// Time a client has to send its whole request
+const INTAKE_READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Local HTTP endpoint accepting orders as JSON bodies of POST requests. Accepted orders
+/// are appended to a journal before the request is answered, and read back from there.
+pub struct HttpIntakeWatcher {
+    journal: JsonLinesWatcher,
+}
+
+impl HttpIntakeWatcher {
+    pub async fn start(chain_id: ChainId, address: SocketAddr, journal: PathBuf) -> Result<Self, Error> {
+        let listener = TcpListener::bind(address).await?;
+        info!("Accepting orders of chain {} on http://{}", chain_id.0, address);
+        // Connections are served concurrently, but append to the journal one at a time
+        let path = Arc::new(Mutex::new(journal.clone()));
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer)) => {
+                        let path = path.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = Self::serve(stream, chain_id, path).await {
+                                warn!("Intake request from {} failed: {}", peer, e);
+                            }
+                        });
+                    }
+                    Err(e) => error!("Failed to accept intake connection: {}", e),
+                }
+            }
+        });
+        Ok(Self {
+            journal: JsonLinesWatcher::new(chain_id, journal),
+        })
+    }
+
+    /// Answer one request. Connections are not kept alive.
+    async fn serve(stream: TcpStream, chain_id: ChainId, journal: Arc<Mutex<PathBuf>>) -> Result<(), Error> {
+        let mut reader = BufReader::new(stream);
+        let request = tokio::time::timeout(INTAKE_READ_TIMEOUT, Self::read_request(&mut reader))
+            .await
+            .map_err(|_| failure::format_err!("Request not received within {:?}", INTAKE_READ_TIMEOUT))?;
+        let (status, answer) = match request {
+            Err(IntakeError(status, answer)) => (status, answer),
+            Ok(body) => {
+                let accepted = tokio::task::spawn_blocking(move || {
+                    let journal = journal.lock().unwrap();
+                    Self::accept(&body, chain_id, &journal)
+                })
+                .await?;
+                match accepted {
+                    Ok(order) => ("202 Accepted", order.interop_tx_id().base58()),
+                    Err(e) => ("400 Bad Request", e.to_string()),
+                }
+            }
+        };
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            answer.len(),
+            answer
+        );
+        reader.into_inner().write_all(response.as_bytes()).await?;
+        Ok(())
+    }
+
+    /// Read the body of a POST request, refusing bodies over `MAX_INTAKE_BODY` before reading
+    /// them.
+    async fn read_request(reader: &mut BufReader<TcpStream>) -> Result<Vec<u8>, IntakeError> {
+        let request_line = Self::read_line(reader).await?;
+        let mut content_length = 0;
+        loop {
+            let header = Self::read_line(reader).await?;
+            if header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':')
+                && name.trim().eq_ignore_ascii_case("content-length")
+            {
+                content_length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| IntakeError("400 Bad Request", "Invalid Content-Length".to_string()))?;
+            }
+        }
+        if !request_line.starts_with("POST ") {
+            return Err(IntakeError("405 Method Not Allowed", "Only POST is supported".to_string()));
+        }
+        if content_length > MAX_INTAKE_BODY {
+            return Err(IntakeError(
+                "413 Payload Too Large",
+                format!("Orders are at most {} bytes", MAX_INTAKE_BODY),
+            ));
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).await.map_err(IntakeError::io)?;
+        Ok(body)
+    }
+
+    /// Read one line of the request head, up to `MAX_INTAKE_LINE` bytes.
+    async fn read_line(reader: &mut BufReader<TcpStream>) -> Result<String, IntakeError> {
+        let mut line = String::new();
+        (&mut *reader).take(MAX_INTAKE_LINE).read_line(&mut line).await.map_err(IntakeError::io)?;
+        if line.len() as u64 == MAX_INTAKE_LINE && !line.ends_with('\n') {
+            return Err(IntakeError("431 Request Header Fields Too Large", "Request line or header too long".to_string()));
+        }
+        if !line.ends_with('\n') {
+            return Err(IntakeError("400 Bad Request", "Incomplete request head".to_string()));
+        }
+        Ok(line)
+    }
+
+    /// Check an order and make it durable in the journal.
+    fn accept(body: &[u8], chain_id: ChainId, journal: &Path) -> Result<SourceOrder, Error> {
+        let order: SourceOrder = serde_json::from_slice(body)?;
+        if order.source_chain() != chain_id {
+            return Err(failure::format_err!(
+                "This intake only accepts orders from chain {}",
+                chain_id.0
+            ));
+        }
+        order.check_signature()?;
+        let mut line = serde_json::to_vec(&order)?;
+        line.push(b'\n');
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(journal)?;
+        file.write_all(&line)?;
+        file.sync_data()?;
+        Ok(order)
+    }
+}
+
+/// Status and explanation of a request the intake refuses before reading its order
+struct IntakeError(&'static str, String);
+
+impl IntakeError {
+    fn io(error: std::io::Error) -> Self {
+        IntakeError("400 Bad Request", error.to_string())
+    }
+}
+
+impl SourceWatcher for HttpIntakeWatcher {
+    fn chain_id(&self) -> ChainId {
+        self.journal.chain_id
+    }
+
+    fn poll(&mut self, cursor: u64) -> BoxFuture<'_, Result<SourceBatch, Error>> {
+        self.journal.poll(cursor)
+    }
+}
+
+/// Reads the transfer orders logged by the source portal program through the JSON-RPC
+/// endpoint of an SVM chain. The portal logs each order as `Program data: <base64>` of its
+/// bincode encoding. The cursor is a slot: a slot is only passed once all its transactions are
+/// read, so a restart may see a slot again but never skips one.
+///
+/// The watcher polls rather than using `logsSubscribe`: a subscription only delivers the logs
+/// of transactions landing while it is connected, so the transactions since the cursor must be
+/// read back this way after every restart or dropped connection anyway. Each poll makes a
+/// bounded number of calls: a watcher far behind walks back to its cursor over several polls,
+/// then reads the transactions it found over several more.
+pub struct SvmLogWatcher {
+    chain_id: ChainId,
+    client: SvmRpcClient,
+    portal_program: String,
+    /// Walk from the newest transactions back to the cursor, in progress
+    walk: Option<SignatureWalk>,
+    /// Successful transactions past `pending_cursor`, oldest first, to be read
+    pending: VecDeque<(u64, String)>,
+    pending_cursor: u64,
+    page_size: usize,
+    max_pages: usize,
+    max_transactions: usize,
+}
+
+/// Signatures listed per call
+const SIGNATURES_PAGE: usize = 1000;
+/// Pages of signatures listed per poll
+const MAX_SIGNATURE_PAGES_PER_POLL: usize = 10;
+/// Transactions read per poll
+const MAX_TRANSACTIONS_PER_POLL: usize = 1000;
+/// Transactions found by a walk and kept to be read. The newest are dropped past this, and
+/// found again by a later walk.
+const MAX_PENDING_TRANSACTIONS: usize = 100_000;
+
+/// Progress of a walk back through the transactions of the portal
+struct SignatureWalk {
+    /// Slot the walk goes back to
+    cursor: u64,
+    /// Oldest transaction listed so far, to list the next page from
+    before: Option<String>,
+    /// Successful transactions found, newest first
+    found: VecDeque<(u64, String)>,
+}
+
+impl SvmLogWatcher {
+    pub fn new(chain_id: ChainId, url: String, portal_program: String) -> Self {
+        Self {
+            chain_id,
+            client: SvmRpcClient::new(url),
+            portal_program,
+            walk: None,
+            pending: VecDeque::new(),
+            pending_cursor: 0,
+            page_size: SIGNATURES_PAGE,
+            max_pages: MAX_SIGNATURE_PAGES_PER_POLL,
+            max_transactions: MAX_TRANSACTIONS_PER_POLL,
+        }
+    }
+
+    /// Walk back through the transactions of the portal, up to `max_pages` pages. Returns
+    /// whether the walk reached its cursor.
+    async fn walk_back(&self, walk: &mut SignatureWalk) -> Result<bool, Error> {
+        for _ in 0..self.max_pages {
+            let mut options = json!({ "limit": self.page_size, "commitment": "finalized" });
+            if let Some(before) = &walk.before {
+                options["before"] = json!(before);
+            }
+            let page = self
+                .client
+                .call("getSignaturesForAddress", json!([self.portal_program, options]))
+                .await?;
+            let page = page.as_array().cloned().unwrap_or_default();
+            let mut done = page.len() < self.page_size;
+            for entry in &page {
+                let slot = entry["slot"].as_u64().unwrap_or(0);
+                let signature = entry["signature"].as_str().unwrap_or_default().to_string();
+                walk.before = Some(signature.clone());
+                if slot <= walk.cursor {
+                    done = true;
+                    break;
+                }
+                if entry["err"].is_null() {
+                    walk.found.push_back((slot, signature));
+                }
+            }
+            if walk.found.len() > MAX_PENDING_TRANSACTIONS {
+                // Only whole slots are kept, so that no slot is passed before all its
+                // transactions are read
+                let dropped = walk.found.len() - MAX_PENDING_TRANSACTIONS;
+                let newest_kept = walk.found[dropped].0;
+                walk.found.retain(|(slot, _)| *slot < newest_kept);
+            }
+            if done {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    async fn orders_of(&self, signature: &str) -> Result<Vec<CrossChainTransferOrder>, Error> {
+        let options = json!({
+            "encoding": "json",
+            "commitment": "finalized",
+            "maxSupportedTransactionVersion": 0,
+        });
+        let transaction = self.client.call("getTransaction", json!([signature, options])).await?;
+        let logs = transaction["meta"]["logMessages"].as_array().cloned().unwrap_or_default();
+        let mut orders = Vec::new();
+        for log in logs.iter().filter_map(Value::as_str) {
+            let Some(data) = log.strip_prefix("Program data: ") else {
+                continue;
+            };
+            let Ok(bytes) = base64::engine::general_purpose::STANDARD.decode(data) else {
+                continue;
+            };
+            if let Ok(order) = bincode::deserialize::<CrossChainTransferOrder>(&bytes) {
+                orders.push(order);
+            }
+        }
+        Ok(orders)
+    }
+}
+
+impl SourceWatcher for SvmLogWatcher {
+    fn chain_id(&self) -> ChainId {
+        self.chain_id
+    }
+
+    fn poll(&mut self, cursor: u64) -> BoxFuture<'_, Result<SourceBatch, Error>> {
+        Box::pin(async move {
+            if self.pending.is_empty() || self.pending_cursor != cursor {
+                let mut walk = match self.walk.take() {
+                    Some(walk) if walk.cursor == cursor => walk,
+                    _ => SignatureWalk {
+                        cursor,
+                        before: None,
+                        found: VecDeque::new(),
+                    },
+                };
+                let reached = self.walk_back(&mut walk).await;
+                if !matches!(reached, Ok(true)) {
+                    // Resumed at the next poll
+                    self.walk = Some(walk);
+                    reached?;
+                    return Ok(SourceBatch { orders: Vec::new(), cursor });
+                }
+                self.pending = walk.found.into_iter().rev().collect();
+                self.pending_cursor = cursor;
+            }
+
+            let mut orders = Vec::new();
+            let mut new_cursor = cursor;
+            let read = self.pending.len().min(self.max_transactions);
+            for (index, (slot, signature)) in self.pending.iter().take(read).enumerate() {
+                // Only move past a slot after its last transaction
+                let last_of_slot = self.pending.get(index + 1).is_none_or(|(next, _)| next != slot);
+                let position = if last_of_slot { *slot } else { slot.saturating_sub(1) };
+                for order in self.orders_of(signature).await? {
+                    orders.push((position, SourceOrder::Transfer(order)));
+                }
+                new_cursor = position;
+            }
+            self.pending.drain(..read);
+            self.pending_cursor = new_cursor;
+            Ok(SourceBatch {
+                orders,
+                cursor: new_cursor,
+            })
+        })
+    }
+}
+
+/// Produces one fixed transfer and one fixed message, signed with a well-known key, to try
+/// out a local setup.
+pub struct DemoWatcher(ChainId);
+
+impl SourceWatcher for DemoWatcher {
+    fn chain_id(&self) -> ChainId {
+        self.0
+    }
+
+    fn poll(&mut self, cursor: u64) -> BoxFuture<'_, Result<SourceBatch, Error>> {
+        let chain_id = self.0;
+        Box::pin(async move {
+            if cursor > 1 {
+                return Ok(SourceBatch { orders: Vec::new(), cursor });
+            }
+            let sender_keypair = KeyPair::from([2u8; 32]);
+            let transfer = CrossChainTransfer {
+                source_chain: chain_id,
+                destination_chain: ChainId(chain_id.0.wrapping_add(1)),
+                sender: sender_keypair.public(),
+                recipient: Pubkey([3u8; 32]),
+                amount: 1000,
+                token_mint: Pubkey([4u8; 32]),
+                interop_tx_id: InteropTxId([1u8; 32]),
+                escrow_account: Pubkey([5u8; 32]),
+                nonce: 0,
+                deadline: 4_102_444_800, // 2100-01-01
+            };
+            let message = CrossChainMessage {
+                source_chain: chain_id,
+                destination_chain: ChainId(chain_id.0.wrapping_add(1)),
+                sender: sender_keypair.public(),
+                target_program: Pubkey([7u8; 32]),
+                payload: b"ping".to_vec(),
+                interop_tx_id: InteropTxId([6u8; 32]),
+                nonce: 0,
+            };
+            let orders = vec![
+                (1, SourceOrder::Transfer(CrossChainTransferOrder::new(transfer, &sender_keypair))),
+                (2, SourceOrder::Message(CrossChainMessageOrder::new(message, &sender_keypair))),
+            ];
+            Ok(SourceBatch {
+                orders: orders.into_iter().filter(|(position, _)| *position > cursor).collect(),
+                cursor: 2,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+#[path = "unit_tests/watcher_tests.rs"]
+mod watcher_tests;